@@ -0,0 +1,107 @@
+//! Saving and loading typical kinds of values in each format enabled in the build.
+//!
+//! Run with `cargo bench --all-features` to compare all formats.
+
+extern crate criterion;
+extern crate preferences;
+#[macro_use]
+extern crate serde_derive;
+
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use preferences::{AppInfo, CachedStore, Format, PreferencesMap, PreferencesStore, SaveOptions};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::{env, fs, process};
+
+const APP_INFO: AppInfo = AppInfo {
+    name: "preferences",
+    author: "Rust language community",
+};
+
+#[derive(Serialize, Deserialize)]
+struct Settings {
+    fullscreen: bool,
+    volume: u8,
+    language: String,
+    recent_files: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Nested {
+    name: String,
+    child: Option<Box<Nested>>,
+}
+
+fn settings() -> Settings {
+    Settings {
+        fullscreen: true,
+        volume: 7,
+        language: "en-US".to_owned(),
+        recent_files: vec!["notes.txt".to_owned(), "todo.md".to_owned()],
+    }
+}
+
+fn large_map() -> PreferencesMap {
+    (0..10_000).map(|i| (format!("key {}", i), format!("value {}", i))).collect()
+}
+
+/// Returns a value nested `depth` levels deep, within the recursion limits of all formats.
+fn nested(depth: usize) -> Nested {
+    (0..depth).fold(Nested { name: "leaf".to_owned(), child: None }, |child, i| {
+        Nested {
+            name: format!("level {}", i),
+            child: Some(Box::new(child)),
+        }
+    })
+}
+
+/// Benchmarks saving and loading `value` with the key `name` in each format.
+fn bench_value<T>(c: &mut Criterion, store: &PreferencesStore, name: &str, value: &T)
+    where T: Serialize + DeserializeOwned
+{
+    let mut group = c.benchmark_group(name);
+    for &format in Format::all() {
+        let options = SaveOptions::new().format(format);
+        let id = format!("{:?}", format);
+        group.bench_function(BenchmarkId::new("save", &id), |b| {
+            b.iter(|| store.save_with_options(name, value, &options).unwrap())
+        });
+        group.bench_function(BenchmarkId::new("load", &id), |b| {
+            b.iter(|| store.load_with_format::<T, _>(name, format).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn bench_formats(c: &mut Criterion) {
+    let dir = env::temp_dir().join(format!("preferences-rs-bench-formats-{}", process::id()));
+    let store = PreferencesStore::new(&APP_INFO).unwrap().with_base_dir(&dir);
+    bench_value(c, &store, "small struct", &settings());
+    bench_value(c, &store, "10k-entry map", &large_map());
+    bench_value(c, &store, "deep nesting", &nested(32));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+/// Compares looking up the data directory for each call (as the `Preferences` methods do) with
+/// reusing a store, and loading with reusing loaded values.
+fn bench_store_reuse(c: &mut Criterion) {
+    let dir = env::temp_dir().join(format!("preferences-rs-bench-reuse-{}", process::id()));
+    let store = PreferencesStore::new(&APP_INFO).unwrap().with_base_dir(&dir);
+    store.save("settings", &settings()).unwrap();
+    let cached = CachedStore::new(store.clone());
+
+    let mut group = c.benchmark_group("store reuse");
+    group.bench_function("new store", |b| b.iter(|| PreferencesStore::new(&APP_INFO).unwrap()));
+    group.bench_function("load", |b| b.iter(|| store.load::<Settings, _>("settings").unwrap()));
+    group.bench_function("cached get", |b| {
+        b.iter(|| cached.get::<Settings, _>("settings").unwrap())
+    });
+    group.bench_function("cached get with format", |b| {
+        b.iter(|| cached.get_with_format::<Settings, _>("settings", Format::Json).unwrap())
+    });
+    group.finish();
+    let _ = fs::remove_dir_all(&dir);
+}
+
+criterion_group!(benches, bench_formats, bench_store_reuse);
+criterion_main!(benches);
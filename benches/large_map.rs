@@ -0,0 +1,59 @@
+//! Saving and loading a map with many entries, to and from files.
+//!
+//! The `unbuffered` benchmarks serialize straight to a `File`, as `save_to` and `load_from` did
+//! before they buffered their writer and reader, for comparison.
+
+extern crate criterion;
+extern crate preferences;
+extern crate serde_json;
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use preferences::{AppInfo, Preferences, PreferencesMap, PreferencesStore};
+use std::fs::File;
+use std::io::{Seek, SeekFrom};
+use std::{env, fs, process};
+
+const APP_INFO: AppInfo = AppInfo {
+    name: "preferences",
+    author: "Rust language community",
+};
+
+fn large_map() -> PreferencesMap {
+    (0..10_000).map(|i| (format!("key {}", i), format!("value {}", i))).collect()
+}
+
+fn bench_large_map(c: &mut Criterion) {
+    let dir = env::temp_dir().join(format!("preferences-rs-bench-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let map = large_map();
+    let path = dir.join("map.json");
+
+    c.bench_function("save_to unbuffered", |b| {
+        b.iter(|| serde_json::to_writer(File::create(&path).unwrap(), &map).unwrap())
+    });
+    c.bench_function("save_to", |b| b.iter(|| map.save_to(&mut File::create(&path).unwrap())));
+
+    let mut file = File::open(&path).unwrap();
+    c.bench_function("load_from unbuffered", |b| {
+        b.iter(|| {
+            file.seek(SeekFrom::Start(0)).unwrap();
+            serde_json::from_reader::<_, PreferencesMap>(&mut file).unwrap()
+        })
+    });
+    c.bench_function("load_from", |b| {
+        b.iter(|| {
+            file.seek(SeekFrom::Start(0)).unwrap();
+            <PreferencesMap>::load_from(&mut file).unwrap()
+        })
+    });
+
+    let store = PreferencesStore::new(&APP_INFO).unwrap().with_base_dir(&dir);
+    c.bench_function("store save", |b| b.iter(|| store.save("map", &map).unwrap()));
+    c.bench_function("store load", |b| {
+        b.iter(|| store.load::<PreferencesMap, _>("map").unwrap())
+    });
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+criterion_group!(benches, bench_large_map);
+criterion_main!(benches);
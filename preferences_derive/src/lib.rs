@@ -0,0 +1,154 @@
+//! Derive macro for the [`preferences`](https://docs.rs/preferences) crate.
+//!
+//! This crate is used through the `derive` feature of `preferences`, which re-exports the
+//! [`Prefs`](derive.Prefs.html) macro.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{Data, DeriveInput, Expr, ExprLit, Fields, Lit, LitStr, Path, parse_macro_input};
+
+/// Generates loading and saving of a struct as user data, with defaults for its fields.
+///
+/// The struct must have named fields, implement `Serialize` and `Deserialize`, and have a
+/// `#[prefs(app = "APP_INFO", key = "options/graphics")]` attribute, where `app` is the path of
+/// an `AppInfo` constant and `key` is the key of the data. Fields can have a
+/// `#[prefs(default = 60)]` attribute with the expression of their default value (string
+/// literals are converted with `Into`); other fields default to `Default::default()`.
+///
+/// Fields can also be described for a settings screen with `label`, `description`, `min`, `max`
+/// and `choices` in the same attribute, e.g.
+/// `#[prefs(label = "Quality", choices = ["low", "high"])]`. (See `PrefDescriptor`.)
+///
+/// This generates:
+///
+/// * `PREFS_KEY`, a constant with the key.
+/// * `load()`, `load_or_default()` and `save(&self)`, which work like the methods of the
+///   `Preferences` trait with the app and key filled in.
+/// * `descriptors()`, which returns a `PrefDescriptor` of each field, with the field's name.
+/// * An implementation of `Default` that uses the field defaults. (So the struct must not derive
+///   `Default` too.)
+#[proc_macro_derive(Prefs, attributes(prefs))]
+pub fn derive_prefs(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input).unwrap_or_else(syn::Error::into_compile_error).into()
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let mut app: Option<Path> = None;
+    let mut key: Option<LitStr> = None;
+    for attr in input.attrs.iter().filter(|attr| attr.path().is_ident("prefs")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("app") {
+                app = Some(meta.value()?.parse::<LitStr>()?.parse()?);
+            } else if meta.path.is_ident("key") {
+                key = Some(meta.value()?.parse()?);
+            } else {
+                return Err(meta.error("expected `app` or `key`"));
+            }
+            Ok(())
+        })?;
+    }
+    let missing = |name| {
+        syn::Error::new_spanned(&input.ident,
+                                format!("missing `#[prefs({} = \"...\")]` attribute", name))
+    };
+    let app = app.ok_or_else(|| missing("app"))?;
+    let key = key.ok_or_else(|| missing("key"))?;
+
+    let fields = match input.data {
+        Data::Struct(ref data) => {
+            match data.fields {
+                Fields::Named(ref fields) => Some(&fields.named),
+                _ => None,
+            }
+        }
+        _ => None,
+    };
+    let fields = fields.ok_or_else(|| {
+            syn::Error::new_spanned(&input.ident, "Prefs requires a struct with named fields")
+        })?;
+    let mut defaults = Vec::new();
+    let mut descriptors = Vec::new();
+    for field in fields {
+        let mut default = None;
+        let mut describe = Vec::new();
+        for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("prefs")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("default") {
+                    default = Some(meta.value()?.parse::<Expr>()?);
+                } else if meta.path.is_ident("label") {
+                    let label = meta.value()?.parse::<LitStr>()?;
+                    describe.push(quote!(.label(#label)));
+                } else if meta.path.is_ident("description") {
+                    let description = meta.value()?.parse::<LitStr>()?;
+                    describe.push(quote!(.description(#description)));
+                } else if meta.path.is_ident("min") {
+                    let min = meta.value()?.parse::<Expr>()?;
+                    describe.push(quote!(.min((#min) as f64)));
+                } else if meta.path.is_ident("max") {
+                    let max = meta.value()?.parse::<Expr>()?;
+                    describe.push(quote!(.max((#max) as f64)));
+                } else if meta.path.is_ident("choices") {
+                    let choices = meta.value()?.parse::<Expr>()?;
+                    describe.push(quote!(.choices(&#choices)));
+                } else {
+                    return Err(meta.error("expected `default`, `label`, `description`, `min`, \
+                                           `max` or `choices`"));
+                }
+                Ok(())
+            })?;
+        }
+        let name = &field.ident;
+        let field_name = name.as_ref().map(ToString::to_string);
+        descriptors.push(quote!((#field_name, ::preferences::PrefDescriptor::new() #(#describe)*)));
+        defaults.push(match default {
+            Some(Expr::Lit(ExprLit { lit: Lit::Str(ref text), .. })) => {
+                quote!(#name: ::std::convert::Into::into(#text))
+            }
+            Some(default) => quote!(#name: #default),
+            None => quote!(#name: ::std::default::Default::default()),
+        });
+    }
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics #name #ty_generics #where_clause {
+            /// The key with which this data is saved.
+            pub const PREFS_KEY: &'static str = #key;
+
+            /// Loads this data.
+            pub fn load() -> ::std::result::Result<Self, ::preferences::PreferencesError> {
+                <Self as ::preferences::Preferences>::load(&#app, Self::PREFS_KEY)
+            }
+
+            /// Loads this data, or returns the default value if it wasn't saved yet.
+            pub fn load_or_default()
+                -> ::std::result::Result<Self, ::preferences::PreferencesError> {
+                <Self as ::preferences::Preferences>::load_or_default(&#app, Self::PREFS_KEY)
+            }
+
+            /// Saves this data.
+            pub fn save(&self) -> ::std::result::Result<(), ::preferences::PreferencesError> {
+                ::preferences::Preferences::save(self, &#app, Self::PREFS_KEY)
+            }
+
+            /// Returns a descriptor of each field, with the field's name, for a settings screen.
+            pub fn descriptors()
+                -> ::std::vec::Vec<(&'static str, ::preferences::PrefDescriptor)> {
+                ::std::vec![#(#descriptors),*]
+            }
+        }
+
+        impl #impl_generics ::std::default::Default for #name #ty_generics #where_clause {
+            fn default() -> Self {
+                #name {
+                    #(#defaults,)*
+                }
+            }
+        }
+    })
+}
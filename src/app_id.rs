@@ -0,0 +1,159 @@
+//! Identity of an app beyond its name and author.
+
+use std::path::PathBuf;
+use crate::keys::sanitized;
+
+/// Name and author of an app, which determine where its user data is stored.
+///
+/// It's recommended to create a single `const` instance of `AppInfo`:
+///
+/// ```
+/// use preferences::AppInfo;
+///
+/// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+/// ```
+///
+/// Characters that aren't safe in file names are escaped in the directories named after them, so
+/// the paths are more readable if both are made of letters, digits, spaces, hyphens, underscores
+/// and periods. Neither may be empty. On Linux, only the name is used, so it should be unique.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct AppInfo {
+    /// Name of the app (e.g. "Hearthstone").
+    pub name: &'static str,
+    /// Author of the app (e.g. "Blizzard").
+    pub author: &'static str,
+}
+
+/// An [`AppInfo`](struct.AppInfo.html) together with the app's bundle identifier and version,
+/// which determine its data directory on platforms where they're conventional.
+///
+/// * On macOS, the data directory is named after the bundle identifier (e.g.
+///   `~/Library/Application Support/org.example.MyApp`) instead of the app's author and name,
+///   as Apple recommends.
+/// * On Windows, the data directory has a subdirectory for the version (e.g.
+///   `%APPDATA%\<author>\<app name>\config\2.0`), following Microsoft's
+///   `<company>\<product>\<version>` convention.
+///
+/// Elsewhere, and in directories that aren't the platform's (e.g. with
+/// [`with_base_dir`](struct.PreferencesStore.html#method.with_base_dir), the `PREFERENCES_RS_DIR`
+/// environment variable or portable storage), both are ignored, so the data directory is the
+/// same as for the plain `AppInfo`. Switching an existing app to a bundle identifier or a version
+/// therefore leaves its previously saved data behind on macOS or Windows; see
+/// [`migrate_from`](fn.migrate_from.html).
+///
+/// # Example
+/// ```
+/// use preferences::{AppId, AppInfo, PreferencesStore};
+///
+/// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+///
+/// # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
+/// let app = AppId::new(APP_INFO).bundle_id("org.rust-lang.preferences").version("1.0");
+/// let store = PreferencesStore::for_app(&app).unwrap();
+/// store.save("tests/docs/app_id", &true).unwrap();
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct AppId {
+    info: AppInfo,
+    bundle_id: Option<String>,
+    version: Option<String>,
+}
+
+impl AppId {
+    /// Creates the identity of the app described by `info`, without a bundle identifier or
+    /// version.
+    pub fn new(info: AppInfo) -> Self {
+        AppId {
+            info,
+            bundle_id: None,
+            version: None,
+        }
+    }
+
+    /// Sets the reverse-domain bundle identifier of the app, e.g. `org.example.MyApp`.
+    pub fn bundle_id<S: Into<String>>(mut self, bundle_id: S) -> Self {
+        self.bundle_id = Some(bundle_id.into());
+        self
+    }
+
+    /// Sets the version of the app whose data is kept separately, e.g. `2.0`.
+    pub fn version<S: Into<String>>(mut self, version: S) -> Self {
+        self.version = Some(version.into());
+        self
+    }
+
+    /// Returns the name and author of the app.
+    pub fn info(&self) -> &AppInfo {
+        &self.info
+    }
+
+    /// Returns the bundle identifier of the app, if set.
+    pub fn get_bundle_id(&self) -> Option<&str> {
+        self.bundle_id.as_deref()
+    }
+
+    /// Returns the version of the app, if set.
+    pub fn get_version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    /// Returns the data directory of this app, given the platform's directory `dir` for the
+    /// plain `AppInfo`.
+    pub(crate) fn platform_dir(&self, mut dir: PathBuf) -> PathBuf {
+        if cfg!(target_os = "macos") {
+            if let Some(ref bundle_id) = self.bundle_id {
+                dir.set_file_name(sanitized(bundle_id));
+            }
+        }
+        if cfg!(windows) {
+            if let Some(ref version) = self.version {
+                dir.push(sanitized(version));
+            }
+        }
+        dir
+    }
+}
+
+impl From<AppInfo> for AppId {
+    fn from(info: AppInfo) -> Self {
+        AppId::new(info)
+    }
+}
+
+impl<'a> From<&'a AppInfo> for AppId {
+    fn from(info: &'a AppInfo) -> Self {
+        AppId::new(info.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AppId;
+    use crate::{AppInfo, PreferencesStore, Scope};
+    use std::path::Path;
+
+    const APP_INFO: AppInfo = AppInfo {
+        name: "preferences",
+        author: "Rust language community",
+    };
+
+    #[test]
+    fn test_app_id_dir() {
+        let app = AppId::new(APP_INFO).bundle_id("org.rust-lang.preferences").version("2.0");
+        let plain = Scope::Data.app_dir(&APP_INFO).unwrap();
+        let dir = PreferencesStore::for_app(&app).unwrap().with_scope(Scope::Data).unwrap();
+        let expected = if cfg!(target_os = "macos") {
+            plain.with_file_name("org.rust-lang.preferences")
+        } else if cfg!(windows) {
+            plain.join("2.0")
+        } else {
+            plain
+        };
+        assert_eq!(dir.root(), expected);
+
+        // Explicit directories are used as they are.
+        let store = PreferencesStore::for_app(&app).unwrap().with_base_dir("base");
+        assert_eq!(store.root(), Path::new("base").join("preferences"));
+        assert_eq!(store.app_id(), &app);
+    }
+}
@@ -0,0 +1,202 @@
+//! Asynchronous saving and loading of user data, built on `tokio`.
+
+use crate::checksum;
+use crate::lock::{FileLock, Wait};
+use crate::{AppInfo, Format, Operation, PreferencesError, PreferencesStore};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::future::Future;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+use tokio::fs;
+use tokio::task;
+
+/// Asynchronous version of [`Preferences`](trait.Preferences.html), for apps running on a `tokio`
+/// runtime.
+///
+/// The data is stored in the same files, in the same format, as with `Preferences`, so the two
+/// APIs can be mixed freely. (If a memory backend was installed for the current thread with
+/// [`set_backend_for_tests`](fn.set_backend_for_tests.html), the data is saved and loaded
+/// synchronously in that instead.) Saving writes the file on `tokio`'s blocking thread pool, the
+/// same way as `Preferences` does (with the store's save options), and loading reads it with
+/// `tokio::fs`, waiting for its lock on the blocking thread pool, so the returned futures never
+/// block the runtime.
+///
+/// Like `Preferences`, this trait is implemented for all types that are `Serialize` and
+/// `DeserializeOwned` (and `Send`). Since the method names are the same, import only one of the
+/// two traits into a module, or call the methods in their fully qualified form.
+///
+/// # Example
+/// ```
+/// extern crate preferences;
+/// extern crate tokio;
+/// use preferences::{AppInfo, AsyncPreferences, PreferencesMap};
+///
+/// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+///
+/// fn main() {
+///     # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
+///     let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+///     runtime.block_on(async {
+///         let mut window: PreferencesMap<u32> = PreferencesMap::new();
+///         window.insert("width".into(), 800);
+///
+///         let key = "tests/docs/async";
+///         window.save(&APP_INFO, key).await.unwrap();
+///         assert_eq!(PreferencesMap::<u32>::load(&APP_INFO, key).await.unwrap(), window);
+///     });
+/// }
+/// ```
+pub trait AsyncPreferences: Sized {
+    /// Same as [`Preferences::save`](trait.Preferences.html#tymethod.save), but asynchronous.
+    ///
+    /// The data is serialized before this method returns, so the future doesn't borrow `self`.
+    fn save<S>(&self,
+               app: &AppInfo,
+               key: S)
+               -> impl Future<Output = Result<(), PreferencesError>> + Send
+        where S: AsRef<str>;
+    /// Same as [`Preferences::load`](trait.Preferences.html#tymethod.load), but asynchronous.
+    fn load<S>(app: &AppInfo,
+               key: S)
+               -> impl Future<Output = Result<Self, PreferencesError>> + Send
+        where S: AsRef<str>;
+}
+
+impl<T> AsyncPreferences for T
+    where T: Serialize + DeserializeOwned + Send
+{
+    fn save<S>(&self,
+               app: &AppInfo,
+               key: S)
+               -> impl Future<Output = Result<(), PreferencesError>> + Send
+        where S: AsRef<str>
+    {
+        let saved = PreferencesStore::new(app).map(|store| save_in(&store, key.as_ref(), self));
+        async move { saved?.await }
+    }
+
+    fn load<S>(app: &AppInfo,
+               key: S)
+               -> impl Future<Output = Result<Self, PreferencesError>> + Send
+        where S: AsRef<str>
+    {
+        let loaded = PreferencesStore::new(app).map(|store| load_in(&store, key.as_ref()));
+        async move { loaded?.await }
+    }
+}
+
+/// Same as `AsyncPreferences::save`, but saves to `store`.
+fn save_in<T: Serialize>(store: &PreferencesStore,
+                         key: &str,
+                         value: &T)
+                         -> impl Future<Output = Result<(), PreferencesError>> + Send {
+    let store = store.clone();
+    let key = key.to_owned();
+    let serialized = store.file_path(&key, store.options().get_format()).and_then(|path| {
+        let options = store.options_for(&key, &path, store.options()).into_owned();
+        let bytes = options.serialize(value)
+            .map_err(|e| e.with_context(Operation::Save, &key, &path))?;
+        Ok((path, options, bytes))
+    });
+    async move {
+        let (path, options, bytes) = serialized?;
+        task::spawn_blocking(move || {
+                store.save_serialized(&key, &path, bytes, &options, None, Wait::Block)
+                    .map_err(|e| e.with_context(Operation::Save, &key, &path))
+            })
+            .await
+            .map_err(io::Error::other)?
+    }
+}
+
+/// Same as `AsyncPreferences::load`, but loads from `store`.
+fn load_in<T>(store: &PreferencesStore,
+              key: &str)
+              -> impl Future<Output = Result<T, PreferencesError>> + Send
+    where T: DeserializeOwned + Send
+{
+    let format = Format::default();
+    let in_memory = if store.is_in_memory() { Some(store.load(key)) } else { None };
+    let key = key.to_owned();
+    let path = store.file_path(&key, format);
+    async move {
+        if let Some(result) = in_memory {
+            return result;
+        }
+        let path = path?;
+        let _lock = match lock(&path, false).await {
+            Ok(lock) => Some(lock),
+            // The directory doesn't exist, so neither does the file; report that below.
+            Err(PreferencesError::Io(ref e)) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        let bytes = match fs::read(&path).await {
+            Ok(bytes) => bytes,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => {
+                return Err(PreferencesError::not_found(&key, &path));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        checksum::deserialize(format, &bytes)
+    }
+}
+
+/// Acquires the lock on the preferences file at `path` without blocking the runtime.
+async fn lock(path: &Path, exclusive: bool) -> Result<FileLock, PreferencesError> {
+    let path = path.to_owned();
+    task::spawn_blocking(move || FileLock::acquire(&path, exclusive, Wait::Block))
+        .await
+        .map_err(io::Error::other)?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load_in, save_in, AsyncPreferences};
+    use crate::testing::{APP_INFO, TestBackend, TestDir};
+    use crate::{Durability, Preferences, PreferencesError, PreferencesMap, SaveOptions};
+    use tokio::runtime::Builder;
+
+    #[test]
+    fn test_async_save_load() {
+        let key = "tests/module/async";
+        let mut sample: PreferencesMap<u32> = PreferencesMap::new();
+        sample.insert("answer".into(), 42);
+        let runtime = Builder::new_current_thread().build().unwrap();
+        let dir = TestDir::new("async");
+        for store in dir.stores() {
+            runtime.block_on(async {
+                save_in(&store, key, &sample).await.unwrap();
+                let loaded = load_in::<PreferencesMap<u32>>(&store, key).await;
+                assert_eq!(loaded.unwrap(), sample);
+            });
+            assert_eq!(store.load::<PreferencesMap<u32>, _>(key).unwrap(), sample);
+            store.delete(key).unwrap();
+
+            match runtime.block_on(load_in::<u32>(&store, key)) {
+                Err(PreferencesError::NotFound { .. }) => {}
+                other => panic!("expected NotFound, got {:?}", other),
+            }
+
+            // Saving uses the store's options.
+            let options = SaveOptions::new().backups(1).durability(Durability::FsyncDir);
+            let store = store.with_options(options);
+            runtime.block_on(async {
+                save_in(&store, key, &1u32).await.unwrap();
+                save_in(&store, key, &2u32).await.unwrap();
+            });
+            assert_eq!(store.load::<u32, _>(key).unwrap(), 2);
+            assert_eq!(store.load_previous::<u32, _>(key, 1).unwrap(), 1);
+            store.delete(key).unwrap();
+        }
+
+        let _backend = TestBackend::install();
+        runtime.block_on(async {
+            AsyncPreferences::save(&sample, &APP_INFO, key).await.unwrap();
+            let loaded = <PreferencesMap<u32> as AsyncPreferences>::load(&APP_INFO, key).await;
+            assert_eq!(loaded.unwrap(), sample);
+        });
+        let loaded: PreferencesMap<u32> = Preferences::load(&APP_INFO, key).unwrap();
+        assert_eq!(loaded, sample);
+    }
+}
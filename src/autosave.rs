@@ -0,0 +1,219 @@
+//! A wrapper that saves user data automatically after it was changed.
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::ops::{Deref, DerefMut};
+use std::time::{Duration, Instant};
+use crate::{AppInfo, PreferencesError, PreferencesStore};
+
+/// User data that is saved automatically after it was changed, so that no exit path can forget
+/// to save it.
+///
+/// The wrapper dereferences to the value, and any mutable access marks it as changed ("dirty").
+/// A dirty value is saved when the wrapper is dropped, and, if a debounce interval was set with
+/// `debounce`, also once that interval has passed since it was first changed: either when the
+/// value is next accessed mutably, or when `tick` is called (e.g. from the app's event loop).
+///
+/// Errors when saving on drop can't be reported, so call `save` before dropping the wrapper to
+/// handle them. Other errors leave the value dirty, so saving is retried later.
+///
+/// # Example
+/// ```
+/// #[macro_use]
+/// extern crate serde_derive;
+/// extern crate preferences;
+/// use preferences::{AppInfo, AutoSavePreferences, Preferences};
+///
+/// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+///
+/// #[derive(Serialize, Deserialize, Default)]
+/// struct Options {
+///     volume: u8,
+/// }
+///
+/// fn main() {
+///     # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
+///     let key = "tests/docs/autosave";
+///     {
+///         let mut options = AutoSavePreferences::<Options>::load_or_default(&APP_INFO, key)
+///             .unwrap();
+///         options.volume = 7;
+///     } // saved here
+///     assert_eq!(Options::load(&APP_INFO, key).unwrap().volume, 7);
+/// }
+/// ```
+#[derive(Debug)]
+pub struct AutoSavePreferences<T>
+    where T: Serialize
+{
+    store: PreferencesStore,
+    key: String,
+    value: T,
+    debounce: Option<Duration>,
+    dirty_since: Option<Instant>,
+}
+
+impl<T> AutoSavePreferences<T>
+    where T: Serialize
+{
+    /// Wraps `value`, to be saved with the given `key` in `store`. The value isn't dirty until
+    /// it's changed.
+    pub fn new<S: AsRef<str>>(store: PreferencesStore, key: S, value: T) -> Self {
+        AutoSavePreferences {
+            store,
+            key: key.as_ref().to_owned(),
+            value,
+            debounce: None,
+            dirty_since: None,
+        }
+    }
+
+    /// Sets the interval after the first change at which a dirty value is saved, without
+    /// waiting for the wrapper to be dropped.
+    pub fn debounce(mut self, interval: Duration) -> Self {
+        self.debounce = Some(interval);
+        self
+    }
+
+    /// Returns whether the value was changed since it was last saved.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty_since.is_some()
+    }
+
+    /// Saves the value now if it's dirty.
+    ///
+    /// # Failures
+    /// Same as [`Preferences::save`](trait.Preferences.html#tymethod.save).
+    pub fn save(&mut self) -> Result<(), PreferencesError> {
+        if self.is_dirty() {
+            self.store.save(&self.key, &self.value)?;
+            self.dirty_since = None;
+        }
+        Ok(())
+    }
+
+    /// Saves the value if it's dirty and the debounce interval has passed. Returns whether it
+    /// was saved.
+    ///
+    /// # Failures
+    /// Same as [`Preferences::save`](trait.Preferences.html#tymethod.save).
+    pub fn tick(&mut self) -> Result<bool, PreferencesError> {
+        if !self.is_due() {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Drops the wrapper without saving the changes.
+    pub fn discard(mut self) {
+        self.dirty_since = None;
+    }
+
+    fn is_due(&self) -> bool {
+        match (self.dirty_since, self.debounce) {
+            (Some(since), Some(interval)) => since.elapsed() >= interval,
+            _ => false,
+        }
+    }
+}
+
+impl<T> AutoSavePreferences<T>
+    where T: Serialize + DeserializeOwned
+{
+    /// Loads the user data of `app` saved with the given `key`.
+    ///
+    /// # Failures
+    /// Same as [`Preferences::load`](trait.Preferences.html#tymethod.load).
+    pub fn load<S: AsRef<str>>(app: &AppInfo, key: S) -> Result<Self, PreferencesError> {
+        let store = PreferencesStore::new(app)?;
+        let value = store.load(key.as_ref())?;
+        Ok(AutoSavePreferences::new(store, key, value))
+    }
+
+    /// Same as `load`, but starts with the default value if nothing was saved yet.
+    ///
+    /// # Failures
+    /// Same as [`Preferences::load_or_default`](trait.Preferences.html#method.load_or_default).
+    pub fn load_or_default<S: AsRef<str>>(app: &AppInfo, key: S) -> Result<Self, PreferencesError>
+        where T: Default
+    {
+        let store = PreferencesStore::new(app)?;
+        let value = match store.load(key.as_ref()) {
+            Ok(value) => value,
+            Err(ref e) if e.is_not_found() => T::default(),
+            Err(e) => return Err(e),
+        };
+        Ok(AutoSavePreferences::new(store, key, value))
+    }
+}
+
+impl<T> Deref for AutoSavePreferences<T>
+    where T: Serialize
+{
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for AutoSavePreferences<T>
+    where T: Serialize
+{
+    fn deref_mut(&mut self) -> &mut T {
+        // Errors are retried on the next save.
+        let _ = self.tick();
+        if self.dirty_since.is_none() {
+            self.dirty_since = Some(Instant::now());
+        }
+        &mut self.value
+    }
+}
+
+impl<T> Drop for AutoSavePreferences<T>
+    where T: Serialize
+{
+    fn drop(&mut self) {
+        if self.dirty_since.is_some() {
+            let _ = self.store.save(&self.key, &self.value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AutoSavePreferences;
+    use crate::{AppInfo, MemoryBackend, PreferencesStore};
+    use std::time::Duration;
+
+    const APP_INFO: AppInfo = AppInfo {
+        name: "preferences",
+        author: "Rust language community",
+    };
+
+    #[test]
+    fn test_autosave() {
+        let store = PreferencesStore::new(&APP_INFO)
+            .unwrap()
+            .with_memory_backend(MemoryBackend::new());
+        let key = "tests/module/autosave";
+        let mut value = AutoSavePreferences::new(store.clone(), key, 1u32)
+            .debounce(Duration::from_secs(0));
+        assert!(!value.is_dirty());
+        assert!(!value.tick().unwrap());
+        *value = 2;
+        assert!(value.is_dirty());
+        assert!(value.tick().unwrap());
+        assert_eq!(store.load::<u32, _>(key).unwrap(), 2);
+
+        *value = 3;
+        value.discard();
+        assert_eq!(store.load::<u32, _>(key).unwrap(), 2);
+        {
+            let mut value = AutoSavePreferences::new(store.clone(), key, 1u32);
+            *value += 3;
+        }
+        assert_eq!(store.load::<u32, _>(key).unwrap(), 4);
+    }
+}
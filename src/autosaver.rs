@@ -0,0 +1,307 @@
+//! Saving of user data on a background thread, coalescing rapid changes.
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use crate::flush::Flush;
+use crate::{PreferencesError, PreferencesStore};
+
+/// Saves user data on a background thread, some time after it was changed, so that many changes
+/// in quick succession (e.g. the window geometry while the user resizes a window) result in a
+/// single write.
+///
+/// `save` only records the new value and returns immediately. The thread saves a key's latest
+/// value once `delay` has passed since the first change that wasn't saved yet, so data that
+/// changes continuously is still saved at least that often. Everything that's pending is saved
+/// by `flush`, by [`PreferencesStore::flush`](struct.PreferencesStore.html#method.flush) of its
+/// store, and when the saver is dropped.
+///
+/// Errors can't be reported by the background thread when they happen; the first one is kept
+/// and returned by the next `flush`. A value whose serialization panics is reported the same way,
+/// and doesn't stop the thread.
+///
+/// Not available in WebAssembly for the browser, which has no threads.
+///
+/// # Example
+/// ```
+/// extern crate preferences;
+/// use preferences::{AppInfo, AutoSaver, PreferencesStore};
+/// use std::time::Duration;
+///
+/// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+///
+/// fn main() {
+///     # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
+///     let store = PreferencesStore::new(&APP_INFO).unwrap();
+///     let saver = AutoSaver::new(store.clone(), Duration::from_secs(2));
+///     for width in 800..1000u32 {
+///         saver.save("tests/docs/autosaver/width", width);
+///     }
+///     saver.flush().unwrap();
+///     assert_eq!(store.load::<u32, _>("tests/docs/autosaver/width").unwrap(), 999);
+/// }
+/// ```
+pub struct AutoSaver {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+struct Shared {
+    store: PreferencesStore,
+    delay: Duration,
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    pending: HashMap<String, Pending>,
+    /// Whether everything pending should be saved now, regardless of the delay. The thread
+    /// clears it once nothing is pending and it finished saving.
+    flushing: bool,
+    shutdown: bool,
+    error: Option<PreferencesError>,
+}
+
+/// Saves a value with the given key in the store.
+type SaveFn = Box<dyn FnOnce(&PreferencesStore, &str) -> Result<(), PreferencesError> + Send>;
+
+/// The latest unsaved value of a key.
+struct Pending {
+    since: Instant,
+    save: SaveFn,
+}
+
+impl AutoSaver {
+    /// Starts a thread that saves data in `store`, once `delay` has passed since it changed.
+    pub fn new(store: PreferencesStore, delay: Duration) -> Self {
+        let shared = Arc::new(Shared {
+            store,
+            delay,
+            state: Mutex::default(),
+            changed: Condvar::new(),
+        });
+        let thread = {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name("preferences auto-saver".into())
+                .spawn(move || shared.run())
+                .expect("failed to spawn the auto-saver thread")
+        };
+        let saver: Arc<dyn Flush> = shared.clone();
+        shared.store.pending_writes().register(Arc::downgrade(&saver));
+        AutoSaver {
+            shared,
+            thread: Some(thread),
+        }
+    }
+
+    /// Records `value` to be saved with the given `key`, replacing any value for the same key
+    /// that wasn't saved yet.
+    pub fn save<T, S>(&self, key: S, value: T)
+        where T: Serialize + Send + 'static,
+              S: AsRef<str>
+    {
+        let save = Box::new(move |store: &PreferencesStore, key: &str| store.save(key, &value));
+        let mut state = self.shared.lock();
+        let since = state.pending.get(key.as_ref()).map_or_else(Instant::now, |p| p.since);
+        state.pending.insert(key.as_ref().to_owned(), Pending { since, save });
+        drop(state);
+        self.shared.changed.notify_all();
+    }
+
+    /// Saves all pending values now, and waits until they're saved.
+    ///
+    /// # Failures
+    /// The first error that occurred while saving since the last `flush`, in the background or
+    /// now.
+    pub fn flush(&self) -> Result<(), PreferencesError> {
+        self.shared.flush()
+    }
+}
+
+impl Drop for AutoSaver {
+    /// Saves all pending values and stops the thread.
+    fn drop(&mut self) {
+        self.shared.lock().shutdown = true;
+        self.shared.changed.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl fmt::Debug for AutoSaver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AutoSaver")
+            .field("store", &self.shared.store)
+            .field("delay", &self.shared.delay)
+            .finish()
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Saves values as they become due, until the saver is dropped.
+    fn run(&self) {
+        let mut state = self.lock();
+        loop {
+            let now = Instant::now();
+            let save_all = state.flushing || state.shutdown;
+            let due: Vec<String> = state.pending
+                .iter()
+                .filter(|&(_, pending)| save_all || pending.since + self.delay <= now)
+                .map(|(key, _)| key.clone())
+                .collect();
+            if !due.is_empty() {
+                let saves: Vec<_> = due.into_iter()
+                    .filter_map(|key| state.pending.remove(&key).map(|pending| (key, pending)))
+                    .collect();
+                drop(state);
+                let mut error = None;
+                for (key, pending) in saves {
+                    let save = AssertUnwindSafe(|| (pending.save)(&self.store, &key));
+                    let saved = panic::catch_unwind(save).unwrap_or_else(|_| {
+                        let message = format!("saving {:?} in the background panicked", key);
+                        Err(io::Error::other(message).into())
+                    });
+                    if let Err(e) = saved {
+                        error.get_or_insert(e);
+                    }
+                }
+                state = self.lock();
+                if state.error.is_none() {
+                    state.error = error;
+                }
+                continue;
+            }
+            if state.pending.is_empty() {
+                state.flushing = false;
+                self.changed.notify_all();
+                if state.shutdown {
+                    return;
+                }
+            }
+            let next = state.pending.values().map(|pending| pending.since + self.delay).min();
+            state = match next {
+                Some(next) => {
+                    let timeout = next.saturating_duration_since(now);
+                    self.changed.wait_timeout(state, timeout).unwrap_or_else(|e| e.into_inner()).0
+                }
+                None => self.changed.wait(state).unwrap_or_else(|e| e.into_inner()),
+            };
+        }
+    }
+}
+
+impl Flush for Shared {
+    fn flush(&self) -> Result<(), PreferencesError> {
+        let mut state = self.lock();
+        state.flushing = true;
+        self.changed.notify_all();
+        while state.flushing {
+            state = self.changed.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+        match state.error.take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{APP_INFO, TestDir};
+    use crate::{AutoSaver, Durability, MemoryBackend, PreferencesStore, SaveOptions};
+    use serde::{Serialize, Serializer};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_auto_saver_coalesces() {
+        let store = PreferencesStore::new(&APP_INFO)
+            .unwrap()
+            .with_memory_backend(MemoryBackend::new());
+        let writes = Arc::new(AtomicUsize::new(0));
+        let _subscription = {
+            let writes = writes.clone();
+            store.on_change("", move |_| {
+                writes.fetch_add(1, Ordering::SeqCst);
+            })
+        };
+
+        let saver = AutoSaver::new(store.clone(), Duration::from_millis(100));
+        for width in 0..100u32 {
+            saver.save("width", width);
+        }
+        saver.flush().unwrap();
+        assert_eq!(store.load::<u32, _>("width").unwrap(), 99);
+        assert_eq!(writes.load(Ordering::SeqCst), 1);
+
+        saver.save("height", 600u32);
+        thread::sleep(Duration::from_millis(300));
+        assert_eq!(store.load::<u32, _>("height").unwrap(), 600);
+
+        saver.save("height", 700u32);
+        drop(saver);
+        assert_eq!(store.load::<u32, _>("height").unwrap(), 700);
+        assert_eq!(writes.load(Ordering::SeqCst), 3);
+    }
+
+    struct Panics;
+
+    impl Serialize for Panics {
+        fn serialize<S: Serializer>(&self, _serializer: S) -> Result<S::Ok, S::Error> {
+            panic!("can't serialize");
+        }
+    }
+
+    #[test]
+    fn test_auto_saver_panic() {
+        let store = PreferencesStore::new(&APP_INFO)
+            .unwrap()
+            .with_memory_backend(MemoryBackend::new());
+        let saver = AutoSaver::new(store.clone(), Duration::from_secs(60));
+        saver.save("broken", Panics);
+        saver.save("width", 800u32);
+        assert!(saver.flush().is_err());
+        assert!(store.flush().is_ok());
+        assert_eq!(store.load::<u32, _>("width").unwrap(), 800);
+
+        saver.save("broken", Panics);
+        assert!(store.flush().is_err());
+    }
+
+    #[test]
+    fn test_store_flush() {
+        let dir = TestDir::new("flush");
+        for store in dir.stores() {
+            let store = store.with_options(SaveOptions::new().durability(Durability::None));
+            let saver = AutoSaver::new(store.clone(), Duration::from_secs(3600));
+            saver.save("width", 800u32);
+            assert!(store.load::<u32, _>("width").is_err());
+
+            // Flushing any clone of the store saves what its auto-savers hold.
+            store.clone().flush().unwrap();
+            assert_eq!(store.load::<u32, _>("width").unwrap(), 800);
+            saver.save("width", 1024u32);
+            store.sync_all().unwrap();
+            assert_eq!(store.load::<u32, _>("width").unwrap(), 1024);
+
+            // Dropped auto-savers are no longer flushed.
+            drop(saver);
+            store.flush().unwrap();
+            store.sync_all().unwrap();
+        }
+    }
+}
@@ -0,0 +1,220 @@
+//! Pluggable storage for the files of a `PreferencesStore`.
+
+use std::fmt::Debug;
+use std::fs;
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use crate::{PreferencesError, write_atomically};
+
+/// Storage for user data other than the file system, e.g. a database, used by a
+/// [`PreferencesStore`](struct.PreferencesStore.html) set up with
+/// [`with_backend`](struct.PreferencesStore.html#method.with_backend).
+///
+/// A backend stores the contents that the preferences files would have, keyed by the paths they
+/// would have relative to the app's data directory, with `/` as the separator (e.g.
+/// `options/graphics.prefs.json`). So all of the store's operations, including keys, formats,
+/// listing and deleting, work as usual, and a backend only needs to be able to read, write,
+/// delete and list byte strings. Like with a [`MemoryBackend`](struct.MemoryBackend.html), saves
+/// don't take file locks or keep backups; each write should replace the data atomically.
+///
+/// [`FileBackend`](struct.FileBackend.html) implements this with files, much like a store does
+/// by default, and can serve as a starting point.
+///
+/// # Example
+/// ```
+/// use preferences::{AppInfo, PreferencesStore, StorageBackend};
+/// use std::collections::BTreeMap;
+/// use std::io;
+/// use std::sync::{Arc, Mutex};
+///
+/// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+///
+/// #[derive(Debug, Default)]
+/// struct Table(Mutex<BTreeMap<String, Vec<u8>>>);
+///
+/// impl StorageBackend for Table {
+///     fn read(&self, path: &str) -> io::Result<Option<Vec<u8>>> {
+///         Ok(self.0.lock().unwrap().get(path).cloned())
+///     }
+///     fn write(&self, path: &str, data: &[u8]) -> io::Result<()> {
+///         self.0.lock().unwrap().insert(path.to_owned(), data.to_vec());
+///         Ok(())
+///     }
+///     fn delete(&self, path: &str) -> io::Result<bool> {
+///         Ok(self.0.lock().unwrap().remove(path).is_some())
+///     }
+///     fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+///         let table = self.0.lock().unwrap();
+///         Ok(table.keys().filter(|path| path.starts_with(prefix)).cloned().collect())
+///     }
+/// }
+///
+/// let table = Arc::new(Table::default());
+/// let store = PreferencesStore::new(&APP_INFO).unwrap().with_backend(table.clone());
+/// store.save("options/volume", &7u32).unwrap();
+/// assert_eq!(store.list("options").unwrap(), ["options/volume"]);
+/// assert!(table.read("options/volume.prefs.json").unwrap().is_some());
+/// ```
+pub trait StorageBackend: Debug + Send + Sync {
+    /// Returns the data stored at `path`, or `None` if there is none.
+    ///
+    /// # Failures
+    /// If the storage can't be read.
+    fn read(&self, path: &str) -> io::Result<Option<Vec<u8>>>;
+
+    /// Stores `data` at `path`, replacing any data stored there before.
+    ///
+    /// # Failures
+    /// If the storage can't be written.
+    fn write(&self, path: &str, data: &[u8]) -> io::Result<()>;
+
+    /// Removes the data stored at `path`. Returns whether there was any.
+    ///
+    /// # Failures
+    /// If the storage can't be written.
+    fn delete(&self, path: &str) -> io::Result<bool>;
+
+    /// Returns all paths that have data stored and start with `prefix`, in any order. The
+    /// prefix is either empty or ends with `/`.
+    ///
+    /// # Failures
+    /// If the storage can't be read.
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>>;
+
+    /// Returns when the data at `path` was last written, if the storage keeps track of it. This
+    /// is reported by [`metadata`](fn.metadata.html), and lets a
+    /// [`CachedStore`](struct.CachedStore.html) notice changes of the same size.
+    ///
+    /// The default returns `None`.
+    ///
+    /// # Failures
+    /// If the storage can't be read.
+    fn modified(&self, path: &str) -> io::Result<Option<SystemTime>> {
+        let _ = path;
+        Ok(None)
+    }
+}
+
+/// A [`StorageBackend`](trait.StorageBackend.html) that stores the data in files below a
+/// directory, replacing them atomically.
+///
+/// This is the layout a [`PreferencesStore`](struct.PreferencesStore.html) uses by default, but
+/// without file locks, backups or compressed files. It's mostly useful for wrapping in another
+/// backend that changes what's written, or as an example.
+#[derive(Clone, Debug)]
+pub struct FileBackend {
+    dir: PathBuf,
+}
+
+impl FileBackend {
+    /// Creates a backend that stores data in files below `dir`.
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        FileBackend { dir: dir.as_ref().to_owned() }
+    }
+
+    fn path(&self, path: &str) -> PathBuf {
+        self.dir.join(path)
+    }
+}
+
+impl StorageBackend for FileBackend {
+    fn read(&self, path: &str) -> io::Result<Option<Vec<u8>>> {
+        match fs::read(self.path(path)) {
+            Ok(data) => Ok(Some(data)),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn write(&self, path: &str, data: &[u8]) -> io::Result<()> {
+        let path = self.path(path);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        write_atomically(&path, |file| file.write_all(data).map_err(Into::into))
+            .map_err(|e| match e {
+                PreferencesError::Io(e) => e,
+                e => io::Error::other(e),
+            })
+    }
+
+    fn delete(&self, path: &str) -> io::Result<bool> {
+        match fs::remove_file(self.path(path)) {
+            Ok(()) => Ok(true),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        let mut found = Vec::new();
+        list_files(&self.dir, "", &mut found)?;
+        found.retain(|path| path.starts_with(prefix));
+        Ok(found)
+    }
+
+    fn modified(&self, path: &str) -> io::Result<Option<SystemTime>> {
+        match fs::metadata(self.path(path)) {
+            Ok(metadata) => metadata.modified().map(Some),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Adds the paths of the files below `dir`, relative to the backend's directory, to `found`.
+/// `relative` is the path of `dir` itself, ending with `/` unless it's empty.
+fn list_files(dir: &Path, relative: &str, found: &mut Vec<String>) -> io::Result<()> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            list_files(&entry.path(), &format!("{}{}/", relative, name), found)?;
+        } else if file_type.is_file() {
+            found.push(format!("{}{}", relative, name));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FileBackend, StorageBackend};
+    use crate::testing::{APP_INFO, TestDir};
+    use crate::PreferencesStore;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_file_backend() {
+        let dir = TestDir::new("backend");
+        let backend = Arc::new(FileBackend::new(dir.path()));
+        let store = PreferencesStore::new(&APP_INFO).unwrap().with_backend(backend.clone());
+        store.save("a/b", &1u32).unwrap();
+        store.save("a/c/d", &2u32).unwrap();
+        store.save("e", &3u32).unwrap();
+        assert!(dir.path().join("a/b.prefs.json").is_file());
+        assert_eq!(store.load::<u32, _>("a/c/d").unwrap(), 2);
+        assert_eq!(store.list("a").unwrap(), ["a/b", "a/c/d"]);
+        assert!(store.metadata("e").unwrap().modified > std::time::UNIX_EPOCH);
+
+        store.delete("a/b").unwrap();
+        assert!(store.load::<u32, _>("a/b").unwrap_err().is_not_found());
+        store.delete_all("a").unwrap();
+        assert_eq!(backend.list("").unwrap(), ["e.prefs.json"]);
+
+        // The backend's paths are relative to whichever directory the store uses.
+        let other = store.with_base_dir(dir.path());
+        other.save("f", &4u32).unwrap();
+        assert!(backend.read("f.prefs.json").unwrap().is_some());
+    }
+}
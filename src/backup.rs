@@ -0,0 +1,277 @@
+//! Backups of preferences files, and recovery from them.
+
+use crate::lock::{FileLock, Wait};
+use crate::memory::MemoryBackend;
+use crate::{checksum, compression};
+use crate::{AppInfo, Format, PreferencesError, PreferencesStore, lock_shared, read_compressed};
+use serde::de::DeserializeOwned;
+use std::ffi::OsString;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+static BACKUP_SUFFIX: &str = ".bak";
+
+/// User data loaded with
+/// [`Preferences::load_with_recovery`](trait.Preferences.html#tymethod.load_with_recovery).
+#[derive(Debug)]
+pub struct Recovered<T> {
+    /// The loaded value.
+    pub value: T,
+    /// If the data file couldn't be loaded, the error that occurred. In that case, `value` was
+    /// loaded from a backup.
+    pub error: Option<PreferencesError>,
+    /// If `value` was loaded from a backup, the path of the backup file.
+    pub backup: Option<PathBuf>,
+}
+
+impl<T> Recovered<T> {
+    /// Returns whether the data file was corrupt and the value was loaded from a backup.
+    pub fn is_recovered(&self) -> bool {
+        self.backup.is_some()
+    }
+}
+
+/// Returns the path of the `n`th most recent backup of the preferences file at `path`, counting
+/// from 1. The most recent backup is `<file>.bak`, older ones are `<file>.bak.2` and so on.
+pub fn backup_path(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.file_name().map(OsString::from).unwrap_or_default();
+    name.push(BACKUP_SUFFIX);
+    if n > 1 {
+        name.push(format!(".{}", n));
+    }
+    path.with_file_name(name)
+}
+
+/// Keeps a copy of the preferences file at `path` as its most recent backup, discarding backups
+/// beyond the `count` most recent ones. Does nothing if the file doesn't exist yet.
+pub fn rotate_backups(path: &Path, count: usize) -> Result<(), PreferencesError> {
+    if count == 0 || !path.is_file() {
+        return Ok(());
+    }
+    let _ = fs::remove_file(backup_path(path, count));
+    for n in (1..count).rev() {
+        match fs::rename(backup_path(path, n), backup_path(path, n + 1)) {
+            Ok(()) => {}
+            Err(ref e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    fs::copy(path, backup_path(path, 1))?;
+    Ok(())
+}
+
+/// Removes all backups of the preferences file at `path`.
+pub fn remove_backups(path: &Path) {
+    let mut n = 1;
+    while fs::remove_file(backup_path(path, n)).is_ok() {
+        n += 1;
+    }
+}
+
+/// Loads the preferences file at `path`, falling back to its backups, most recent first, if the
+/// file exists but can't be loaded.
+pub fn load_recovering<T>(path: &Path, format: Format) -> Result<Recovered<T>, PreferencesError>
+    where T: DeserializeOwned
+{
+    let _lock = lock_shared(path, Wait::Block)?;
+    let (path, compression) = compression::find(path);
+    let error = match read_compressed(&path, format, compression) {
+        Ok(value) => {
+            return Ok(Recovered {
+                value,
+                error: None,
+                backup: None,
+            })
+        }
+        Err(e) => e,
+    };
+    if error.is_not_found() {
+        return Err(error);
+    }
+    let mut n = 1;
+    loop {
+        let backup = backup_path(&path, n);
+        match read_compressed(&backup, format, compression) {
+            Ok(value) => {
+                return Ok(Recovered {
+                    value,
+                    error: Some(error),
+                    backup: Some(backup),
+                })
+            }
+            Err(ref e) if e.is_not_found() => return Err(error),
+            Err(_) => n += 1,
+        }
+    }
+}
+
+/// Same as `load_recovering`, for the data at `path` in `memory`.
+pub fn load_recovering_from<T>(memory: &MemoryBackend,
+                               path: &Path,
+                               format: Format)
+                               -> Result<Recovered<T>, PreferencesError>
+    where T: DeserializeOwned
+{
+    let read = |path: &Path| -> Result<T, PreferencesError> {
+        checksum::deserialize(format, &memory.read(path)?)
+    };
+    let error = match read(path) {
+        Ok(value) => {
+            return Ok(Recovered {
+                value,
+                error: None,
+                backup: None,
+            })
+        }
+        Err(e) => e,
+    };
+    if error.is_not_found() {
+        return Err(error);
+    }
+    let mut n = 1;
+    loop {
+        let backup = backup_path(path, n);
+        match read(&backup) {
+            Ok(value) => {
+                return Ok(Recovered {
+                    value,
+                    error: Some(error),
+                    backup: Some(backup),
+                })
+            }
+            Err(ref e) if e.is_not_found() => return Err(error),
+            Err(_) => n += 1,
+        }
+    }
+}
+
+/// Loads the `n`th most recent backup of the preferences file at `path`, or the file itself if
+/// `n` is 0.
+pub fn load_backup<T>(path: &Path, format: Format, n: usize) -> Result<T, PreferencesError>
+    where T: DeserializeOwned
+{
+    let _lock = lock_shared(path, Wait::Block)?;
+    let (path, compression) = compression::find(path);
+    let path = if n == 0 { path } else { backup_path(&path, n) };
+    read_compressed(&path, format, compression)
+}
+
+/// Replaces the preferences file at `path` with its most recent backup, and makes the older
+/// backups more recent by one.
+pub fn restore_backup(path: &Path) -> Result<(), PreferencesError> {
+    let _lock = FileLock::acquire(path, true, Wait::Block)?;
+    let (path, _) = compression::find(path);
+    fs::rename(backup_path(&path, 1), &path)?;
+    let mut n = 2;
+    while fs::rename(backup_path(&path, n), backup_path(&path, n - 1)).is_ok() {
+        n += 1;
+    }
+    Ok(())
+}
+
+/// Loads a previous version of the user data of `app` saved with the given `key`: the version
+/// before the last save if `n` is 1, the one before that if `n` is 2, and so on. (If `n` is 0,
+/// this is the same as [`Preferences::load`](trait.Preferences.html#tymethod.load).)
+///
+/// Previous versions are only kept if the data was saved with
+/// [`SaveOptions::backups`](struct.SaveOptions.html#method.backups), and only as many as set
+/// there. A [`MemoryBackend`](struct.MemoryBackend.html) keeps them in memory, the same way.
+///
+/// # Failures
+/// An I/O error of kind `NotFound` if the version isn't kept, or the same as `load` if it
+/// cannot be loaded.
+///
+/// # Example
+/// ```
+/// use preferences::{AppInfo, PreferencesStore, SaveOptions, load_previous, revert};
+///
+/// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+///
+/// # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
+/// let key = "tests/docs/revert";
+/// let store = PreferencesStore::new(&APP_INFO).unwrap()
+///     .with_options(SaveOptions::new().backups(5));
+/// store.save(key, &"light".to_owned()).unwrap();
+/// store.save(key, &"dark".to_owned()).unwrap();
+/// assert_eq!(load_previous::<String, _>(&APP_INFO, key, 1).unwrap(), "light");
+///
+/// revert(&APP_INFO, key).unwrap();
+/// assert_eq!(store.load::<String, _>(key).unwrap(), "light");
+/// ```
+pub fn load_previous<T, S>(app: &AppInfo, key: S, n: usize) -> Result<T, PreferencesError>
+    where T: DeserializeOwned,
+          S: AsRef<str>
+{
+    PreferencesStore::new(app)?.load_previous(key, n)
+}
+
+/// Restores the user data of `app` saved with the given `key` to the version before the last
+/// save, e.g. to undo a configuration change that turned out to be bad. Reverting again goes
+/// back another version, as long as older versions are kept.
+///
+/// # Failures
+/// An I/O error of kind `NotFound` if no previous version is kept (see
+/// [`load_previous`](fn.load_previous.html)), or if the file cannot be replaced.
+pub fn revert<S: AsRef<str>>(app: &AppInfo, key: S) -> Result<(), PreferencesError> {
+    PreferencesStore::new(app)?.revert(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::backup_path;
+    use crate::testing::TestDir;
+    use crate::{PreferencesStore, SaveOptions};
+    use std::fs;
+    use std::path::Path;
+
+    /// Returns whether `store` has data at `path`.
+    fn exists(store: &PreferencesStore, path: &Path) -> bool {
+        match store.memory() {
+            Some(memory) => memory.contains(path),
+            None => path.is_file(),
+        }
+    }
+
+    fn corrupt(store: &PreferencesStore, path: &Path) {
+        match store.memory() {
+            Some(memory) => memory.write(path, b"corrupt".to_vec()).unwrap(),
+            None => fs::write(path, "corrupt").unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_backup_recovery() {
+        let key = "tests/module/backup";
+        let dir = TestDir::new("backup");
+        for store in dir.stores() {
+            let store = store.with_options(SaveOptions::new().backups(2));
+            let path = store.file_path(key, Default::default()).unwrap();
+            for n in 1..5u32 {
+                store.save(key, &n).unwrap();
+            }
+            assert!(exists(&store, &backup_path(&path, 2)));
+            assert!(!exists(&store, &backup_path(&path, 3)));
+            assert_eq!(store.load_previous::<u32, _>(key, 2).unwrap(), 2);
+
+            corrupt(&store, &path);
+            corrupt(&store, &backup_path(&path, 1));
+            let recovered = store.load_with_recovery::<u32, _>(key).unwrap();
+            assert_eq!(recovered.value, 2);
+            assert!(recovered.error.is_some());
+            assert_eq!(recovered.backup, Some(backup_path(&path, 2)));
+
+            store.revert(key).unwrap();
+            assert!(store.load::<u32, _>(key).is_err());
+            assert_eq!(store.load_previous::<u32, _>(key, 1).unwrap(), 2);
+            assert!(store.load_previous::<u32, _>(key, 2).unwrap_err().is_not_found());
+            store.revert(key).unwrap();
+            assert_eq!(store.load::<u32, _>(key).unwrap(), 2);
+            assert!(store.revert(key).unwrap_err().is_not_found());
+
+            store.delete(key).unwrap();
+            assert!(!exists(&store, &backup_path(&path, 1)));
+            assert!(!exists(&store, &backup_path(&path, 2)));
+        }
+    }
+}
@@ -0,0 +1,156 @@
+//! `prefs`: inspects and edits the preferences of an app from the command line, e.g. to debug a
+//! user's configuration. The files are found in the same way as by the library.
+//!
+//! Requires the `cli` feature:
+//!
+//! ```text
+//! cargo install preferences --features cli
+//! prefs --app "My App" --author "Me" list
+//! prefs --app "My App" --author "Me" get settings/window
+//! prefs --app "My App" --author "Me" set settings/window '{"width": 800}'
+//! ```
+
+extern crate clap;
+extern crate preferences;
+extern crate serde_json;
+
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use preferences::{AppInfo, PreferencesError, PreferencesStore, Scope};
+use serde_json::Value;
+use std::io::{self, Write};
+use std::process;
+
+fn command() -> Command {
+    let key = || Arg::new("key").required(true).help("Preferences key, e.g. settings/window");
+    Command::new("prefs")
+        .about("Inspects and edits the preferences of an app")
+        .version(env!("CARGO_PKG_VERSION"))
+        .subcommand_required(true)
+        .arg(Arg::new("app").long("app").required(true).help("Name of the app"))
+        .arg(Arg::new("author")
+                 .long("author")
+                 .required(true)
+                 .help("Author of the app"))
+        .arg(Arg::new("system")
+                 .long("system")
+                 .action(ArgAction::SetTrue)
+                 .help("Uses the system-wide preferences instead of the current user's"))
+        .arg(Arg::new("base-dir")
+                 .long("base-dir")
+                 .value_name("DIR")
+                 .help("Uses the preferences under DIR instead of the platform's data directory, \
+                        e.g. a copy of a user's files"))
+        .subcommand(Command::new("list")
+                        .about("Lists the keys of all saved preferences")
+                        .arg(Arg::new("prefix")
+                                 .default_value("")
+                                 .help("Lists only the keys under this prefix")))
+        .subcommand(Command::new("get").about("Prints the value saved with a key").arg(key()))
+        .subcommand(Command::new("set")
+                        .about("Saves a value with a key")
+                        .arg(key())
+                        .arg(Arg::new("value")
+                                 .required(true)
+                                 .help("The value, as JSON (other text is saved as a string)")))
+        .subcommand(Command::new("delete").about("Deletes the value saved with a key").arg(key()))
+        .subcommand(Command::new("path")
+                        .about("Prints the path of the data directory, or of the file of a key")
+                        .arg(Arg::new("key").help("Preferences key, e.g. settings/window")))
+}
+
+/// Returns the store of the app given on the command line.
+fn store(matches: &ArgMatches) -> Result<PreferencesStore, PreferencesError> {
+    // The store needs static strings, which live for the whole run anyway.
+    let app = AppInfo {
+        name: Box::leak(matches.get_one::<String>("app").unwrap().clone().into_boxed_str()),
+        author: Box::leak(matches.get_one::<String>("author").unwrap().clone().into_boxed_str()),
+    };
+    let mut store = PreferencesStore::new(&app)?;
+    if matches.get_flag("system") {
+        store = store.with_scope(Scope::System)?;
+    }
+    if let Some(dir) = matches.get_one::<String>("base-dir") {
+        store = store.with_base_dir(dir);
+    }
+    Ok(store)
+}
+
+/// Runs the command of `matches`, and writes its output to `out`.
+fn run<W: Write>(matches: &ArgMatches, out: &mut W) -> Result<(), PreferencesError> {
+    let store = store(matches)?;
+    let (name, matches) = matches.subcommand().unwrap();
+    let key = || matches.get_one::<String>("key").unwrap();
+    match name {
+        "list" => {
+            for key in store.list(matches.get_one::<String>("prefix").unwrap())? {
+                writeln!(out, "{}", key)?;
+            }
+        }
+        "get" => {
+            let value: Value = store.load_any_format(key())?;
+            writeln!(out, "{}", serde_json::to_string_pretty(&value)?)?;
+        }
+        "set" => {
+            let text = matches.get_one::<String>("value").unwrap();
+            let value = serde_json::from_str(text).unwrap_or_else(|_| Value::String(text.clone()));
+            // Keeps the format of existing data, so that the app finds the new value.
+            let format = store.detect_format(key())?.unwrap_or(store.options().get_format());
+            let options = store.options().clone().format(format);
+            store.save_with_options(key(), &value, &options)?;
+        }
+        "delete" => store.delete(key())?,
+        "path" => {
+            let path = match matches.get_one::<String>("key") {
+                Some(key) => {
+                    let format = store.detect_format(key)?.unwrap_or(store.options().get_format());
+                    store.file_path(key, format)?
+                }
+                None => store.root().to_owned(),
+            };
+            writeln!(out, "{}", path.display())?;
+        }
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+fn main() {
+    let matches = command().get_matches();
+    if let Err(e) = run(&matches, &mut io::stdout().lock()) {
+        eprintln!("prefs: {}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{command, run};
+    use std::{env, fs, process};
+
+    fn prefs(base: &str, args: &[&str]) -> String {
+        let mut argv = vec!["prefs", "--app", "preferences", "--author", "Rust language community",
+                            "--base-dir", base];
+        argv.extend_from_slice(args);
+        let mut out = Vec::new();
+        run(&command().try_get_matches_from(argv).unwrap(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_prefs() {
+        command().debug_assert();
+        let base = env::temp_dir().join(format!("preferences-rs-cli-{}", process::id()));
+        let base = base.to_str().unwrap();
+        prefs(base, &["set", "ui/window", r#"{"width": 800}"#]);
+        prefs(base, &["set", "ui/theme", "dark"]);
+        assert_eq!(prefs(base, &["list"]), "ui/theme\nui/window\n");
+        assert_eq!(prefs(base, &["get", "ui/theme"]), "\"dark\"\n");
+        assert_eq!(prefs(base, &["get", "ui/window"]), "{\n  \"width\": 800\n}\n");
+        let path = prefs(base, &["path", "ui/window"]);
+        assert!(fs::metadata(path.trim_end()).unwrap().is_file());
+
+        prefs(base, &["delete", "ui/theme"]);
+        assert_eq!(prefs(base, &["list", "ui"]), "ui/window\n");
+        fs::remove_dir_all(base).unwrap();
+    }
+}
@@ -0,0 +1,101 @@
+//! Loading and saving the user data of many keys at once.
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use crate::{AppInfo, PreferencesError, PreferencesStore};
+
+/// Loads the user data of `app` saved with each of the given `keys`, e.g. all of an app's
+/// settings at startup. The data directory is looked up only once, and with the `rayon` feature,
+/// the keys are loaded in parallel.
+///
+/// Returns the result of loading each key (as with
+/// [`Preferences::load`](trait.Preferences.html#tymethod.load)) by key, so that the data of
+/// some keys can be used even if loading others failed, e.g. because nothing was saved yet.
+///
+/// # Failures
+/// If the data directory cannot be determined.
+///
+/// # Example
+/// ```
+/// use preferences::{AppInfo, load_many, save_many};
+///
+/// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+///
+/// # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
+/// save_many(&APP_INFO, &[("tests/docs/many/a", 1u32), ("tests/docs/many/b", 2)]).unwrap();
+/// let loaded = load_many::<u32, _>(&APP_INFO, &["tests/docs/many/a", "tests/docs/many/c"])
+///     .unwrap();
+/// assert_eq!(*loaded["tests/docs/many/a"].as_ref().unwrap(), 1);
+/// assert!(loaded["tests/docs/many/c"].as_ref().unwrap_err().is_not_found());
+/// ```
+pub fn load_many<T, S>(app: &AppInfo,
+                       keys: &[S])
+                       -> Result<HashMap<String, Result<T, PreferencesError>>, PreferencesError>
+    where T: DeserializeOwned + Send,
+          S: AsRef<str> + Sync
+{
+    Ok(PreferencesStore::new(app)?.load_many(keys))
+}
+
+/// Saves each of the given `values` as the user data of `app` with its key. The data directory
+/// is looked up only once, and with the `rayon` feature, the values are saved in parallel.
+///
+/// Returns the result of saving each value (as with
+/// [`Preferences::save`](trait.Preferences.html#tymethod.save)) by key. Unlike with a
+/// [`Transaction`](struct.Transaction.html), the values are saved independently, so some may be
+/// saved even if others fail.
+///
+/// # Failures
+/// If the data directory cannot be determined.
+pub fn save_many<T, S>(app: &AppInfo,
+                       values: &[(S, T)])
+                       -> Result<HashMap<String, Result<(), PreferencesError>>, PreferencesError>
+    where T: Serialize + Sync,
+          S: AsRef<str> + Sync
+{
+    Ok(PreferencesStore::new(app)?.save_many(values))
+}
+
+/// Returns the results of `f` for each of `items`, which it runs in parallel with the `rayon`
+/// feature.
+pub(crate) fn map_all<I, R, F>(items: &[I], f: F) -> Vec<R>
+    where I: Sync,
+          R: Send,
+          F: Fn(&I) -> R + Send + Sync
+{
+    #[cfg(feature = "rayon")]
+    {
+        items.par_iter().map(f).collect()
+    }
+    #[cfg(not(feature = "rayon"))]
+    {
+        items.iter().map(f).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::TestDir;
+
+    #[test]
+    fn test_load_and_save_many() {
+        let dir = TestDir::new("many");
+        for store in dir.stores() {
+            let values: Vec<_> = (0..40u32).map(|i| (format!("many/{}", i), i)).collect();
+            let saved = store.save_many(&values);
+            assert_eq!(saved.len(), 40);
+            assert!(saved.values().all(Result::is_ok));
+
+            let mut keys: Vec<_> = values.iter().map(|value| value.0.clone()).collect();
+            keys.push("many/missing".to_owned());
+            let loaded = store.load_many::<u32, _>(&keys);
+            assert_eq!(loaded.len(), 41);
+            assert_eq!(*loaded["many/7"].as_ref().unwrap(), 7);
+            assert!(loaded["many/missing"].as_ref().unwrap_err().is_not_found());
+            store.delete_all("").unwrap();
+        }
+    }
+}
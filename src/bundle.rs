@@ -0,0 +1,96 @@
+//! Exporting all of an app's user data to a single document, and importing it back.
+
+use serde_json::{self, Map, Value};
+use std::io::{Read, Write};
+use crate::{AppInfo, PreferencesError, PreferencesStore};
+
+/// Writes all user data of `app` to `writer`, as one JSON object that maps each key to the data
+/// saved with it, e.g. so that users can back up their settings or move them to another machine.
+/// (See [`import_all`](fn.import_all.html).)
+///
+/// Data saved in any format is included, but it must be self-describing (i.e. not
+/// [`Format::Bincode`](enum.Format.html)).
+///
+/// # Failures
+/// If the data directory cannot be determined, any of the data cannot be loaded, or `writer`
+/// fails.
+///
+/// # Example
+/// ```
+/// use preferences::{AppInfo, MemoryBackend, PreferencesStore};
+///
+/// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+///
+/// let old_machine = PreferencesStore::new(&APP_INFO).unwrap()
+///     .with_memory_backend(MemoryBackend::new());
+/// old_machine.save("window/width", &800u32).unwrap();
+/// old_machine.save("theme", &"dark".to_owned()).unwrap();
+/// let mut bundle = Vec::new();
+/// old_machine.export_all(&mut bundle).unwrap();
+///
+/// let new_machine = PreferencesStore::new(&APP_INFO).unwrap()
+///     .with_memory_backend(MemoryBackend::new());
+/// new_machine.import_all(&bundle[..]).unwrap();
+/// assert_eq!(new_machine.load::<u32, _>("window/width").unwrap(), 800);
+/// ```
+pub fn export_all<W: Write>(app: &AppInfo, writer: W) -> Result<(), PreferencesError> {
+    PreferencesStore::new(app)?.export_all(writer)
+}
+
+/// Saves all user data in a document written by [`export_all`](fn.export_all.html) from
+/// `reader` as the user data of `app`, in the default format.
+///
+/// Either all of the data is saved, or none of it (see
+/// [`Transaction`](struct.Transaction.html)). Data saved with keys that aren't in the document
+/// is kept.
+///
+/// # Failures
+/// If the data directory cannot be determined, the document cannot be read or isn't a JSON
+/// object, any of its keys is invalid, or the data cannot be saved.
+pub fn import_all<R: Read>(app: &AppInfo, reader: R) -> Result<(), PreferencesError> {
+    PreferencesStore::new(app)?.import_all(reader)
+}
+
+/// Loads all user data in `store` into a map keyed by its keys.
+pub(crate) fn export(store: &PreferencesStore) -> Result<Map<String, Value>, PreferencesError> {
+    let mut bundle = Map::new();
+    for key in store.list("")? {
+        let value = store.load_any_format(&key)?;
+        bundle.insert(key, value);
+    }
+    Ok(bundle)
+}
+
+/// Saves all user data in the JSON object read from `reader` in `store`.
+pub(crate) fn import<R: Read>(store: &PreferencesStore, reader: R) -> Result<(), PreferencesError> {
+    let bundle: Map<String, Value> = serde_json::from_reader(reader)?;
+    let mut transaction = store.transaction();
+    for (key, value) in &bundle {
+        transaction.save(key, value)?;
+    }
+    transaction.commit()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::TestDir;
+    use serde_json::{self, Value};
+
+    #[test]
+    fn test_export_import() {
+        let dir = TestDir::new("bundle");
+        let store = dir.store().with_base_dir(dir.path().join("old"));
+        store.save("a", &1u32).unwrap();
+        store.save("b/c", &vec!["d"]).unwrap();
+        let mut bundle = Vec::new();
+        store.export_all(&mut bundle).unwrap();
+        let exported: Value = serde_json::from_slice(&bundle).unwrap();
+        assert_eq!(exported, serde_json::json!({"a": 1, "b/c": ["d"]}));
+
+        let imported = dir.store().with_base_dir(dir.path().join("new"));
+        imported.import_all(&bundle[..]).unwrap();
+        assert_eq!(imported.list("").unwrap(), vec!["a", "b/c"]);
+        assert_eq!(imported.load::<Vec<String>, _>("b/c").unwrap(), vec!["d"]);
+        assert!(imported.import_all(&b"[1]"[..]).is_err());
+    }
+}
@@ -0,0 +1,255 @@
+//! Notifications of changes of user data made by other processes.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+use crate::lock::{FileLock, Wait};
+use crate::{PreferencesError, PreferencesStore};
+
+/// Name of the file, in a store's directory, to which the changes are appended.
+static BUS_FILE_NAME: &str = "changes.bus";
+
+/// Size of the bus file above which it's emptied before the next change is appended.
+const MAX_BUS_LEN: u64 = 64 * 1024;
+
+/// How often subscribers check for new changes by default.
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+struct Member {
+    id: usize,
+    root: PathBuf,
+    path: PathBuf,
+}
+
+static MEMBERS: Mutex<Vec<Member>> = Mutex::new(Vec::new());
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// A change of user data made by another process, received from a
+/// [`ChangeBus`](struct.ChangeBus.html).
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PrefsChanged {
+    /// The key of the data that was saved or deleted.
+    pub key: String,
+}
+
+/// Membership of the current process in the change bus of a store's directory, which lets
+/// running instances of an app learn about each other's saves, e.g. when each of its windows is a
+/// separate process. Created with
+/// [`PreferencesStore::change_bus`](struct.PreferencesStore.html#method.change_bus).
+///
+/// While the bus exists, every save and delete made by this process in the store's directory is
+/// announced to the other processes by appending its key to a small file, `changes.bus`, in the
+/// directory. [`subscribe`](#method.subscribe) starts a background thread that checks the file
+/// periodically and sends the changes made by other members to a channel. Changes made by the
+/// current process aren't sent; use
+/// [`PreferencesStore::on_change`](struct.PreferencesStore.html#method.on_change) for those.
+///
+/// Only processes that are members of the bus announce their changes, so unlike
+/// [`watch_prefs`](fn.watch_prefs.html), this doesn't report changes made by editing the files,
+/// but it works on any file system, and without the `notify` feature. If a very large number of
+/// changes is made between two checks, some of them may not be reported. The process leaves the
+/// bus and its subscriptions end when the `ChangeBus` is dropped.
+///
+/// # Example
+/// ```no_run
+/// use preferences::{AppInfo, PreferencesStore};
+///
+/// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+///
+/// let store = PreferencesStore::new(&APP_INFO).unwrap();
+/// let bus = store.change_bus().unwrap();
+/// let changes = bus.subscribe();
+/// for change in changes {
+///     println!("Another window changed {}", change.key);
+/// }
+/// ```
+#[derive(Debug)]
+pub struct ChangeBus {
+    id: usize,
+    path: PathBuf,
+    poll_interval: Duration,
+    stopped: Arc<AtomicBool>,
+}
+
+impl ChangeBus {
+    /// Sets how often subscriptions made after this check for changes. The default is 100 ms.
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Returns the path of the file through which the changes are announced.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns a channel that receives the changes that other processes make from now on.
+    ///
+    /// The changes are checked for on a background thread, which ends when the receiver is
+    /// dropped or the bus is dropped.
+    pub fn subscribe(&self) -> Receiver<PrefsChanged> {
+        let (sender, receiver) = mpsc::channel();
+        let path = self.path.clone();
+        let interval = self.poll_interval;
+        let stopped = self.stopped.clone();
+        // Only changes made after subscribing are reported.
+        let offset = fs::metadata(&path).map(|metadata| metadata.len()).unwrap_or(0);
+        thread::spawn(move || poll(&path, offset, interval, &stopped, &sender));
+        receiver
+    }
+}
+
+impl Drop for ChangeBus {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        members().retain(|member| member.id != self.id);
+    }
+}
+
+/// Makes the current process a member of the change bus of `store`'s directory.
+pub(crate) fn join(store: &PreferencesStore) -> Result<ChangeBus, PreferencesError> {
+    if store.memory().is_some() {
+        let message = "Stores that don't keep their data in files have no change bus";
+        return Err(io::Error::new(ErrorKind::Unsupported, message).into());
+    }
+    let root = store.root().to_owned();
+    fs::create_dir_all(&root)?;
+    let path = root.join(BUS_FILE_NAME);
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    members().push(Member {
+        id,
+        root,
+        path: path.clone(),
+    });
+    Ok(ChangeBus {
+        id,
+        path,
+        poll_interval: DEFAULT_POLL_INTERVAL,
+        stopped: Arc::new(AtomicBool::new(false)),
+    })
+}
+
+/// Announces the change of `key` of the data stored in `root`, if the current process is a
+/// member of its bus. Failures are ignored, since the change itself succeeded.
+pub(crate) fn publish(root: &Path, key: &str) {
+    let path = match members().iter().find(|member| member.root == root) {
+        Some(member) => member.path.clone(),
+        None => return,
+    };
+    let _ = append(&path, key);
+}
+
+fn append(path: &Path, key: &str) -> Result<(), PreferencesError> {
+    // Each change is a line with the ID of the process and the key as a JSON string, so that
+    // keys containing line breaks can't forge changes.
+    let line = format!("{} {}\n", process::id(), serde_json::to_string(key)?);
+    let _lock = FileLock::acquire(path, true, Wait::Block)?;
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    if file.metadata()?.len() > MAX_BUS_LEN {
+        file.set_len(0)?;
+    }
+    file.write_all(line.as_bytes()).map_err(Into::into)
+}
+
+/// Sends the changes appended to the bus file at `path` after `offset` by other processes to
+/// `sender`, every `interval`, until the receiver or the bus is dropped.
+fn poll(path: &Path,
+        mut offset: u64,
+        interval: Duration,
+        stopped: &AtomicBool,
+        sender: &Sender<PrefsChanged>) {
+    let own_id = process::id().to_string();
+    loop {
+        thread::sleep(interval);
+        if stopped.load(Ordering::Relaxed) {
+            return;
+        }
+        let lines = match read_lines(path, &mut offset) {
+            Ok(lines) => lines,
+            Err(_) => continue,
+        };
+        for line in lines {
+            let key = match line.split_once(' ') {
+                Some((id, key)) if id != own_id => serde_json::from_str(key),
+                _ => continue,
+            };
+            if let Ok(key) = key {
+                if sender.send(PrefsChanged { key }).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Reads the complete lines of the bus file at `path` after `offset`, and advances `offset` past
+/// them. If the file was emptied since, it's read from the start.
+fn read_lines(path: &Path, offset: &mut u64) -> Result<Vec<String>, PreferencesError> {
+    let _lock = FileLock::acquire(path, false, Wait::Block)?;
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => {
+            *offset = 0;
+            return Ok(Vec::new());
+        }
+        Err(e) => return Err(e.into()),
+    };
+    if file.metadata()?.len() < *offset {
+        *offset = 0;
+    }
+    file.seek(SeekFrom::Start(*offset))?;
+    let mut text = String::new();
+    file.read_to_string(&mut text)?;
+    let complete = text.rfind('\n').map_or(0, |end| end + 1);
+    *offset += complete as u64;
+    Ok(text[..complete].lines().map(str::to_owned).collect())
+}
+
+fn members() -> MutexGuard<'static, Vec<Member>> {
+    MEMBERS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PrefsChanged;
+    use crate::testing::TestDir;
+    use crate::MemoryBackend;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::process;
+    use std::time::Duration;
+
+    #[test]
+    fn test_change_bus() {
+        let dir = TestDir::new("bus");
+        let store = dir.store();
+        let bus = store.change_bus().unwrap().poll_interval(Duration::from_millis(10));
+        let changes = bus.subscribe();
+
+        // Changes by this process are announced, but not received.
+        store.save("window/size", &[800, 600]).unwrap();
+        let announced = fs::read_to_string(bus.path()).unwrap();
+        assert_eq!(announced, format!("{} \"window/size\"\n", process::id()));
+
+        // A change by another process, the last one without its line break yet.
+        let mut file = OpenOptions::new().append(true).open(bus.path()).unwrap();
+        file.write_all(b"1 \"theme\"\n1 \"volu").unwrap();
+        let timeout = Duration::from_secs(10);
+        assert_eq!(changes.recv_timeout(timeout).unwrap(),
+                   PrefsChanged { key: "theme".to_owned() });
+        file.write_all(b"me\"\n").unwrap();
+        assert_eq!(changes.recv_timeout(timeout).unwrap().key, "volume");
+        assert_eq!(store.list("").unwrap(), ["window/size"]);
+
+        drop(bus);
+        assert!(changes.recv_timeout(timeout).is_err());
+        let memory_store = store.with_memory_backend(MemoryBackend::new());
+        assert!(memory_store.change_bus().is_err());
+    }
+}
@@ -0,0 +1,90 @@
+//! Saving and loading of data that the system may purge, in the platform's cache directory.
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use crate::{AppInfo, PreferencesError, PreferencesStore, Scope};
+
+/// Trait for types that can be saved & loaded as cached data, which the app can recreate if it's
+/// deleted, e.g. thumbnails or search indexes.
+///
+/// This works like [`Preferences`](trait.Preferences.html), and is likewise implemented for all
+/// serializable types, but the data is stored in the platform's cache directory (e.g.
+/// `~/.cache/<app name>` on Linux), which the system or the user may clear at any time, and
+/// which backup tools usually skip. It's the same as using a
+/// [`PreferencesStore`](struct.PreferencesStore.html) with `Scope::Cache`.
+///
+/// # Example
+/// ```
+/// extern crate preferences;
+/// use preferences::{AppInfo, CachePreferences, PreferencesMap};
+///
+/// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+///
+/// fn main() {
+///     # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
+///     let mut index: PreferencesMap<u64> = PreferencesMap::new();
+///     index.insert("photo.jpg".into(), 1024);
+///     index.save_cache(&APP_INFO, "tests/docs/index").unwrap();
+///
+///     // The cache may have been cleared, so be ready to rebuild the data.
+///     let index = PreferencesMap::<u64>::load_cache(&APP_INFO, "tests/docs/index")
+///         .unwrap_or_default();
+///     assert_eq!(index["photo.jpg"], 1024);
+///     PreferencesMap::<u64>::delete_cache(&APP_INFO, "tests/docs/index").unwrap();
+/// }
+/// ```
+pub trait CachePreferences: Sized {
+    /// Saves this value in the cache directory with the given `key`.
+    ///
+    /// # Failures
+    /// Same as [`Preferences::save`](trait.Preferences.html#tymethod.save).
+    fn save_cache<S: AsRef<str>>(&self, app: &AppInfo, key: S) -> Result<(), PreferencesError>;
+    /// Loads a value previously saved with `save_cache`.
+    ///
+    /// # Failures
+    /// Same as [`Preferences::load`](trait.Preferences.html#tymethod.load); in particular,
+    /// `PreferencesError::NotFound` if the data was never saved or was purged.
+    fn load_cache<S: AsRef<str>>(app: &AppInfo, key: S) -> Result<Self, PreferencesError>;
+    /// Deletes a value previously saved with `save_cache`.
+    ///
+    /// # Failures
+    /// Same as [`Preferences::delete`](trait.Preferences.html#method.delete).
+    fn delete_cache<S: AsRef<str>>(app: &AppInfo, key: S) -> Result<(), PreferencesError> {
+        cache_store(app)?.delete(key)
+    }
+}
+
+impl<T> CachePreferences for T
+    where T: Serialize + DeserializeOwned + Sized
+{
+    fn save_cache<S: AsRef<str>>(&self, app: &AppInfo, key: S) -> Result<(), PreferencesError> {
+        cache_store(app)?.save(key, self)
+    }
+    fn load_cache<S: AsRef<str>>(app: &AppInfo, key: S) -> Result<Self, PreferencesError> {
+        cache_store(app)?.load(key)
+    }
+}
+
+fn cache_store(app: &AppInfo) -> Result<PreferencesStore, PreferencesError> {
+    PreferencesStore::new(app)?.with_scope(Scope::Cache)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CachePreferences;
+    use crate::testing::{APP_INFO, TestBackend};
+    use crate::{Scope, prefs_exist};
+
+    #[test]
+    fn test_cache_scope() {
+        let backend = TestBackend::install();
+        let key = "tests/module/cache";
+        42u32.save_cache(&APP_INFO, key).unwrap();
+        let dir = Scope::Cache.app_dir(&APP_INFO).unwrap();
+        assert!(backend.backend().contains(&dir.join("tests/module/cache.prefs.json")));
+        assert!(!prefs_exist(&APP_INFO, key).unwrap());
+        assert_eq!(u32::load_cache(&APP_INFO, key).unwrap(), 42);
+        u32::delete_cache(&APP_INFO, key).unwrap();
+        assert!(u32::load_cache(&APP_INFO, key).unwrap_err().is_not_found());
+    }
+}
@@ -0,0 +1,187 @@
+//! Memoization of loaded user data, for apps that read the same data in many places.
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::SystemTime;
+use crate::{Format, PreferencesError, PreferencesStore, PrefsMetadata};
+
+/// A [`PreferencesStore`](struct.PreferencesStore.html) that keeps the values it loaded, and
+/// only loads them again when their files change.
+///
+/// `get` checks the modification time and size of the data's file, which is much cheaper than
+/// reading and deserializing it, and returns the value loaded before if neither changed. So the
+/// values stay up to date with saves by other stores and processes, and by the user editing the
+/// files. (A change that keeps the size the same within the file system's timestamp resolution,
+/// often a few milliseconds, can go unnoticed until the next change.)
+///
+/// The cache is shared by clones of the store, and can be used from several threads.
+///
+/// # Example
+/// ```
+/// #[macro_use]
+/// extern crate serde_derive;
+/// extern crate preferences;
+/// use preferences::{AppInfo, CachedStore, PreferencesStore};
+///
+/// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+///
+/// #[derive(Serialize, Deserialize)]
+/// struct Graphics {
+///     fullscreen: bool,
+/// }
+///
+/// fn main() {
+///     # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
+///     let store = CachedStore::new(PreferencesStore::new(&APP_INFO).unwrap());
+///     store.save("tests/docs/cached", &Graphics { fullscreen: true }).unwrap();
+///
+///     let graphics = store.get::<Graphics, _>("tests/docs/cached").unwrap();
+///     assert!(graphics.fullscreen);
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct CachedStore {
+    store: PreferencesStore,
+    cache: Arc<Mutex<HashMap<String, CachedValue>>>,
+}
+
+struct CachedValue {
+    /// The modification time and size of the file from which the value was loaded.
+    stamp: (SystemTime, u64),
+    value: Arc<dyn Any + Send + Sync>,
+}
+
+impl std::fmt::Debug for CachedValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("CachedValue").field("stamp", &self.stamp).finish()
+    }
+}
+
+impl CachedStore {
+    /// Creates an empty cache of the data in `store`.
+    pub fn new(store: PreferencesStore) -> Self {
+        CachedStore {
+            store,
+            cache: Arc::default(),
+        }
+    }
+
+    /// Returns the store whose data is cached. Saving or deleting data through it is noticed by
+    /// the cache like any other change.
+    pub fn store(&self) -> &PreferencesStore {
+        &self.store
+    }
+
+    /// Returns the value saved with the given `key`, loading it only if it wasn't loaded as `T`
+    /// before, or if its file changed since.
+    ///
+    /// # Failures
+    /// Same as [`PreferencesStore::load`](struct.PreferencesStore.html#method.load).
+    pub fn get<T, S>(&self, key: S) -> Result<Arc<T>, PreferencesError>
+        where T: DeserializeOwned + Send + Sync + 'static,
+              S: AsRef<str>
+    {
+        let key = key.as_ref();
+        self.get_stamped(key, self.store.metadata(key))
+    }
+
+    /// Same as `get`, but only checks the data saved in the given `format` for changes. This is
+    /// faster when several formats are enabled, but data saved in another format is ignored.
+    ///
+    /// # Failures
+    /// Same as `PreferencesStore::load_with_format`.
+    pub fn get_with_format<T, S>(&self, key: S, format: Format) -> Result<Arc<T>, PreferencesError>
+        where T: DeserializeOwned + Send + Sync + 'static,
+              S: AsRef<str>
+    {
+        let key = key.as_ref();
+        self.get_stamped(key, self.store.metadata_with_format(key, format))
+    }
+
+    /// Returns the cached value of `key` if `metadata` matches the file it was loaded from, or
+    /// loads it.
+    fn get_stamped<T>(&self,
+                      key: &str,
+                      metadata: Result<PrefsMetadata, PreferencesError>)
+                      -> Result<Arc<T>, PreferencesError>
+        where T: DeserializeOwned + Send + Sync + 'static
+    {
+        let metadata = match metadata {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                self.cache().remove(key);
+                return Err(e);
+            }
+        };
+        let stamp = (metadata.modified, metadata.size);
+        if let Some(cached) = self.cache().get(key) {
+            if cached.stamp == stamp {
+                if let Ok(value) = cached.value.clone().downcast::<T>() {
+                    return Ok(value);
+                }
+            }
+        }
+        let value = Arc::new(self.store.load_with_format::<T, _>(key, metadata.format)?);
+        self.cache().insert(key.to_owned(),
+                            CachedValue {
+                                stamp,
+                                value: value.clone(),
+                            });
+        Ok(value)
+    }
+
+    /// Saves `value` with the given `key`, like
+    /// [`PreferencesStore::save`](struct.PreferencesStore.html#method.save). The next `get`
+    /// loads it again.
+    pub fn save<T, S>(&self, key: S, value: &T) -> Result<(), PreferencesError>
+        where T: Serialize,
+              S: AsRef<str>
+    {
+        self.invalidate(key.as_ref());
+        self.store.save(key, value)
+    }
+
+    /// Forgets the value loaded for the given `key`, so that the next `get` loads it again.
+    pub fn invalidate<S: AsRef<str>>(&self, key: S) {
+        self.cache().remove(key.as_ref());
+    }
+
+    /// Forgets all loaded values.
+    pub fn clear(&self) {
+        self.cache().clear();
+    }
+
+    fn cache(&self) -> MutexGuard<'_, HashMap<String, CachedValue>> {
+        self.cache.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::TestDir;
+    use crate::{CachedStore, Format};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_cached_store() {
+        let dir = TestDir::new("cached");
+        for store in dir.stores() {
+            let cached = CachedStore::new(store.clone());
+            store.save("value", &1u32).unwrap();
+            let first = cached.get::<u32, _>("value").unwrap();
+            assert!(Arc::ptr_eq(&first, &cached.get::<u32, _>("value").unwrap()));
+            // The same data loaded as another type is loaded again.
+            assert_eq!(*cached.get::<u64, _>("value").unwrap(), 1);
+
+            store.save("value", &200u32).unwrap();
+            assert_eq!(*cached.get::<u32, _>("value").unwrap(), 200);
+            let value = cached.get_with_format::<u32, _>("value", Format::Json).unwrap();
+            assert!(Arc::ptr_eq(&value, &cached.get::<u32, _>("value").unwrap()));
+            store.delete("value").unwrap();
+            assert!(cached.get::<u32, _>("value").unwrap_err().is_not_found());
+        }
+    }
+}
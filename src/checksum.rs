@@ -0,0 +1,65 @@
+//! CRC-32 checksums stored in the headers of saved files, to detect corrupted data.
+
+use serde::de::DeserializeOwned;
+use crate::envelope;
+use crate::header;
+use crate::{Format, PreferencesError};
+
+/// Returns the serialized data in `bytes` without its header (see `header`), or all of `bytes` if
+/// there's no header, after verifying its checksum if the header has one.
+///
+/// # Failures
+/// `PreferencesError::Corrupted` if the header is damaged or the checksum doesn't match.
+pub(crate) fn verify(bytes: &[u8]) -> Result<&[u8], PreferencesError> {
+    let (header, payload) = header::split(bytes)?;
+    match header.and_then(|header| header.checksum) {
+        Some(checksum) if checksum != crc32fast::hash(payload) => Err(PreferencesError::Corrupted),
+        _ => Ok(payload),
+    }
+}
+
+/// Verifies the checksum of `bytes`, if they have one, and deserializes them, unwrapping them
+/// from their envelope, if any.
+pub(crate) fn deserialize<T>(format: Format, bytes: &[u8]) -> Result<T, PreferencesError>
+    where T: DeserializeOwned
+{
+    envelope::deserialize(format, verify(bytes)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::verify;
+    use crate::testing::TestDir;
+    use crate::{Format, PreferencesError, SaveOptions};
+    use std::fs;
+
+    #[test]
+    fn test_checksum() {
+        let sealed = SaveOptions::new().format(Format::Json).checksum(true).serialize(&()).unwrap();
+        assert_eq!(verify(&sealed).unwrap(), b"null");
+        assert_eq!(verify(b"{}").unwrap(), b"{}");
+        assert!(verify(&sealed[..sealed.len() - 1]).is_err());
+        assert!(verify(&sealed[..20]).is_err());
+        // Data that merely looks like a header isn't one.
+        assert_eq!(verify(b"preferences-crc32 0\n{}").unwrap(), b"preferences-crc32 0\n{}");
+    }
+
+    #[test]
+    fn test_corrupted_file() {
+        let dir = TestDir::new("checksum");
+        let store = dir.store().with_options(SaveOptions::new().checksum(true));
+        store.save("value", &"hello".to_owned()).unwrap();
+        assert_eq!(store.load::<String, _>("value").unwrap(), "hello");
+        let header = store.file_header("value").unwrap().unwrap();
+        assert!(header.checksum.is_some());
+        assert_eq!(header.key, None);
+
+        let path = store.file_path("value", Default::default()).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        fs::write(&path, text.replace("hello", "jello")).unwrap();
+        match store.load::<String, _>("value").as_ref().map_err(PreferencesError::inner) {
+            Err(&PreferencesError::Corrupted) => {}
+            other => panic!("expected corrupted data, got {:?}", other),
+        }
+    }
+}
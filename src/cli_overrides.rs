@@ -0,0 +1,296 @@
+//! Overrides of user data from command-line arguments.
+
+use clap::{Arg, ArgAction, ArgMatches, Args, Command, FromArgMatches};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::{self, Value};
+use crate::env_overrides::set_field;
+use crate::{AppInfo, PreferencesError, PreferencesStore};
+
+/// Id of the `--set` argument.
+static ARG_ID: &str = "preferences_set";
+
+/// Overrides of the fields of user data from `--set <field>=<value>` command-line arguments,
+/// parsed with [clap](https://docs.rs/clap), e.g. for debugging or scripting an app without
+/// changing its saved preferences.
+///
+/// Nested fields are separated by periods, e.g. `--set ui.theme=dark`. As with
+/// [`EnvOverrides`](struct.EnvOverrides.html), values are parsed as JSON unless they override a
+/// string field (or aren't valid JSON), and field names are matched case-insensitively.
+///
+/// The `--set` argument is added to a clap `Command` with `CliOverrides::augment_args` (or
+/// `#[command(flatten)]` with the clap derive API), and the overrides are then read from the
+/// matches with `CliOverrides::from_arg_matches`. The overrides are never saved by themselves;
+/// to also keep them out of the user's file when the app saves the overridden data, load and
+/// save it with `LayeredPreferences::with_cli_overrides`.
+///
+/// Requires the `cli` feature.
+///
+/// # Example
+/// ```
+/// #[macro_use]
+/// extern crate serde_derive;
+/// extern crate clap;
+/// extern crate preferences;
+/// use clap::{Args, Command, FromArgMatches};
+/// use preferences::CliOverrides;
+///
+/// #[derive(Serialize, Deserialize)]
+/// struct Ui {
+///     theme: String,
+///     scale: f32,
+/// }
+///
+/// fn main() {
+///     let command = CliOverrides::augment_args(Command::new("app"));
+///     let matches = command.get_matches_from(["app", "--set", "ui.theme=dark"]);
+///     let overrides = CliOverrides::from_arg_matches(&matches).unwrap();
+///
+///     let ui = Ui { theme: "light".into(), scale: 1.0 };
+///     let ui = overrides.apply(&serde_json::json!({ "ui": ui })).unwrap();
+///     assert_eq!(ui.value["ui"]["theme"], "dark");
+///     assert_eq!(ui.overridden, ["ui.theme"]);
+/// }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CliOverrides {
+    overrides: Vec<(String, String)>,
+}
+
+/// A value with command-line overrides applied, returned by
+/// [`CliOverrides::apply`](struct.CliOverrides.html#method.apply).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Overridden<T> {
+    /// The value, with the overrides applied.
+    pub value: T,
+    /// The fields that were overridden, in the order in which they were first given on the
+    /// command line, e.g. `ui.theme`.
+    pub overridden: Vec<String>,
+}
+
+impl CliOverrides {
+    /// Creates an empty set of overrides.
+    pub fn new() -> Self {
+        CliOverrides::default()
+    }
+
+    /// Adds an override of the field at `path` (with nested fields separated by periods) with
+    /// `value`, as if `--set <path>=<value>` was given.
+    pub fn set<P: Into<String>, V: Into<String>>(mut self, path: P, value: V) -> Self {
+        self.overrides.push((path.into(), value.into()));
+        self
+    }
+
+    /// Returns the `--set` argument, for adding it to a clap `Command` by hand.
+    pub fn arg() -> Arg {
+        Arg::new(ARG_ID)
+            .long("set")
+            .value_name("FIELD=VALUE")
+            .action(ArgAction::Append)
+            .value_parser(parse_override)
+            .help("Overrides a preference for this run only, e.g. --set ui.theme=dark")
+    }
+
+    /// Returns the overrides, as field paths and values, in the order in which they were given.
+    pub fn get_overrides(&self) -> &[(String, String)] {
+        &self.overrides
+    }
+
+    /// Returns whether no overrides were given.
+    pub fn is_empty(&self) -> bool {
+        self.overrides.is_empty()
+    }
+
+    /// Returns a copy of `value` with the overrides applied, and the fields that were
+    /// overridden.
+    ///
+    /// # Failures
+    /// If `value` cannot be serialized, or if the overridden value cannot be deserialized as `T`
+    /// (e.g. because an override has a value of the wrong type).
+    pub fn apply<T>(&self, value: &T) -> Result<Overridden<T>, PreferencesError>
+        where T: Serialize + DeserializeOwned
+    {
+        let mut value = serde_json::to_value(value)?;
+        let overridden = self.apply_to_value(&mut value);
+        Ok(Overridden {
+            value: serde_json::from_value(value)?,
+            overridden,
+        })
+    }
+
+    /// Loads the user data of `app` with the given `key`, and applies the overrides.
+    ///
+    /// # Failures
+    /// Same as [`Preferences::load`](trait.Preferences.html#tymethod.load), or if the overridden
+    /// value cannot be deserialized as `T`.
+    pub fn load<T, S>(&self, app: &AppInfo, key: S) -> Result<Overridden<T>, PreferencesError>
+        where T: DeserializeOwned,
+              S: AsRef<str>
+    {
+        let mut value = PreferencesStore::new(app)?.load(key)?;
+        let overridden = self.apply_to_value(&mut value);
+        Ok(Overridden {
+            value: serde_json::from_value(value)?,
+            overridden,
+        })
+    }
+
+    /// Applies the overrides to `value`, and returns the fields that were overridden.
+    pub(crate) fn apply_to_value(&self, value: &mut Value) -> Vec<String> {
+        let mut overridden: Vec<String> = Vec::new();
+        for (path, text) in &self.overrides {
+            let segments: Vec<&str> = path.split('.').collect();
+            set_field(value, &segments, text.clone());
+            if !overridden.contains(path) {
+                overridden.push(path.clone());
+            }
+        }
+        overridden
+    }
+
+    /// Undoes the overrides in `value` where it still has the overridden values, by restoring
+    /// the values of `previous` (the data before the overrides were applied), so that saving
+    /// `value` doesn't save the overrides.
+    pub(crate) fn restore(&self, value: &mut Value, previous: &Value) {
+        let mut applied = previous.clone();
+        for path in self.apply_to_value(&mut applied) {
+            let segments: Vec<&str> = path.split('.').collect();
+            if field(value, &segments) == field(&applied, &segments) {
+                restore_field(value, &segments, field(previous, &segments).cloned());
+            }
+        }
+    }
+}
+
+/// Returns the field at the path `segments` in `value`, matching names case-insensitively.
+fn field<'a>(value: &'a Value, segments: &[&str]) -> Option<&'a Value> {
+    segments.iter().try_fold(value, |value, name| {
+        value.as_object()?.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, v)| v)
+    })
+}
+
+/// Sets the field at the path `segments` in `value` to `previous`, or removes it if it didn't
+/// exist before.
+fn restore_field(value: &mut Value, segments: &[&str], previous: Option<Value>) {
+    let (name, parents) = match segments.split_last() {
+        Some(split) => split,
+        None => return,
+    };
+    let parent = parents.iter().try_fold(value, |value, parent| {
+        value.as_object_mut()?
+            .iter_mut()
+            .find(|(key, _)| key.eq_ignore_ascii_case(parent))
+            .map(|(_, v)| v)
+    });
+    if let Some(Value::Object(map)) = parent {
+        let key = map.keys()
+            .find(|key| key.eq_ignore_ascii_case(name))
+            .cloned()
+            .unwrap_or_else(|| name.to_string());
+        match previous {
+            Some(previous) => map.insert(key, previous),
+            None => map.remove(&key),
+        };
+    }
+}
+
+impl FromArgMatches for CliOverrides {
+    fn from_arg_matches(matches: &ArgMatches) -> Result<Self, clap::Error> {
+        // The argument may not have been added to the command that was matched.
+        let overrides = match matches.try_get_many::<(String, String)>(ARG_ID) {
+            Ok(Some(overrides)) => overrides.cloned().collect(),
+            _ => Vec::new(),
+        };
+        Ok(CliOverrides { overrides })
+    }
+
+    fn update_from_arg_matches(&mut self, matches: &ArgMatches) -> Result<(), clap::Error> {
+        *self = CliOverrides::from_arg_matches(matches)?;
+        Ok(())
+    }
+}
+
+impl Args for CliOverrides {
+    fn augment_args(command: Command) -> Command {
+        command.arg(CliOverrides::arg())
+    }
+
+    fn augment_args_for_update(command: Command) -> Command {
+        CliOverrides::augment_args(command)
+    }
+}
+
+/// Parses the value of a `--set` argument into the path of the field and its value.
+fn parse_override(text: &str) -> Result<(String, String), String> {
+    match text.split_once('=') {
+        Some((path, value)) if !path.split('.').any(str::is_empty) => {
+            Ok((path.to_owned(), value.to_owned()))
+        }
+        _ => Err(format!("expected FIELD=VALUE (e.g. ui.theme=dark), got {:?}", text)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CliOverrides;
+    use clap::{Args, Command, FromArgMatches};
+    use crate::{AppInfo, LayeredPreferences, MemoryBackend, PreferencesStore};
+    use serde_json::{self, Value};
+
+    const APP_INFO: AppInfo = AppInfo {
+        name: "preferences",
+        author: "Rust language community",
+    };
+
+    #[test]
+    fn test_cli_overrides() {
+        let command = CliOverrides::augment_args(Command::new("app"));
+        let matches = command.clone()
+            .try_get_matches_from(["app", "--set", "volume=7", "--set", "graphics.vsync=false",
+                                   "--set", "name=123", "--set", "volume=8"])
+            .unwrap();
+        let overrides = CliOverrides::from_arg_matches(&matches).unwrap();
+        assert_eq!(overrides.get_overrides().len(), 4);
+
+        let value: Value =
+            serde_json::from_str(r#"{"volume": 5, "name": "x", "graphics": {"vsync": true}}"#)
+                .unwrap();
+        let overridden = overrides.apply(&value).unwrap();
+        let expected: Value =
+            serde_json::from_str(r#"{"volume": 8, "name": "123", "graphics": {"vsync": false}}"#)
+                .unwrap();
+        assert_eq!(overridden.value, expected);
+        assert_eq!(overridden.overridden, ["volume", "graphics.vsync", "name"]);
+
+        for bad in &["volume", "=7", "graphics..vsync=false"] {
+            assert!(command.clone().try_get_matches_from(["app", "--set", bad]).is_err());
+        }
+        let matches = Command::new("app").get_matches_from(["app"]);
+        assert!(CliOverrides::from_arg_matches(&matches).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_cli_overrides_not_saved() {
+        let store = PreferencesStore::new(&APP_INFO)
+            .unwrap()
+            .with_memory_backend(MemoryBackend::new());
+        store.save("ui", &serde_json::json!({"theme": "light", "scale": 1})).unwrap();
+        let overrides = CliOverrides::new().set("theme", "dark").set("window.width", "800");
+        let layered = LayeredPreferences::with_store(store.clone(), "ui")
+            .with_cli_overrides(overrides);
+        let mut ui: Value = layered.load().unwrap();
+        assert_eq!(ui,
+                   serde_json::json!({"theme": "dark", "scale": 1, "window": {"width": 800}}));
+
+        ui["scale"] = 2.into();
+        layered.save(&ui).unwrap();
+        let saved: Value = store.load("ui").unwrap();
+        assert_eq!(saved, serde_json::json!({"theme": "light", "scale": 2, "window": {}}));
+
+        // Changes of overridden fields are saved.
+        ui["theme"] = "blue".into();
+        layered.save(&ui).unwrap();
+        let saved: Value = store.load("ui").unwrap();
+        assert_eq!(saved["theme"], "blue");
+    }
+}
@@ -0,0 +1,107 @@
+//! Detection of keys whose files differ only in case from the files of other keys.
+
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use crate::compression::Compression;
+use crate::PreferencesError;
+
+/// Checks that no file or directory on the way from `root` to the preferences file at `path`
+/// has a name that differs from the one in `path` only in case. On case-insensitive file
+/// systems (the default on Windows and macOS), saving `key` would overwrite such a file.
+///
+/// # Failures
+/// `PreferencesError::KeyCollision` with the path of the existing file or directory.
+pub(crate) fn check(root: &Path, path: &Path, key: &str) -> Result<(), PreferencesError> {
+    let relative = match path.strip_prefix(root) {
+        Ok(relative) => relative,
+        Err(_) => return Ok(()),
+    };
+    let mut dir = root.to_owned();
+    let mut components = relative.iter().peekable();
+    while let Some(name) = components.next() {
+        let names: Vec<OsString> = if components.peek().is_some() {
+            vec![name.to_owned()]
+        } else {
+            Compression::all()
+                .iter()
+                .filter_map(|compression| compression.apply_to(Path::new(name)).file_name()
+                    .map(OsStr::to_owned))
+                .collect()
+        };
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut exists = false;
+        for entry in entries {
+            let entry = entry?;
+            let entry_name = entry.file_name();
+            if names.contains(&entry_name) {
+                exists = true;
+            } else if names.iter().any(|name| differ_in_case(name, &entry_name)) {
+                return Err(PreferencesError::KeyCollision {
+                    key: key.to_owned(),
+                    path: entry.path(),
+                });
+            }
+        }
+        if !exists {
+            return Ok(());
+        }
+        dir.push(name);
+    }
+    Ok(())
+}
+
+/// Returns whether `a` and `b` are the same name, except for case.
+fn differ_in_case(a: &OsStr, b: &OsStr) -> bool {
+    match (a.to_str(), b.to_str()) {
+        (Some(a), Some(b)) => a != b && a.to_lowercase() == b.to_lowercase(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::TestDir;
+    use crate::PreferencesError;
+
+    #[test]
+    fn test_key_collision() {
+        let dir = TestDir::new("collision");
+        let store = dir.store().with_collision_check(true);
+        store.save("Theme", &"dark").unwrap();
+        store.save("Theme", &"light").unwrap();
+        store.save("options/Font", &"serif").unwrap();
+        for key in ["theme", "THEME", "Options/font", "options/font"] {
+            let error = store.save(key, &"other").unwrap_err();
+            match *error.inner() {
+                PreferencesError::KeyCollision { key: ref collided, ref path } => {
+                    assert_eq!(collided, key);
+                    assert!(path.starts_with(store.root()));
+                }
+                ref other => panic!("unexpected error: {}", other),
+            }
+        }
+        assert_eq!(store.load::<String, _>("Theme").unwrap(), "light");
+        assert_eq!(store.load::<String, _>("options/Font").unwrap(), "serif");
+        store.save("options/size", &12).unwrap();
+
+        let mut transaction = store.transaction();
+        transaction.save("options/size", &14).unwrap();
+        transaction.save("theme", &"other").unwrap();
+        assert!(transaction.commit().is_err());
+        assert_eq!(store.load::<u32, _>("options/size").unwrap(), 12);
+        let mut guard = store.edit::<String, _>("THEME").unwrap();
+        guard.push('!');
+        assert!(guard.commit().is_err());
+
+        // Without the check, keys are only as distinct as the file system makes them.
+        let unchecked = store.clone().with_collision_check(false);
+        unchecked.save("theme", &"other").unwrap();
+        assert_eq!(store.load::<String, _>("theme").unwrap(), "other");
+    }
+}
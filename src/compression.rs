@@ -0,0 +1,156 @@
+//! Compression of preferences files.
+
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// Compression applied to the files of saved user data, set with
+/// [`SaveOptions::compression`](struct.SaveOptions.html#method.compression).
+///
+/// Compressed files get an additional extension, e.g. `.prefs.json.zst`, and are decompressed
+/// transparently when they're loaded, regardless of the options used for loading. Each
+/// compression is enabled with the cargo feature of the same name (e.g. `zstd`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Compression {
+    /// No compression. This is the default.
+    #[default]
+    None,
+    /// gzip, via the `flate2` crate. Files get the additional extension `.gz`.
+    #[cfg(feature = "gzip")]
+    Gzip,
+    /// Zstandard, via the `zstd` crate, which is faster and compresses better than gzip. Files
+    /// get the additional extension `.zst`.
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl Compression {
+    /// File extension added for this compression, without the leading period, or `None` for
+    /// uncompressed files.
+    pub fn extension(&self) -> Option<&'static str> {
+        match *self {
+            Compression::None => None,
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => Some("gz"),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Some("zst"),
+        }
+    }
+
+    /// Returns all compressions enabled in this build, starting with `None`.
+    pub fn all() -> &'static [Compression] {
+        &[Compression::None,
+          #[cfg(feature = "gzip")]
+          Compression::Gzip,
+          #[cfg(feature = "zstd")]
+          Compression::Zstd]
+    }
+
+    /// Compresses `bytes`.
+    pub(crate) fn compress(&self, bytes: Vec<u8>) -> io::Result<Vec<u8>> {
+        match *self {
+            Compression::None => Ok(bytes),
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => {
+                use std::io::Write;
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(),
+                                                                flate2::Compression::default());
+                encoder.write_all(&bytes)?;
+                encoder.finish()
+            }
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => zstd::encode_all(&bytes[..], 0),
+        }
+    }
+
+    /// Wraps `reader` so that it reads decompressed data.
+    pub(crate) fn decompress<'a, R: Read + 'a>(&self, reader: R) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(match *self {
+            Compression::None => Box::new(reader),
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => Box::new(flate2::read::GzDecoder::new(reader)),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Box::new(zstd::Decoder::new(reader)?),
+        })
+    }
+
+    /// Returns the path of the preferences file at `path` compressed with this compression.
+    pub(crate) fn apply_to(&self, path: &Path) -> PathBuf {
+        match self.extension() {
+            Some(extension) => {
+                let mut name = path.file_name().map(OsString::from).unwrap_or_default();
+                name.push(".");
+                name.push(extension);
+                path.with_file_name(name)
+            }
+            None => path.to_owned(),
+        }
+    }
+
+    /// Returns the file extension of this compression in a file name, including the period.
+    pub(crate) fn suffix(&self) -> String {
+        self.extension().map(|extension| format!(".{}", extension)).unwrap_or_default()
+    }
+}
+
+/// Returns the path of the file in which the preferences file at `path` exists, in any enabled
+/// compression, and that compression. If it doesn't exist, returns `path` itself.
+pub(crate) fn find(path: &Path) -> (PathBuf, Compression) {
+    for &compression in Compression::all() {
+        let candidate = compression.apply_to(path);
+        if candidate.is_file() {
+            return (candidate, compression);
+        }
+    }
+    (path.to_owned(), Compression::None)
+}
+
+/// Removes the preferences file at `path` in all enabled compressions other than `kept`, so
+/// that no stale copy is loaded instead of the one just saved.
+pub(crate) fn remove_others(path: &Path, kept: Compression) {
+    for &compression in Compression::all() {
+        if compression != kept {
+            let _ = fs::remove_file(compression.apply_to(path));
+        }
+    }
+}
+
+#[cfg(all(test, any(feature = "gzip", feature = "zstd")))]
+mod tests {
+    use super::Compression;
+    use crate::testing::TestDir;
+    use crate::SaveOptions;
+
+    fn check_compression(compression: Compression) {
+        let key = format!("tests/module/compression/{:?}", compression);
+        let dir = TestDir::new(&format!("compression-{:?}", compression));
+        let options = SaveOptions::new().compression(compression).compress_above(16);
+        let store = dir.store().with_options(options);
+        let path = store.file_path(&key, Default::default()).unwrap();
+        let large = vec![7u32; 100];
+        store.save(&key, &large).unwrap();
+        assert!(compression.apply_to(&path).is_file());
+        assert!(!path.exists());
+        assert_eq!(store.load::<Vec<u32>, _>(&key).unwrap(), large);
+        assert_eq!(store.list("tests/module/compression").unwrap(), vec![key.clone()]);
+
+        store.save(&key, &vec![1u32]).unwrap();
+        assert!(path.is_file());
+        assert!(!compression.apply_to(&path).exists());
+        assert_eq!(store.load::<Vec<u32>, _>(&key).unwrap(), vec![1]);
+        store.delete(&key).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "gzip")]
+    fn test_gzip() {
+        check_compression(Compression::Gzip);
+    }
+
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn test_zstd() {
+        check_compression(Compression::Zstd);
+    }
+}
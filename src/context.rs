@@ -0,0 +1,142 @@
+//! The key, file and operation that errors occurred with.
+
+use std::fmt;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use crate::PreferencesError;
+
+/// An operation on user data, as reported by
+/// [`PreferencesError::operation`](enum.PreferencesError.html#method.operation).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Operation {
+    /// Saving the data.
+    Save,
+    /// Loading the data.
+    Load,
+    /// Deleting the data.
+    Delete,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Operation::Save => "save",
+            Operation::Load => "load",
+            Operation::Delete => "delete",
+        })
+    }
+}
+
+/// An error together with the user data it occurred with. (See
+/// [`PreferencesError::Context`](enum.PreferencesError.html#variant.Context).)
+#[derive(Debug)]
+pub struct ErrorContext {
+    /// What was done with the data.
+    pub operation: Operation,
+    /// The key of the data.
+    pub key: String,
+    /// The file of the data.
+    pub path: PathBuf,
+    /// The error that occurred.
+    pub error: PreferencesError,
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "Couldn't {} preferences `{}` ({}): {}",
+               self.operation,
+               self.key,
+               self.path.display(),
+               self.error)
+    }
+}
+
+impl PreferencesError {
+    /// Returns the file of the user data that this error occurred with, if known.
+    pub fn path(&self) -> Option<&Path> {
+        match *self {
+            PreferencesError::Context(ref context) => Some(&context.path),
+            PreferencesError::Conflict { ref path, .. } |
+            PreferencesError::KeyCollision { ref path, .. } |
+            PreferencesError::NotFound { ref path, .. } |
+            PreferencesError::Validation { ref path, .. } => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Returns the key of the user data that this error occurred with, if known.
+    pub fn key(&self) -> Option<&str> {
+        match *self {
+            PreferencesError::Context(ref context) => Some(&context.key),
+            PreferencesError::Conflict { ref key, .. } |
+            PreferencesError::KeyCollision { ref key, .. } |
+            PreferencesError::NotFound { ref key, .. } |
+            PreferencesError::Validation { ref key, .. } => Some(key),
+            _ => None,
+        }
+    }
+
+    /// Returns what was being done with the user data when this error occurred, if known.
+    pub fn operation(&self) -> Option<Operation> {
+        match *self {
+            PreferencesError::Context(ref context) => Some(context.operation),
+            _ => None,
+        }
+    }
+
+    /// Returns the error without its context, e.g. to match on the kind of error.
+    ///
+    /// # Example
+    /// ```
+    /// use preferences::{AppInfo, Preferences, PreferencesError};
+    ///
+    /// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+    ///
+    /// # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
+    /// let error = u32::load(&APP_INFO, "tests/docs/missing").unwrap_err();
+    /// assert_eq!(error.key(), Some("tests/docs/missing"));
+    /// match *error.inner() {
+    ///     PreferencesError::NotFound { .. } => println!("Nothing saved yet"),
+    ///     PreferencesError::Io(ref e) => println!("{} ({:?})", e, error.path()),
+    ///     ref other => panic!("unexpected error: {}", other),
+    /// }
+    /// ```
+    pub fn inner(&self) -> &PreferencesError {
+        match *self {
+            PreferencesError::Context(ref context) => &context.error,
+            ref error => error,
+        }
+    }
+
+    /// Adds the context of the user data to this error, unless it already has it. A missing file
+    /// when loading or deleting becomes `PreferencesError::NotFound`.
+    pub(crate) fn with_context(self, operation: Operation, key: &str, path: &Path) -> Self {
+        match self {
+            PreferencesError::Context(_) |
+            PreferencesError::Conflict { .. } |
+            PreferencesError::KeyCollision { .. } |
+            PreferencesError::NotFound { .. } |
+            PreferencesError::Validation { .. } => self,
+            PreferencesError::Io(ref e) if e.kind() == ErrorKind::NotFound &&
+                                           operation != Operation::Save => {
+                PreferencesError::not_found(key, path)
+            }
+            error => {
+                PreferencesError::Context(Box::new(ErrorContext {
+                    operation,
+                    key: key.to_owned(),
+                    path: path.to_owned(),
+                    error,
+                }))
+            }
+        }
+    }
+
+    pub(crate) fn not_found(key: &str, path: &Path) -> Self {
+        PreferencesError::NotFound {
+            key: key.to_owned(),
+            path: path.to_owned(),
+        }
+    }
+}
@@ -0,0 +1,106 @@
+//! Descriptions of preferences for settings screens.
+
+/// A description of a preference for the user (its label, explanation and allowed values), from
+/// which GUI frameworks can generate a settings screen.
+///
+/// Descriptors are attached to keys with
+/// [`PreferencesStore::describe`](struct.PreferencesStore.html#method.describe) and enumerated
+/// with [`descriptors`](struct.PreferencesStore.html#method.descriptors). With the `derive`
+/// feature, `#[derive(Prefs)]` also generates a `descriptors()` function with a descriptor of
+/// each field of a struct; see [`Prefs`](derive.Prefs.html).
+///
+/// Descriptors don't restrict the values that can be saved; see
+/// [`PreferencesStore::clamp`](struct.PreferencesStore.html#method.clamp) for that.
+///
+/// # Example
+/// ```
+/// use preferences::{AppInfo, PrefDescriptor, PrefKey, PreferencesStore};
+///
+/// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+/// const VOLUME: PrefKey<u8> = PrefKey::new("audio/volume");
+/// const THEME: PrefKey<String> = PrefKey::new("ui/theme");
+///
+/// let store = PreferencesStore::new(&APP_INFO).unwrap();
+/// store.describe(VOLUME, PrefDescriptor::new().label("Volume").min(0.0).max(10.0));
+/// store.describe(THEME,
+///                PrefDescriptor::new()
+///                    .label("Theme")
+///                    .description("Colors of the app's windows.")
+///                    .choices(&["light", "dark"]));
+///
+/// for (key, descriptor) in store.descriptors() {
+///     // Add a control for `key` to the settings screen...
+///     # assert!(descriptor.get_label().is_some());
+/// }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PrefDescriptor {
+    label: Option<String>,
+    description: Option<String>,
+    min: Option<f64>,
+    max: Option<f64>,
+    choices: Vec<String>,
+}
+
+impl PrefDescriptor {
+    /// Creates an empty descriptor.
+    pub fn new() -> Self {
+        PrefDescriptor::default()
+    }
+
+    /// Sets the short name of the preference, e.g. for the label of its control.
+    pub fn label<S: Into<String>>(mut self, label: S) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    /// Sets a longer explanation of the preference, e.g. for a tooltip.
+    pub fn description<S: Into<String>>(mut self, description: S) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    /// Sets the smallest value of a numeric preference.
+    pub fn min(mut self, min: f64) -> Self {
+        self.min = Some(min);
+        self
+    }
+
+    /// Sets the largest value of a numeric preference.
+    pub fn max(mut self, max: f64) -> Self {
+        self.max = Some(max);
+        self
+    }
+
+    /// Sets the values that the preference can have, e.g. for a drop-down list.
+    pub fn choices<S: AsRef<str>>(mut self, choices: &[S]) -> Self {
+        self.choices = choices.iter().map(|choice| choice.as_ref().to_owned()).collect();
+        self
+    }
+
+    /// Returns the short name of the preference, if set.
+    pub fn get_label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    /// Returns the explanation of the preference, if set.
+    pub fn get_description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    /// Returns the smallest value of the preference, if set.
+    pub fn get_min(&self) -> Option<f64> {
+        self.min
+    }
+
+    /// Returns the largest value of the preference, if set.
+    pub fn get_max(&self) -> Option<f64> {
+        self.max
+    }
+
+    /// Returns the values that the preference can have, or an empty slice if they aren't
+    /// restricted to a list.
+    pub fn get_choices(&self) -> &[String] {
+        &self.choices
+    }
+}
@@ -0,0 +1,364 @@
+//! Pluggable lookup of the platform directories for user data.
+
+#[cfg(feature = "app_dirs")]
+use app_dirs::{AppDirsError, get_app_root};
+use std::env;
+use std::error::Error;
+use std::ffi::OsString;
+use std::fmt::{self, Debug};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use crate::keys::sanitized;
+use crate::sandbox;
+use crate::{AppId, AppInfo, PreferencesError, Scope, app_dir_in};
+
+static DIR_PROVIDER: RwLock<Option<Arc<dyn DirProvider>>> = RwLock::new(None);
+
+/// Error type describing why the directory of an app's user data couldn't be determined.
+#[derive(Debug)]
+pub enum DirError {
+    /// An I/O error occurred while looking up the directory.
+    Io(io::Error),
+    /// The platform has no such directory, or it couldn't be found (e.g. because the home
+    /// directory is unknown).
+    NotSupported,
+    /// The name or author of the app is empty.
+    InvalidAppInfo,
+}
+
+impl fmt::Display for DirError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DirError::Io(ref e) => fmt::Display::fmt(e, f),
+            DirError::NotSupported => f.write_str("App data directories not supported"),
+            DirError::InvalidAppInfo => f.write_str("Invalid app name or author"),
+        }
+    }
+}
+
+impl Error for DirError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            DirError::Io(ref e) => Some(e),
+            DirError::NotSupported | DirError::InvalidAppInfo => None,
+        }
+    }
+}
+
+#[cfg(feature = "app_dirs")]
+impl From<AppDirsError> for DirError {
+    fn from(e: AppDirsError) -> Self {
+        match e {
+            AppDirsError::Io(e) => DirError::Io(e),
+            AppDirsError::NotSupported => DirError::NotSupported,
+            AppDirsError::InvalidAppInfo => DirError::InvalidAppInfo,
+        }
+    }
+}
+
+/// Source of the platform directories in which the user data of apps is stored.
+///
+/// The default provider is [`DirectoriesProvider`](struct.DirectoriesProvider.html). Apps can
+/// also implement this trait themselves, e.g. for platforms that it doesn't support, and install
+/// it with [`set_dir_provider`](fn.set_dir_provider.html) or
+/// [`PreferencesStore::with_dir_provider`](struct.PreferencesStore.html#method.with_dir_provider).
+pub trait DirProvider: Debug + Send + Sync {
+    /// Returns the directory in which the user data of `app` is stored in `scope`. This makes no
+    /// guarantees that the directory exists.
+    ///
+    /// # Failures
+    /// If the directory cannot be determined, e.g. because `scope` has no directory on the
+    /// current platform.
+    fn app_dir(&self, scope: Scope, app: &AppInfo) -> Result<PathBuf, PreferencesError>;
+}
+
+/// The default [`DirProvider`](trait.DirProvider.html), which uses the project directories of the
+/// `directories-next` crate, e.g. `~/.config/<app name>` on Linux,
+/// `%APPDATA%\<author>\<app name>\config` on Windows and
+/// `~/Library/Application Support/<author>.<app name>` on macOS for `Scope::User`.
+///
+/// `directories-next` has no machine-wide directories, so `Scope::System` uses the first of
+/// `$XDG_CONFIG_DIRS` (by default `/etc/xdg`) on Linux, `/Library/Application Support` on macOS
+/// and `%PROGRAMDATA%\<author>` on Windows, each with a subdirectory named after the app. On
+/// iOS, it uses the `Library` directories of the app's sandbox, and in Flatpak and Snap
+/// sandboxes on Linux, it uses the sandbox's private directories. (See
+/// [`sandbox`](fn.sandbox.html).)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DirectoriesProvider;
+
+impl DirProvider for DirectoriesProvider {
+    fn app_dir(&self, scope: Scope, app: &AppInfo) -> Result<PathBuf, PreferencesError> {
+        if app.name.is_empty() || app.author.is_empty() {
+            return Err(DirError::InvalidAppInfo.into());
+        }
+        if let Some(dir) = sandboxed_app_dir(scope, app) {
+            return dir;
+        }
+        let dirs = directories_next::ProjectDirs::from("", app.author, app.name)
+            .ok_or(DirError::NotSupported)?;
+        Ok(match scope {
+            Scope::User => dirs.config_dir().to_owned(),
+            // `data_local_dir` is `%LOCALAPPDATA%\<author>\<app name>\data` on Windows.
+            Scope::Local if cfg!(windows) => dirs.data_local_dir().with_file_name("config"),
+            Scope::Local => dirs.config_dir().to_owned(),
+            Scope::System => return app_dir_in(&system_base_dir(app)?, app),
+            Scope::Cache => dirs.cache_dir().to_owned(),
+            Scope::Data => dirs.data_dir().to_owned(),
+            Scope::State => dirs.data_local_dir().to_owned(),
+        })
+    }
+}
+
+/// A [`DirProvider`](trait.DirProvider.html) that uses the directories chosen by the `app_dirs`
+/// crate, e.g. `~/.config/<app name>` on Linux, `%APPDATA%\<author>\<app name>` on Windows and
+/// `~/Library/Application Support/<app name>` on macOS for `Scope::User`. Like
+/// [`DirectoriesProvider`](struct.DirectoriesProvider.html), it uses the sandbox's directories on
+/// iOS and in Flatpak and Snap sandboxes.
+///
+/// These are the directories that version 1 of this crate used. `app_dirs` is no longer
+/// maintained, but apps that saved data with version 1 can install this provider with
+/// [`set_dir_provider`](fn.set_dir_provider.html) to keep finding it, since the default
+/// directories differ on Windows and macOS, and on Linux for app names with uppercase letters
+/// or spaces.
+///
+/// Requires the `app_dirs` feature.
+#[cfg(feature = "app_dirs")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AppDirsProvider;
+
+#[cfg(feature = "app_dirs")]
+impl DirProvider for AppDirsProvider {
+    fn app_dir(&self, scope: Scope, app: &AppInfo) -> Result<PathBuf, PreferencesError> {
+        if let Some(dir) = sandboxed_app_dir(scope, app) {
+            return dir;
+        }
+        let info = app_dirs::AppInfo {
+            name: app.name,
+            author: app.author,
+        };
+        get_app_root(scope.data_type(), &info).map_err(|e| DirError::from(e).into())
+    }
+}
+
+/// A [`DirProvider`](trait.DirProvider.html) that follows the
+/// [XDG Base Directory Specification](https://specifications.freedesktop.org/basedir-spec/)
+/// strictly, on every platform, as some Linux distributions' packaging guidelines require.
+///
+/// Each scope uses the directory named by its environment variable, if that is set to an
+/// absolute path, and the default from the specification otherwise:
+///
+/// * `Scope::User` and `Scope::Local`: `$XDG_CONFIG_HOME`, default `~/.config`
+/// * `Scope::System`: the first entry of `$XDG_CONFIG_DIRS`, default `/etc/xdg`
+/// * `Scope::Cache`: `$XDG_CACHE_HOME`, default `~/.cache`
+/// * `Scope::Data`: `$XDG_DATA_HOME`, default `~/.local/share`
+/// * `Scope::State`: `$XDG_STATE_HOME`, default `~/.local/state`, unless splitting state from
+///   configuration is turned off with `split_state`
+///
+/// The app's data is stored in a subdirectory named after the app.
+///
+/// # Example
+/// ```
+/// use std::sync::Arc;
+/// use preferences::{XdgProvider, set_dir_provider};
+///
+/// set_dir_provider(Some(Arc::new(XdgProvider::new())));
+/// # set_dir_provider(None);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct XdgProvider {
+    split_state: bool,
+}
+
+impl XdgProvider {
+    /// Creates a provider that stores `Scope::State` in `$XDG_STATE_HOME`.
+    pub fn new() -> Self {
+        XdgProvider { split_state: true }
+    }
+
+    /// Sets whether `Scope::State` is stored in `$XDG_STATE_HOME` (the default), or together
+    /// with the configuration in `$XDG_CONFIG_HOME`.
+    pub fn split_state(mut self, split: bool) -> Self {
+        self.split_state = split;
+        self
+    }
+
+    /// Returns the base directory for `scope`, looking up environment variables with `var`.
+    fn base_dir<F>(&self, scope: Scope, var: F) -> Option<PathBuf>
+        where F: Fn(&str) -> Option<OsString>
+    {
+        let absolute = |name: &str| {
+            var(name).map(PathBuf::from).filter(|dir| dir.is_absolute())
+        };
+        let home = || absolute("HOME");
+        match scope {
+            Scope::User | Scope::Local => {
+                absolute("XDG_CONFIG_HOME").or_else(|| Some(home()?.join(".config")))
+            }
+            Scope::System => {
+                let dirs = var("XDG_CONFIG_DIRS").unwrap_or_default();
+                Some(env::split_paths(&dirs)
+                    .find(|dir| dir.is_absolute())
+                    .unwrap_or_else(|| PathBuf::from("/etc/xdg")))
+            }
+            Scope::Cache => absolute("XDG_CACHE_HOME").or_else(|| Some(home()?.join(".cache"))),
+            Scope::Data => {
+                absolute("XDG_DATA_HOME").or_else(|| Some(home()?.join(".local/share")))
+            }
+            Scope::State if !self.split_state => self.base_dir(Scope::User, var),
+            Scope::State => {
+                absolute("XDG_STATE_HOME").or_else(|| Some(home()?.join(".local/state")))
+            }
+        }
+    }
+}
+
+impl Default for XdgProvider {
+    fn default() -> Self {
+        XdgProvider::new()
+    }
+}
+
+impl DirProvider for XdgProvider {
+    fn app_dir(&self, scope: Scope, app: &AppInfo) -> Result<PathBuf, PreferencesError> {
+        let base = self.base_dir(scope, |name| env::var_os(name))
+            .ok_or(DirError::NotSupported)?;
+        app_dir_in(&base, app)
+    }
+}
+
+/// Installs `provider` as the source of the platform directories for the whole process, or
+/// restores the default [`DirectoriesProvider`](struct.DirectoriesProvider.html) if `provider` is
+/// `None`.
+///
+/// This affects every [`Scope`](enum.Scope.html), and with it all stores created afterwards and
+/// the [`Preferences`](trait.Preferences.html) methods. The `PREFERENCES_RS_DIR` environment
+/// variable still takes precedence for `Scope::User`.
+pub fn set_dir_provider(provider: Option<Arc<dyn DirProvider>>) {
+    *DIR_PROVIDER.write().unwrap_or_else(|e| e.into_inner()) = provider;
+}
+
+/// Returns the directory of `app` in `scope` according to the installed provider.
+pub(crate) fn provider_app_dir(scope: Scope, app: &AppId) -> Result<PathBuf, PreferencesError> {
+    let provider = DIR_PROVIDER.read().unwrap_or_else(|e| e.into_inner()).clone();
+    let dir = match provider {
+        Some(provider) => provider.app_dir(scope, app.info())?,
+        None => DirectoriesProvider.app_dir(scope, app.info())?,
+    };
+    Ok(app.platform_dir(dir))
+}
+
+/// Returns the base directory for `Scope::User` of the default provider, e.g. `~/.config` on
+/// Linux, for [`prefs_base_dir`](fn.prefs_base_dir.html).
+pub(crate) fn default_base_dir() -> Option<PathBuf> {
+    directories_next::BaseDirs::new().map(|dirs| dirs.config_dir().to_owned())
+}
+
+/// Returns the directory of `app` in `scope` in the iOS app sandbox or the Linux application
+/// sandbox the app is running in, or `None` if it isn't sandboxed.
+fn sandboxed_app_dir(scope: Scope, app: &AppInfo) -> Option<Result<PathBuf, PreferencesError>> {
+    if cfg!(target_os = "ios") {
+        let dir = env::var_os("HOME")
+            .ok_or_else(|| DirError::NotSupported.into())
+            .and_then(|home| app_dir_in(&sandbox_dir(Path::new(&home), scope), app));
+        return Some(dir);
+    }
+    sandbox::sandbox_base_dir(scope).map(|base| app_dir_in(&base, app))
+}
+
+/// Returns the directory for `scope` in the iOS app sandbox whose home directory is `home`.
+/// There's no data shared with other users, so shared data goes in the user's directories.
+fn sandbox_dir(home: &Path, scope: Scope) -> PathBuf {
+    let library = home.join("Library");
+    match scope {
+        Scope::User | Scope::Local | Scope::System => library.join("Preferences"),
+        Scope::Cache => library.join("Caches"),
+        Scope::Data | Scope::State => library.join("Application Support"),
+    }
+}
+
+/// Returns the base directory of the machine-wide configuration of `app`, in which `app_dirs`
+/// kept it too.
+fn system_base_dir(app: &AppInfo) -> Result<PathBuf, DirError> {
+    if cfg!(windows) {
+        let data = env::var_os("PROGRAMDATA").ok_or(DirError::NotSupported)?;
+        Ok(Path::new(&data).join(sanitized(app.author)))
+    } else if cfg!(target_os = "macos") {
+        Ok(PathBuf::from("/Library/Application Support"))
+    } else {
+        XdgProvider::new()
+            .base_dir(Scope::System, |name| env::var_os(name))
+            .ok_or(DirError::NotSupported)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DirProvider, XdgProvider, sandbox_dir};
+    use crate::{AppInfo, PreferencesError, PreferencesStore, Scope};
+    use std::path::{Path, PathBuf};
+
+    const APP_INFO: AppInfo = AppInfo {
+        name: "preferences",
+        author: "Rust language community",
+    };
+
+    #[derive(Debug)]
+    struct FixedProvider;
+
+    impl DirProvider for FixedProvider {
+        fn app_dir(&self, scope: Scope, app: &AppInfo) -> Result<PathBuf, PreferencesError> {
+            Ok(Path::new("/fixed").join(format!("{:?}", scope)).join(app.name))
+        }
+    }
+
+    #[test]
+    fn test_dir_provider() {
+        let store = PreferencesStore::new(&APP_INFO)
+            .unwrap()
+            .with_dir_provider(&FixedProvider, Scope::Cache)
+            .unwrap();
+        assert_eq!(store.root(), Path::new("/fixed/Cache/preferences"));
+    }
+
+    #[test]
+    fn test_xdg_provider() {
+        let var = |name: &str| {
+            match name {
+                "HOME" => Some("/home/user".into()),
+                "XDG_CONFIG_HOME" => Some("relative/config".into()),
+                "XDG_CONFIG_DIRS" => Some("/etc/xdg/vendor:/etc/xdg".into()),
+                "XDG_STATE_HOME" => Some("/state".into()),
+                _ => None,
+            }
+        };
+        let provider = XdgProvider::new();
+        let base = |scope| provider.base_dir(scope, var).unwrap();
+        assert_eq!(base(Scope::User), Path::new("/home/user/.config"));
+        assert_eq!(base(Scope::Local), Path::new("/home/user/.config"));
+        assert_eq!(base(Scope::System), Path::new("/etc/xdg/vendor"));
+        assert_eq!(base(Scope::Data), Path::new("/home/user/.local/share"));
+        assert_eq!(base(Scope::State), Path::new("/state"));
+        assert_eq!(provider.split_state(false).base_dir(Scope::State, var).unwrap(),
+                   Path::new("/home/user/.config"));
+    }
+
+    #[cfg(feature = "app_dirs")]
+    #[test]
+    fn test_app_dirs_provider() {
+        use super::{AppDirsProvider, DirectoriesProvider};
+
+        let app_dirs = AppDirsProvider.app_dir(Scope::User, &APP_INFO).unwrap();
+        assert!(app_dirs.ends_with("preferences"));
+        // Both keep the machine-wide configuration in the same directory.
+        assert_eq!(DirectoriesProvider.app_dir(Scope::System, &APP_INFO).unwrap(),
+                   AppDirsProvider.app_dir(Scope::System, &APP_INFO).unwrap());
+    }
+
+    #[test]
+    fn test_ios_sandbox_dir() {
+        let home = Path::new("/var/mobile/Containers/Data/Application/app");
+        assert_eq!(sandbox_dir(home, Scope::User), home.join("Library/Preferences"));
+        assert_eq!(sandbox_dir(home, Scope::Cache), home.join("Library/Caches"));
+    }
+}
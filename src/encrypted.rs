@@ -0,0 +1,283 @@
+//! Encrypted storage of user data.
+
+use chacha20poly1305::aead::{Aead, OsRng, Payload};
+use chacha20poly1305::{AeadCore, ChaCha20Poly1305, Key, KeyInit, Nonce};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+#[cfg(feature = "keyring")]
+use crate::SecretPreferences;
+use crate::{AppInfo, Format, Operation, PreferencesError, PreferencesStore};
+
+static ENCRYPTED_FILE_EXTENSION: &str = "enc";
+const NONCE_LENGTH: usize = 12;
+
+/// Saves and loads user data encrypted with a secret key, so that it isn't stored in plaintext.
+///
+/// The data is serialized as JSON and encrypted with ChaCha20-Poly1305, using a fresh random nonce
+/// for every save. The app name and the preferences key are authenticated along with the data, so
+/// an encrypted file can't be passed off as the data of another key. Files use the extension
+/// `.prefs.enc`, and are saved like other preferences (locked, atomically, and with the store's
+/// options), except that they're always only accessible to the current user (see
+/// [`SaveOptions::private`](struct.SaveOptions.html#method.private)).
+///
+/// Keeping the secret key itself safe is up to the app. With the `keyring` feature,
+/// [`from_keyring`](#method.from_keyring) keeps it in the platform's secret store.
+///
+/// Requires the `encryption` feature.
+///
+/// # Example
+/// ```
+/// extern crate preferences;
+/// use preferences::{AppInfo, EncryptedPreferences, PreferencesMap};
+///
+/// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+///
+/// fn main() {
+///     # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
+///     let secret = EncryptedPreferences::generate_key();
+///     let encrypted = EncryptedPreferences::new(&secret);
+///
+///     let mut session: PreferencesMap = PreferencesMap::new();
+///     session.insert("token".into(), "hunter2".into());
+///
+///     let prefs_key = "tests/docs/encrypted";
+///     encrypted.save(&APP_INFO, prefs_key, &session).unwrap();
+///     let loaded: PreferencesMap = encrypted.load(&APP_INFO, prefs_key).unwrap();
+///     assert_eq!(loaded, session);
+/// }
+/// ```
+#[derive(Clone)]
+pub struct EncryptedPreferences {
+    cipher: ChaCha20Poly1305,
+}
+
+impl EncryptedPreferences {
+    /// Creates an instance that encrypts and decrypts data with the given 256-bit secret key.
+    pub fn new(key: &[u8; 32]) -> Self {
+        EncryptedPreferences { cipher: ChaCha20Poly1305::new(Key::from_slice(key)) }
+    }
+
+    /// Generates a new random secret key, suitable for `new`.
+    pub fn generate_key() -> [u8; 32] {
+        ChaCha20Poly1305::generate_key(&mut OsRng).into()
+    }
+
+    /// Creates an instance with the secret key saved in the platform's secret store with the
+    /// given `name`, generating and saving a new key the first time. (See
+    /// [`SecretPreferences`](trait.SecretPreferences.html).)
+    ///
+    /// If the key is lost (e.g. because the user cleared the secret store), the data encrypted
+    /// with it can no longer be loaded.
+    ///
+    /// Requires the `keyring` feature.
+    ///
+    /// # Failures
+    /// If the secret store is unavailable or refuses access.
+    ///
+    /// # Example
+    /// ```no_run
+    /// extern crate preferences;
+    /// use preferences::{AppInfo, EncryptedPreferences};
+    ///
+    /// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+    ///
+    /// fn main() {
+    ///     let encrypted = EncryptedPreferences::from_keyring(&APP_INFO, "secret-key").unwrap();
+    ///     encrypted.save(&APP_INFO, "session", &"hunter2").unwrap();
+    /// }
+    /// ```
+    #[cfg(feature = "keyring")]
+    pub fn from_keyring(app: &AppInfo, name: &str) -> Result<Self, PreferencesError> {
+        let key = match <[u8; 32]>::load_secret(app, name) {
+            Ok(key) => key,
+            Err(PreferencesError::Keyring(ref e))
+                if matches!(e.downcast_ref(), Some(keyring::Error::NoEntry)) => {
+                let key = Self::generate_key();
+                key.save_secret(app, name)?;
+                key
+            }
+            Err(e) => return Err(e),
+        };
+        Ok(Self::new(&key))
+    }
+
+    /// Encrypts and saves `value` under the given `key`. (See
+    /// [`Preferences::save`](trait.Preferences.html#tymethod.save).)
+    pub fn save<T, S>(&self, app: &AppInfo, key: S, value: &T) -> Result<(), PreferencesError>
+        where T: Serialize,
+              S: AsRef<str>
+    {
+        self.save_in(&PreferencesStore::new(app)?, key.as_ref(), value)
+    }
+
+    /// Loads and decrypts the value previously saved under the given `key`. (See
+    /// [`Preferences::load`](trait.Preferences.html#tymethod.load).)
+    ///
+    /// # Failures
+    /// In addition to the failures of `Preferences::load`, fails with
+    /// `PreferencesError::Decryption` if the secret key is wrong or the file was tampered with.
+    pub fn load<T, S>(&self, app: &AppInfo, key: S) -> Result<T, PreferencesError>
+        where T: DeserializeOwned,
+              S: AsRef<str>
+    {
+        self.load_in(&PreferencesStore::new(app)?, key.as_ref())
+    }
+
+    /// Same as `save`, but saves to `store`.
+    pub(crate) fn save_in<T: Serialize>(&self,
+                                        store: &PreferencesStore,
+                                        key: &str,
+                                        value: &T)
+                                        -> Result<(), PreferencesError> {
+        let mut bytes = Vec::new();
+        self.write_encrypted(value, &associated_data(store.app(), key), &mut bytes)?;
+        let options = store.options().clone().private(true);
+        let path = file_path(store, key)?;
+        store.save_bytes(key, &path, bytes, &options)
+            .map_err(|e| e.with_context(Operation::Save, key, &path))
+    }
+
+    /// Same as `load`, but loads from `store`.
+    pub(crate) fn load_in<T: DeserializeOwned>(&self,
+                                               store: &PreferencesStore,
+                                               key: &str)
+                                               -> Result<T, PreferencesError> {
+        let path = file_path(store, key)?;
+        let bytes = store.load_bytes(&path)
+            .map_err(|e| e.with_context(Operation::Load, key, &path))?;
+        self.read_encrypted(&associated_data(store.app(), key), &mut &bytes[..])
+    }
+
+    /// Same as `save`, but writes the encrypted data to an arbitrary writer.
+    pub fn save_to<T, W>(&self, value: &T, writer: &mut W) -> Result<(), PreferencesError>
+        where T: Serialize,
+              W: Write
+    {
+        self.write_encrypted(value, &[], writer)
+    }
+
+    /// Same as `load`, but reads the encrypted data from an arbitrary reader.
+    pub fn load_from<T, R>(&self, reader: &mut R) -> Result<T, PreferencesError>
+        where T: DeserializeOwned,
+              R: Read
+    {
+        self.read_encrypted(&[], reader)
+    }
+
+    fn write_encrypted<T, W>(&self, value: &T, aad: &[u8], writer: &mut W)
+                             -> Result<(), PreferencesError>
+        where T: Serialize,
+              W: Write
+    {
+        let mut plaintext = Vec::new();
+        Format::Json.serialize(value, &mut plaintext)?;
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let payload = Payload {
+            msg: &plaintext,
+            aad,
+        };
+        let ciphertext = self.cipher
+            .encrypt(&nonce, payload)
+            .map_err(|_| PreferencesError::Encryption)?;
+        writer.write_all(&nonce)?;
+        writer.write_all(&ciphertext)?;
+        Ok(())
+    }
+
+    fn read_encrypted<T, R>(&self, aad: &[u8], reader: &mut R) -> Result<T, PreferencesError>
+        where T: DeserializeOwned,
+              R: Read
+    {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        if data.len() < NONCE_LENGTH {
+            return Err(PreferencesError::Decryption);
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_LENGTH);
+        let payload = Payload {
+            msg: ciphertext,
+            aad,
+        };
+        let plaintext = self.cipher
+            .decrypt(Nonce::from_slice(nonce), payload)
+            .map_err(|_| PreferencesError::Decryption)?;
+        Format::Json.deserialize(&mut &plaintext[..])
+    }
+}
+
+fn extension() -> String {
+    format!("{}.{}", Format::Json.extension(), ENCRYPTED_FILE_EXTENSION)
+}
+
+/// Returns the path of the file in `store` holding the encrypted data with the given `key`.
+fn file_path(store: &PreferencesStore, key: &str) -> Result<PathBuf, PreferencesError> {
+    store.file_path_with_extension(key, &extension())
+}
+
+fn associated_data(app: &AppInfo, key: &str) -> Vec<u8> {
+    format!("{}\n{}", app.name, key).into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{file_path, EncryptedPreferences};
+    use crate::testing::{APP_INFO, TestBackend, TestDir};
+    use crate::PreferencesError;
+    use std::fs;
+
+    #[test]
+    fn test_encrypted_round_trip() {
+        let dir = TestDir::new("encrypted");
+        let store = dir.store();
+        let key = "tests/module/encrypted";
+        let encrypted = EncryptedPreferences::new(&EncryptedPreferences::generate_key());
+        let secret = String::from("correct horse battery staple");
+        encrypted.save_in(&store, key, &secret).unwrap();
+        assert_eq!(encrypted.load_in::<String>(&store, key).unwrap(), secret);
+
+        let path = file_path(&store, key).unwrap();
+        let raw = fs::read(&path).unwrap();
+        assert!(!String::from_utf8_lossy(&raw).contains("horse"));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+
+        let other = EncryptedPreferences::new(&EncryptedPreferences::generate_key());
+        match other.load_in::<String>(&store, key) {
+            Err(PreferencesError::Decryption) => {}
+            other => panic!("expected decryption error, got {:?}", other),
+        }
+        // The data can only be decrypted with the key it was saved with.
+        fs::copy(&path, file_path(&store, "tests/module/encrypted-moved").unwrap()).unwrap();
+        match encrypted.load_in::<String>(&store, "tests/module/encrypted-moved") {
+            Err(PreferencesError::Decryption) => {}
+            other => panic!("expected decryption error, got {:?}", other),
+        }
+        match encrypted.load_in::<String>(&store, "tests/module/encrypted-missing") {
+            Err(PreferencesError::NotFound { ref key, .. }) => {
+                assert_eq!(key, "tests/module/encrypted-missing");
+            }
+            other => panic!("expected NotFound, got {:?}", other),
+        }
+
+        // The file is named like the store's other files.
+        let store = store.with_file_suffix("").unwrap();
+        encrypted.save_in(&store, key, &secret).unwrap();
+        assert_eq!(file_path(&store, key).unwrap(),
+                   store.root().join("tests").join("module").join("encrypted.json.enc"));
+        assert_eq!(encrypted.load_in::<String>(&store, key).unwrap(), secret);
+    }
+
+    #[test]
+    fn test_encrypted_test_backend() {
+        let backend = TestBackend::install();
+        let encrypted = EncryptedPreferences::new(&EncryptedPreferences::generate_key());
+        encrypted.save(&APP_INFO, "tests/module/encrypted", &7u32).unwrap();
+        assert_eq!(encrypted.load::<u32, _>(&APP_INFO, "tests/module/encrypted").unwrap(), 7);
+        assert!(!backend.backend().is_empty());
+    }
+}
@@ -0,0 +1,174 @@
+//! Overrides of user data from environment variables.
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::{self, Map, Value};
+use std::env;
+use crate::{AppInfo, PreferencesError, PreferencesStore};
+
+static DEFAULT_SEPARATOR: &str = "__";
+
+/// Overrides of the fields of user data from environment variables, e.g. for CI, containers or
+/// debugging without editing the preferences files.
+///
+/// Each environment variable whose name is the prefix followed by a field name overrides that
+/// field, e.g. `MYAPP_VOLUME=7` for the prefix `MYAPP_`. Nested fields are separated by `__`
+/// (or the separator set with `separator`), e.g. `MYAPP_GRAPHICS__VSYNC=false`. Field names are
+/// matched case-insensitively.
+///
+/// The value of a variable that overrides a string field is used as is. Otherwise, it's parsed
+/// as JSON if possible (e.g. `false`, `42` or `[1, 2]`), and used as a string if not.
+///
+/// # Example
+/// ```
+/// #[macro_use]
+/// extern crate serde_derive;
+/// extern crate preferences;
+/// use preferences::EnvOverrides;
+///
+/// #[derive(Serialize, Deserialize, Debug)]
+/// struct Graphics {
+///     vsync: bool,
+/// }
+///
+/// #[derive(Serialize, Deserialize, Debug)]
+/// struct Options {
+///     graphics: Graphics,
+/// }
+///
+/// fn main() {
+///     std::env::set_var("DOCS_ENV_GRAPHICS__VSYNC", "false");
+///     let options = Options { graphics: Graphics { vsync: true } };
+///     let options = EnvOverrides::new("DOCS_ENV_").apply(&options).unwrap();
+///     assert!(!options.graphics.vsync);
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EnvOverrides {
+    prefix: String,
+    separator: String,
+}
+
+impl EnvOverrides {
+    /// Creates overrides from the environment variables whose names start with `prefix`.
+    pub fn new<S: AsRef<str>>(prefix: S) -> Self {
+        EnvOverrides {
+            prefix: prefix.as_ref().to_owned(),
+            separator: DEFAULT_SEPARATOR.to_owned(),
+        }
+    }
+
+    /// Sets the separator between the names of nested fields. The default is `__`.
+    pub fn separator<S: AsRef<str>>(mut self, separator: S) -> Self {
+        self.separator = separator.as_ref().to_owned();
+        self
+    }
+
+    /// Returns the prefix of the environment variables.
+    pub fn get_prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// Returns the separator between the names of nested fields.
+    pub fn get_separator(&self) -> &str {
+        &self.separator
+    }
+
+    /// Returns a copy of `value` with the overrides from the environment applied.
+    ///
+    /// # Failures
+    /// If `value` cannot be serialized, or if the overridden value cannot be deserialized as `T`
+    /// (e.g. because a variable has a value of the wrong type).
+    pub fn apply<T>(&self, value: &T) -> Result<T, PreferencesError>
+        where T: Serialize + DeserializeOwned
+    {
+        let mut value = serde_json::to_value(value)?;
+        self.apply_to_value(&mut value);
+        serde_json::from_value(value).map_err(Into::into)
+    }
+
+    /// Loads the user data of `app` with the given `key`, and applies the overrides from the
+    /// environment.
+    ///
+    /// # Failures
+    /// Same as [`Preferences::load`](trait.Preferences.html#tymethod.load), or if the overridden
+    /// value cannot be deserialized as `T`.
+    pub fn load<T, S>(&self, app: &AppInfo, key: S) -> Result<T, PreferencesError>
+        where T: DeserializeOwned,
+              S: AsRef<str>
+    {
+        let mut value = PreferencesStore::new(app)?.load(key)?;
+        self.apply_to_value(&mut value);
+        serde_json::from_value(value).map_err(Into::into)
+    }
+
+    pub(crate) fn apply_to_value(&self, value: &mut Value) {
+        self.apply_vars(value, env::vars());
+    }
+
+    fn apply_vars<I>(&self, value: &mut Value, vars: I)
+        where I: IntoIterator<Item = (String, String)>
+    {
+        for (name, text) in vars {
+            match name.strip_prefix(&*self.prefix) {
+                Some(path) if !path.is_empty() => {
+                    let segments: Vec<&str> = path.split(&*self.separator).collect();
+                    set_field(value, &segments, text);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Sets the field at the path `segments` in `value` to `text`, creating maps along the way.
+pub(crate) fn set_field(value: &mut Value, segments: &[&str], text: String) {
+    let (name, rest) = match segments.split_first() {
+        Some(split) => split,
+        None => {
+            *value = parse_field(value, text);
+            return;
+        }
+    };
+    if !value.is_object() {
+        *value = Value::Object(Map::new());
+    }
+    if let Value::Object(ref mut map) = *value {
+        let key = map.keys()
+            .find(|key| key.eq_ignore_ascii_case(name))
+            .cloned()
+            .unwrap_or_else(|| name.to_lowercase());
+        set_field(map.entry(key).or_insert(Value::Null), rest, text);
+    }
+}
+
+/// Converts `text` to the value that overrides `current`.
+fn parse_field(current: &Value, text: String) -> Value {
+    if current.is_string() {
+        return Value::String(text);
+    }
+    serde_json::from_str(&text).unwrap_or(Value::String(text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EnvOverrides;
+    use serde_json::{self, Value};
+
+    #[test]
+    fn test_env_overrides() {
+        let mut value: Value =
+            serde_json::from_str(r#"{"volume": 5, "name": "x", "graphics": {"vsync": true}}"#)
+                .unwrap();
+        let vars = vec![("APP_VOLUME".to_owned(), "7".to_owned()),
+                        ("APP_NAME".to_owned(), "123".to_owned()),
+                        ("APP_GRAPHICS.VSYNC".to_owned(), "false".to_owned()),
+                        ("APP_NEW.FIELD".to_owned(), "text".to_owned()),
+                        ("OTHER_VOLUME".to_owned(), "1".to_owned())];
+        EnvOverrides::new("APP_").separator(".").apply_vars(&mut value, vars);
+        let expected: Value = serde_json::from_str(r#"{"volume": 7, "name": "123",
+            "graphics": {"vsync": false}, "new": {"field": "text"}}"#)
+            .unwrap();
+        assert_eq!(value, expected);
+    }
+}
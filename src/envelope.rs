@@ -0,0 +1,351 @@
+//! Envelopes that keep metadata managed by the crate together with the saved data.
+
+use serde::de::{self, DeserializeOwned, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::ser::{SerializeStruct, Serializer};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::marker::PhantomData;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::expiring::millis;
+use crate::header::now;
+use crate::{Format, PreferencesError};
+
+/// Saved data together with metadata about it, in which data is saved if
+/// [`SaveOptions::envelope`](struct.SaveOptions.html#method.envelope) is enabled. Loading
+/// unwraps it transparently; [`load_with_meta`](struct.PreferencesStore.html#method.load_with_meta)
+/// returns it whole.
+///
+/// In the file, the envelope is a struct with the fields `meta` and `data`, e.g. in JSON:
+///
+/// ```json
+/// {"meta":{"version":2,"created":1700000000000,"modified":1700000360000,"app_version":"1.4"},
+///  "data":{"volume":3}}
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Envelope<T> {
+    /// The metadata of the data.
+    pub meta: EnvelopeMeta,
+    /// The data itself.
+    pub data: T,
+}
+
+/// The metadata in an [`Envelope`](struct.Envelope.html). Times are stored in milliseconds
+/// since the Unix epoch.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EnvelopeMeta {
+    /// The version of the layout of the data, set with
+    /// [`SaveOptions::schema_version`](struct.SaveOptions.html#method.schema_version).
+    pub version: Option<u32>,
+    /// When the data was first saved in an envelope. For data that was saved without one before,
+    /// this is when that data was last modified.
+    pub created: SystemTime,
+    /// When the data was last saved.
+    pub modified: SystemTime,
+    /// The version of the app that last saved the data, if its store was created from an
+    /// [`AppId`](struct.AppId.html) with a version.
+    pub app_version: Option<String>,
+}
+
+impl EnvelopeMeta {
+    /// Describes data saved now, which was first saved at `created` (or now, if `None`).
+    pub(crate) fn new(version: Option<u32>,
+                      created: Option<SystemTime>,
+                      app_version: Option<String>)
+                      -> Self {
+        let modified = now();
+        EnvelopeMeta {
+            version,
+            created: created.unwrap_or(modified),
+            modified,
+            app_version,
+        }
+    }
+}
+
+/// Returns whether data in `format` can be saved in an envelope. Bincode data isn't
+/// self-describing, so an envelope couldn't be told apart from the data.
+pub(crate) fn supports(format: Format) -> bool {
+    match format {
+        #[cfg(feature = "bincode")]
+        Format::Bincode => false,
+        _ => true,
+    }
+}
+
+/// Deserializes the data in `bytes`, unwrapping it if it's saved in an envelope.
+pub(crate) fn deserialize<T>(format: Format, bytes: &[u8]) -> Result<T, PreferencesError>
+    where T: DeserializeOwned
+{
+    open(format, bytes).map(|(data, _)| data)
+}
+
+/// Deserializes the data in `bytes`, whether it's saved in an envelope or as it is, and returns
+/// it with the metadata of its envelope, if any.
+pub(crate) fn open<T>(format: Format,
+                      bytes: &[u8])
+                      -> Result<(T, Option<EnvelopeMeta>), PreferencesError>
+    where T: DeserializeOwned
+{
+    if !supports(format) {
+        return Ok((format.deserialize_slice(bytes)?, None));
+    }
+    // The envelope is tried first, since e.g. a `serde_json::Value` would accept it as the data.
+    let error = match format.deserialize_slice::<Envelope<T>>(bytes) {
+        Ok(envelope) => return Ok((envelope.data, Some(envelope.meta))),
+        Err(e) => e,
+    };
+    match format.deserialize_slice(bytes) {
+        Ok(data) => Ok((data, None)),
+        // Report why the data of an envelope doesn't fit, rather than that the envelope doesn't.
+        Err(_) if format.deserialize_slice::<MetaOf>(bytes).is_ok() => Err(error),
+        Err(raw_error) => Err(raw_error),
+    }
+}
+
+/// Returns when the data in `bytes` was first saved, if it's saved in an envelope.
+pub(crate) fn created(format: Format, bytes: &[u8]) -> Option<SystemTime> {
+    if !supports(format) {
+        return None;
+    }
+    format.deserialize_slice::<MetaOf>(bytes).ok().map(|meta| meta.0.created)
+}
+
+const FIELDS: &[&str] = &["meta", "data"];
+const META_FIELDS: &[&str] = &["version", "created", "modified", "app_version"];
+
+fn from_millis(millis: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(millis)
+}
+
+impl<T: Serialize> Serialize for Envelope<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Envelope", FIELDS.len())?;
+        state.serialize_field("meta", &self.meta)?;
+        state.serialize_field("data", &self.data)?;
+        state.end()
+    }
+}
+
+impl Serialize for EnvelopeMeta {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("EnvelopeMeta", META_FIELDS.len())?;
+        match self.version {
+            Some(ref version) => state.serialize_field("version", version)?,
+            None => state.skip_field("version")?,
+        }
+        state.serialize_field("created", &millis(self.created))?;
+        state.serialize_field("modified", &millis(self.modified))?;
+        match self.app_version {
+            Some(ref version) => state.serialize_field("app_version", version)?,
+            None => state.skip_field("app_version")?,
+        }
+        state.end()
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Envelope<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_struct("Envelope", FIELDS, EnvelopeVisitor(PhantomData))
+    }
+}
+
+struct EnvelopeVisitor<T>(PhantomData<T>);
+
+impl<'de, T: Deserialize<'de>> Visitor<'de> for EnvelopeVisitor<T> {
+    type Value = Envelope<T>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("data with its metadata")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Envelope<T>, A::Error> {
+        let meta = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let data = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
+        Ok(Envelope { meta, data })
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Envelope<T>, A::Error> {
+        let (mut meta, mut data) = (None, None);
+        while let Some(field) = map.next_key::<FieldName>()? {
+            match &field.0[..] {
+                "meta" => meta = Some(map.next_value()?),
+                "data" => data = Some(map.next_value()?),
+                // Data that isn't in an envelope is rejected as early as possible.
+                _ => return Err(de::Error::unknown_field(&field.0, FIELDS)),
+            }
+        }
+        Ok(Envelope {
+            meta: meta.ok_or_else(|| de::Error::missing_field("meta"))?,
+            data: data.ok_or_else(|| de::Error::missing_field("data"))?,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for EnvelopeMeta {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_struct("EnvelopeMeta", META_FIELDS, MetaVisitor)
+    }
+}
+
+struct MetaVisitor;
+
+impl<'de> Visitor<'de> for MetaVisitor {
+    type Value = EnvelopeMeta;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("the metadata of saved data")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<EnvelopeMeta, A::Error> {
+        let version = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let created = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
+        let modified = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(2, &self))?;
+        let app_version = seq.next_element()?.unwrap_or(None);
+        Ok(EnvelopeMeta {
+            version,
+            created: from_millis(created),
+            modified: from_millis(modified),
+            app_version,
+        })
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<EnvelopeMeta, A::Error> {
+        let (mut version, mut created, mut modified, mut app_version) = (None, None, None, None);
+        while let Some(field) = map.next_key::<FieldName>()? {
+            match &field.0[..] {
+                "version" => version = Some(map.next_value()?),
+                "created" => created = Some(map.next_value()?),
+                "modified" => modified = Some(map.next_value()?),
+                "app_version" => app_version = Some(map.next_value()?),
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(EnvelopeMeta {
+            version,
+            created: from_millis(created.ok_or_else(|| de::Error::missing_field("created"))?),
+            modified: from_millis(modified.ok_or_else(|| de::Error::missing_field("modified"))?),
+            app_version,
+        })
+    }
+}
+
+/// The name of a field, deserialized as an identifier, which formats like RON distinguish from
+/// strings.
+struct FieldName(String);
+
+impl<'de> Deserialize<'de> for FieldName {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_identifier(FieldNameVisitor)
+    }
+}
+
+struct FieldNameVisitor;
+
+impl<'de> Visitor<'de> for FieldNameVisitor {
+    type Value = FieldName;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a field name")
+    }
+
+    fn visit_str<E: de::Error>(self, name: &str) -> Result<FieldName, E> {
+        Ok(FieldName(name.to_owned()))
+    }
+}
+
+/// The metadata of an envelope, deserialized without its data.
+struct MetaOf(EnvelopeMeta);
+
+impl<'de> Deserialize<'de> for MetaOf {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_struct("Envelope", FIELDS, MetaOfVisitor)
+    }
+}
+
+struct MetaOfVisitor;
+
+impl<'de> Visitor<'de> for MetaOfVisitor {
+    type Value = MetaOf;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("data with its metadata")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<MetaOf, A::Error> {
+        let meta = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        Ok(MetaOf(meta))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<MetaOf, A::Error> {
+        let mut meta = None;
+        while let Some(field) = map.next_key::<FieldName>()? {
+            match &field.0[..] {
+                "meta" => meta = Some(map.next_value()?),
+                "data" => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+                _ => return Err(de::Error::unknown_field(&field.0, FIELDS)),
+            }
+        }
+        meta.map(MetaOf).ok_or_else(|| de::Error::missing_field("meta"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{APP_INFO, TestDir};
+    use crate::{AppId, Format, PreferencesMap, SaveOptions};
+    use std::time::Duration;
+
+    #[test]
+    fn test_envelope() {
+        let dir = TestDir::new("envelope");
+        let mut value = PreferencesMap::new();
+        value.insert("volume".to_owned(), 3u32);
+        for store in dir.stores_for(&AppId::new(APP_INFO).version("1.4")) {
+            for &format in Format::all() {
+                if !super::supports(format) {
+                    continue;
+                }
+                let options = SaveOptions::new().format(format).envelope(true).schema_version(2);
+                let store = store.clone().with_options(options);
+                store.save("audio", &value).unwrap();
+                let first = store.load_with_meta::<PreferencesMap<u32>, _>("audio").unwrap();
+                assert_eq!(first.data, value);
+                assert_eq!(first.meta.version, Some(2));
+                assert_eq!(first.meta.app_version.as_deref(), Some("1.4"));
+                assert_eq!(first.meta.created, first.meta.modified);
+
+                store.save("audio", &value).unwrap();
+                let second = store.load_with_meta::<PreferencesMap<u32>, _>("audio").unwrap();
+                assert_eq!(second.meta.created, first.meta.created);
+                assert!(second.meta.modified >= first.meta.modified);
+                assert_eq!(store.load::<PreferencesMap<u32>, _>("audio").unwrap(), value);
+                assert!(store.load::<u32, _>("audio").is_err());
+                let json = store.load::<serde_json::Value, _>("audio").unwrap();
+                assert!(json.get("volume").is_some() && json.get("meta").is_none());
+
+                // Data saved as it is, e.g. by an earlier version of the app, gets an envelope
+                // on its next save.
+                let raw = store.clone().with_options(SaveOptions::new().format(format));
+                raw.save("audio", &value).unwrap();
+                assert_eq!(store.load::<PreferencesMap<u32>, _>("audio").unwrap(), value);
+                let bare = store.load_with_meta::<PreferencesMap<u32>, _>("audio").unwrap();
+                assert_eq!(bare.data, value);
+                assert_eq!((bare.meta.version, bare.meta.app_version), (None, None));
+                store.save("audio", &value).unwrap();
+                let upgraded = store.load_with_meta::<PreferencesMap<u32>, _>("audio").unwrap();
+                assert_eq!(upgraded.meta.version, Some(2));
+                let since = bare.meta.created.duration_since(upgraded.meta.created).unwrap();
+                assert!(since < Duration::from_millis(1));
+                assert_eq!(raw.load::<PreferencesMap<u32>, _>("audio").unwrap(), value);
+                raw.save("audio", &value).unwrap();
+                assert_eq!(store.load_with_meta::<PreferencesMap<u32>, _>("audio").unwrap().data,
+                           value);
+                store.delete("audio").unwrap();
+            }
+        }
+    }
+}
@@ -0,0 +1,230 @@
+//! User data that's only valid for a limited time, e.g. cached tokens or API responses.
+
+use serde::de::{self, DeserializeOwned, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{SerializeStruct, Serializer};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::{AppInfo, PreferencesError, PreferencesStore};
+
+/// A value together with the time it was saved and the time it expires, as saved by
+/// [`save_with_ttl`](fn.save_with_ttl.html).
+///
+/// The times are stored as milliseconds since the Unix epoch, next to the value, so the data
+/// can be loaded as `Expiring<T>` to look at them, or with
+/// [`load_unexpired`](fn.load_unexpired.html), which treats expired data as absent. The wrapper
+/// dereferences to the value.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Expiring<T> {
+    value: T,
+    saved_at: u64,
+    expires_at: u64,
+}
+
+impl<T> Expiring<T> {
+    /// Wraps `value`, saved now, to expire once `ttl` has passed.
+    pub fn new(value: T, ttl: Duration) -> Self {
+        let saved_at = millis(SystemTime::now());
+        let ttl = u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX);
+        Expiring {
+            value,
+            saved_at,
+            expires_at: saved_at.saturating_add(ttl),
+        }
+    }
+
+    /// Returns the time the value was saved.
+    pub fn saved_at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(self.saved_at)
+    }
+
+    /// Returns the time the value expires.
+    pub fn expires_at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(self.expires_at)
+    }
+
+    /// Returns whether the value has expired, i.e. its time to live has passed.
+    pub fn is_expired(&self) -> bool {
+        millis(SystemTime::now()) >= self.expires_at
+    }
+
+    /// Returns the value, whether it has expired or not.
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T> Deref for Expiring<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+/// Returns `time` in milliseconds since the Unix epoch, or 0 for times before it.
+pub(crate) fn millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| u64::try_from(since.as_millis()).unwrap_or(u64::MAX))
+}
+
+const FIELDS: &[&str] = &["saved_at", "expires_at", "value"];
+
+impl<T: Serialize> Serialize for Expiring<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Expiring", FIELDS.len())?;
+        state.serialize_field("saved_at", &self.saved_at)?;
+        state.serialize_field("expires_at", &self.expires_at)?;
+        state.serialize_field("value", &self.value)?;
+        state.end()
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Expiring<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_struct("Expiring", FIELDS, ExpiringVisitor(PhantomData))
+    }
+}
+
+struct ExpiringVisitor<T>(PhantomData<T>);
+
+impl<'de, T: Deserialize<'de>> Visitor<'de> for ExpiringVisitor<T> {
+    type Value = Expiring<T>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a value with its expiry time")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Expiring<T>, A::Error> {
+        let saved_at = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let expires_at = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
+        let value = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(2, &self))?;
+        Ok(Expiring {
+            value,
+            saved_at,
+            expires_at,
+        })
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Expiring<T>, A::Error> {
+        let (mut saved_at, mut expires_at, mut value) = (None, None, None);
+        while let Some(field) = map.next_key::<String>()? {
+            match &field[..] {
+                "saved_at" => saved_at = Some(map.next_value()?),
+                "expires_at" => expires_at = Some(map.next_value()?),
+                "value" => value = Some(map.next_value()?),
+                _ => {
+                    map.next_value::<de::IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(Expiring {
+            value: value.ok_or_else(|| de::Error::missing_field("value"))?,
+            saved_at: saved_at.ok_or_else(|| de::Error::missing_field("saved_at"))?,
+            expires_at: expires_at.ok_or_else(|| de::Error::missing_field("expires_at"))?,
+        })
+    }
+}
+
+/// Saves `value` as the user data of `app` with the given `key`, to be treated as absent by
+/// [`load_unexpired`](fn.load_unexpired.html) once `ttl` has passed.
+///
+/// The value is saved as an [`Expiring<T>`](struct.Expiring.html). Expired data isn't deleted
+/// automatically; it's replaced by the next save, or can be deleted like any other data.
+///
+/// # Failures
+/// Same as [`Preferences::save`](trait.Preferences.html#tymethod.save).
+///
+/// # Example
+/// ```
+/// use preferences::{AppInfo, load_unexpired, save_with_ttl};
+/// use std::time::Duration;
+///
+/// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+///
+/// # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
+/// let key = "tests/docs/expiring";
+/// save_with_ttl(&APP_INFO, key, &"token".to_owned(), Duration::from_secs(3600)).unwrap();
+/// assert_eq!(load_unexpired::<String, _>(&APP_INFO, key).unwrap(), "token");
+///
+/// save_with_ttl(&APP_INFO, key, &"token".to_owned(), Duration::from_secs(0)).unwrap();
+/// assert!(load_unexpired::<String, _>(&APP_INFO, key).unwrap_err().is_not_found());
+/// ```
+pub fn save_with_ttl<T, S>(app: &AppInfo,
+                           key: S,
+                           value: &T,
+                           ttl: Duration)
+                           -> Result<(), PreferencesError>
+    where T: Serialize,
+          S: AsRef<str>
+{
+    PreferencesStore::new(app)?.save_with_ttl(key, value, ttl)
+}
+
+/// Loads the user data of `app` saved with [`save_with_ttl`](fn.save_with_ttl.html), unless it
+/// has expired.
+///
+/// # Failures
+/// `PreferencesError::NotFound` if the data has expired, or the same as
+/// [`Preferences::load`](trait.Preferences.html#tymethod.load).
+pub fn load_unexpired<T, S>(app: &AppInfo, key: S) -> Result<T, PreferencesError>
+    where T: DeserializeOwned,
+          S: AsRef<str>
+{
+    PreferencesStore::new(app)?.load_unexpired(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Expiring;
+    use crate::{AppInfo, MemoryBackend, PreferencesStore};
+    use std::time::Duration;
+
+    const APP_INFO: AppInfo = AppInfo {
+        name: "preferences",
+        author: "Rust language community",
+    };
+
+    #[test]
+    fn test_expiring() {
+        let store = PreferencesStore::new(&APP_INFO)
+            .unwrap()
+            .with_memory_backend(MemoryBackend::new());
+        store.save_with_ttl("token", &"abc", Duration::from_secs(60)).unwrap();
+        assert_eq!(store.load_unexpired::<String, _>("token").unwrap(), "abc");
+        let saved = store.load::<Expiring<String>, _>("token").unwrap();
+        assert!(!saved.is_expired());
+        assert_eq!(saved.expires_at().duration_since(saved.saved_at()).unwrap(),
+                   Duration::from_secs(60));
+
+        store.save_with_ttl("token", &"abc", Duration::from_secs(0)).unwrap();
+        assert!(store.load_unexpired::<String, _>("token").unwrap_err().is_not_found());
+        assert!(store.load::<Expiring<String>, _>("token").unwrap().is_expired());
+    }
+
+    #[test]
+    fn test_expiring_json() {
+        let expiring = Expiring::new(vec![1u8, 2], Duration::from_millis(1500));
+        let json = serde_json::to_value(&expiring).unwrap();
+        assert_eq!(json["value"], serde_json::json!([1, 2]));
+        assert_eq!(json["expires_at"].as_u64().unwrap() - json["saved_at"].as_u64().unwrap(),
+                   1500);
+        assert_eq!(serde_json::from_value::<Expiring<Vec<u8>>>(json).unwrap(), expiring);
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn test_expiring_bincode() {
+        use crate::{Format, SaveOptions};
+
+        let store = PreferencesStore::new(&APP_INFO)
+            .unwrap()
+            .with_memory_backend(MemoryBackend::new())
+            .with_options(SaveOptions::new().format(Format::Bincode));
+        store.save_with_ttl("count", &7u32, Duration::from_secs(60)).unwrap();
+        assert_eq!(store.load_unexpired::<u32, _>("count").unwrap(), 7);
+    }
+}
@@ -0,0 +1,107 @@
+//! Tracking of saves that aren't complete or durable yet, for flushing them at shutdown.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, Weak};
+use crate::compression;
+use crate::{Durability, PreferencesError};
+
+/// Something that holds values for a store that it hasn't saved yet, e.g. an
+/// [`AutoSaver`](struct.AutoSaver.html).
+pub(crate) trait Flush: Send + Sync {
+    /// Saves all pending values, and waits until they're saved.
+    fn flush(&self) -> Result<(), PreferencesError>;
+}
+
+/// The saves of a store and its clones that aren't complete or durable yet.
+#[derive(Default)]
+pub(crate) struct PendingWrites {
+    /// The savers with values for the store, which are skipped once dropped.
+    savers: Mutex<Vec<Weak<dyn Flush>>>,
+    /// The files that were saved, with the durability they were saved with, until they're
+    /// synced by `sync_all`.
+    unsynced: Mutex<BTreeMap<PathBuf, Durability>>,
+}
+
+impl PendingWrites {
+    /// Registers `saver` to be flushed by `flush`.
+    pub(crate) fn register(&self, saver: Weak<dyn Flush>) {
+        let mut savers = lock(&self.savers);
+        savers.retain(|saver| saver.strong_count() > 0);
+        savers.push(saver);
+    }
+
+    /// Records that the file at `path` was saved with `durability`.
+    pub(crate) fn saved(&self, path: &Path, durability: Durability) {
+        if durability != Durability::FsyncDir {
+            lock(&self.unsynced).insert(path.to_owned(), durability);
+        }
+    }
+
+    /// Flushes all registered savers that still exist.
+    ///
+    /// # Failures
+    /// The first error of a saver; the others are flushed regardless.
+    pub(crate) fn flush(&self) -> Result<(), PreferencesError> {
+        let savers: Vec<_> = lock(&self.savers).iter().filter_map(Weak::upgrade).collect();
+        let mut result = Ok(());
+        for saver in savers {
+            if let Err(e) = saver.flush() {
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+        result
+    }
+
+    /// Writes the files saved since the last call, and their directories, to storage.
+    ///
+    /// # Failures
+    /// If a file or directory can't be synced. The files that weren't synced are kept for the
+    /// next call.
+    pub(crate) fn sync_all(&self) -> Result<(), PreferencesError> {
+        let mut unsynced = lock(&self.unsynced);
+        while let Some((path, durability)) = unsynced.pop_first() {
+            if let Err(e) = sync_file(&path, durability) {
+                unsynced.insert(path, durability);
+                return Err(e.into());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for PendingWrites {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PendingWrites")
+            .field("savers", &lock(&self.savers).len())
+            .field("unsynced", &*lock(&self.unsynced))
+            .finish()
+    }
+}
+
+/// Writes the preferences file at `path`, which was saved with `durability`, and its directory
+/// to storage, as far as the save didn't. A file that was deleted since is skipped.
+fn sync_file(path: &Path, durability: Durability) -> Result<(), std::io::Error> {
+    let (path, _) = compression::find(path);
+    if durability == Durability::None || durability == Durability::Flush {
+        match File::open(&path) {
+            Ok(file) => file.sync_all()?,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        }
+    }
+    match path.parent() {
+        Some(dir) => Durability::FsyncDir.sync_dir(dir),
+        None => Ok(()),
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // The lists are never left inconsistent by a panic.
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
@@ -0,0 +1,340 @@
+//! Serialization formats for preferences files.
+
+use serde::Serialize;
+#[cfg(feature = "mmap")]
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+#[cfg(all(feature = "mmap", any(feature = "plist", feature = "xml")))]
+use std::io::{self, ErrorKind};
+#[cfg(feature = "xml")]
+use std::io::BufReader;
+use std::io::{Read, Write};
+#[cfg(feature = "ini")]
+use crate::ini;
+use crate::PreferencesError;
+
+/// On-disk serialization format for user data.
+///
+/// JSON is always available and is the default. Other formats are enabled with the cargo feature
+/// of the same name (e.g. `toml`).
+///
+/// Since the variants depend on the features, matches on a format need a wildcard arm.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Format {
+    /// JSON, via `serde_json`. Files use the extension `.prefs.json`.
+    #[default]
+    Json,
+    /// TOML, via the `toml` crate. Files use the extension `.prefs.toml`.
+    ///
+    /// Note that TOML documents must be tables, so only structs and maps can be stored in this
+    /// format.
+    #[cfg(feature = "toml")]
+    Toml,
+    /// XML property list, via the `plist` crate. Files use the extension `.prefs.plist`.
+    #[cfg(feature = "plist")]
+    Plist,
+    /// Rusty Object Notation, via the `ron` crate. Files use the extension `.prefs.ron`.
+    ///
+    /// Unlike JSON, RON distinguishes structs, tuples and enum variants, so types like tuple
+    /// structs and unit variants are stored the way they are written in Rust.
+    #[cfg(feature = "ron")]
+    Ron,
+    /// Binary encoding, via the `bincode` crate. Files use the extension `.prefs.bin`.
+    ///
+    /// This is much faster and more compact than the text formats, but the files aren't
+    /// human-readable, and the data can't be loaded as a different type than it was saved as.
+    #[cfg(feature = "bincode")]
+    Bincode,
+    /// YAML, via the `serde_yaml` crate (enabled with the `yaml` feature). Files use the
+    /// extension `.prefs.yaml`.
+    #[cfg(feature = "yaml")]
+    Yaml,
+    /// INI, enabled with the `ini` feature. Files use the extension `.prefs.ini`.
+    ///
+    /// Only maps and structs can be stored in this format, with values that are strings, numbers
+    /// or flags, or maps and structs of those, which become sections. Since INI values have no
+    /// types, a value saved as a number can be loaded as a string and vice versa, which makes it
+    /// suited to a `PreferencesMap<String>` that other tools read or users edit by hand.
+    #[cfg(feature = "ini")]
+    Ini,
+    /// XML, via the `quick-xml` crate (enabled with the `xml` feature). Files use the extension
+    /// `.prefs.xml`, with the data in a `<preferences>` element, e.g. for tools that consume XML
+    /// configuration like those of group policies.
+    ///
+    /// Only structs and maps can be stored in this format, and map keys must be valid XML element
+    /// names.
+    #[cfg(feature = "xml")]
+    Xml,
+}
+
+/// Name of the root element of XML preferences files.
+#[cfg(feature = "xml")]
+const XML_ROOT: &str = "preferences";
+
+
+impl Format {
+    /// File extension used for this format, without the leading period.
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            Format::Json => "json",
+            #[cfg(feature = "toml")]
+            Format::Toml => "toml",
+            #[cfg(feature = "plist")]
+            Format::Plist => "plist",
+            #[cfg(feature = "ron")]
+            Format::Ron => "ron",
+            #[cfg(feature = "bincode")]
+            Format::Bincode => "bin",
+            #[cfg(feature = "yaml")]
+            Format::Yaml => "yaml",
+            #[cfg(feature = "ini")]
+            Format::Ini => "ini",
+            #[cfg(feature = "xml")]
+            Format::Xml => "xml",
+        }
+    }
+
+    /// Returns all formats enabled in this build, starting with the default.
+    pub fn all() -> &'static [Format] {
+        &[Format::Json,
+          #[cfg(feature = "toml")]
+          Format::Toml,
+          #[cfg(feature = "plist")]
+          Format::Plist,
+          #[cfg(feature = "ron")]
+          Format::Ron,
+          #[cfg(feature = "bincode")]
+          Format::Bincode,
+          #[cfg(feature = "yaml")]
+          Format::Yaml,
+          #[cfg(feature = "ini")]
+          Format::Ini,
+          #[cfg(feature = "xml")]
+          Format::Xml]
+    }
+
+    /// Returns the enabled format that uses the given file `extension` (without the leading
+    /// period), if any.
+    pub fn from_extension(extension: &str) -> Option<Format> {
+        Format::all().iter().cloned().find(|format| format.extension() == extension)
+    }
+
+    /// Serializes `value` in this format to `writer`.
+    pub fn serialize<T, W>(&self, value: &T, writer: &mut W) -> Result<(), PreferencesError>
+        where T: Serialize,
+              W: Write
+    {
+        match *self {
+            Format::Json => serde_json::to_writer(writer, value).map_err(Into::into),
+            #[cfg(feature = "toml")]
+            Format::Toml => {
+                let text = toml::to_string(value)?;
+                writer.write_all(text.as_bytes()).map_err(Into::into)
+            }
+            #[cfg(feature = "plist")]
+            Format::Plist => plist::to_writer_xml(writer, value).map_err(Into::into),
+            #[cfg(feature = "ron")]
+            Format::Ron => ron::ser::to_writer(writer, value).map_err(Into::into),
+            #[cfg(feature = "bincode")]
+            Format::Bincode => bincode::serialize_into(writer, value).map_err(Into::into),
+            #[cfg(feature = "yaml")]
+            Format::Yaml => serde_yaml::to_writer(writer, value).map_err(Into::into),
+            #[cfg(feature = "ini")]
+            Format::Ini => ini::to_writer(writer, value),
+            #[cfg(feature = "xml")]
+            Format::Xml => {
+                let text = quick_xml::se::to_string_with_root(XML_ROOT, value)?;
+                writer.write_all(text.as_bytes()).map_err(Into::into)
+            }
+        }
+    }
+
+    /// Same as `serialize`, but uses a human-friendly layout where the format supports one.
+    ///
+    /// For JSON, the output is indented and map keys and struct fields are written in sorted
+    /// order, so that saving the same data always produces the same file.
+    pub fn serialize_pretty<T, W>(&self, value: &T, writer: &mut W) -> Result<(), PreferencesError>
+        where T: Serialize,
+              W: Write
+    {
+        match *self {
+            Format::Json => {
+                let value = serde_json::to_value(value)?;
+                serde_json::to_writer_pretty(writer, &value).map_err(Into::into)
+            }
+            #[cfg(feature = "toml")]
+            Format::Toml => {
+                let text = toml::to_string_pretty(value)?;
+                writer.write_all(text.as_bytes()).map_err(Into::into)
+            }
+            #[cfg(feature = "plist")]
+            Format::Plist => plist::to_writer_xml(writer, value).map_err(Into::into),
+            #[cfg(feature = "ron")]
+            Format::Ron => {
+                ron::ser::to_writer_pretty(writer, value, Default::default()).map_err(Into::into)
+            }
+            #[cfg(feature = "bincode")]
+            Format::Bincode => bincode::serialize_into(writer, value).map_err(Into::into),
+            #[cfg(feature = "yaml")]
+            Format::Yaml => serde_yaml::to_writer(writer, value).map_err(Into::into),
+            #[cfg(feature = "ini")]
+            Format::Ini => ini::to_writer(writer, value),
+            #[cfg(feature = "xml")]
+            Format::Xml => {
+                let mut text = String::new();
+                let mut serializer = quick_xml::se::Serializer::with_root(&mut text,
+                                                                          Some(XML_ROOT))?;
+                serializer.indent(' ', 2);
+                value.serialize(serializer)?;
+                writer.write_all(text.as_bytes()).map_err(Into::into)
+            }
+        }
+    }
+
+    /// Deserializes a value in this format from `reader`.
+    pub fn deserialize<T, R>(&self, reader: &mut R) -> Result<T, PreferencesError>
+        where T: DeserializeOwned,
+              R: Read
+    {
+        match *self {
+            Format::Json => serde_json::from_reader(reader).map_err(Into::into),
+            #[cfg(feature = "toml")]
+            Format::Toml => {
+                let mut bytes = Vec::new();
+                reader.read_to_end(&mut bytes)?;
+                let text = String::from_utf8(bytes)?;
+                toml::from_str(&text).map_err(Into::into)
+            }
+            #[cfg(feature = "plist")]
+            Format::Plist => plist::from_reader_xml(reader).map_err(Into::into),
+            #[cfg(feature = "ron")]
+            Format::Ron => ron::de::from_reader(reader).map_err(Into::into),
+            #[cfg(feature = "bincode")]
+            Format::Bincode => bincode::deserialize_from(reader).map_err(Into::into),
+            #[cfg(feature = "yaml")]
+            Format::Yaml => serde_yaml::from_reader(reader).map_err(Into::into),
+            #[cfg(feature = "ini")]
+            Format::Ini => ini::from_reader(reader),
+            #[cfg(feature = "xml")]
+            Format::Xml => quick_xml::de::from_reader(BufReader::new(reader)).map_err(Into::into),
+        }
+    }
+
+    /// Same as `deserialize`, but errors in JSON text are reported as `PreferencesError::Parse`,
+    /// with an excerpt of `bytes` where they occurred.
+    pub(crate) fn deserialize_slice<T>(&self, bytes: &[u8]) -> Result<T, PreferencesError>
+        where T: DeserializeOwned
+    {
+        if *self == Format::Json {
+            serde_json::from_slice(bytes).map_err(|e| parse_error(bytes, e))
+        } else {
+            self.deserialize(&mut &*bytes)
+        }
+    }
+
+    /// Same as `deserialize_slice`, but the value can borrow from `bytes`.
+    ///
+    /// # Failures
+    /// An I/O error of kind `Unsupported` for property lists, which can only be deserialized
+    /// into owned values.
+    #[cfg(feature = "mmap")]
+    pub(crate) fn deserialize_borrowed<'a, T>(&self, bytes: &'a [u8]) -> Result<T, PreferencesError>
+        where T: Deserialize<'a>
+    {
+        match *self {
+            Format::Json => serde_json::from_slice(bytes).map_err(|e| parse_error(bytes, e)),
+            #[cfg(feature = "toml")]
+            Format::Toml => toml::from_slice(bytes).map_err(Into::into),
+            #[cfg(feature = "plist")]
+            Format::Plist => {
+                Err(io::Error::new(ErrorKind::Unsupported,
+                                   "property lists can't be deserialized into borrowed data")
+                    .into())
+            }
+            #[cfg(feature = "ron")]
+            Format::Ron => ron::de::from_bytes(bytes).map_err(Into::into),
+            #[cfg(feature = "bincode")]
+            Format::Bincode => bincode::deserialize(bytes).map_err(Into::into),
+            #[cfg(feature = "yaml")]
+            Format::Yaml => serde_yaml::from_slice(bytes).map_err(Into::into),
+            #[cfg(feature = "ini")]
+            Format::Ini => ini::from_slice(bytes),
+            #[cfg(feature = "xml")]
+            Format::Xml => {
+                let text = std::str::from_utf8(bytes)
+                    .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+                quick_xml::de::from_str(text).map_err(Into::into)
+            }
+        }
+    }
+}
+
+/// The number of characters around the error that are included in a snippet.
+const SNIPPET_BEFORE: usize = 30;
+const SNIPPET_AFTER: usize = 20;
+
+/// Converts a JSON error in `bytes` to `PreferencesError::Parse` if it has a position.
+fn parse_error(bytes: &[u8], error: serde_json::Error) -> PreferencesError {
+    if error.line() == 0 {
+        return error.into();
+    }
+    let text = String::from_utf8_lossy(bytes);
+    let line: Vec<char> = text.lines().nth(error.line() - 1).unwrap_or("").chars().collect();
+    // serde_json's columns are 1-based, except for errors at the start of a line.
+    let column = error.column().saturating_sub(1).min(line.len());
+    let start = column.saturating_sub(SNIPPET_BEFORE);
+    let end = (column + SNIPPET_AFTER).min(line.len());
+    let mut snippet: String = line[start..end].iter().collect::<String>().trim().to_owned();
+    if start > 0 {
+        snippet.insert_str(0, "...");
+    }
+    if end < line.len() {
+        snippet.push_str("...");
+    }
+    PreferencesError::Parse {
+        line: error.line(),
+        column: error.column(),
+        snippet,
+        error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::PreferencesError;
+    use super::Format;
+
+    #[test]
+    fn test_parse_error() {
+        let text = "{\n  \"volume\": 5,\n  \"name\": \"player\",\n}\n";
+        match Format::Json.deserialize_slice::<serde_json::Value>(text.as_bytes()) {
+            Err(PreferencesError::Parse { line, ref snippet, ref error, .. }) => {
+                assert_eq!(line, 4);
+                assert_eq!(snippet, "}");
+                assert_eq!(error.line(), 4);
+            }
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+
+        let long = format!("[{}, true false]", "1, ".repeat(20));
+        match Format::Json.deserialize_slice::<Vec<u32>>(long.as_bytes()) {
+            Err(PreferencesError::Parse { line: 1, ref snippet, .. }) => {
+                assert!(snippet.starts_with("...1, 1,"), "{}", snippet);
+                assert!(snippet.contains("true false]"), "{}", snippet);
+            }
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[cfg(feature = "xml")]
+    #[test]
+    fn test_xml_root() {
+        let mut map = std::collections::BTreeMap::new();
+        map.insert("volume", 7);
+        let mut text = Vec::new();
+        Format::Xml.serialize_pretty(&map, &mut text).unwrap();
+        assert_eq!(String::from_utf8(text).unwrap(),
+                   "<preferences>\n  <volume>7</volume>\n</preferences>");
+    }
+}
@@ -0,0 +1,393 @@
+//! GNOME settings, read and written with the `gsettings` command-line tool.
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::{self, Map, Number, Value};
+use std::collections::BTreeMap;
+use std::io::{self, ErrorKind};
+use std::iter::Peekable;
+use std::process::Command;
+use std::str::Chars;
+use crate::PreferencesError;
+
+/// Type keywords that `gsettings` may put before a value to disambiguate its type.
+static TYPE_KEYWORDS: &[&str] = &["boolean", "byte", "int16", "uint16", "int32", "uint32", "int64",
+                                  "uint64", "handle", "double", "string", "objectpath",
+                                  "signature"];
+
+/// The settings of a GSettings schema, for GNOME apps, read and written with the `gsettings`
+/// command-line tool.
+///
+/// Settings stored this way show up in `dconf-editor` and respect the lockdown of keys by
+/// administrators. Since the schema defines the keys and their types, the schema must be
+/// installed (usually by the app's package), and each value must be of the type the schema
+/// declares.
+///
+/// This is a wrapper around the `gsettings` tool, not a binding to GIO, and not a backend of
+/// [`PreferencesStore`](struct.PreferencesStore.html): the feature adds no dependencies, but the
+/// tool (which comes with GLib) must be installed where the app runs. Every call starts a
+/// `gsettings` process: `load_all` starts one, and `save_all` starts one for each key it sets, so
+/// this suits loading settings at startup and saving the odd change, not frequent updates.
+///
+/// Values are converted to and from the GVariant text format through JSON: booleans, numbers,
+/// strings, sequences (e.g. `Vec`s and tuples) and maps (e.g. structs and `HashMap`s) are
+/// supported. With `save_all` and `load_all`, the fields of a struct map to the keys of the
+/// schema, with underscores in field names replaced by hyphens, as is usual for GSettings keys.
+///
+/// Requires the `gsettings` feature.
+///
+/// # Example
+/// ```no_run
+/// extern crate preferences;
+/// use preferences::GSettings;
+///
+/// fn main() {
+///     let settings = GSettings::new("org.gnome.desktop.interface");
+///     let font: String = settings.load("font-name").unwrap();
+///     println!("Interface font: {}", font);
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct GSettings {
+    schema: String,
+}
+
+impl GSettings {
+    /// Opens the settings of the schema with the given ID, e.g. `org.gnome.TextEditor`.
+    pub fn new(schema_id: &str) -> Self {
+        GSettings { schema: schema_id.to_owned() }
+    }
+
+    /// Opens the settings of a relocatable schema at the given dconf `path`, which must start
+    /// and end with a slash.
+    pub fn with_path(schema_id: &str, path: &str) -> Self {
+        GSettings { schema: format!("{}:{}", schema_id, path) }
+    }
+
+    /// Returns the schema, including the path for relocatable schemas.
+    pub fn schema(&self) -> &str {
+        &self.schema
+    }
+
+    /// Sets `key` to `value`.
+    ///
+    /// # Failures
+    /// If `value` cannot be represented as a GVariant, if it doesn't have the type that the
+    /// schema declares, if the key is locked down, or if `gsettings` fails.
+    pub fn save<T: Serialize>(&self, key: &str, value: &T) -> Result<(), PreferencesError> {
+        self.set(key, &to_gvariant(&serde_json::to_value(value)?)?)
+    }
+
+    /// Loads the value of `key`, which is its default value from the schema if it was never set.
+    ///
+    /// # Failures
+    /// If the key doesn't exist, if the value cannot be deserialized as `T`, or if `gsettings`
+    /// fails.
+    pub fn load<T: DeserializeOwned>(&self, key: &str) -> Result<T, PreferencesError> {
+        let text = self.run(&["get", &self.schema, key])?;
+        serde_json::from_value(from_gvariant(&text)?).map_err(Into::into)
+    }
+
+    /// Resets `key` to its default value from the schema.
+    pub fn reset(&self, key: &str) -> Result<(), PreferencesError> {
+        self.run(&["reset", &self.schema, key]).map(|_| ())
+    }
+
+    /// Returns whether `key` can be changed, i.e. whether it isn't locked down.
+    pub fn is_writable(&self, key: &str) -> Result<bool, PreferencesError> {
+        Ok(self.run(&["writable", &self.schema, key])?.trim() == "true")
+    }
+
+    /// Lists all keys of the schema, in sorted order.
+    pub fn keys(&self) -> Result<Vec<String>, PreferencesError> {
+        let output = self.run(&["list-keys", &self.schema])?;
+        let mut keys: Vec<String> = output.lines().map(str::to_owned).collect();
+        keys.sort();
+        Ok(keys)
+    }
+
+    /// Sets each key that corresponds to a field of `value`, which must serialize as a map (e.g.
+    /// a struct).
+    ///
+    /// Nothing is written unless every field is a key of the schema and can be represented as a
+    /// GVariant. `gsettings` sets one key at a time, so if setting a key fails (e.g. because it's
+    /// locked down, or its value has the wrong type), the keys set before it are set back to
+    /// their previous values, and the error is returned. Other processes may see some of the new
+    /// values in the meantime.
+    pub fn save_all<T: Serialize>(&self, value: &T) -> Result<(), PreferencesError> {
+        let fields = match serde_json::to_value(value)? {
+            Value::Object(fields) => fields,
+            _ => return Err(invalid_data("Only maps can be saved as a whole schema").into()),
+        };
+        let previous: BTreeMap<String, String> = self.values()?.into_iter().collect();
+        let mut changes = Vec::with_capacity(fields.len());
+        for (field, value) in &fields {
+            let key = field.replace('_', "-");
+            if !previous.contains_key(&key) {
+                let msg = format!("The schema {} has no key {}", self.schema, key);
+                return Err(invalid_data(msg).into());
+            }
+            changes.push((key, to_gvariant(value)?));
+        }
+        for (i, (key, text)) in changes.iter().enumerate() {
+            if let Err(e) = self.set(key, text) {
+                for (key, _) in changes[..i].iter().rev() {
+                    let _ = self.set(key, &previous[key]);
+                }
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Loads all keys of the schema into a value whose fields correspond to the keys, e.g. a
+    /// struct.
+    pub fn load_all<T: DeserializeOwned>(&self) -> Result<T, PreferencesError> {
+        let mut fields = Map::new();
+        for (key, text) in self.values()? {
+            fields.insert(key.replace('-', "_"), from_gvariant(&text)?);
+        }
+        serde_json::from_value(Value::Object(fields)).map_err(Into::into)
+    }
+
+    /// Returns every key of the schema with its value in the GVariant text format, in sorted
+    /// order, from a single `gsettings` process.
+    fn values(&self) -> Result<Vec<(String, String)>, PreferencesError> {
+        let schema_id = self.schema.split(':').next().unwrap_or("");
+        parse_list(&self.run(&["list-recursively", &self.schema])?, schema_id)
+    }
+
+    fn set(&self, key: &str, text: &str) -> Result<(), PreferencesError> {
+        self.run(&["set", &self.schema, key, text]).map(|_| ())
+    }
+
+    fn run(&self, args: &[&str]) -> Result<String, PreferencesError> {
+        let output = Command::new("gsettings").args(args).output()?;
+        if !output.status.success() {
+            let msg = String::from_utf8_lossy(&output.stderr).trim().to_owned();
+            return Err(io::Error::other(msg).into());
+        }
+        String::from_utf8(output.stdout).map_err(|e| invalid_data(e).into())
+    }
+}
+
+/// Parses the output of `gsettings list-recursively`, which has a line with the schema ID, the key
+/// and the value of each key, keeping the keys of the schema `schema_id` (and not of its child
+/// schemas).
+fn parse_list(output: &str, schema_id: &str) -> Result<Vec<(String, String)>, PreferencesError> {
+    let mut values = Vec::new();
+    for line in output.lines().filter(|line| !line.trim().is_empty()) {
+        let mut parts = line.splitn(3, ' ');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(schema), Some(key), Some(text)) => {
+                if schema == schema_id {
+                    values.push((key.to_owned(), text.to_owned()));
+                }
+            }
+            _ => return Err(invalid_data(format!("Unexpected gsettings output {:?}", line)).into()),
+        }
+    }
+    values.sort();
+    Ok(values)
+}
+
+/// Formats `value` in the GVariant text format.
+fn to_gvariant(value: &Value) -> Result<String, PreferencesError> {
+    Ok(match *value {
+        Value::Null => return Err(invalid_data("GVariant has no null value").into()),
+        Value::Bool(b) => b.to_string(),
+        Value::Number(ref n) => n.to_string(),
+        Value::String(ref s) => quote(s),
+        Value::Array(ref items) => {
+            let items: Result<Vec<String>, _> = items.iter().map(to_gvariant).collect();
+            format!("[{}]", items?.join(", "))
+        }
+        Value::Object(ref map) => {
+            let mut entries = Vec::new();
+            for (key, value) in map {
+                entries.push(format!("{}: {}", quote(key), to_gvariant(value)?));
+            }
+            format!("{{{}}}", entries.join(", "))
+        }
+    })
+}
+
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+/// Parses a value in the GVariant text format, as printed by `gsettings get`.
+fn from_gvariant(text: &str) -> Result<Value, PreferencesError> {
+    let mut chars = text.trim().chars().peekable();
+    let value = parse_value(&mut chars)?;
+    skip_whitespace(&mut chars);
+    match chars.next() {
+        None => Ok(value),
+        Some(c) => Err(invalid_data(format!("Unexpected {:?} in GVariant", c)).into()),
+    }
+}
+
+fn parse_value(chars: &mut Peekable<Chars>) -> Result<Value, PreferencesError> {
+    skip_whitespace(chars);
+    match chars.peek().cloned() {
+        Some('\'') | Some('"') => parse_string(chars).map(Value::String),
+        Some('[') | Some('(') => {
+            let close = if chars.next() == Some('[') { ']' } else { ')' };
+            let mut items = Vec::new();
+            while !at(chars, close) {
+                items.push(parse_value(chars)?);
+                if !at(chars, close) {
+                    expect(chars, ',')?;
+                }
+            }
+            chars.next();
+            Ok(Value::Array(items))
+        }
+        Some('{') => {
+            chars.next();
+            let mut map = Map::new();
+            while !at(chars, '}') {
+                let key = match parse_value(chars)? {
+                    Value::String(key) => key,
+                    key => key.to_string(),
+                };
+                expect(chars, ':')?;
+                map.insert(key, parse_value(chars)?);
+                if !at(chars, '}') {
+                    expect(chars, ',')?;
+                }
+            }
+            chars.next();
+            Ok(Value::Object(map))
+        }
+        Some('<') => {
+            chars.next();
+            let value = parse_value(chars)?;
+            expect(chars, '>')?;
+            Ok(value)
+        }
+        Some('@') => {
+            // A type annotation, e.g. `@as []`.
+            while chars.peek().is_some_and(|c| !c.is_whitespace()) {
+                chars.next();
+            }
+            parse_value(chars)
+        }
+        _ => {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_alphanumeric() || "+-._".contains(c) {
+                    word.push(c);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            if TYPE_KEYWORDS.contains(&&word[..]) {
+                return parse_value(chars);
+            }
+            parse_word(&word)
+        }
+    }
+}
+
+fn parse_word(word: &str) -> Result<Value, PreferencesError> {
+    match word {
+        "true" => return Ok(Value::Bool(true)),
+        "false" => return Ok(Value::Bool(false)),
+        _ => {}
+    }
+    let number = if let Ok(n) = word.parse::<i64>() {
+        Some(Number::from(n))
+    } else if let Ok(n) = word.parse::<u64>() {
+        Some(Number::from(n))
+    } else {
+        word.parse::<f64>().ok().and_then(Number::from_f64)
+    };
+    number.map(Value::Number)
+        .ok_or_else(|| invalid_data(format!("Unsupported GVariant value {:?}", word)).into())
+}
+
+fn parse_string(chars: &mut Peekable<Chars>) -> Result<String, PreferencesError> {
+    let quote = chars.next();
+    let mut s = String::new();
+    loop {
+        match chars.next() {
+            Some('\\') => {
+                match chars.next() {
+                    Some('n') => s.push('\n'),
+                    Some('t') => s.push('\t'),
+                    Some(c) => s.push(c),
+                    None => break,
+                }
+            }
+            Some(c) if Some(c) == quote => return Ok(s),
+            Some(c) => s.push(c),
+            None => break,
+        }
+    }
+    Err(invalid_data("Unterminated string in GVariant").into())
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+    while chars.peek().is_some_and(|c| c.is_whitespace()) {
+        chars.next();
+    }
+}
+
+/// Skips whitespace and returns whether the next character is `c`.
+fn at(chars: &mut Peekable<Chars>, c: char) -> bool {
+    skip_whitespace(chars);
+    chars.peek() == Some(&c)
+}
+
+fn expect(chars: &mut Peekable<Chars>, c: char) -> Result<(), PreferencesError> {
+    if at(chars, c) {
+        chars.next();
+        Ok(())
+    } else {
+        Err(invalid_data(format!("Expected {:?} in GVariant", c)).into())
+    }
+}
+
+fn invalid_data<E>(error: E) -> io::Error
+    where E: Into<Box<dyn ::std::error::Error + Send + Sync>>
+{
+    io::Error::new(ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_gvariant, parse_list, to_gvariant};
+    use serde_json::{self, Value};
+
+    #[test]
+    fn test_gvariant_text() {
+        let value: Value =
+            serde_json::from_str(r#"{"name": "it's", "sizes": [1, 2.5], "dark": true}"#).unwrap();
+        let text = to_gvariant(&value).unwrap();
+        assert_eq!(text, r#"{'dark': true, 'name': 'it\'s', 'sizes': [1, 2.5]}"#);
+        assert_eq!(from_gvariant(&text).unwrap(), value);
+
+        assert_eq!(from_gvariant("uint32 5\n").unwrap(), Value::from(5));
+        assert_eq!(from_gvariant("@as []").unwrap(), Value::Array(vec![]));
+        assert_eq!(from_gvariant("[('xkb', \"us\")]").unwrap(),
+                   serde_json::from_str::<Value>(r#"[["xkb", "us"]]"#).unwrap());
+        assert!(from_gvariant("nothing").is_err());
+    }
+
+    #[test]
+    fn test_parse_list() {
+        let output = "org.example.App window-size (800, 600)\n\
+                      org.example.App.child enabled true\n\
+                      org.example.App dark-mode false\n\
+                      org.example.App title 'My app'\n";
+        let values = parse_list(output, "org.example.App").unwrap();
+        assert_eq!(values,
+                   [("dark-mode".to_owned(), "false".to_owned()),
+                    ("title".to_owned(), "'My app'".to_owned()),
+                    ("window-size".to_owned(), "(800, 600)".to_owned())]);
+        assert_eq!(from_gvariant(&values[1].1).unwrap(), Value::from("My app"));
+        assert!(parse_list("org.example.App broken\n", "org.example.App").is_err());
+    }
+}
@@ -0,0 +1,306 @@
+//! Read-modify-write updates of user data that save on drop.
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use std::ops::{Deref, DerefMut};
+use std::path::PathBuf;
+use crate::checksum;
+use crate::lock::{FileLock, Wait};
+use crate::permissions;
+use crate::{AppInfo, PreferencesError, PreferencesStore, read_file};
+
+/// Exclusive access to user data for a read-modify-write update, returned by
+/// [`edit`](fn.edit.html) and [`PreferencesStore::edit`](struct.PreferencesStore.html#method.edit).
+///
+/// The guard dereferences to the loaded value. If the value was accessed mutably, it's saved
+/// when the guard is dropped. Errors when saving on drop can't be reported, so call `commit` to
+/// handle them. The data stays locked while the guard exists, so that other threads and
+/// processes can't make conflicting updates in the meantime.
+#[derive(Debug)]
+pub struct PreferencesGuard<T>
+    where T: Serialize
+{
+    store: PreferencesStore,
+    key: String,
+    path: PathBuf,
+    value: T,
+    changed: bool,
+    lock: Option<FileLock>,
+}
+
+impl<T> PreferencesGuard<T>
+    where T: Serialize + DeserializeOwned + Default
+{
+    pub(crate) fn new(store: &PreferencesStore, key: &str) -> Result<Self, PreferencesError> {
+        let format = store.options().get_format();
+        let path = store.file_path(key, format)?;
+        let (lock, loaded) = match store.memory() {
+            Some(memory) => {
+                let loaded = memory.read(&path)
+                    .map_err(PreferencesError::from)
+                    .and_then(|bytes| checksum::deserialize(format, &bytes));
+                (None, loaded)
+            }
+            None => {
+                let private = store.options().is_private();
+                if let Some(dir) = path.parent() {
+                    permissions::create_dirs(dir, private)?;
+                }
+                let lock = FileLock::acquire(&path, true, Wait::Block)?;
+                (Some(lock), read_file(&path, format))
+            }
+        };
+        let value = match loaded {
+            Ok(value) => value,
+            Err(ref e) if e.is_not_found() => T::default(),
+            Err(e) => return Err(e),
+        };
+        Ok(PreferencesGuard {
+            store: store.clone(),
+            key: key.to_owned(),
+            path,
+            value,
+            changed: false,
+            lock,
+        })
+    }
+}
+
+impl<T> PreferencesGuard<T>
+    where T: Serialize
+{
+    /// Saves the value if it was accessed mutably, and releases the lock.
+    ///
+    /// # Failures
+    /// Same as [`Preferences::save`](trait.Preferences.html#tymethod.save).
+    pub fn commit(mut self) -> Result<(), PreferencesError> {
+        self.save()
+    }
+
+    fn save(&mut self) -> Result<(), PreferencesError> {
+        if !self.changed {
+            return Ok(());
+        }
+        self.changed = false;
+        // Saving is the last thing a guard does, so the lock is released once the value is
+        // written, before it's synced.
+        self.store.save_at(&self.key,
+                           &self.path,
+                           &self.value,
+                           self.store.options(),
+                           self.lock.take(),
+                           Wait::Block)
+    }
+}
+
+impl<T> Deref for PreferencesGuard<T>
+    where T: Serialize
+{
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for PreferencesGuard<T>
+    where T: Serialize
+{
+    fn deref_mut(&mut self) -> &mut T {
+        self.changed = true;
+        &mut self.value
+    }
+}
+
+impl<T> Drop for PreferencesGuard<T>
+    where T: Serialize
+{
+    fn drop(&mut self) {
+        let _ = self.save();
+    }
+}
+
+/// Loads the user data of `app` saved with the given `key` (or the default value if nothing was
+/// saved yet) for a read-modify-write update, which is saved when the returned guard is dropped
+/// or committed.
+///
+/// # Failures
+/// If the data directory cannot be determined, or if the data exists but cannot be loaded.
+///
+/// # Example
+/// ```
+/// use preferences::{AppInfo, PreferencesMap, edit};
+///
+/// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+///
+/// # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
+/// let key = "tests/docs/edit";
+/// {
+///     let mut counts = edit::<PreferencesMap<u32>, _>(&APP_INFO, key).unwrap();
+///     *counts.entry("launches".into()).or_insert(0) += 1;
+///     counts.commit().unwrap();
+/// }
+/// ```
+pub fn edit<T, S>(app: &AppInfo, key: S) -> Result<PreferencesGuard<T>, PreferencesError>
+    where T: Serialize + DeserializeOwned + Default,
+          S: AsRef<str>
+{
+    PreferencesStore::new(app)?.edit(key)
+}
+
+/// Loads the user data of `app` saved with the given `key` (or the default value if nothing was
+/// saved yet), changes it with `f`, and saves it, all while holding the lock on it. Returns the
+/// result of `f`.
+///
+/// # Failures
+/// Same as [`edit`](fn.edit.html) and [`Preferences::save`](trait.Preferences.html#tymethod.save).
+///
+/// # Example
+/// ```
+/// use preferences::{AppInfo, PreferencesMap, update};
+///
+/// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+///
+/// # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
+/// let key = "tests/docs/update";
+/// update(&APP_INFO, key, |map: &mut PreferencesMap| {
+///     map.insert("theme".into(), "dark".into());
+/// }).unwrap();
+/// ```
+pub fn update<T, S, F, R>(app: &AppInfo, key: S, f: F) -> Result<R, PreferencesError>
+    where T: Serialize + DeserializeOwned + Default,
+          S: AsRef<str>,
+          F: FnOnce(&mut T) -> R
+{
+    PreferencesStore::new(app)?.update(key, f)
+}
+
+/// Applies `partial` as a [JSON merge patch](https://tools.ietf.org/html/rfc7396) to the user
+/// data of `app` saved with the given `key`: the maps (e.g. structs) in `partial` are merged
+/// into the saved data recursively, `null` values (e.g. `None`) remove the corresponding
+/// entries, and any other values replace the saved ones. If nothing was saved yet, `partial` is
+/// applied to an empty map.
+///
+/// This changes a few entries of a large map without having to name its type.
+///
+/// # Failures
+/// Same as [`update`](fn.update.html), or if `partial` cannot be serialized.
+///
+/// # Example
+/// ```
+/// #[macro_use]
+/// extern crate serde_derive;
+/// extern crate preferences;
+/// use preferences::{AppInfo, PreferencesMap, Preferences, patch};
+///
+/// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+///
+/// #[derive(Serialize)]
+/// struct ThemePatch {
+///     theme: &'static str,
+///     font: Option<String>,
+/// }
+///
+/// fn main() {
+///     # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
+///     let key = "tests/docs/patch";
+///     let mut prefs: PreferencesMap = PreferencesMap::new();
+///     prefs.insert("font".into(), "Sans".into());
+///     prefs.insert("language".into(), "en".into());
+///     prefs.save(&APP_INFO, key).unwrap();
+///
+///     patch(&APP_INFO, key, &ThemePatch { theme: "dark", font: None }).unwrap();
+///     let prefs = PreferencesMap::<String>::load(&APP_INFO, key).unwrap();
+///     assert_eq!(prefs.len(), 2);
+///     assert_eq!(prefs["theme"], "dark");
+/// }
+/// ```
+pub fn patch<P, S>(app: &AppInfo, key: S, partial: &P) -> Result<(), PreferencesError>
+    where P: Serialize,
+          S: AsRef<str>
+{
+    PreferencesStore::new(app)?.patch(key, partial)
+}
+
+/// Applies the JSON merge patch `patch` to `target`.
+pub(crate) fn merge_patch(target: &mut Value, patch: Value) {
+    let patch = match patch {
+        Value::Object(patch) => patch,
+        patch => {
+            *target = patch;
+            return;
+        }
+    };
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    if let Value::Object(ref mut map) = *target {
+        for (key, value) in patch {
+            if value.is_null() {
+                map.remove(&key);
+            } else {
+                merge_patch(map.entry(key).or_insert(Value::Null), value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{AppInfo, MemoryBackend, PreferencesStore};
+    use serde_json::{self, Value};
+
+    const APP_INFO: AppInfo = AppInfo {
+        name: "preferences",
+        author: "Rust language community",
+    };
+
+    #[test]
+    fn test_edit() {
+        let store = PreferencesStore::new(&APP_INFO)
+            .unwrap()
+            .with_memory_backend(MemoryBackend::new());
+        let key = "tests/module/guard";
+        {
+            let mut value = store.edit::<u32, _>(key).unwrap();
+            assert_eq!(*value, 0);
+            *value += 2;
+        }
+        let mut value = store.edit::<u32, _>(key).unwrap();
+        *value *= 3;
+        value.commit().unwrap();
+        assert_eq!(store.load::<u32, _>(key).unwrap(), 6);
+
+        let value = store.edit::<u32, _>(key).unwrap();
+        assert_eq!(*value, 6);
+        drop(value);
+        assert!(!store.exists("tests/module/guard/unchanged").unwrap());
+        drop(store.edit::<u32, _>("tests/module/guard/unchanged").unwrap());
+        assert!(!store.exists("tests/module/guard/unchanged").unwrap());
+    }
+
+    #[test]
+    fn test_patch() {
+        let store = PreferencesStore::new(&APP_INFO)
+            .unwrap()
+            .with_memory_backend(MemoryBackend::new());
+        let key = "tests/module/patch";
+        store.patch(key, &json(r#"{"a": {"b": 1, "c": 2}, "d": [1]}"#)).unwrap();
+        store.patch(key, &json(r#"{"a": {"b": null, "e": 3}, "d": [2]}"#)).unwrap();
+        assert_eq!(store.load::<Value, _>(key).unwrap(),
+                   json(r#"{"a": {"c": 2, "e": 3}, "d": [2]}"#));
+
+        let len = store.update(key, |value: &mut Value| {
+                value["f"] = json("4");
+                value.as_object().unwrap().len()
+            })
+            .unwrap();
+        assert_eq!(len, 3);
+        assert_eq!(store.load::<Value, _>(key).unwrap()["f"], json("4"));
+    }
+
+    fn json(text: &str) -> Value {
+        serde_json::from_str(text).unwrap()
+    }
+}
@@ -0,0 +1,209 @@
+//! Headers describing saved data, written on a line before it.
+
+use serde_json::{Map, Value};
+use std::convert::TryFrom;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::expiring::millis;
+use crate::{AppInfo, Format, PreferencesError, PreferencesStore};
+
+/// Starts the first line of a file with a header, which is followed by the fields of the header
+/// as a JSON object.
+const PREFIX: &[u8] = b"preferences-header ";
+
+/// The description of saved data that is written before it if
+/// [`SaveOptions::header`](struct.SaveOptions.html#method.header) is enabled, returned by
+/// [`file_header`](fn.file_header.html).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileHeader {
+    /// The key with which the data was saved, exactly as it was given. (The key that
+    /// [`list_prefs`](fn.list_prefs.html) derives from the file name is normalized, and may differ
+    /// in case from the original on case-insensitive file systems.)
+    pub key: Option<String>,
+    /// The name of the app that saved the data.
+    pub app: Option<String>,
+    /// The format of the data.
+    pub format: Format,
+    /// The version of the data's layout, set with
+    /// [`SaveOptions::schema_version`](struct.SaveOptions.html#method.schema_version).
+    pub schema_version: Option<u32>,
+    /// When the data was saved, to the millisecond.
+    pub saved: SystemTime,
+    /// The CRC-32 of the data after the header, if it was saved with
+    /// [`SaveOptions::checksum`](struct.SaveOptions.html#method.checksum).
+    pub checksum: Option<u32>,
+}
+
+impl FileHeader {
+    /// Describes data in `format` saved now with `key` by `app`.
+    pub(crate) fn new(key: Option<&str>,
+                      app: Option<&str>,
+                      format: Format,
+                      schema_version: Option<u32>)
+                      -> Self {
+        FileHeader {
+            key: key.map(str::to_owned),
+            app: app.map(str::to_owned),
+            format,
+            schema_version,
+            saved: now(),
+            checksum: None,
+        }
+    }
+
+    /// Returns the header as a JSON object.
+    fn to_json(&self) -> Value {
+        let mut fields = Map::new();
+        if let Some(ref key) = self.key {
+            fields.insert("key".into(), key.clone().into());
+        }
+        if let Some(ref app) = self.app {
+            fields.insert("app".into(), app.clone().into());
+        }
+        fields.insert("format".into(), self.format.extension().into());
+        if let Some(version) = self.schema_version {
+            fields.insert("schema_version".into(), version.into());
+        }
+        fields.insert("saved".into(), millis(self.saved).into());
+        if let Some(checksum) = self.checksum {
+            fields.insert("crc32".into(), checksum.into());
+        }
+        Value::Object(fields)
+    }
+
+    /// Reads a header from a JSON object, or returns `None` if it isn't one.
+    fn from_json(json: &Value) -> Option<Self> {
+        let string = |name: &str| json.get(name).and_then(Value::as_str).map(str::to_owned);
+        Some(FileHeader {
+            key: string("key"),
+            app: string("app"),
+            format: Format::from_extension(json.get("format")?.as_str()?)?,
+            schema_version: match json.get("schema_version") {
+                Some(version) => Some(u32::try_from(version.as_u64()?).ok()?),
+                None => None,
+            },
+            saved: UNIX_EPOCH + Duration::from_millis(json.get("saved")?.as_u64()?),
+            checksum: match json.get("crc32") {
+                Some(checksum) => Some(u32::try_from(checksum.as_u64()?).ok()?),
+                None => None,
+            },
+        })
+    }
+}
+
+/// Returns the current time, or `UNIX_EPOCH` in WebAssembly for the browser, where there's no
+/// clock.
+pub(crate) fn now() -> SystemTime {
+    if cfg!(all(target_arch = "wasm32", target_os = "unknown")) {
+        UNIX_EPOCH
+    } else {
+        SystemTime::now()
+    }
+}
+
+/// Prepends `header` to the serialized data in `bytes`.
+pub(crate) fn prepend(header: &FileHeader, bytes: Vec<u8>) -> Vec<u8> {
+    let json = header.to_json().to_string();
+    let mut prepended = Vec::with_capacity(PREFIX.len() + json.len() + 1 + bytes.len());
+    prepended.extend_from_slice(PREFIX);
+    prepended.extend_from_slice(json.as_bytes());
+    prepended.push(b'\n');
+    prepended.extend_from_slice(&bytes);
+    prepended
+}
+
+/// Returns the header of the saved data in `bytes`, if it has one, and the data after it.
+///
+/// # Failures
+/// `PreferencesError::Corrupted` if the header is damaged.
+pub(crate) fn split(bytes: &[u8]) -> Result<(Option<FileHeader>, &[u8]), PreferencesError> {
+    if !bytes.starts_with(PREFIX) {
+        return Ok((None, bytes));
+    }
+    let end = bytes.iter().position(|&b| b == b'\n').ok_or(PreferencesError::Corrupted)?;
+    let header = serde_json::from_slice(&bytes[PREFIX.len()..end])
+        .ok()
+        .and_then(|json| FileHeader::from_json(&json))
+        .ok_or(PreferencesError::Corrupted)?;
+    Ok((Some(header), &bytes[end + 1..]))
+}
+
+/// Returns the header of the user data of `app` saved with the given `key`, or `None` if it was
+/// saved without one, e.g. to find out which app and key a stray file belongs to. (Data saved with
+/// a checksum but without [`SaveOptions::header`](struct.SaveOptions.html#method.header) has a
+/// header that only holds its format, time and checksum.) If the data was
+/// saved in several formats, the header of the most recently saved one is returned.
+///
+/// # Failures
+/// `PreferencesError::NotFound` if no data was saved with `key`, or
+/// `PreferencesError::Corrupted` if the header is damaged.
+///
+/// # Example
+/// ```
+/// use preferences::{AppInfo, PreferencesStore, SaveOptions, file_header};
+///
+/// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+///
+/// # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
+/// let options = SaveOptions::new().header(true).schema_version(2);
+/// let store = PreferencesStore::new(&APP_INFO).unwrap().with_options(options);
+/// store.save("tests/docs/header", &42).unwrap();
+///
+/// let header = file_header(&APP_INFO, "tests/docs/header").unwrap().unwrap();
+/// assert_eq!(header.key.as_deref(), Some("tests/docs/header"));
+/// assert_eq!(header.schema_version, Some(2));
+/// ```
+pub fn file_header<S>(app: &AppInfo, key: S) -> Result<Option<FileHeader>, PreferencesError>
+    where S: AsRef<str>
+{
+    PreferencesStore::new(app)?.file_header(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{prepend, split, FileHeader};
+    use crate::testing::TestDir;
+    use crate::{Format, PreferencesError, SaveOptions};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_header_round_trip() {
+        let header = FileHeader {
+            key: Some("options/Theme".into()),
+            app: None,
+            format: Format::Json,
+            schema_version: Some(3),
+            saved: UNIX_EPOCH + Duration::from_millis(1_500_000_000_123),
+            checksum: Some(0xcbf4_3926),
+        };
+        let bytes = prepend(&header, b"{}".to_vec());
+        assert_eq!(split(&bytes).unwrap(), (Some(header), &b"{}"[..]));
+        assert_eq!(split(b"{}").unwrap(), (None, &b"{}"[..]));
+        assert!(split(&bytes[..20]).is_err());
+        assert!(split(b"preferences-header {}\n{}").is_err());
+    }
+
+    #[test]
+    fn test_file_header() {
+        let dir = TestDir::new("header");
+        let options = SaveOptions::new().header(true).checksum(true).schema_version(1);
+        for store in dir.stores() {
+            let store = store.with_options(options.clone());
+            store.save("Options//window", &vec![1, 2]).unwrap();
+            assert_eq!(store.load::<Vec<u8>, _>("Options/window").unwrap(), [1, 2]);
+            let header = store.file_header("Options/window").unwrap().unwrap();
+            assert_eq!(header.key.as_deref(), Some("Options//window"));
+            assert_eq!(header.app.as_deref(), Some("preferences"));
+            assert_eq!(header.format, Format::Json);
+            assert_eq!(header.schema_version, Some(1));
+
+            let plain = store.clone().with_options(SaveOptions::new());
+            plain.save("Options/window", &vec![3]).unwrap();
+            assert_eq!(store.file_header("Options/window").unwrap(), None);
+            match *store.file_header("missing").unwrap_err().inner() {
+                PreferencesError::NotFound { .. } => {}
+                ref other => panic!("unexpected error: {}", other),
+            }
+            store.delete_all("").unwrap();
+        }
+    }
+}
@@ -0,0 +1,396 @@
+//! The INI format, for user data that other tools read or users edit by hand.
+
+use serde::de::value::{MapDeserializer, StringDeserializer};
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Unexpected, Visitor};
+use serde::{Deserialize, Serialize, forward_to_deserialize_any, ser};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{Read, Write};
+use crate::PreferencesError;
+
+/// An error that occurred during INI serialization or deserialization.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IniError {
+    line: Option<usize>,
+    message: String,
+}
+
+impl IniError {
+    fn new<M: Into<String>>(message: M) -> Self {
+        IniError {
+            line: None,
+            message: message.into(),
+        }
+    }
+
+    fn at_line<M: Into<String>>(line: usize, message: M) -> Self {
+        IniError {
+            line: Some(line),
+            message: message.into(),
+        }
+    }
+
+    /// Returns the line of the INI text at which the error occurred, starting at 1, if known.
+    pub fn line(&self) -> Option<usize> {
+        self.line
+    }
+}
+
+impl fmt::Display for IniError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{} at line {}", self.message, line),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+impl std::error::Error for IniError {}
+
+impl ser::Error for IniError {
+    fn custom<T: fmt::Display>(message: T) -> Self {
+        IniError::new(message.to_string())
+    }
+}
+
+impl de::Error for IniError {
+    fn custom<T: fmt::Display>(message: T) -> Self {
+        IniError::new(message.to_string())
+    }
+}
+
+/// Writes `value`, which must serialize as a map or struct, to `writer` as INI. Maps and structs
+/// nested in it become sections; other values and deeper nesting can't be written. `None` values
+/// are left out.
+pub(crate) fn to_writer<T, W>(writer: &mut W, value: &T) -> Result<(), PreferencesError>
+    where T: Serialize,
+          W: Write
+{
+    let map = match serde_json::to_value(value)? {
+        Value::Object(map) => map,
+        _ => return Err(IniError::new("only maps and structs can be saved as INI").into()),
+    };
+    let mut text = String::new();
+    for (key, value) in &map {
+        if !value.is_object() {
+            write_entry(&mut text, key, value)?;
+        }
+    }
+    for (name, value) in &map {
+        if let Value::Object(ref section) = *value {
+            write_section(&mut text, name, section)?;
+        }
+    }
+    writer.write_all(text.as_bytes()).map_err(Into::into)
+}
+
+fn write_section(text: &mut String,
+                 name: &str,
+                 section: &Map<String, Value>)
+                 -> Result<(), PreferencesError> {
+    let valid_name = !name.is_empty() && name.trim() == name &&
+                     !name.contains(|c: char| c == ']' || c.is_control());
+    if !valid_name {
+        let message = format!("`{}` can't be the name of an INI section", name);
+        return Err(IniError::new(message).into());
+    }
+    if !text.is_empty() {
+        text.push('\n');
+    }
+    text.push_str(&format!("[{}]\n", name));
+    for (key, value) in section {
+        if value.is_object() {
+            let message = format!("`{}.{}` is nested too deeply to be saved as INI", name, key);
+            return Err(IniError::new(message).into());
+        }
+        write_entry(text, key, value)?;
+    }
+    Ok(())
+}
+
+fn write_entry(text: &mut String, key: &str, value: &Value) -> Result<(), PreferencesError> {
+    let valid_key = !key.is_empty() && key.trim() == key &&
+                    !key.starts_with(['[', ';', '#']) &&
+                    !key.contains(|c: char| c == '=' || c.is_control());
+    if !valid_key {
+        return Err(IniError::new(format!("`{}` can't be a key in INI", key)).into());
+    }
+    let value = match *value {
+        Value::Null => return Ok(()),
+        Value::Bool(value) => value.to_string(),
+        Value::Number(ref value) => value.to_string(),
+        Value::String(ref value) => {
+            // Strings that wouldn't be read back as they are are quoted, with JSON escapes.
+            let plain = value.trim() == value && !value.starts_with('"') &&
+                        !value.contains(char::is_control);
+            if plain {
+                value.clone()
+            } else {
+                serde_json::to_string(value)?
+            }
+        }
+        Value::Array(_) | Value::Object(_) => {
+            let message = format!("the value of `{}` can't be saved as INI", key);
+            return Err(IniError::new(message).into());
+        }
+    };
+    text.push_str(&format!("{} = {}\n", key, value));
+    Ok(())
+}
+
+/// Reads INI text from `reader`. Values are strings that are converted to the types of `T` as
+/// they're deserialized, so e.g. `volume = 7` can be loaded as a number or as a string.
+pub(crate) fn from_reader<T, R>(reader: &mut R) -> Result<T, PreferencesError>
+    where T: DeserializeOwned,
+          R: Read
+{
+    let mut text = String::new();
+    reader.read_to_string(&mut text)?;
+    from_str(&text)
+}
+
+/// Same as `from_reader`, but reads the INI text in `bytes`.
+#[cfg(feature = "mmap")]
+pub(crate) fn from_slice<'de, T: Deserialize<'de>>(bytes: &[u8]) -> Result<T, PreferencesError> {
+    from_str(std::str::from_utf8(bytes).map_err(|_| IniError::new("INI text isn't UTF-8"))?)
+}
+
+fn from_str<'de, T: Deserialize<'de>>(text: &str) -> Result<T, PreferencesError> {
+    let root = parse(text)?;
+    T::deserialize(Entry::Section(root)).map_err(Into::into)
+}
+
+/// A value, or a section of values, of INI text.
+enum Entry {
+    Value(String),
+    Section(BTreeMap<String, Entry>),
+}
+
+fn parse(text: &str) -> Result<BTreeMap<String, Entry>, IniError> {
+    let mut root = BTreeMap::new();
+    let mut section: Option<String> = None;
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[') {
+            let name = name.strip_suffix(']')
+                .ok_or_else(|| IniError::at_line(index + 1, "expected `]`"))?;
+            let name = name.trim().to_owned();
+            match root.get(&name) {
+                Some(&Entry::Section(_)) => {}
+                _ => {
+                    root.insert(name.clone(), Entry::Section(BTreeMap::new()));
+                }
+            }
+            section = Some(name);
+            continue;
+        }
+        let (key, value) = line.split_once('=')
+            .ok_or_else(|| IniError::at_line(index + 1, "expected `key = value`"))?;
+        let value = value.trim();
+        let value = if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+            serde_json::from_str(value).unwrap_or_else(|_| value.to_owned())
+        } else {
+            value.to_owned()
+        };
+        let entries = match section {
+            Some(ref name) => {
+                match root.get_mut(name) {
+                    Some(&mut Entry::Section(ref mut entries)) => entries,
+                    _ => unreachable!("the section was inserted at its header"),
+                }
+            }
+            None => &mut root,
+        };
+        entries.insert(key.trim().to_owned(), Entry::Value(value));
+    }
+    Ok(root)
+}
+
+/// Parses the common spellings of flags in INI files.
+fn parse_bool(text: &str) -> Option<bool> {
+    match text.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Some(true),
+        "false" | "no" | "off" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+impl<'de> IntoDeserializer<'de, IniError> for Entry {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, IniError> {
+                match self {
+                    Entry::Value(text) => {
+                        match text.parse() {
+                            Ok(value) => visitor.$visit(value),
+                            Err(_) => Err(de::Error::invalid_value(Unexpected::Str(&text),
+                                                                   &visitor)),
+                        }
+                    }
+                    section => section.deserialize_any(visitor),
+                }
+            }
+        )*
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Entry {
+    type Error = IniError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, IniError> {
+        match self {
+            Entry::Value(text) => visitor.visit_string(text),
+            Entry::Section(entries) => {
+                visitor.visit_map(MapDeserializer::new(entries.into_iter()))
+            }
+        }
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, IniError> {
+        match self {
+            Entry::Value(text) => {
+                match parse_bool(&text) {
+                    Some(value) => visitor.visit_bool(value),
+                    None => Err(de::Error::invalid_value(Unexpected::Str(&text), &visitor)),
+                }
+            }
+            section => section.deserialize_any(visitor),
+        }
+    }
+
+    deserialize_parsed! {
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, IniError> {
+        // `None` values are left out, so any value that's present is `Some`.
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V>(self,
+                                     _name: &'static str,
+                                     visitor: V)
+                                     -> Result<V::Value, IniError>
+        where V: Visitor<'de>
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V>(self,
+                           _name: &'static str,
+                           _variants: &'static [&'static str],
+                           visitor: V)
+                           -> Result<V::Value, IniError>
+        where V: Visitor<'de>
+    {
+        match self {
+            Entry::Value(text) => {
+                let variant: StringDeserializer<IniError> = text.into_deserializer();
+                visitor.visit_enum(variant)
+            }
+            section => section.deserialize_any(visitor),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        i128 u128 char str string bytes byte_buf unit unit_struct seq tuple tuple_struct map
+        struct identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Format, IniError, PreferencesError, PreferencesMap};
+    use serde_derive::{Deserialize, Serialize};
+    use std::collections::BTreeMap;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Settings {
+        name: String,
+        volume: u8,
+        muted: bool,
+        scale: Option<f64>,
+        window: Window,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Window {
+        width: u32,
+        title: String,
+    }
+
+    #[test]
+    fn test_ini_round_trip() {
+        let settings = Settings {
+            name: "  padded  ".into(),
+            volume: 7,
+            muted: false,
+            scale: None,
+            window: Window {
+                width: 800,
+                title: "Main = \"first\"\nwindow".into(),
+            },
+        };
+        let mut text = Vec::new();
+        Format::Ini.serialize(&settings, &mut text).unwrap();
+        assert_eq!(String::from_utf8(text.clone()).unwrap(),
+                   "muted = false\nname = \"  padded  \"\nvolume = 7\n\n[window]\ntitle = \
+                    \"Main = \\\"first\\\"\\nwindow\"\nwidth = 800\n");
+        let loaded: Settings = Format::Ini.deserialize(&mut &text[..]).unwrap();
+        assert_eq!(loaded, settings);
+
+        // Files edited by hand, with comments and other spellings of flags.
+        let text = "; Settings\nname=Player\nvolume = 3\nmuted = yes\nscale = 1.5\n\n\
+                    [window]\n# Size\nwidth=1024\ntitle=Main\n";
+        let loaded: Settings = Format::Ini.deserialize(&mut text.as_bytes()).unwrap();
+        assert!(loaded.muted);
+        assert_eq!(loaded.scale, Some(1.5));
+        assert_eq!(loaded.window.width, 1024);
+        let map: PreferencesMap<String> = Format::Ini.deserialize(&mut "volume = 3".as_bytes())
+            .unwrap();
+        assert_eq!(map["volume"], "3");
+    }
+
+    #[test]
+    fn test_ini_errors() {
+        let mut text = Vec::new();
+        let mut nested = BTreeMap::new();
+        nested.insert("a", BTreeMap::new());
+        nested.get_mut("a").unwrap().insert("b", BTreeMap::<String, String>::new());
+        assert!(Format::Ini.serialize(&nested, &mut text).is_err());
+        assert!(Format::Ini.serialize(&vec![1, 2], &mut text).is_err());
+        assert!(Format::Ini.serialize(&[("a=b", 1)].iter().cloned().collect::<BTreeMap<_, _>>(),
+                                      &mut text)
+            .is_err());
+
+        match Format::Ini.deserialize::<PreferencesMap<String>, _>(&mut "a = 1\nb".as_bytes()) {
+            Err(PreferencesError::Serialization(ref e)) => {
+                assert_eq!(e.downcast_ref::<IniError>().unwrap().line(), Some(2))
+            }
+            other => panic!("expected an INI error, got {:?}", other),
+        }
+        assert!(Format::Ini.deserialize::<PreferencesMap<u32>, _>(&mut "a = x".as_bytes())
+            .is_err());
+    }
+}
@@ -0,0 +1,261 @@
+//! Append-only storage of user data that keeps every saved version.
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::{self, Map, Value};
+use std::fs::{self, File, OpenOptions, create_dir_all};
+use std::io::{ErrorKind, Read, Write};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::lock::{FileLock, Wait};
+use crate::observe;
+use crate::{AppInfo, PreferencesError, PreferencesStore, file_path_in};
+
+/// File extension of journals, which contain one JSON document per line.
+const JOURNAL_EXTENSION: &str = "jsonl";
+
+/// User data that is saved by appending each new version to a journal file, instead of
+/// overwriting the previous version.
+///
+/// Each line of the journal is a JSON object with the time of the save (`time`, in milliseconds
+/// since the Unix epoch) and the saved value (`value`). The journal is stored next to the other
+/// user data, with the extension `.jsonl`, so it doesn't conflict with data saved with
+/// [`Preferences::save`](trait.Preferences.html#tymethod.save) under the same key.
+///
+/// This keeps a complete history of the data, e.g. for undo or to find out when a setting was
+/// changed, at the cost of a file that grows with every save. Use `clear` to start over.
+///
+/// # Example
+/// ```
+/// use preferences::{AppInfo, Journal};
+///
+/// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+///
+/// # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
+/// let journal = Journal::new(&APP_INFO, "tests/docs/journal").unwrap();
+/// journal.append(&"light".to_owned()).unwrap();
+/// journal.append(&"dark".to_owned()).unwrap();
+///
+/// assert_eq!(journal.load_latest::<String>().unwrap(), "dark");
+/// let history = journal.history::<String>().unwrap();
+/// assert_eq!(history.len(), 2);
+/// assert_eq!(history[0].value, "light");
+/// ```
+#[derive(Clone, Debug)]
+pub struct Journal {
+    store: PreferencesStore,
+    key: String,
+}
+
+/// A version of the user data in a [`Journal`](struct.Journal.html).
+#[derive(Clone, Debug, PartialEq)]
+pub struct JournalEntry<T> {
+    /// When the value was appended.
+    pub time: SystemTime,
+    /// The value.
+    pub value: T,
+}
+
+impl Journal {
+    /// Opens the journal of the user data of `app` with the given `key`. The journal file is
+    /// created by the first `append`.
+    ///
+    /// # Failures
+    /// If the data directory cannot be determined (e.g. because `app` has an empty name).
+    pub fn new<S: AsRef<str>>(app: &AppInfo, key: S) -> Result<Self, PreferencesError> {
+        Ok(Journal::with_store(PreferencesStore::new(app)?, key))
+    }
+
+    /// Same as `new`, but stores the journal in `store`.
+    pub fn with_store<S: AsRef<str>>(store: PreferencesStore, key: S) -> Self {
+        Journal {
+            store,
+            key: key.as_ref().to_owned(),
+        }
+    }
+
+    /// Returns the key of the user data.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Returns the path of the journal file. This makes no guarantees that the file exists.
+    ///
+    /// # Failures
+    /// If the key is invalid.
+    pub fn path(&self) -> Result<PathBuf, PreferencesError> {
+        file_path_in(self.store.root(), &self.key, JOURNAL_EXTENSION)
+    }
+
+    /// Appends `value` to the journal, with the current time.
+    ///
+    /// # Failures
+    /// If the key is invalid, `value` cannot be serialized, or the journal file cannot be
+    /// written.
+    pub fn append<T: Serialize>(&self, value: &T) -> Result<(), PreferencesError> {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_millis() as u64)
+            .unwrap_or(0);
+        let mut entry = Map::new();
+        entry.insert("time".into(), millis.into());
+        entry.insert("value".into(), serde_json::to_value(value)?);
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+
+        let path = self.path()?;
+        if !self.store.writes()? {
+            return Ok(());
+        }
+        match self.store.memory() {
+            Some(memory) => {
+                let mut bytes = match memory.read(&path) {
+                    Ok(bytes) => bytes,
+                    Err(ref e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+                    Err(e) => return Err(e.into()),
+                };
+                bytes.extend_from_slice(&line);
+                memory.write(&path, bytes)?;
+            }
+            None => {
+                path.parent().map(create_dir_all);
+                let _lock = FileLock::acquire(&path, true, Wait::Block)?;
+                let mut file = OpenOptions::new().read(true).append(true).create(true).open(&path)?;
+                let mut bytes = Vec::new();
+                file.read_to_end(&mut bytes)?;
+                // Discard a line left incomplete by an interrupted append.
+                if !bytes.is_empty() && !bytes.ends_with(b"\n") {
+                    let end = bytes.iter().rposition(|&byte| byte == b'\n').map_or(0, |i| i + 1);
+                    file.set_len(end as u64)?;
+                }
+                file.write_all(&line)?;
+                file.sync_all()?;
+            }
+        }
+        observe::notify(self.store.root(), &self.key);
+        Ok(())
+    }
+
+    /// Loads the most recently appended value.
+    ///
+    /// # Failures
+    /// Same as `history`, or an I/O error of kind `NotFound` if the journal is empty.
+    pub fn load_latest<T: DeserializeOwned>(&self) -> Result<T, PreferencesError> {
+        let mut lines = self.read_lines()?;
+        match lines.pop() {
+            Some(line) => Ok(parse_entry(line)?.value),
+            None => Err(std::io::Error::from(ErrorKind::NotFound).into()),
+        }
+    }
+
+    /// Loads all values in the journal, oldest first, with the times they were appended.
+    ///
+    /// A malformed last line, as left by an interrupted append, is skipped.
+    ///
+    /// # Failures
+    /// If the journal doesn't exist or cannot be read, or if any entry cannot be deserialized as
+    /// `T`.
+    pub fn history<T: DeserializeOwned>(&self) -> Result<Vec<JournalEntry<T>>, PreferencesError> {
+        self.read_lines()?.into_iter().map(parse_entry).collect()
+    }
+
+    /// Deletes the journal.
+    ///
+    /// # Failures
+    /// If the journal exists but cannot be deleted.
+    pub fn clear(&self) -> Result<(), PreferencesError> {
+        let path = self.path()?;
+        if !self.store.writes()? {
+            return Ok(());
+        }
+        let result = match self.store.memory() {
+            Some(memory) => memory.remove(&path),
+            None => {
+                let lock = FileLock::acquire(&path, true, Wait::Block);
+                let result = fs::remove_file(&path);
+                if let Ok(lock) = lock {
+                    lock.remove();
+                }
+                result
+            }
+        };
+        match result {
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            result => result.map_err(Into::into),
+        }
+    }
+
+    /// Reads the lines of the journal that contain entries.
+    fn read_lines(&self) -> Result<Vec<Value>, PreferencesError> {
+        let path = self.path()?;
+        let bytes = match self.store.memory() {
+            Some(memory) => memory.read(&path)?,
+            None => {
+                let _lock = FileLock::acquire(&path, false, Wait::Block)?;
+                let mut bytes = Vec::new();
+                File::open(&path)?.read_to_end(&mut bytes)?;
+                bytes
+            }
+        };
+        let lines: Vec<&[u8]> = bytes.split(|&byte| byte == b'\n')
+            .filter(|line| !line.iter().all(u8::is_ascii_whitespace))
+            .collect();
+        let mut entries = Vec::with_capacity(lines.len());
+        for (i, line) in lines.iter().enumerate() {
+            match serde_json::from_slice(line) {
+                Ok(entry) => entries.push(entry),
+                Err(_) if i + 1 == lines.len() => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(entries)
+    }
+}
+
+fn parse_entry<T: DeserializeOwned>(mut entry: Value) -> Result<JournalEntry<T>, PreferencesError> {
+    let millis = entry.get("time").and_then(Value::as_u64).unwrap_or(0);
+    let value = entry.get_mut("value").map(Value::take).unwrap_or(Value::Null);
+    Ok(JournalEntry {
+        time: UNIX_EPOCH + Duration::from_millis(millis),
+        value: serde_json::from_value(value)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{APP_INFO, TestDir};
+    use crate::{MemoryBackend, PreferencesStore};
+    use std::fs::OpenOptions;
+    use std::io::Write;
+
+    #[test]
+    fn test_journal_memory() {
+        let store = PreferencesStore::new(&APP_INFO)
+            .unwrap()
+            .with_memory_backend(MemoryBackend::new());
+        let journal = store.journal("tests/module/journal");
+        assert!(journal.load_latest::<u32>().unwrap_err().is_not_found());
+        for i in 0..3u32 {
+            journal.append(&i).unwrap();
+        }
+        assert_eq!(journal.load_latest::<u32>().unwrap(), 2);
+        let values: Vec<u32> = journal.history().unwrap().into_iter().map(|e| e.value).collect();
+        assert_eq!(values, vec![0, 1, 2]);
+        assert!(!store.exists("tests/module/journal").unwrap());
+        journal.clear().unwrap();
+        assert!(journal.history::<u32>().unwrap_err().is_not_found());
+    }
+
+    #[test]
+    fn test_journal_interrupted_append() {
+        let dir = TestDir::new("journal");
+        let journal = dir.store().journal("j");
+        journal.append(&1u32).unwrap();
+        let mut file = OpenOptions::new().append(true).open(journal.path().unwrap()).unwrap();
+        file.write_all(b"{\"time\":12").unwrap();
+        assert_eq!(journal.load_latest::<u32>().unwrap(), 1);
+        journal.append(&2u32).unwrap();
+        assert_eq!(journal.history::<u32>().unwrap().len(), 2);
+        assert_eq!(journal.load_latest::<u32>().unwrap(), 2);
+    }
+}
@@ -0,0 +1,462 @@
+//! Mapping between preferences keys and file system paths.
+
+use std::error::Error;
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+
+/// The longest file name, in bytes, that a key segment may map to. File systems commonly allow
+/// 255, which leaves room for the suffixes of data, temporary and backup files.
+pub const MAX_SEGMENT_LEN: usize = 200;
+
+/// Names that Windows reserves for devices, even with an extension.
+const RESERVED_NAMES: &[&str] = &["con", "prn", "aux", "nul", "com1", "com2", "com3", "com4",
+                                  "com5", "com6", "com7", "com8", "com9", "lpt1", "lpt2", "lpt3",
+                                  "lpt4", "lpt5", "lpt6", "lpt7", "lpt8", "lpt9"];
+
+/// The longest path that Windows accepts without the `\\?\` prefix, including the terminating
+/// null character.
+#[cfg(any(windows, test))]
+const MAX_PATH: usize = 260;
+
+/// Error type describing why a key isn't a valid preferences key.
+///
+/// See [`validate_key`](fn.validate_key.html).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KeyError {
+    /// The key has no segments.
+    Empty,
+    /// The key contains an empty segment (e.g. a leading, trailing or double slash).
+    EmptySegment,
+    /// A segment of the key starts with a period, which would make it a hidden file on some
+    /// platforms.
+    LeadingPeriod(String),
+    /// A segment of the key contains a character that isn't allowed in file names on all
+    /// platforms.
+    InvalidCharacter(String, char),
+    /// A segment of the key is a name that Windows reserves for devices (like `con` or `lpt1`),
+    /// or ends with a period or space, which Windows removes from file names. Segments that would
+    /// map to a file name that refers to another directory (like `..`) are also reported as
+    /// reserved, even without strict key validation.
+    ReservedName(String),
+    /// A segment of the key is longer than
+    /// [`MAX_SEGMENT_LEN`](constant.MAX_SEGMENT_LEN.html) bytes once mapped to a file name, so
+    /// it can't be stored on common file systems. Unlike other errors, this is reported even
+    /// without strict key validation.
+    TooLong(String),
+    /// The key is an absolute path, starts with a drive letter (like `C:`), or has a `.` or `..`
+    /// segment (also between backslashes), so a platform could interpret it as a path outside of
+    /// the app's directory. Like `TooLong`, this is reported even without strict key
+    /// validation.
+    Traversal(String),
+}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::KeyError::*;
+        match *self {
+            Empty => f.write_str("Preferences key is empty"),
+            EmptySegment => f.write_str("Preferences key contains an empty segment"),
+            LeadingPeriod(ref segment) => {
+                write!(f, "Preferences key segment {:?} starts with a period", segment)
+            }
+            InvalidCharacter(ref segment, c) => {
+                write!(f,
+                       "Preferences key segment {:?} contains invalid character {:?}",
+                       segment,
+                       c)
+            }
+            ReservedName(ref segment) => {
+                write!(f, "Preferences key segment {:?} is reserved on Windows", segment)
+            }
+            TooLong(ref segment) => {
+                write!(f, "Preferences key segment {:?} is too long for a file name", segment)
+            }
+            Traversal(ref key) => {
+                write!(f,
+                       "Preferences key {:?} could refer to a path outside of the app's directory",
+                       key)
+            }
+        }
+    }
+}
+
+impl Error for KeyError {}
+
+/// Checks that `key` maps to a file path without being transformed.
+///
+/// Valid keys consist of one or more segments separated by forward slashes. Each segment must be
+/// non-empty, must not start with a period, and may only contain ASCII letters, digits, spaces,
+/// hyphens, underscores, and periods. Segments must also be usable as file names on Windows:
+/// they can't be device names like `con`, can't end with a period or space, and can be at most
+/// [`MAX_SEGMENT_LEN`](constant.MAX_SEGMENT_LEN.html) bytes long. Other keys are still accepted
+/// by [`Preferences`](trait.Preferences.html), but are sanitized first, so two different keys
+/// may end up referring to the same data. Use [`set_strict_keys`](fn.set_strict_keys.html) to
+/// reject such keys instead. Keys that look like paths outside of the app's directory (like
+/// `../other`) are always rejected, with `KeyError::Traversal`.
+///
+/// # Example
+/// ```
+/// use preferences::{KeyError, validate_key};
+///
+/// assert!(validate_key("options/graphics").is_ok());
+/// assert_eq!(validate_key("options//graphics"), Err(KeyError::EmptySegment));
+/// ```
+pub fn validate_key(key: &str) -> Result<(), KeyError> {
+    if key.is_empty() {
+        return Err(KeyError::Empty);
+    }
+    check_traversal(key)?;
+    for segment in key.split('/') {
+        if segment.is_empty() {
+            return Err(KeyError::EmptySegment);
+        }
+        if segment.starts_with('.') {
+            return Err(KeyError::LeadingPeriod(segment.into()));
+        }
+        let invalid = segment.chars().find(|&c| {
+            !(c.is_ascii_alphanumeric() || c == ' ' || c == '-' || c == '_' || c == '.')
+        });
+        if let Some(c) = invalid {
+            return Err(KeyError::InvalidCharacter(segment.into(), c));
+        }
+        if is_reserved_name(segment) || segment.ends_with(' ') || segment.ends_with('.') {
+            return Err(KeyError::ReservedName(segment.into()));
+        }
+        if segment.len() > MAX_SEGMENT_LEN {
+            return Err(KeyError::TooLong(segment.into()));
+        }
+    }
+    Ok(())
+}
+
+/// Returns the file name that a key segment maps to: the segment escaped by `sanitized`, and on
+/// Windows, with reserved names escaped as well.
+///
+/// # Failures
+/// `KeyError::TooLong` if the file name would be longer than `MAX_SEGMENT_LEN`.
+pub fn segment_file_name(segment: &str) -> Result<String, KeyError> {
+    let mut name = sanitized(segment);
+    if cfg!(windows) {
+        name = escape_reserved(name);
+    }
+    if name.len() > MAX_SEGMENT_LEN {
+        return Err(KeyError::TooLong(segment.into()));
+    }
+    // `sanitized` escapes leading periods and separators, so this can't happen, but the data
+    // must never end up outside of the app's directory even if its escaping changes.
+    let mut components = Path::new(&name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(file_name)), None) if file_name == name.as_str() => Ok(name),
+        _ => Err(KeyError::ReservedName(segment.into())),
+    }
+}
+
+/// Checks that `key` isn't absolute, doesn't start with a drive letter, and has no `.` or `..`
+/// segments, whether separated by slashes or backslashes.
+///
+/// Such keys are rejected regardless of strict key validation: their segments would be escaped
+/// like any others, but whether a key can refer to a file outside of the app's directory
+/// shouldn't depend on the details of that escaping.
+///
+/// # Failures
+/// `KeyError::Traversal`.
+pub fn check_traversal(key: &str) -> Result<(), KeyError> {
+    let has_drive = match key.as_bytes() {
+        [drive, b':'] | [drive, b':', b'/' | b'\\', ..] => drive.is_ascii_alphabetic(),
+        _ => false,
+    };
+    if key.starts_with(['/', '\\']) || has_drive ||
+       key.split(['/', '\\']).any(|segment| segment == "." || segment == "..") {
+        return Err(KeyError::Traversal(key.into()));
+    }
+    Ok(())
+}
+
+/// Returns the canonical form of `key`: the key with which its data is actually stored, and
+/// which [`list_prefs`](fn.list_prefs.html) returns for it (unless it's empty).
+///
+/// Keys are stored without empty segments, so e.g. `options//graphics/` is the same key as
+/// `options/graphics`. Two keys refer to the same data exactly when their canonical forms are
+/// equal. Each segment is stored in a file or directory of the app's directory whose name is the
+/// segment with unsafe characters escaped, so no key can refer to a file outside of it.
+///
+/// # Failures
+/// `KeyError::Traversal` if the key could be interpreted as a path outside of the app's
+/// directory (see `KeyError::Traversal`), or `KeyError::TooLong` if a segment is too long to be
+/// stored.
+///
+/// # Example
+/// ```
+/// use preferences::{KeyError, canonical_key};
+///
+/// assert_eq!(canonical_key("options//graphics/").unwrap(), "options/graphics");
+/// assert_eq!(canonical_key("../../etc/passwd"),
+///            Err(KeyError::Traversal("../../etc/passwd".into())));
+/// ```
+pub fn canonical_key(key: &str) -> Result<String, KeyError> {
+    check_traversal(key)?;
+    let segments = key_segments(key);
+    for segment in &segments {
+        segment_file_name(segment)?;
+    }
+    Ok(segments.join("/"))
+}
+
+/// Escapes the first character of a reserved device name, and a trailing period or space, the
+/// same way as `sanitized` escapes other characters, so `unsanitized` still reverses it.
+fn escape_reserved(mut name: String) -> String {
+    let escape = |c: char| format!(",{},", c as u32);
+    if is_reserved_name(&name) {
+        let first = name.remove(0);
+        name.insert_str(0, &escape(first));
+    }
+    if name.ends_with(' ') || name.ends_with('.') {
+        let last = name.pop().unwrap_or(' ');
+        name.push_str(&escape(last));
+    }
+    name
+}
+
+/// Returns whether Windows reserves the file `name` for a device.
+fn is_reserved_name(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or(name).trim_end();
+    RESERVED_NAMES.iter().any(|reserved| stem.eq_ignore_ascii_case(reserved))
+}
+
+/// Adds the `\\?\` prefix to `path` if it's too long for the Windows API otherwise.
+#[cfg(windows)]
+pub fn extended_length_path(path: PathBuf) -> PathBuf {
+    match path.to_str().and_then(extended_length) {
+        Some(extended) => extended.into(),
+        None => path,
+    }
+}
+
+/// Does nothing, since paths are only limited in length on Windows.
+#[cfg(not(windows))]
+pub fn extended_length_path(path: PathBuf) -> PathBuf {
+    path
+}
+
+/// Returns the extended-length form of the absolute Windows `path` if it's at least `MAX_PATH`
+/// long, or `None` if it's short enough, relative, or already extended.
+#[cfg(any(windows, test))]
+fn extended_length(path: &str) -> Option<String> {
+    if path.encode_utf16().count() < MAX_PATH || path.starts_with(r"\\?\") {
+        return None;
+    }
+    if let Some(share) = path.strip_prefix(r"\\") {
+        return Some(format!(r"\\?\UNC\{}", share));
+    }
+    match path.as_bytes() {
+        [drive, b':', b'\\', ..] if drive.is_ascii_alphabetic() => {
+            Some(format!(r"\\?\{}", path))
+        }
+        _ => None,
+    }
+}
+
+/// Escapes `segment` for use as a file name: each character that isn't an ASCII letter or digit,
+/// a space, a hyphen, an underscore or a period (other than a leading one) is replaced by
+/// `,<code point>,`.
+///
+/// This is the escaping of the `app_dirs` crate, which earlier versions used, so existing files
+/// keep their names.
+pub(crate) fn sanitized(segment: &str) -> String {
+    let mut buf = String::with_capacity(segment.len());
+    for (i, c) in segment.chars().enumerate() {
+        if c.is_ascii_alphanumeric() || c == ' ' || c == '-' || c == '_' || (c == '.' && i != 0) {
+            buf.push(c);
+        } else {
+            buf.push_str(&format!(",{},", c as u32));
+        }
+    }
+    buf
+}
+
+/// Reverses the escaping applied by `sanitized` to a single path segment, where each character
+/// that isn't filename-safe is replaced by `,<code point>,`.
+///
+/// Returns `None` if `segment` couldn't have been produced by that escaping.
+pub fn unsanitized(segment: &str) -> Option<String> {
+    let mut buf = String::with_capacity(segment.len());
+    let mut chars = segment.chars();
+    while let Some(c) = chars.next() {
+        if c != ',' {
+            buf.push(c);
+            continue;
+        }
+        let code: String = chars.by_ref().take_while(|&c| c != ',').collect();
+        let code = code.parse().ok()?;
+        buf.push(::std::char::from_u32(code)?);
+    }
+    Some(buf)
+}
+
+/// Splits a key into its non-empty, slash-separated segments.
+pub fn key_segments(key: &str) -> Vec<&str> {
+    key.split('/').filter(|s| !s.is_empty()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{KeyError, MAX_SEGMENT_LEN, canonical_key, check_traversal, escape_reserved,
+                extended_length, sanitized, segment_file_name, unsanitized, validate_key};
+    use crate::file_path_in;
+    use crate::testing::TestDir;
+    use proptest::prelude::*;
+    use std::collections::BTreeMap;
+    use std::path::{Component, Path};
+
+    /// Keys made of arbitrary segments, and of segments that try to escape the app's directory.
+    fn any_key() -> impl Strategy<Value = String> {
+        let segment = prop_oneof![Just("..".to_owned()),
+                                  Just(".".to_owned()),
+                                  Just(String::new()),
+                                  Just("C:".to_owned()),
+                                  Just(r"..\..".to_owned()),
+                                  Just("con".to_owned()),
+                                  "[a-z.]{1,3}",
+                                  any::<String>()];
+        prop::collection::vec(segment, 0..6).prop_map(|segments| segments.join("/"))
+    }
+
+    #[test]
+    fn test_unsanitized_round_trip() {
+        for segment in &["plain", "with space", "a,b", "ünïcødé", ".hidden", "a:b\\c", ""] {
+            assert_eq!(unsanitized(&sanitized(segment)).as_deref(), Some(*segment));
+        }
+        assert_eq!(unsanitized("bad,code"), None);
+        assert_eq!(unsanitized("bad,99999999,"), None);
+        assert_eq!(sanitized(".a.b c/ü"), ",46,a.b c,47,,252,");
+        #[cfg(feature = "app_dirs")]
+        for segment in &["plain", "with space", "a,b", "ünïcødé", ".hidden", "a:b\\c", ""] {
+            assert_eq!(sanitized(segment), app_dirs::sanitized(segment));
+        }
+    }
+
+    #[test]
+    fn test_validate_key() {
+        for key in &["a", "options/graphics", "with space/and-hyphen_underscore.period"] {
+            assert_eq!(validate_key(key), Ok(()));
+            for segment in key.split('/') {
+                assert_eq!(sanitized(segment), *segment);
+            }
+        }
+        assert_eq!(validate_key(""), Err(KeyError::Empty));
+        assert_eq!(validate_key("a//b"), Err(KeyError::EmptySegment));
+        assert_eq!(validate_key("a/"), Err(KeyError::EmptySegment));
+        assert_eq!(validate_key("a/.b"), Err(KeyError::LeadingPeriod(".b".into())));
+        assert_eq!(validate_key("a/b:c"),
+                   Err(KeyError::InvalidCharacter("b:c".into(), ':')));
+        assert_eq!(validate_key("Théme"),
+                   Err(KeyError::InvalidCharacter("Théme".into(), 'é')));
+        assert_eq!(validate_key("a/CON"), Err(KeyError::ReservedName("CON".into())));
+        assert_eq!(validate_key("lpt1.txt/b"), Err(KeyError::ReservedName("lpt1.txt".into())));
+        assert_eq!(validate_key("a /b"), Err(KeyError::ReservedName("a ".into())));
+        assert_eq!(validate_key("console"), Ok(()));
+        let long = "x".repeat(MAX_SEGMENT_LEN + 1);
+        assert_eq!(validate_key(&long), Err(KeyError::TooLong(long.clone())));
+    }
+
+    #[test]
+    fn test_escape_reserved() {
+        for name in &["con", "Aux", "nul.json", "com9", "trailing.", "trailing "] {
+            let escaped = escape_reserved(name.to_string());
+            assert_ne!(escaped, *name);
+            assert_eq!(unsanitized(&escaped).as_deref(), Some(*name));
+        }
+        assert_eq!(escape_reserved("con".into()), ",99,on");
+        assert_eq!(escape_reserved("a.".into()), "a,46,");
+        for name in &["console", "com10", "icon", "a.b"] {
+            assert_eq!(escape_reserved(name.to_string()), *name);
+        }
+
+        // Each `ü` is escaped as `,252,`.
+        assert!(segment_file_name(&"ü".repeat(MAX_SEGMENT_LEN / 5)).is_ok());
+        let long = "ü".repeat(MAX_SEGMENT_LEN / 5 + 1);
+        assert_eq!(segment_file_name(&long), Err(KeyError::TooLong(long.clone())));
+    }
+
+    #[test]
+    fn test_check_traversal() {
+        for key in &["..", "a/../b", "./a", "a/.", r"a\..\b", r"..\b", "/etc/passwd", r"\\server",
+                     "C:", "c:/Windows", r"C:\Windows"] {
+            assert_eq!(check_traversal(key), Err(KeyError::Traversal(key.to_string())));
+            assert_eq!(validate_key(key), Err(KeyError::Traversal(key.to_string())));
+        }
+        for key in &["", "a", "a..b/c.", ".hidden", "a/C:", "ab:c", "a:b"] {
+            assert_eq!(check_traversal(key), Ok(()));
+        }
+    }
+
+    #[test]
+    fn test_canonical_key() {
+        assert_eq!(canonical_key("").unwrap(), "");
+        assert_eq!(canonical_key("a///b//").unwrap(), "a/b");
+        assert_eq!(canonical_key("a..b/c.").unwrap(), "a..b/c.");
+        let long = "x".repeat(MAX_SEGMENT_LEN + 1);
+        assert_eq!(canonical_key(&format!("a/{}", long)), Err(KeyError::TooLong(long.clone())));
+    }
+
+    proptest! {
+        #[test]
+        fn test_keys_map_into_app_dir(key in any_key()) {
+            let root = Path::new("/app");
+            match canonical_key(&key) {
+                Ok(canonical) => {
+                    let path = file_path_in(root, &key, "json").unwrap();
+                    prop_assert!(path.starts_with(root));
+                    let normal = |c| c == Component::RootDir || matches!(c, Component::Normal(_));
+                    prop_assert!(path.components().all(normal));
+                    prop_assert_eq!(file_path_in(root, &canonical, "json").unwrap(), path);
+                    prop_assert_eq!(canonical_key(&canonical).unwrap(), canonical);
+                }
+                Err(e) => {
+                    prop_assert!(matches!(e, KeyError::TooLong(_) | KeyError::Traversal(_)));
+                    prop_assert!(file_path_in(root, &key, "json").is_err());
+                }
+            }
+        }
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        #[test]
+        fn test_keys_round_trip(key in any_key(),
+                                value in any::<BTreeMap<String, (i64, Option<String>)>>()) {
+            let dir = TestDir::new("keys");
+            for store in dir.stores() {
+                let canonical = match canonical_key(&key) {
+                    Ok(canonical) => canonical,
+                    Err(_) => {
+                        prop_assert!(store.save(&key, &value).is_err());
+                        continue;
+                    }
+                };
+                store.save(&key, &value).unwrap();
+                prop_assert_eq!(&store.load::<BTreeMap<_, _>, _>(&key).unwrap(), &value);
+                prop_assert_eq!(&store.load::<BTreeMap<_, _>, _>(&canonical).unwrap(), &value);
+                // The data of the empty key is stored in the app's directory itself.
+                if !canonical.is_empty() {
+                    prop_assert!(store.list("").unwrap().contains(&canonical));
+                }
+                store.delete_all("").unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_extended_length() {
+        let dir = format!(r"C:\Users\me\AppData\Roaming\{}", "x".repeat(250));
+        assert_eq!(extended_length(&dir), Some(format!(r"\\?\{}", dir)));
+        assert_eq!(extended_length(r"C:\Users\me\AppData"), None);
+        let extended = format!(r"\\?\{}", dir);
+        assert_eq!(extended_length(&extended), None);
+        let share = format!(r"\\server\share\{}", "x".repeat(250));
+        assert_eq!(extended_length(&share),
+                   Some(format!(r"\\?\UNC\server\share\{}", "x".repeat(250))));
+        assert_eq!(extended_length(&"x".repeat(300)), None);
+    }
+}
@@ -0,0 +1,301 @@
+//! Preferences merged from several layers: defaults, system-wide, user, environment and command
+//! line.
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::{self, Map, Value};
+#[cfg(feature = "cli")]
+use crate::CliOverrides;
+use crate::{AppInfo, EnvOverrides, PreferencesError, PreferencesStore, Scope};
+
+/// User data that is merged from several layers when it's loaded.
+///
+/// The layers are, from lowest to highest precedence:
+///
+/// 1. Compiled-in defaults, set with `with_defaults`.
+/// 2. The system-wide file, in [`Scope::System`](enum.Scope.html#variant.System), if enabled
+///    with `with_system_layer`.
+/// 3. The user's file, as saved with [`Preferences::save`](trait.Preferences.html#tymethod.save).
+/// 4. Environment variables, if enabled with `with_env_overrides` or `with_env_prefix`.
+/// 5. Command-line arguments, if enabled with `with_cli_overrides` (requires the `cli`
+///    feature).
+///
+/// Maps (e.g. structs) are merged field by field, recursively, so each layer only needs to
+/// contain the fields it overrides. Any other value in a higher layer replaces the value in the
+/// lower layers.
+///
+/// Saving writes only to the user's file, and only the fields that differ from the lower layers,
+/// so that e.g. a default that the user never changed can still be updated by a new version of
+/// the app.
+///
+/// # Example
+/// ```
+/// #[macro_use]
+/// extern crate serde_derive;
+/// extern crate preferences;
+/// use preferences::{AppInfo, LayeredPreferences};
+///
+/// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+///
+/// #[derive(Serialize, Deserialize, Debug, PartialEq)]
+/// struct Options {
+///     volume: u8,
+///     theme: String,
+/// }
+///
+/// fn main() {
+///     # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
+///     let defaults = Options { volume: 5, theme: "light".into() };
+///     let layered = LayeredPreferences::new(&APP_INFO, "tests/docs/layered")
+///         .unwrap()
+///         .with_defaults(&defaults)
+///         .unwrap();
+///
+///     let mut options: Options = layered.load().unwrap();
+///     options.theme = "dark".into();
+///     layered.save(&options).unwrap();
+///
+///     assert_eq!(layered.load::<Options>().unwrap(), options);
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct LayeredPreferences {
+    key: String,
+    defaults: Option<Value>,
+    system: Option<PreferencesStore>,
+    user: PreferencesStore,
+    env: Option<EnvOverrides>,
+    #[cfg(feature = "cli")]
+    cli: Option<CliOverrides>,
+}
+
+impl LayeredPreferences {
+    /// Creates layered preferences for the user data of `app` with the given `key`, with only
+    /// the user layer enabled.
+    ///
+    /// # Failures
+    /// If the data directory cannot be determined (e.g. because `app` has an empty name).
+    pub fn new<S: AsRef<str>>(app: &AppInfo, key: S) -> Result<Self, PreferencesError> {
+        Ok(LayeredPreferences::with_store(PreferencesStore::new(app)?, key))
+    }
+
+    /// Same as `new`, but uses `store` for the user layer.
+    pub fn with_store<S: AsRef<str>>(store: PreferencesStore, key: S) -> Self {
+        LayeredPreferences {
+            key: key.as_ref().to_owned(),
+            defaults: None,
+            system: None,
+            user: store,
+            env: None,
+            #[cfg(feature = "cli")]
+            cli: None,
+        }
+    }
+
+    /// Sets the compiled-in defaults, which form the lowest layer.
+    ///
+    /// # Failures
+    /// If `defaults` cannot be serialized.
+    pub fn with_defaults<T: Serialize>(mut self, defaults: &T) -> Result<Self, PreferencesError> {
+        self.defaults = Some(serde_json::to_value(defaults)?);
+        Ok(self)
+    }
+
+    /// Enables the system-wide layer, which is read from the same key in
+    /// [`Scope::System`](enum.Scope.html#variant.System).
+    ///
+    /// # Failures
+    /// If the system-wide data directory cannot be determined.
+    pub fn with_system_layer(mut self) -> Result<Self, PreferencesError> {
+        self.system = Some(self.user.clone().with_scope(Scope::System)?);
+        Ok(self)
+    }
+
+    /// Enables the environment layer, which applies `overrides`.
+    pub fn with_env_overrides(mut self, overrides: EnvOverrides) -> Self {
+        self.env = Some(overrides);
+        self
+    }
+
+    /// Same as `with_env_overrides(EnvOverrides::new(prefix))`, e.g. so that `MYAPP_VOLUME`
+    /// overrides the field `volume` for the prefix `MYAPP_`.
+    pub fn with_env_prefix<S: AsRef<str>>(self, prefix: S) -> Self {
+        self.with_env_overrides(EnvOverrides::new(prefix))
+    }
+
+    /// Enables the command-line layer, which applies `overrides` on top of all other layers.
+    ///
+    /// Requires the `cli` feature.
+    #[cfg(feature = "cli")]
+    pub fn with_cli_overrides(mut self, overrides: CliOverrides) -> Self {
+        self.cli = Some(overrides);
+        self
+    }
+
+    /// Returns the key of the user data.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Returns the store of the user layer.
+    pub fn user_store(&self) -> &PreferencesStore {
+        &self.user
+    }
+
+    /// Loads all layers and merges them.
+    ///
+    /// # Failures
+    /// If a layer that exists cannot be loaded, or if the merged value cannot be deserialized as
+    /// `T` (e.g. because no layer has a required field). Missing layers are skipped.
+    pub fn load<T: DeserializeOwned>(&self) -> Result<T, PreferencesError> {
+        let mut merged = self.load_lower_layers()?;
+        if let Some(user) = load_layer(&self.user, &self.key)? {
+            merge(&mut merged, user);
+        }
+        if let Some(ref env) = self.env {
+            env.apply_to_value(&mut merged);
+        }
+        #[cfg(feature = "cli")]
+        if let Some(ref cli) = self.cli {
+            cli.apply_to_value(&mut merged);
+        }
+        serde_json::from_value(merged).map_err(Into::into)
+    }
+
+    /// Saves `value` to the user layer, leaving out the fields that are equal in the lower
+    /// layers (including environment overrides). Fields that still have the values of
+    /// command-line overrides keep the values they had before the overrides.
+    ///
+    /// # Failures
+    /// Same as [`Preferences::save`](trait.Preferences.html#tymethod.save).
+    pub fn save<T: Serialize>(&self, value: &T) -> Result<(), PreferencesError> {
+        let mut lower = self.load_lower_layers()?;
+        if let Some(ref env) = self.env {
+            env.apply_to_value(&mut lower);
+        }
+        let value = serde_json::to_value(value)?;
+        #[cfg(feature = "cli")]
+        let value = self.restore_cli_overrides(value, &lower)?;
+        let user = match diff(&value, &lower) {
+            Some(user) => user,
+            None if value.is_object() => Value::Object(Map::new()),
+            None => value,
+        };
+        self.user.save(&self.key, &user)
+    }
+
+    /// Restores the values that fields of `value` had before the command-line overrides, if they
+    /// still have the overridden values, given the `lower` layers.
+    #[cfg(feature = "cli")]
+    fn restore_cli_overrides(&self,
+                             mut value: Value,
+                             lower: &Value)
+                             -> Result<Value, PreferencesError> {
+        if let Some(ref cli) = self.cli {
+            let mut previous = lower.clone();
+            if let Some(user) = load_layer(&self.user, &self.key)? {
+                merge(&mut previous, user);
+            }
+            cli.restore(&mut value, &previous);
+        }
+        Ok(value)
+    }
+
+    /// Merges the defaults and the system-wide layer.
+    fn load_lower_layers(&self) -> Result<Value, PreferencesError> {
+        let mut merged = self.defaults.clone().unwrap_or(Value::Null);
+        if let Some(ref system) = self.system {
+            if let Some(layer) = load_layer(system, &self.key)? {
+                merge(&mut merged, layer);
+            }
+        }
+        Ok(merged)
+    }
+}
+
+fn load_layer(store: &PreferencesStore, key: &str) -> Result<Option<Value>, PreferencesError> {
+    match store.load(key) {
+        Ok(value) => Ok(Some(value)),
+        Err(ref e) if e.is_not_found() => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Merges `overlay` into `base`: maps are merged recursively, and other values are replaced.
+pub(crate) fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (&mut Value::Object(ref mut base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                merge(base.entry(key).or_insert(Value::Null), value);
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Returns the parts of `value` that differ from `base`, such that merging them into `base`
+/// results in `value`, or `None` if they're equal.
+fn diff(value: &Value, base: &Value) -> Option<Value> {
+    if value == base {
+        return None;
+    }
+    match (value, base) {
+        (Value::Object(value), Value::Object(base)) => {
+            let mut changed = Map::new();
+            for (key, field) in value {
+                let changed_field = match base.get(key) {
+                    Some(base_field) => diff(field, base_field),
+                    None => Some(field.clone()),
+                };
+                if let Some(changed_field) = changed_field {
+                    changed.insert(key.clone(), changed_field);
+                }
+            }
+            Some(Value::Object(changed))
+        }
+        _ => Some(value.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LayeredPreferences;
+    use crate::{AppInfo, MemoryBackend, PreferencesStore, Scope, set_backend_for_tests};
+    use serde_json::{self, Value};
+
+    const APP_INFO: AppInfo = AppInfo {
+        name: "preferences",
+        author: "Rust language community",
+    };
+
+    #[test]
+    fn test_layered() {
+        set_backend_for_tests(Some(MemoryBackend::new()));
+        let key = "tests/module/layered";
+        let system = PreferencesStore::new(&APP_INFO)
+            .unwrap()
+            .with_scope(Scope::System)
+            .unwrap();
+        system.save(key, &json(r#"{"window": {"width": 800}, "theme": "blue"}"#)).unwrap();
+
+        let layered = LayeredPreferences::new(&APP_INFO, key)
+            .unwrap()
+            .with_defaults(&json(r#"{"window": {"width": 640, "height": 480}, "theme": "light"}"#))
+            .unwrap()
+            .with_system_layer()
+            .unwrap();
+        let merged: Value = layered.load().unwrap();
+        assert_eq!(merged,
+                   json(r#"{"window": {"width": 800, "height": 480}, "theme": "blue"}"#));
+
+        layered.save(&json(r#"{"window": {"width": 800, "height": 600}, "theme": "blue"}"#))
+            .unwrap();
+        let user: Value = layered.user_store().load(key).unwrap();
+        assert_eq!(user, json(r#"{"window": {"height": 600}}"#));
+        set_backend_for_tests(None);
+    }
+
+    fn json(text: &str) -> Value {
+        serde_json::from_str(text).unwrap()
+    }
+}
@@ -0,0 +1,124 @@
+//! Mapping of keys to the paths of their files, and moving data between such mappings.
+
+use std::fs;
+use std::path::Path;
+use crate::compression;
+use crate::lock::{FileLock, Wait};
+use crate::{Format, Operation, PreferencesError, PreferencesStore, remove_empty_dirs};
+
+/// Where a [`PreferencesStore`](struct.PreferencesStore.html) keeps the file of each key, set
+/// with [`with_key_layout`](struct.PreferencesStore.html#method.with_key_layout).
+///
+/// Either way, the segments of a key before the last one are directories, and the empty key is
+/// saved in `prefs.json` (in JSON) in the app's directory.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum KeyLayout {
+    /// The last segment of a key names its file, e.g. the key `options/graphics` is saved in
+    /// `options/graphics.prefs.json`. This is how keys have always been saved.
+    #[default]
+    LeafAsFile,
+    /// The last segment of a key names a directory, which holds its file, e.g. the key
+    /// `options/graphics` is saved in `options/graphics/prefs.json`. This keeps all files of a
+    /// key, like its backups, in a directory of their own.
+    LeafAsDir,
+}
+
+/// Moves the data of all keys that `store` lists in the `from` layout to the files of its own
+/// layout, and returns the keys that were moved. Keys whose data already exists in the store's
+/// layout are skipped.
+pub(crate) fn migrate(store: &PreferencesStore,
+                      from: KeyLayout)
+                      -> Result<Vec<String>, PreferencesError> {
+    let mut migrated = Vec::new();
+    if from == store.key_layout() || !store.writes()? {
+        return Ok(migrated);
+    }
+    let old = store.clone().with_key_layout(from);
+    for key in old.list("")? {
+        let mut moved = false;
+        for &format in Format::all() {
+            let old_path = old.file_path(&key, format)?;
+            let new_path = store.file_path(&key, format)?;
+            moved |= move_file(store, &old_path, &new_path)
+                .map_err(|e| e.with_context(Operation::Save, &key, &new_path))?;
+        }
+        if moved {
+            migrated.push(key);
+        }
+    }
+    Ok(migrated)
+}
+
+/// Moves the preferences file at `old_path` to `new_path`, unless it doesn't exist or there is
+/// a file at `new_path` already. Returns whether the file was moved.
+fn move_file(store: &PreferencesStore,
+             old_path: &Path,
+             new_path: &Path)
+             -> Result<bool, PreferencesError> {
+    if let Some(memory) = store.memory() {
+        if !memory.contains(old_path) || memory.contains(new_path) {
+            return Ok(false);
+        }
+        memory.write(new_path, memory.read(old_path)?)?;
+        memory.remove(old_path)?;
+        return Ok(true);
+    }
+    if !compression::find(old_path).0.is_file() {
+        return Ok(false);
+    }
+    let lock = FileLock::acquire(old_path, true, Wait::Block)?;
+    let (actual, compression) = compression::find(old_path);
+    if !actual.is_file() || compression::find(new_path).0.is_file() {
+        return Ok(false);
+    }
+    if let Some(dir) = new_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::rename(&actual, compression.apply_to(new_path))?;
+    lock.remove();
+    remove_empty_dirs(store.root(), old_path.parent());
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::KeyLayout;
+    use crate::testing::TestDir;
+    use crate::Format;
+
+    #[test]
+    fn test_key_layout() {
+        let dir = TestDir::new("layout");
+        for files in dir.stores() {
+            let dirs = files.clone().with_key_layout(KeyLayout::LeafAsDir);
+            let root = files.root().to_owned();
+            assert_eq!(dirs.file_path("options/graphics", Format::Json).unwrap(),
+                       root.join("options").join("graphics").join("prefs.json"));
+            dirs.save("options/graphics", &1u32).unwrap();
+            dirs.save("options/graphics/detail", &2u32).unwrap();
+            assert_eq!(dirs.list("").unwrap(), ["options/graphics", "options/graphics/detail"]);
+            assert!(files.list("").unwrap().is_empty());
+            dirs.delete("options/graphics/detail").unwrap();
+            assert_eq!(dirs.list("options").unwrap(), ["options/graphics"]);
+
+            // Data saved in the other layout is only found with the fallback.
+            files.save("theme", &"dark").unwrap();
+            assert!(dirs.load::<String, _>("theme").unwrap_err().is_not_found());
+            let fallback = dirs.clone().with_layout_fallback(true);
+            assert_eq!(fallback.load::<String, _>("theme").unwrap(), "dark");
+
+            assert_eq!(dirs.migrate_layout(KeyLayout::LeafAsFile).unwrap(), ["theme"]);
+            assert_eq!(dirs.load::<String, _>("theme").unwrap(), "dark");
+            assert!(files.list("").unwrap().is_empty());
+            assert_eq!(files.migrate_layout(KeyLayout::LeafAsDir).unwrap(),
+                       ["options/graphics", "theme"]);
+            assert_eq!(files.load::<u32, _>("options/graphics").unwrap(), 1);
+            assert!(dirs.list("").unwrap().is_empty());
+            if !files.is_in_memory() {
+                assert!(root.join("options").join("graphics.prefs.json").is_file());
+                assert!(!root.join("theme").exists());
+            }
+            files.delete_all("").unwrap();
+        }
+    }
+}
@@ -27,6 +27,7 @@
 //! const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
 //!
 //! fn main() {
+//!     # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
 //!
 //!     // Create a new preferences key-value map
 //!     // (Under the hood: HashMap<String, String>)
@@ -69,6 +70,7 @@
 //! }
 //!
 //! fn main() {
+//!     # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
 //!
 //!     let player = PlayerData{level: 2, health: 0.75};
 //!
@@ -97,6 +99,7 @@
 //! struct Point(f32, f32);
 //!
 //! fn main() {
+//!     # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
 //!
 //!     let mut places = PreferencesMap::new();
 //!     places.insert("treasure".into(), Point(1.0, 1.0));
@@ -126,6 +129,7 @@
 //! struct Point(usize, usize);
 //!
 //! fn main() {
+//!     # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
 //!
 //!     let square = vec![
 //!         Point(0,0),
@@ -145,19 +149,164 @@
 //! }
 //! ```
 //!
+//! # Navigating the preferences hierarchy
+//! Keys form a hierarchy, much like the nodes of Java's Preferences API.
+//! [`PreferencesNode`](struct.PreferencesNode.html) lets you navigate that hierarchy at runtime:
+//! list the values and children of a node, read and write values relative to it, and remove
+//! whole subtrees.
+//!
+//! # Layered defaults
+//! [`LayeredPreferences`](struct.LayeredPreferences.html) merges compiled-in defaults, a
+//! system-wide file and environment variables with the user's file, and saves only what the user
+//! changed. With the `cli` feature, [`CliOverrides`](struct.CliOverrides.html) adds a `--set
+//! ui.theme=dark` argument to the app's command line, which overrides preferences for one run
+//! without saving them.
+//!
+//! With the `cli` feature, the crate also builds a `prefs` binary, which lists, prints, sets and
+//! deletes the preferences of any app (given with `--app` and `--author`), and prints the paths
+//! of their files, e.g. to help debug a user's configuration.
+//!
+//! # Migrating from other locations
+//! [`migrate_from_path`](fn.migrate_from_path.html) and [`migrate_from`](fn.migrate_from.html)
+//! import the configuration file of a previous version of the app, or of the `confy` crate, into
+//! the app's preferences, and report what they imported. With
+//! [`MigrateOptions`](struct.MigrateOptions.html), they also remove the imported files.
+//!
+//! # Typed keys
+//! A [`PrefKey`](struct.PrefKey.html) declares a key together with the type of its value, so that
+//! [`PreferencesStore::get`](struct.PreferencesStore.html#method.get) and
+//! [`set`](struct.PreferencesStore.html#method.set) can't load or save it as another type.
+//!
+//! # Testing
+//! To keep tests from reading and writing the real user data, tests can install a
+//! [`MemoryBackend`](struct.MemoryBackend.html) with
+//! [`set_backend_for_tests`](fn.set_backend_for_tests.html), which keeps all data saved on the
+//! current thread in memory instead.
+//!
+//! # Watching for changes
+//! With the `notify` feature, [`watch_prefs`](fn.watch_prefs.html) calls back with the reloaded
+//! value whenever the user data saved under a key changes on disk, e.g. because another instance
+//! of the app saved it, so that settings can be applied live. Within a single process,
+//! [`PreferencesStore::on_change`](struct.PreferencesStore.html#method.on_change) notifies
+//! subsystems of the app of each other's saves without any file watching, and
+//! [`PreferencesStore::change_bus`](struct.PreferencesStore.html#method.change_bus) lets
+//! several running instances of an app receive each other's changes through a channel.
+//!
+//! # Asynchronous API
+//! With the `tokio` feature, [`AsyncPreferences`](trait.AsyncPreferences.html) provides `save`
+//! and `load` methods that return futures, so that apps running on a `tokio` runtime can persist
+//! user data without blocking it.
+//!
+//! # Performance
+//! Each function of [`Preferences`](trait.Preferences.html) looks up the data directory again,
+//! and checks the files of every enabled format and compression where needed. Apps that access
+//! their data often can keep a [`PreferencesStore`](struct.PreferencesStore.html) instead, and
+//! keep loaded values in a [`CachedStore`](struct.CachedStore.html), which only loads them again
+//! when their files change. Most of the time of a small save is spent syncing the file to
+//! storage; see [`SaveOptions::durability`](struct.SaveOptions.html#method.durability). Apps
+//! that save with a lower durability, or with an [`AutoSaver`](struct.AutoSaver.html), can call
+//! [`PreferencesStore::sync_all`](struct.PreferencesStore.html#method.sync_all) on shutdown to
+//! make their data durable. The benchmarks in `benches/` compare the formats (run
+//! `cargo bench --all-features`).
+//!
 //! # Under the hood
 //! Data is written to flat files under the active user's home directory in a location specific to
-//! the operating system. This location is decided by the `app_dirs` crate with the data type
-//! `UserConfig`. Within the data directory, the files are stored in a folder hierarchy that maps
-//! to a sanitized version of the preferences key passed to `save(..)`.
+//! the operating system. This location is the app's configuration directory as chosen by the
+//! `directories-next` crate, unless another [`DirProvider`](trait.DirProvider.html) is
+//! installed. (Version 1 of this crate used the directories of the `app_dirs` crate instead;
+//! apps upgrading from it can keep their data there with
+//! [`AppDirsProvider`](struct.AppDirsProvider.html) and the `app_dirs` feature.) Within
+//! the data directory, the files are stored in a folder hierarchy that maps to a sanitized
+//! version of the preferences key passed to `save(..)`. (On Windows, names reserved for devices
+//! like `con` are escaped too, and long paths use the `\\?\` prefix.) The key
+//! `options/graphics` is saved in `options/graphics.prefs.json`; a store can keep it in
+//! `options/graphics/prefs.json` instead, and name its files differently to match an app's
+//! existing files; see [`KeyLayout`](enum.KeyLayout.html) and
+//! [`with_file_suffix`](struct.PreferencesStore.html#method.with_file_suffix).
+//! Portable apps can keep their data next to the executable instead; see
+//! [`StorageLocation`](enum.StorageLocation.html).
+//! On Windows, settings that shouldn't follow the user to other machines, like monitor layouts,
+//! can be kept in the local rather than the roaming `AppData` directory with
+//! [`Scope::Local`](enum.Scope.html#variant.Local).
+//! Data that isn't configuration, like saved games (in the user data directory, e.g.
+//! `~/.local/share` on Linux) or caches, can be kept in its own platform directory with a
+//! [`PreferencesStore`](struct.PreferencesStore.html) with a [`Scope`](enum.Scope.html), or
+//! with [`CachePreferences`](trait.CachePreferences.html).
+//! On iOS, the data is stored in `Library/Preferences` within the app's sandbox instead.
+//! Apps with a bundle identifier or a version can use them for their directories on macOS and
+//! Windows with a store created from an [`AppId`](struct.AppId.html).
+//! In WebAssembly builds for the browser (`wasm32-unknown-unknown`), which have no files, the
+//! data is kept in `window.localStorage` instead, keyed by the paths the files would have.
 //!
-//! The data is stored in JSON format. This has several advantages:
+//! By default, the data is stored in JSON format. This has several advantages:
 //!
 //! * Human-readable and self-describing
 //! * More compact than e.g. XML
 //! * Better adoption rates and language compatibility than e.g. TOML
 //! * Not reliant on a consistent memory layout like e.g. binary
 //!
+//! Other formats can be enabled with cargo features and selected with
+//! [`Format`](enum.Format.html):
+//!
+//! * `toml`: stores data as TOML in `.prefs.toml` files
+//! * `ron`: stores data as [RON](https://github.com/ron-rs/ron) in `.prefs.ron` files, which
+//!   preserves Rust-specific structure like tuple structs and enum variants
+//! * `bincode`: stores data in the compact binary `bincode` encoding in `.prefs.bin` files, for
+//!   large data like caches that doesn't need to be human-readable
+//! * `yaml`: stores data as YAML in `.prefs.yaml` files
+//! * `plist`: stores data as XML property lists in `.prefs.plist` files. This feature also
+//!   provides [`PlistDomain`](struct.PlistDomain.html), which stores all keys in a single
+//!   macOS-style `~/Library/Preferences/<bundle id>.plist` file.
+//! * `ini`: stores maps and structs of simple values as INI in `.prefs.ini` files, for settings
+//!   that other tools read or users edit by hand, such as a `PreferencesMap<String>`
+//! * `xml`: stores data as XML in `.prefs.xml` files, for tools that consume XML configuration
+//!
+//! To switch an app to another format, a store can load the data saved in the previous format,
+//! and convert it when it's next saved; see
+//! [`with_format_detection`](struct.PreferencesStore.html#method.with_format_detection).
+//!
+//! With the `schema` feature, [`save_schema`](fn.save_schema.html) saves a JSON Schema of a
+//! preferences type next to its file, so that editors can help users who edit it by hand.
+//!
+//! With the `mmap` feature,
+//! [`PreferencesStore::map_prefs`](struct.PreferencesStore.html#method.map_prefs) memory-maps
+//! large data, so that it can be deserialized into values that borrow from it.
+//!
+//! Large data can be compressed with gzip or Zstandard (with the `gzip` and `zstd` features); see
+//! [`SaveOptions::compression`](struct.SaveOptions.html#method.compression).
+//!
+//! With the `encryption` feature, [`EncryptedPreferences`](struct.EncryptedPreferences.html)
+//! stores data encrypted with a secret key instead, for data like access tokens that shouldn't be
+//! kept in plaintext.
+//!
+//! With the `signing` feature, [`SignedPreferences`](struct.SignedPreferences.html) stores data
+//! with a signature made with a secret key, so that changes made outside of the app are detected.
+//!
+//! With the `keyring` feature, [`SecretPreferences`](trait.SecretPreferences.html) keeps
+//! individual secrets in the platform's secret store (e.g. the macOS Keychain) instead of in
+//! files.
+//!
+//! With the `gsettings` feature, [`GSettings`](struct.GSettings.html) reads and writes the
+//! settings of GNOME apps through their GSettings schemas by running the `gsettings` tool, so
+//! they show up in `dconf-editor`.
+//!
+//! With the `derive` feature, `#[derive(Prefs)]` generates `load`, `save` and default values for
+//! a struct of settings with a fixed key; see [`Prefs`](derive.Prefs.html).
+//!
+//! [`load_many`](fn.load_many.html) and [`save_many`](fn.save_many.html) load and save the data
+//! of many keys at once, e.g. at startup; with the `rayon` feature, they do so in parallel.
+//!
+//! A [`PreferencesStore`](struct.PreferencesStore.html) can keep its data in other storage than
+//! files, such as a database, through a [`StorageBackend`](trait.StorageBackend.html). With the
+//! `sqlite` feature, [`SqliteBackend`](struct.SqliteBackend.html) keeps all of an app's data in a
+//! single SQLite database, and with the `redb` feature, [`RedbBackend`](struct.RedbBackend.html)
+//! keeps it in an embedded key-value database, for apps that save very often.
+//!
+//! On devices without a file system, e.g. embedded targets with a flash key-value store, any
+//! preferences can be saved in custom storage that implements
+//! [`KeyValueStorage`](trait.KeyValueStorage.html), with
+//! [`Preferences::save_in`](trait.Preferences.html#method.save_in).
+//!
 //! You could, of course, implement `Preferences` yourself and store your user data in
 //! whatever location and format that you wanted. But that would defeat the purpose of this
 //! library. &#128522;
@@ -167,24 +316,271 @@
 
 #![warn(missing_docs)]
 
+#[cfg(feature = "app_dirs")]
 extern crate app_dirs;
+extern crate crc32fast;
+extern crate directories_next;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+extern crate fs2;
 extern crate serde;
 extern crate serde_json;
+#[cfg(feature = "bincode")]
+extern crate bincode;
+#[cfg(feature = "yaml")]
+extern crate serde_yaml;
+#[cfg(feature = "encryption")]
+extern crate chacha20poly1305;
+#[cfg(feature = "cli")]
+extern crate clap;
+#[cfg(feature = "gzip")]
+extern crate flate2;
+#[cfg(feature = "signing")]
+extern crate hmac;
+#[cfg(feature = "keyring")]
+extern crate keyring;
+#[cfg(feature = "mmap")]
+extern crate memmap2;
+#[cfg(feature = "notify")]
+extern crate notify;
+#[cfg(feature = "plist")]
+extern crate plist;
+#[cfg(feature = "derive")]
+extern crate preferences_derive;
+#[cfg(feature = "rayon")]
+extern crate rayon;
+#[cfg(feature = "redb")]
+extern crate redb;
+#[cfg(feature = "xml")]
+extern crate quick_xml;
+#[cfg(feature = "ron")]
+extern crate ron;
+#[cfg(feature = "sqlite")]
+extern crate rusqlite;
+#[cfg(feature = "schema")]
+extern crate schemars;
+#[cfg(feature = "signing")]
+extern crate sha2;
+#[cfg(feature = "tokio")]
+extern crate tokio;
+#[cfg(feature = "toml")]
+extern crate toml;
+#[cfg(feature = "zstd")]
+extern crate zstd;
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+extern crate web_sys;
 
-pub use app_dirs::{AppDirsError, AppInfo};
-use app_dirs::{AppDataType, get_data_root, get_app_dir};
-use serde::{Serialize, Deserialize};
+mod app_id;
+#[cfg(feature = "tokio")]
+mod async_prefs;
+mod autosave;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+mod autosaver;
+mod backend;
+mod backup;
+mod bulk;
+mod bundle;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+mod bus;
+mod cache;
+mod cached;
+mod checksum;
+#[cfg(feature = "cli")]
+mod cli_overrides;
+mod collision;
+mod compression;
+mod context;
+mod descriptor;
+mod dirs;
+#[cfg(feature = "encryption")]
+mod encrypted;
+mod env_overrides;
+mod envelope;
+mod expiring;
+mod flush;
+mod format;
+#[cfg(feature = "gsettings")]
+mod gsettings;
+mod guard;
+mod header;
+#[cfg(feature = "ini")]
+mod ini;
+mod journal;
+mod keys;
+mod layered;
+mod layout;
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+mod local_storage;
+mod location;
+mod lock;
+mod memory;
+mod metadata;
+mod migrate;
+#[cfg(feature = "mmap")]
+mod mmap;
+mod node;
+mod observe;
+mod options;
+mod permissions;
+#[cfg(feature = "plist")]
+mod plist_domain;
+mod pref_key;
+mod preload;
+mod preserving;
+mod quota;
+#[cfg(feature = "redb")]
+mod redb_backend;
+mod sandbox;
+#[cfg(feature = "schema")]
+mod schema;
+#[cfg(feature = "keyring")]
+mod secret;
+mod shared;
+#[cfg(feature = "signing")]
+mod signed;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod storage;
+mod store;
+mod sync;
+#[cfg(test)]
+mod testing;
+mod transaction;
+mod typed;
+mod version;
+#[cfg(feature = "notify")]
+mod watch;
+
+pub use app_id::{AppId, AppInfo};
+#[cfg(feature = "tokio")]
+pub use async_prefs::AsyncPreferences;
+pub use autosave::AutoSavePreferences;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub use autosaver::AutoSaver;
+pub use backend::{FileBackend, StorageBackend};
+pub use backup::{Recovered, load_previous, revert};
+pub use bulk::{load_many, save_many};
+pub use bundle::{export_all, import_all};
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub use bus::{ChangeBus, PrefsChanged};
+pub use cache::CachePreferences;
+pub use cached::CachedStore;
+#[cfg(feature = "cli")]
+pub use cli_overrides::{CliOverrides, Overridden};
+pub use compression::Compression;
+pub use context::{ErrorContext, Operation};
+pub use descriptor::PrefDescriptor;
+#[cfg(feature = "app_dirs")]
+pub use dirs::AppDirsProvider;
+pub use dirs::{DirError, DirProvider, DirectoriesProvider, XdgProvider, set_dir_provider};
+#[cfg(feature = "encryption")]
+pub use encrypted::EncryptedPreferences;
+pub use env_overrides::EnvOverrides;
+pub use envelope::{Envelope, EnvelopeMeta};
+pub use expiring::{Expiring, load_unexpired, save_with_ttl};
+pub use format::Format;
+#[cfg(feature = "gsettings")]
+pub use gsettings::GSettings;
+pub use guard::{PreferencesGuard, edit, patch, update};
+pub use header::{FileHeader, file_header};
+#[cfg(feature = "ini")]
+pub use ini::IniError;
+pub use journal::{Journal, JournalEntry};
+pub use keys::{KeyError, MAX_SEGMENT_LEN, canonical_key, validate_key};
+pub use layered::LayeredPreferences;
+pub use layout::KeyLayout;
+pub use location::{PORTABLE_DIR, PORTABLE_MARKER_FILE, Scope, StorageLocation};
+pub use memory::{MemoryBackend, set_backend_for_tests};
+pub use metadata::{PrefsMetadata, metadata};
+pub use migrate::{LegacyLocation, MigrateOptions, MigrationReport, migrate_from,
+                  migrate_from_path};
+#[cfg(feature = "mmap")]
+pub use mmap::MappedPrefs;
+pub use node::PreferencesNode;
+pub use observe::Subscription;
+pub use options::{Durability, SaveOptions};
+#[cfg(feature = "plist")]
+pub use plist_domain::PlistDomain;
+/// # Example
+/// ```
+/// #[macro_use]
+/// extern crate serde_derive;
+/// extern crate preferences;
+/// use preferences::{AppInfo, Prefs};
+///
+/// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+///
+/// #[derive(Prefs, Serialize, Deserialize, Debug, PartialEq)]
+/// #[prefs(app = "APP_INFO", key = "tests/docs/derive")]
+/// struct Graphics {
+///     #[prefs(default = 60, label = "Frame rate limit", min = 30, max = 240)]
+///     fps: u32,
+///     #[prefs(default = "high", label = "Quality", choices = ["low", "medium", "high"])]
+///     quality: String,
+///     vsync: bool,
+/// }
+///
+/// fn main() {
+///     # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
+///     let (name, quality) = Graphics::descriptors().remove(1);
+///     assert_eq!(name, "quality");
+///     assert_eq!(quality.get_choices(), ["low", "medium", "high"]);
+///
+///     let mut graphics = Graphics::load_or_default().unwrap();
+///     assert_eq!(graphics.fps, 60);
+///     graphics.vsync = true;
+///     graphics.save().unwrap();
+///     assert_eq!(Graphics::load().unwrap(), graphics);
+/// }
+/// ```
+#[cfg(feature = "derive")]
+pub use preferences_derive::Prefs;
+pub use pref_key::PrefKey;
+pub use preload::Preloaded;
+pub use preserving::PreservingPreferences;
+#[cfg(feature = "redb")]
+pub use redb_backend::RedbBackend;
+pub use sandbox::{Sandbox, sandbox};
+#[cfg(feature = "schema")]
+pub use schema::{save_schema, schema_for};
+#[cfg(feature = "keyring")]
+pub use secret::SecretPreferences;
+pub use shared::SharedPreferences;
+#[cfg(feature = "signing")]
+pub use signed::SignedPreferences;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteBackend;
+pub use storage::KeyValueStorage;
+pub use store::{AliasPolicy, FormatDetection, PreferencesStore, ValidationPolicy, WriteMode};
+pub use sync::{SyncProvider, SyncRecord, SyncVersion, set_sync_provider};
+pub use transaction::Transaction;
+pub use typed::{PreferencesMapExt, ValueError};
+pub use version::{PrefsVersion, compare_and_save, load_versioned};
+#[cfg(feature = "notify")]
+pub use watch::{PrefsWatcher, watch_prefs};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use lock::{FileLock, Wait};
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::env;
 use std::ffi::OsString;
 use std::fmt;
-use std::fs::{File, create_dir_all};
-use std::io::{self, ErrorKind, Read, Write};
-use std::path::PathBuf;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
 use std::string::FromUtf8Error;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
-const DATA_TYPE: AppDataType = AppDataType::UserConfig;
-static PREFS_FILE_EXTENSION: &'static str = ".prefs.json";
-static DEFAULT_PREFS_FILENAME: &'static str = "prefs.json";
+/// Name of the environment variable that overrides the base directory for preferences. (See
+/// [`prefs_base_dir`](fn.prefs_base_dir.html).)
+pub const BASE_DIR_ENV_VAR: &str = "PREFERENCES_RS_DIR";
+
+static PREFS_FILE_SUFFIX: &str = ".prefs";
+static DEFAULT_PREFS_FILENAME: &str = "prefs";
+static TEMP_FILE_SUFFIX: &str = ".tmp";
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+static STRICT_KEYS: AtomicBool = AtomicBool::new(false);
 
 /// Generic key-value store for user data.
 ///
@@ -195,20 +591,133 @@ static DEFAULT_PREFS_FILENAME: &'static str = "prefs.json";
 /// To save or load user data, use the methods defined for the trait
 /// [`Preferences`](trait.Preferences.html), which will be automatically implemented for
 /// `PreferencesMap<T>` as long as `T` is serializable. (See the
-/// [module documentation](index.html) for examples and more details.)
+/// [module documentation](index.html) for examples and more details.) To read and write
+/// numbers and flags in a `PreferencesMap<String>`, use
+/// [`PreferencesMapExt`](trait.PreferencesMapExt.html).
 ///
 /// [hashmap-api]: https://doc.rust-lang.org/nightly/std/collections/struct.HashMap.html
 pub type PreferencesMap<T = String> = HashMap<String, T>;
 
 /// Error type representing the errors that can occur when saving or loading user data.
+///
+/// The variants don't depend on the crate's features, but new ones may be added in minor
+/// releases.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum PreferencesError {
     /// An error occurred during JSON serialization or deserialization.
     Json(serde_json::Error),
     /// An error occurred during preferences file I/O.
     Io(io::Error),
     /// Couldn't figure out where to put or find the serialized data.
-    Directory(AppDirsError),
+    Directory(DirError),
+    /// The key was rejected because it isn't valid and strict key validation is enabled. (See
+    /// [`set_strict_keys`](fn.set_strict_keys.html).)
+    InvalidKey(KeyError),
+    /// An error occurred during serialization or deserialization in a format other than JSON
+    /// (see [`Format`](enum.Format.html)). The error of the format's crate can be downcast from
+    /// it, e.g. an [`IniError`](struct.IniError.html) to find the line of an INI syntax error.
+    Serialization(Box<dyn std::error::Error + Send + Sync>),
+    /// Data could not be encrypted before saving it. (See
+    /// [`EncryptedPreferences`](struct.EncryptedPreferences.html).)
+    Encryption,
+    /// Encrypted data could not be decrypted, either because the secret key is wrong or because
+    /// the data was corrupted or tampered with. (See
+    /// [`EncryptedPreferences`](struct.EncryptedPreferences.html).)
+    Decryption,
+    /// Signed data doesn't match its signature, either because the secret key is wrong or
+    /// because the data was changed outside of the app. (See
+    /// [`SignedPreferences`](struct.SignedPreferences.html).)
+    TamperDetected,
+    /// An error occurred while accessing the platform's secret store. The error of the `keyring`
+    /// crate can be downcast from it.
+    Keyring(Box<dyn std::error::Error + Send + Sync>),
+    /// An error occurred while watching preferences files for changes. The error of the `notify`
+    /// crate can be downcast from it.
+    Watch(Box<dyn std::error::Error + Send + Sync>),
+    /// The data is locked by another save or load (usually in another process), and a
+    /// non-blocking operation like `try_save` was used.
+    WouldBlock,
+    /// The data doesn't match the checksum saved with it, so it was damaged after it was saved
+    /// (e.g. by a truncated write or by failing storage). (See
+    /// [`SaveOptions::checksum`](struct.SaveOptions.html#method.checksum).)
+    Corrupted,
+    /// Another error occurred while saving, loading or deleting the user data with a key. This
+    /// wraps the errors of most operations on a key, to tell which key and file they occurred
+    /// with; use [`inner`](#method.inner) to get the error itself.
+    Context(Box<ErrorContext>),
+    /// Saved JSON data isn't valid, e.g. because the file was edited by hand and has a syntax
+    /// error, or doesn't match the type it's loaded as. (Other JSON errors are reported as
+    /// `Json`.)
+    Parse {
+        /// The line of the error, starting at 1.
+        line: usize,
+        /// The column of the error, starting at 1.
+        column: usize,
+        /// The text of the line around the error, shortened with `...` if it's long.
+        snippet: String,
+        /// The error itself.
+        error: serde_json::Error,
+    },
+    /// The data to be saved is larger than allowed by
+    /// [`SaveOptions::max_size`](struct.SaveOptions.html#method.max_size), or would make the
+    /// app's data larger than allowed by
+    /// [`SaveOptions::max_total_size`](struct.SaveOptions.html#method.max_total_size).
+    TooLarge {
+        /// The size of the data, or the total size of the app's data with it, in bytes.
+        size: u64,
+        /// The limit that the size exceeds.
+        limit: u64,
+    },
+    /// The user data was changed (e.g. by another process) since the version on which an update
+    /// was based was loaded. (See [`compare_and_save`](fn.compare_and_save.html).)
+    Conflict {
+        /// The key of the data.
+        key: String,
+        /// The file of the data.
+        path: PathBuf,
+    },
+    /// The data wasn't saved because the name of its file, or of one of its directories, differs
+    /// from that of existing data only in case, so on a case-insensitive file system it would
+    /// overwrite the data of another key. (See
+    /// [`with_collision_check`](struct.PreferencesStore.html#method.with_collision_check).)
+    KeyCollision {
+        /// The key of the data.
+        key: String,
+        /// The existing file or directory.
+        path: PathBuf,
+    },
+    /// The data wasn't changed because the store is read-only. (See
+    /// [`PreferencesStore::with_write_mode`](struct.PreferencesStore.html#method.with_write_mode).)
+    ReadOnly,
+    /// No user data was saved with the key that was loaded or deleted.
+    NotFound {
+        /// The key of the data.
+        key: String,
+        /// The file in which the data would be saved.
+        path: PathBuf,
+    },
+    /// The loaded value was rejected by the validator of its key. (See
+    /// [`PreferencesStore::validator`](struct.PreferencesStore.html#method.validator).)
+    Validation {
+        /// The key of the data.
+        key: String,
+        /// The file of the data.
+        path: PathBuf,
+    },
+}
+
+impl PreferencesError {
+    /// Returns whether this error means that no user data was saved with the requested key, as
+    /// opposed to the data being unreadable or corrupt.
+    pub fn is_not_found(&self) -> bool {
+        match *self {
+            PreferencesError::Io(ref e) => e.kind() == ErrorKind::NotFound,
+            PreferencesError::Context(ref context) => context.error.is_not_found(),
+            PreferencesError::NotFound { .. } => true,
+            _ => false,
+        }
+    }
 }
 
 impl fmt::Display for PreferencesError {
@@ -218,27 +727,59 @@ impl fmt::Display for PreferencesError {
             Json(ref e) => e.fmt(f),
             Io(ref e) => e.fmt(f),
             Directory(ref e) => e.fmt(f),
+            InvalidKey(ref e) => e.fmt(f),
+            Serialization(ref e) | Keyring(ref e) | Watch(ref e) => e.fmt(f),
+            Encryption => f.write_str("Preferences data could not be encrypted"),
+            Decryption => f.write_str("Preferences data could not be decrypted"),
+            TamperDetected => f.write_str("Preferences data doesn't match its signature"),
+            WouldBlock => f.write_str("Preferences file is locked by another operation"),
+            Corrupted => f.write_str("Preferences data doesn't match its checksum"),
+            Context(ref context) => context.fmt(f),
+            Parse { ref snippet, ref error, .. } => write!(f, "{}: `{}`", error, snippet),
+            TooLarge { size, limit } => {
+                write!(f, "Preferences data of {} bytes exceeds the limit of {}", size, limit)
+            }
+            Conflict { ref key, ref path } => {
+                write!(f,
+                       "Preferences `{}` ({}) were changed by another save",
+                       key,
+                       path.display())
+            }
+            KeyCollision { ref key, ref path } => {
+                write!(f,
+                       "Preferences `{}` would overwrite {}, whose name differs only in case",
+                       key,
+                       path.display())
+            }
+            ReadOnly => f.write_str("Preferences store is read-only"),
+            NotFound { ref key, ref path } => {
+                write!(f, "No preferences saved with key `{}` ({})", key, path.display())
+            }
+            Validation { ref key, ref path } => {
+                write!(f, "Preferences `{}` ({}) have an invalid value", key, path.display())
+            }
         }
     }
 }
 
 impl std::error::Error for PreferencesError {
-    fn description(&self) -> &str {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         use PreferencesError::*;
         match *self {
-            Json(ref e) => e.description(),
-            Io(ref e) => e.description(),
-            Directory(ref e) => e.description(),
+            Json(ref e) => Some(e),
+            Io(ref e) => Some(e),
+            Directory(ref e) => Some(e),
+            InvalidKey(ref e) => Some(e),
+            Serialization(ref e) | Keyring(ref e) | Watch(ref e) => Some(&**e),
+            Encryption | Decryption | TamperDetected => None,
+            WouldBlock => None,
+            Corrupted | ReadOnly => None,
+            Context(ref context) => Some(&context.error),
+            Parse { ref error, .. } => Some(error),
+            TooLarge { .. } | Conflict { .. } | KeyCollision { .. } | NotFound { .. } |
+            Validation { .. } => None,
         }
     }
-    fn cause(&self) -> Option<&std::error::Error> {
-        use PreferencesError::*;
-        Some(match *self {
-            Json(ref e) => e,
-            Io(ref e) => e,
-            Directory(ref e) => e,
-        })
-    }
 }
 
 impl From<serde_json::Error> for PreferencesError {
@@ -247,6 +788,90 @@ impl From<serde_json::Error> for PreferencesError {
     }
 }
 
+#[cfg(feature = "toml")]
+impl From<toml::ser::Error> for PreferencesError {
+    fn from(e: toml::ser::Error) -> Self {
+        PreferencesError::Serialization(Box::new(e))
+    }
+}
+
+#[cfg(feature = "toml")]
+impl From<toml::de::Error> for PreferencesError {
+    fn from(e: toml::de::Error) -> Self {
+        PreferencesError::Serialization(Box::new(e))
+    }
+}
+
+#[cfg(feature = "plist")]
+impl From<plist::Error> for PreferencesError {
+    fn from(e: plist::Error) -> Self {
+        PreferencesError::Serialization(Box::new(e))
+    }
+}
+
+#[cfg(feature = "keyring")]
+impl From<keyring::Error> for PreferencesError {
+    fn from(e: keyring::Error) -> Self {
+        PreferencesError::Keyring(Box::new(e))
+    }
+}
+
+#[cfg(feature = "notify")]
+impl From<notify::Error> for PreferencesError {
+    fn from(e: notify::Error) -> Self {
+        PreferencesError::Watch(Box::new(e))
+    }
+}
+
+#[cfg(feature = "ron")]
+impl From<ron::Error> for PreferencesError {
+    fn from(e: ron::Error) -> Self {
+        PreferencesError::Serialization(Box::new(e))
+    }
+}
+
+#[cfg(feature = "ron")]
+impl From<ron::error::SpannedError> for PreferencesError {
+    fn from(e: ron::error::SpannedError) -> Self {
+        PreferencesError::Serialization(Box::new(e))
+    }
+}
+
+#[cfg(feature = "bincode")]
+impl From<bincode::Error> for PreferencesError {
+    fn from(e: bincode::Error) -> Self {
+        PreferencesError::Serialization(Box::new(e))
+    }
+}
+
+#[cfg(feature = "yaml")]
+impl From<serde_yaml::Error> for PreferencesError {
+    fn from(e: serde_yaml::Error) -> Self {
+        PreferencesError::Serialization(Box::new(e))
+    }
+}
+
+#[cfg(feature = "ini")]
+impl From<IniError> for PreferencesError {
+    fn from(e: IniError) -> Self {
+        PreferencesError::Serialization(Box::new(e))
+    }
+}
+
+#[cfg(feature = "xml")]
+impl From<quick_xml::SeError> for PreferencesError {
+    fn from(e: quick_xml::SeError) -> Self {
+        PreferencesError::Serialization(Box::new(e))
+    }
+}
+
+#[cfg(feature = "xml")]
+impl From<quick_xml::DeError> for PreferencesError {
+    fn from(e: quick_xml::DeError) -> Self {
+        PreferencesError::Serialization(Box::new(e))
+    }
+}
+
 impl From<FromUtf8Error> for PreferencesError {
     fn from(_: FromUtf8Error) -> Self {
         let kind = ErrorKind::InvalidData;
@@ -262,16 +887,22 @@ impl From<std::io::Error> for PreferencesError {
     }
 }
 
-impl From<AppDirsError> for PreferencesError {
-    fn from(e: AppDirsError) -> Self {
+impl From<DirError> for PreferencesError {
+    fn from(e: DirError) -> Self {
         PreferencesError::Directory(e)
     }
 }
 
+impl From<KeyError> for PreferencesError {
+    fn from(e: KeyError) -> Self {
+        PreferencesError::InvalidKey(e)
+    }
+}
+
 /// Trait for types that can be saved & loaded as user data.
 ///
 /// This type is automatically implemented for any struct/enum `T` which implements both
-/// `Serialize` and `Deserialize` (from `serde`). (Trivially, you can annotate the type
+/// `Serialize` and `DeserializeOwned` (from `serde`). (Trivially, you can annotate the type
 /// with `#[derive(Serialize, Deserialize)`). It is encouraged to use the provided
 /// type, [`PreferencesMap`](type.PreferencesMap.html), to bundle related user preferences.
 ///
@@ -287,7 +918,8 @@ impl From<AppDirsError> for PreferencesError {
 /// preferences data. It roughly maps to a platform-dependent directory hierarchy, with forward
 /// slashes used as separators on all platforms. Keys are sanitized to be valid paths; to ensure
 /// human-readable paths, use only letters, digits, spaces, hyphens, underscores, periods, and
-/// slashes.
+/// slashes. (See [`validate_key`](fn.validate_key.html) and
+/// [`set_strict_keys`](fn.set_strict_keys.html) to detect keys that need sanitizing.)
 ///
 /// # Example keys
 /// * `options/graphics`
@@ -297,6 +929,14 @@ pub trait Preferences: Sized {
     /// Saves the current state of this object. Implementation is platform-dependent, but the data
     /// will be local to the active user.
     ///
+    /// The data is first written to a temporary file in the same directory, which is then renamed
+    /// over the previous file. Thus, if the save is interrupted (e.g. by a crash), the previously
+    /// saved data is left intact.
+    ///
+    /// Saves and loads of the same key are serialized with an advisory file lock, so concurrent
+    /// saves (e.g. from several instances of the app) don't interfere with each other. If another
+    /// save or load holds the lock, this method waits for it to be released.
+    ///
     /// # Failures
     /// If a serialization or file I/O error (e.g. permission denied) occurs.
     fn save<S: AsRef<str>>(&self, app: &AppInfo, key: S) -> Result<(), PreferencesError>;
@@ -306,51 +946,647 @@ pub trait Preferences: Sized {
     /// the preferences object.
     ///
     /// # Failures
-    /// If a deserialization or file I/O error (e.g. permission denied) occurs, or if no user data
-    /// exists at that `path`.
+    /// If a deserialization or file I/O error (e.g. permission denied) occurs, or
+    /// `PreferencesError::NotFound` if no user data exists at that `path`.
     fn load<S: AsRef<str>>(app: &AppInfo, key: S) -> Result<Self, PreferencesError>;
-    /// Same as `save`, but writes the serialized preferences to an arbitrary writer.
+    /// Same as `save`, but fails with a `PreferencesError::WouldBlock` (see
+    /// [`inner`](enum.PreferencesError.html#method.inner)) instead of waiting if the data is
+    /// currently locked by another save or load.
+    fn try_save<S: AsRef<str>>(&self, app: &AppInfo, key: S) -> Result<(), PreferencesError>;
+    /// Same as `load`, but fails with a `PreferencesError::WouldBlock` (see
+    /// [`inner`](enum.PreferencesError.html#method.inner)) instead of waiting if the data is
+    /// currently locked by another save.
+    fn try_load<S: AsRef<str>>(app: &AppInfo, key: S) -> Result<Self, PreferencesError>;
+    /// Same as `load`, but if the data file exists and can't be loaded (e.g. because it's
+    /// corrupt), falls back to the most recent backup that can. Backups are only kept if the data
+    /// was saved with [`SaveOptions::backups`](struct.SaveOptions.html#method.backups).
+    ///
+    /// Whether the value was recovered from a backup, and the error that made it necessary, are
+    /// reported in the returned [`Recovered`](struct.Recovered.html).
+    ///
+    /// # Failures
+    /// Same as `load`, if neither the data file nor any backup can be loaded. The error is the
+    /// one that occurred loading the data file.
+    fn load_with_recovery<S>(app: &AppInfo, key: S) -> Result<Recovered<Self>, PreferencesError>
+        where S: AsRef<str>;
+    /// Same as `load`, but detects the format in which the data was saved (e.g. with
+    /// `save_with_format`) from the file extension, instead of assuming the default format. If
+    /// the data was saved in several formats, the most recently saved one is loaded.
+    fn load_any_format<S: AsRef<str>>(app: &AppInfo, key: S) -> Result<Self, PreferencesError>;
+    /// Same as `save`, but stores the data in the given `format` instead of the default (JSON).
+    /// The file extension depends on the format, so data saved in one format won't be found when
+    /// loading with another.
+    fn save_with_format<S: AsRef<str>>(&self,
+                                       app: &AppInfo,
+                                       key: S,
+                                       format: Format)
+                                       -> Result<(), PreferencesError>;
+    /// Same as `load`, but reads data previously stored in the given `format`.
+    fn load_with_format<S: AsRef<str>>(app: &AppInfo,
+                                       key: S,
+                                       format: Format)
+                                       -> Result<Self, PreferencesError>;
+    /// Same as `save`, but with more control over how the data is stored. (See
+    /// [`SaveOptions`](struct.SaveOptions.html).)
+    fn save_with_options<S: AsRef<str>>(&self,
+                                        app: &AppInfo,
+                                        key: S,
+                                        options: &SaveOptions)
+                                        -> Result<(), PreferencesError>;
+    /// Checks whether user data was previously saved with the given `key`, without loading it.
+    ///
+    /// This is equivalent to calling [`prefs_exist`](fn.prefs_exist.html).
+    ///
+    /// # Failures
+    /// If the data directory cannot be determined, or if a file I/O error (other than the file not
+    /// existing) occurs.
+    fn exists<S: AsRef<str>>(app: &AppInfo, key: S) -> Result<bool, PreferencesError> {
+        prefs_exist(app, key)
+    }
+    /// Deletes previously saved user data with the given `key`. Any directories that are left
+    /// empty by the deletion are removed as well (up to the app's own directory).
+    ///
+    /// This is equivalent to calling [`delete_prefs`](fn.delete_prefs.html).
+    ///
+    /// # Failures
+    /// If a file I/O error (e.g. permission denied) occurs, or `PreferencesError::NotFound` if no
+    /// user data exists at that `key`.
+    fn delete<S: AsRef<str>>(app: &AppInfo, key: S) -> Result<(), PreferencesError> {
+        delete_prefs(app, key)
+    }
+    /// Same as `load`, but returns `Self::default()` if no user data was saved with the given
+    /// `key`.
+    ///
+    /// # Failures
+    /// Unlike `load(..).unwrap_or_default()`, this still fails if the data exists but cannot be
+    /// read or deserialized (e.g. because the file is corrupt), so that it isn't silently
+    /// discarded on the next save.
+    fn load_or_default<S>(app: &AppInfo, key: S) -> Result<Self, PreferencesError>
+        where S: AsRef<str>,
+              Self: Default
+    {
+        Self::load_or_else(app, key, Self::default)
+    }
+    /// Same as `load_or_default`, but calls `f` to create the value if no user data was saved
+    /// with the given `key`.
+    fn load_or_else<S, F>(app: &AppInfo, key: S, f: F) -> Result<Self, PreferencesError>
+        where S: AsRef<str>,
+              F: FnOnce() -> Self
+    {
+        match Self::load(app, key) {
+            Err(ref e) if e.is_not_found() => Ok(f()),
+            result => result,
+        }
+    }
+    /// Same as `load_or_else`, but also saves the value created by `f` with the given `key`, so
+    /// that later loads return it.
+    ///
+    /// # Failures
+    /// In addition to the failures of `load_or_default`, fails if the created value cannot be
+    /// saved.
+    fn load_or_insert_with<S, F>(app: &AppInfo, key: S, f: F) -> Result<Self, PreferencesError>
+        where S: AsRef<str>,
+              F: FnOnce() -> Self
+    {
+        match Self::load(app, key.as_ref()) {
+            Err(ref e) if e.is_not_found() => {
+                let value = f();
+                value.save(app, key)?;
+                Ok(value)
+            }
+            result => result,
+        }
+    }
+    /// Same as `load_or_default`, but fields that are missing from the saved data (e.g. because
+    /// they were added to the type after the data was saved) get their values from
+    /// `Self::default()`, instead of making the load fail. Structs nested in the data are
+    /// completed the same way. Fields in the saved data that the type doesn't have are ignored;
+    /// use `save_merged` to keep them in the file.
+    ///
+    /// The saved data is merged in a self-describing form, so this doesn't work with
+    /// `Format::Bincode`.
+    ///
+    /// # Failures
+    /// Same as `load_or_default`.
+    ///
+    /// # Example
+    /// ```
+    /// #[macro_use]
+    /// extern crate serde_derive;
+    /// extern crate preferences;
+    /// use preferences::{AppInfo, Preferences, PreferencesMap};
+    ///
+    /// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+    ///
+    /// #[derive(Serialize, Deserialize, Default)]
+    /// struct Options {
+    ///     theme: String,
+    ///     font_size: u32,
+    /// }
+    ///
+    /// fn main() {
+    ///     # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
+    ///     let key = "tests/docs/load_lenient";
+    ///     let mut old_options = PreferencesMap::new();
+    ///     old_options.insert("theme".to_owned(), "dark".to_owned());
+    ///     old_options.save(&APP_INFO, key).unwrap();
+    ///
+    ///     let options = Options::load_lenient(&APP_INFO, key).unwrap();
+    ///     assert_eq!(options.theme, "dark");
+    ///     assert_eq!(options.font_size, 0);
+    /// }
+    /// ```
+    fn load_lenient<S>(app: &AppInfo, key: S) -> Result<Self, PreferencesError>
+        where S: AsRef<str>,
+              Self: Serialize + DeserializeOwned + Default
+    {
+        PreferencesStore::new(app)?.load_lenient(key)
+    }
+    /// Same as `save`, but keeps the fields of the saved data that this value doesn't have (e.g.
+    /// because they were saved by a newer version of the app), instead of removing them. Only
+    /// the fields at the top level of the data are kept this way; nested structs and maps are
+    /// replaced as a whole, so that entries removed from a map stay removed.
+    ///
+    /// # Failures
+    /// Same as `save` and `load_lenient`.
+    fn save_merged<S>(&self, app: &AppInfo, key: S) -> Result<(), PreferencesError>
+        where S: AsRef<str>,
+              Self: Serialize
+    {
+        PreferencesStore::new(app)?.save_merged(key, self)
+    }
+    /// Same as `save`, but writes the serialized preferences to an arbitrary writer. The writer
+    /// doesn't need to be buffered.
     fn save_to<W: Write>(&self, writer: &mut W) -> Result<(), PreferencesError>;
-    /// Same as `load`, but reads the serialized preferences from an arbitrary writer.
+    /// Same as `load`, but reads the serialized preferences from an arbitrary writer. The reader
+    /// doesn't need to be buffered, and may be read past the end of the preferences.
     fn load_from<R: Read>(reader: &mut R) -> Result<Self, PreferencesError>;
+    /// Same as `save`, but stores the serialized preferences with the given `key` in `storage`
+    /// instead of in a file. (See [`KeyValueStorage`](trait.KeyValueStorage.html).)
+    ///
+    /// # Failures
+    /// If a serialization error occurs, or writing to `storage` fails.
+    fn save_in<K>(&self, storage: &mut K, key: &str) -> Result<(), PreferencesError>
+        where K: KeyValueStorage + ?Sized
+    {
+        check_key(key)?;
+        let mut bytes = Vec::new();
+        self.save_to(&mut bytes)?;
+        storage.write(key, &bytes)
+    }
+    /// Same as `load`, but loads the preferences saved with `save_in` from `storage`.
+    ///
+    /// # Failures
+    /// If reading from `storage` or deserialization fails, or `PreferencesError::NotFound` if
+    /// nothing is stored with `key`. The error's path is then the key itself.
+    fn load_in<K>(storage: &K, key: &str) -> Result<Self, PreferencesError>
+        where K: KeyValueStorage + ?Sized
+    {
+        check_key(key)?;
+        match storage.read(key)? {
+            Some(bytes) => Self::load_from(&mut &bytes[..]),
+            None => Err(PreferencesError::not_found(key, Path::new(key))),
+        }
+    }
 }
 
-fn compute_file_path<S: AsRef<str>>(app: &AppInfo, key: S) -> Result<PathBuf, PreferencesError> {
-    let mut path = get_app_dir(DATA_TYPE, app, key.as_ref())?;
-    let new_name = match path.file_name() {
-        Some(name) if !name.is_empty() => {
-            let mut new_name = OsString::with_capacity(name.len() + PREFS_FILE_EXTENSION.len());
-            new_name.push(name);
-            new_name.push(PREFS_FILE_EXTENSION);
-            new_name
+/// Returns the path of the directory that corresponds to `key` within the app's directory `root`.
+fn key_dir_path(root: &Path, key: &str) -> Result<PathBuf, PreferencesError> {
+    keys::check_traversal(key)?;
+    let mut path = root.to_path_buf();
+    for segment in keys::key_segments(key) {
+        path.push(keys::segment_file_name(segment)?);
+    }
+    Ok(keys::extended_length_path(path))
+}
+
+/// How the files holding user data are named. (See
+/// [`PreferencesStore::with_file_suffix`](struct.PreferencesStore.html#method.with_file_suffix).)
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct FileNames {
+    /// Appended to the last segment of a key, before the extension of the format.
+    suffix: Cow<'static, str>,
+    /// Name of the file of the empty key, and of all keys in the `LeafAsDir` layout, without the
+    /// extension of the format.
+    default_name: Cow<'static, str>,
+    layout: KeyLayout,
+}
+
+impl Default for FileNames {
+    fn default() -> Self {
+        FileNames {
+            suffix: Cow::Borrowed(PREFS_FILE_SUFFIX),
+            default_name: Cow::Borrowed(DEFAULT_PREFS_FILENAME),
+            layout: KeyLayout::LeafAsFile,
         }
-        _ => DEFAULT_PREFS_FILENAME.into(),
-    };
+    }
+}
+
+/// Returns the path of the file holding the user data with the given `key` within the app's
+/// directory `root`.
+fn file_path_in(root: &Path, key: &str, extension: &str) -> Result<PathBuf, PreferencesError> {
+    file_path_named(root, key, extension, &FileNames::default())
+}
+
+/// Same as `file_path_in`, but with files named as set by `names`.
+fn file_path_named(root: &Path,
+                   key: &str,
+                   extension: &str,
+                   names: &FileNames)
+                   -> Result<PathBuf, PreferencesError> {
+    check_key(key)?;
+    let mut path = key_dir_path(root, key)?;
+    if keys::key_segments(key).is_empty() || names.layout == KeyLayout::LeafAsDir {
+        // There's no segment to name the file after, and naming it after `root` instead would
+        // put it next to the app's directory rather than in it. (In the `LeafAsDir` layout,
+        // every key's file is in the key's own directory.)
+        path.push(format!("{}.{}", names.default_name, extension));
+        return Ok(keys::extended_length_path(path));
+    }
+    let name = path.file_name().unwrap_or_default();
+    let mut new_name = OsString::with_capacity(name.len() + names.suffix.len() +
+                                               extension.len() + 1);
+    new_name.push(name);
+    new_name.push(&names.suffix[..]);
+    new_name.push(".");
+    new_name.push(extension);
     path.set_file_name(new_name);
-    Ok(path)
+    Ok(keys::extended_length_path(path))
+}
+
+/// Validates `key` if strict key validation is enabled.
+fn check_key(key: &str) -> Result<(), PreferencesError> {
+    if strict_keys() {
+        validate_key(key)?;
+    }
+    Ok(())
+}
+
+/// Enables or disables strict key validation for the whole process.
+///
+/// By default, keys are sanitized to be valid file paths, which means that different keys (e.g.
+/// `a:b` and `a,58,b`) can end up referring to the same data. When strict validation is enabled,
+/// all operations instead fail with `PreferencesError::InvalidKey` for any key that doesn't
+/// pass [`validate_key`](fn.validate_key.html).
+pub fn set_strict_keys(strict: bool) {
+    STRICT_KEYS.store(strict, Ordering::SeqCst);
+}
+
+/// Returns whether strict key validation is enabled. (See
+/// [`set_strict_keys`](fn.set_strict_keys.html).)
+pub fn strict_keys() -> bool {
+    STRICT_KEYS.load(Ordering::SeqCst)
+}
+
+fn compute_temp_file_path(path: &Path) -> PathBuf {
+    let count = TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let mut temp_name = OsString::from(".");
+    if let Some(name) = path.file_name() {
+        temp_name.push(name);
+    }
+    temp_name.push(format!(".{}-{}{}", process::id(), count, TEMP_FILE_SUFFIX));
+    path.with_file_name(temp_name)
+}
+
+/// Writes a file by writing to a temporary file, syncing it to disk, and renaming it over `path`.
+///
+/// `write` gets a buffered writer, so it can write many small pieces without a system call each.
+fn write_atomically<F>(path: &Path, write: F) -> Result<(), PreferencesError>
+    where F: FnOnce(&mut BufWriter<File>) -> Result<(), PreferencesError>
+{
+    write_atomically_with(path, &SaveOptions::default(), write)
+}
+
+/// Same as `write_atomically`, but with the permissions and durability of `options`.
+fn write_atomically_with<F>(path: &Path,
+                            options: &SaveOptions,
+                            write: F)
+                            -> Result<(), PreferencesError>
+    where F: FnOnce(&mut BufWriter<File>) -> Result<(), PreferencesError>
+{
+    let durability = options.get_durability();
+    let temp_path = compute_temp_file_path(path);
+    let result = permissions::create_file(&temp_path, options.is_private())
+        .map_err(PreferencesError::from)
+        .and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            write(&mut writer)?;
+            let file = writer.into_inner().map_err(|e| e.into_error())?;
+            durability.sync_file(&file)?;
+            Ok(())
+        })
+        .and_then(|_| fs::rename(&temp_path, path).map_err(Into::into))
+        .and_then(|_| match path.parent() {
+            Some(dir) => durability.sync_dir(dir).map_err(Into::into),
+            None => Ok(()),
+        });
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
 }
 
 impl<T> Preferences for T
-    where T: Serialize + Deserialize + Sized
+    where T: Serialize + DeserializeOwned + Sized
 {
     fn save<S>(&self, app: &AppInfo, key: S) -> Result<(), PreferencesError>
         where S: AsRef<str>
     {
-        let path = compute_file_path(app, key.as_ref())?;
-        path.parent().map(create_dir_all);
-        let mut file = File::create(path)?;
-        self.save_to(&mut file)
+        self.save_with_format(app, key, Format::default())
     }
     fn load<S: AsRef<str>>(app: &AppInfo, key: S) -> Result<Self, PreferencesError> {
-        let path = compute_file_path(app, key.as_ref())?;
-        let mut file = File::open(path)?;
-        Self::load_from(&mut file)
+        Self::load_with_format(app, key, Format::default())
+    }
+    fn save_with_format<S>(&self,
+                           app: &AppInfo,
+                           key: S,
+                           format: Format)
+                           -> Result<(), PreferencesError>
+        where S: AsRef<str>
+    {
+        self.save_with_options(app, key, &SaveOptions::new().format(format))
+    }
+    fn save_with_options<S>(&self,
+                            app: &AppInfo,
+                            key: S,
+                            options: &SaveOptions)
+                            -> Result<(), PreferencesError>
+        where S: AsRef<str>
+    {
+        PreferencesStore::new(app)?.save_with_options(key, self, options)
+    }
+    fn load_with_format<S>(app: &AppInfo, key: S, format: Format) -> Result<Self, PreferencesError>
+        where S: AsRef<str>
+    {
+        PreferencesStore::new(app)?.load_with_format(key, format)
+    }
+    fn try_save<S: AsRef<str>>(&self, app: &AppInfo, key: S) -> Result<(), PreferencesError> {
+        PreferencesStore::new(app)?.try_save(key, self)
+    }
+    fn try_load<S: AsRef<str>>(app: &AppInfo, key: S) -> Result<Self, PreferencesError> {
+        PreferencesStore::new(app)?.try_load(key)
+    }
+    fn load_with_recovery<S>(app: &AppInfo, key: S) -> Result<Recovered<Self>, PreferencesError>
+        where S: AsRef<str>
+    {
+        PreferencesStore::new(app)?.load_with_recovery(key)
+    }
+    fn load_any_format<S: AsRef<str>>(app: &AppInfo, key: S) -> Result<Self, PreferencesError> {
+        PreferencesStore::new(app)?.load_any_format(key)
     }
     fn save_to<W: Write>(&self, writer: &mut W) -> Result<(), PreferencesError> {
-        serde_json::to_writer(writer, self).map_err(Into::into)
+        // Serializers write many small pieces, which would each be a system call with a `File`.
+        let mut writer = BufWriter::new(writer);
+        Format::default().serialize(self, &mut writer)?;
+        writer.flush().map_err(Into::into)
     }
     fn load_from<R: Read>(reader: &mut R) -> Result<Self, PreferencesError> {
-        serde_json::from_reader(reader).map_err(Into::into)
+        Format::default().deserialize(&mut BufReader::new(reader))
+    }
+}
+
+fn save_locked(bytes: &[u8],
+               path: &Path,
+               options: &SaveOptions,
+               wait: Wait)
+               -> Result<(), PreferencesError> {
+    if let Some(dir) = path.parent() {
+        permissions::create_dirs(dir, options.is_private())?;
+    }
+    let _lock = FileLock::acquire(path, true, wait)?;
+    write_file(bytes, path, options)
+}
+
+/// Saves `bytes`, which `options` serialized, to the preferences file at `path`, which the
+/// caller has locked. They're compressed if `options` say so.
+fn write_file(bytes: &[u8], path: &Path, options: &SaveOptions) -> Result<(), PreferencesError> {
+    let compression = options.compression_for(bytes.len());
+    let compressed = if compression == Compression::None {
+        None
+    } else {
+        Some(compression.compress(bytes.to_vec())?)
+    };
+    let contents = compressed.as_deref().unwrap_or(bytes);
+    let target = compression.apply_to(path);
+    backup::rotate_backups(&target, options.get_backups())?;
+    write_atomically_with(&target,
+                          options,
+                          |file| file.write_all(contents).map_err(Into::into))?;
+    compression::remove_others(path, compression);
+    Ok(())
+}
+
+fn load_locked<T: DeserializeOwned>(path: &Path,
+                                    format: Format,
+                                    wait: Wait)
+                                    -> Result<T, PreferencesError> {
+    let _lock = lock_shared(path, wait)?;
+    read_file(path, format)
+}
+
+/// Takes a shared lock on the preferences file at `path`, or returns `None` if the file's
+/// directory doesn't exist (so neither does the file).
+fn lock_shared(path: &Path, wait: Wait) -> Result<Option<FileLock>, PreferencesError> {
+    match FileLock::acquire(path, false, wait) {
+        Ok(lock) => Ok(Some(lock)),
+        Err(ref e) if e.is_not_found() => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Reads the preferences file at `path`, in whatever compression it was saved.
+fn read_file<T: DeserializeOwned>(path: &Path, format: Format) -> Result<T, PreferencesError> {
+    let (path, compression) = compression::find(path);
+    read_compressed(&path, format, compression)
+}
+
+fn read_compressed<T>(path: &Path,
+                      format: Format,
+                      compression: Compression)
+                      -> Result<T, PreferencesError>
+    where T: DeserializeOwned
+{
+    let mut bytes = Vec::new();
+    compression.decompress(BufReader::new(File::open(path)?))?.read_to_end(&mut bytes)?;
+    checksum::deserialize(format, &bytes)
+}
+
+/// Checks whether user data was previously saved with the given `key`, regardless of its type.
+///
+/// # Failures
+/// If the data directory cannot be determined, or if a file I/O error (other than the file not
+/// existing) occurs.
+pub fn prefs_exist<S: AsRef<str>>(app: &AppInfo, key: S) -> Result<bool, PreferencesError> {
+    PreferencesStore::new(app)?.exists(key)
+}
+
+/// Returns the path of the file in which the user data of `app` with the given `key` is stored,
+/// e.g. to show users where their settings are, or to attach the file to a bug report.
+///
+/// If the data was saved, this is the file it was saved in, in whatever format and compression
+/// (the most recently saved one, if there are several). Otherwise, it's the file in which `save`
+/// would store it. (With a memory backend, the path identifies the data in the backend, but
+/// there is no such file.)
+///
+/// # Failures
+/// If the data directory cannot be determined, if `key` is invalid, or if a file I/O error
+/// (other than the file not existing) occurs.
+///
+/// # Example
+/// ```
+/// use preferences::{AppInfo, Preferences, prefs_file_path};
+///
+/// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+///
+/// # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
+/// let key = "tests/docs/file-path";
+/// 42u32.save(&APP_INFO, key).unwrap();
+/// let path = prefs_file_path(&APP_INFO, key).unwrap();
+/// assert!(path.ends_with("file-path.prefs.json"));
+/// ```
+pub fn prefs_file_path<S: AsRef<str>>(app: &AppInfo, key: S) -> Result<PathBuf, PreferencesError> {
+    let store = PreferencesStore::new(app)?;
+    let format = store.detect_format(key.as_ref())?.unwrap_or_default();
+    let path = store.file_path(key, format)?;
+    if store.is_in_memory() {
+        return Ok(path);
+    }
+    Ok(compression::find(&path).0)
+}
+
+/// Lists the keys of all user data saved under the given `prefix`, in sorted order.
+///
+/// The prefix is matched against whole key segments, so the prefix `options` matches the key
+/// `options/graphics`, but not `optionsfoo` or `options` itself. An empty prefix lists all keys
+/// saved by the app. Keys are returned in normalized form, i.e. without empty segments.
+///
+/// # Failures
+/// If the data directory cannot be determined, or if a file I/O error (e.g. permission denied)
+/// occurs. If nothing was saved under `prefix`, the returned list is simply empty.
+pub fn list_prefs<S>(app: &AppInfo, prefix: S) -> Result<Vec<String>, PreferencesError>
+    where S: AsRef<str>
+{
+    PreferencesStore::new(app)?.list(prefix)
+}
+
+/// Adds the keys of the files below `dir` in the default format, named as set by `names`, to
+/// `found`, with `prefix` prepended.
+fn collect_keys(dir: &Path,
+                prefix: &str,
+                names: &FileNames,
+                found: &mut Vec<String>)
+                -> Result<(), PreferencesError> {
+    for entry in read_key_dir(dir, names)? {
+        let join = |segment: String| if prefix.is_empty() {
+            segment
+        } else {
+            format!("{}/{}", prefix, segment)
+        };
+        match entry {
+            KeyEntry::Key(segment) => found.push(join(segment)),
+            KeyEntry::Dir(segment, path) => {
+                collect_keys(&path, &join(segment), names, found)?
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Entry of a directory of saved user data, with its file name mapped back to a key segment.
+enum KeyEntry {
+    /// A file containing user data.
+    Key(String),
+    /// A subdirectory, which may contain further keys.
+    Dir(String, PathBuf),
+}
+
+/// Lists the entries of `dir` that correspond to keys or key prefixes, i.e. files in the default
+/// format named as set by `names`. A missing directory is treated as empty.
+fn read_key_dir(dir: &Path, names: &FileNames) -> Result<Vec<KeyEntry>, PreferencesError> {
+    let suffixes: Vec<String> = Compression::all()
+        .iter()
+        .map(|compression| {
+            format!("{}.{}{}",
+                    names.suffix,
+                    Format::default().extension(),
+                    compression.suffix())
+        })
+        .collect();
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut found = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        if name.starts_with('.') {
+            continue;
+        }
+        let file_type = entry.file_type()?;
+        let stem = suffixes.iter()
+            .filter_map(|suffix| name.strip_suffix(&suffix[..]))
+            .find(|stem| !stem.is_empty());
+        let (segment, is_dir) = match stem {
+            _ if file_type.is_dir() => (name.as_str(), true),
+            Some(stem) if file_type.is_file() && names.layout == KeyLayout::LeafAsFile => {
+                (stem, false)
+            }
+            _ => continue,
+        };
+        let segment = match keys::unsanitized(segment) {
+            Some(segment) => segment,
+            None => continue,
+        };
+        if !is_dir {
+            found.push(KeyEntry::Key(segment));
+            continue;
+        }
+        let path = entry.path();
+        if names.layout == KeyLayout::LeafAsDir && has_default_file(&path, names) {
+            found.push(KeyEntry::Key(segment.clone()));
+        }
+        found.push(KeyEntry::Dir(segment, path));
+    }
+    Ok(found)
+}
+
+/// Returns whether `dir` contains the file of the key of `dir` in the `LeafAsDir` layout.
+fn has_default_file(dir: &Path, names: &FileNames) -> bool {
+    Compression::all().iter().any(|compression| {
+        let name = format!("{}.{}{}",
+                           names.default_name,
+                           Format::default().extension(),
+                           compression.suffix());
+        dir.join(name).is_file()
+    })
+}
+
+/// Deletes previously saved user data with the given `key`, regardless of its type.
+///
+/// Any directories that are left empty by the deletion are removed as well (up to the app's own
+/// directory).
+///
+/// # Failures
+/// If a file I/O error (e.g. permission denied) occurs, or `PreferencesError::NotFound` if no user
+/// data exists at that `key`.
+pub fn delete_prefs<S: AsRef<str>>(app: &AppInfo, key: S) -> Result<(), PreferencesError> {
+    PreferencesStore::new(app)?.delete(key)
+}
+
+/// Removes `dir` and its ancestors as long as they are empty, stopping at the app's directory
+/// `root`.
+fn remove_empty_dirs(root: &Path, dir: Option<&Path>) {
+    let mut dir = dir;
+    while let Some(current) = dir {
+        if current == root || !current.starts_with(root) || fs::remove_dir(current).is_err() {
+            break;
+        }
+        dir = current.parent();
     }
 }
 
@@ -359,21 +1595,46 @@ impl<T> Preferences for T
 /// This makes no guarantees that the specified directory path actually *exists* (though you can
 /// easily use `std::fs::create_dir_all(..)`). Returns `None` if the directory cannot be determined
 /// or is not available on the current platform.
+///
+/// If the environment variable `PREFERENCES_RS_DIR` is set to a non-empty path, that path is
+/// used as the base directory instead, and each app's data is stored in a subdirectory named
+/// after the app (without the author, on all platforms). This lets portable apps, containers
+/// and integration tests redirect where user data is written.
 pub fn prefs_base_dir() -> Option<PathBuf> {
-    get_data_root(AppDataType::UserConfig).ok()
+    base_dir_override().or_else(dirs::default_base_dir)
+}
+
+/// Returns the base directory set with the `PREFERENCES_RS_DIR` environment variable, if any.
+fn base_dir_override() -> Option<PathBuf> {
+    match env::var_os(BASE_DIR_ENV_VAR) {
+        Some(ref dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        _ => None,
+    }
+}
+
+/// Returns the directory for the user data of `app` within the base directory `base`.
+fn app_dir_in(base: &Path, app: &AppInfo) -> Result<PathBuf, PreferencesError> {
+    if app.name.is_empty() || app.author.is_empty() {
+        return Err(DirError::InvalidAppInfo.into());
+    }
+    Ok(base.join(keys::sanitized(app.name)))
 }
 
 #[cfg(test)]
 mod tests {
-    use {AppInfo, Preferences, PreferencesMap};
-    const APP_INFO: AppInfo = AppInfo {
-        name: "preferences",
-        author: "Rust language community",
-    };
-    const TEST_PREFIX: &'static str = "tests/module";
+    use crate::lock::{FileLock, Wait};
+    use crate::testing::{APP_INFO, TestBackend, TestDir};
+    use crate::{Format, Preferences, PreferencesError, PreferencesMap, PreferencesStore,
+                SaveOptions, TEMP_FILE_SUFFIX, delete_prefs, list_prefs, prefs_exist};
+    use std::fs;
+    use std::path::PathBuf;
+    const TEST_PREFIX: &str = "tests/module";
     fn gen_test_name(name: &str) -> String {
         TEST_PREFIX.to_owned() + "/" + name
     }
+    fn file_path(name: &str) -> PathBuf {
+        PreferencesStore::new(&APP_INFO).unwrap().file_path(name, Format::default()).unwrap()
+    }
     fn gen_sample_prefs() -> PreferencesMap<String> {
         let mut prefs = PreferencesMap::new();
         prefs.insert("foo".into(), "bar".into());
@@ -384,6 +1645,7 @@ mod tests {
     }
     #[test]
     fn test_save_load() {
+        let _backend = TestBackend::install();
         let sample_map = gen_sample_prefs();
         let sample_other: i32 = 4;
         let name_map = gen_test_name("save-load-map");
@@ -399,4 +1661,172 @@ mod tests {
         assert_eq!(load_map_result.unwrap(), sample_map);
         assert_eq!(load_other_result.unwrap(), sample_other);
     }
+    #[test]
+    fn test_save_is_atomic() {
+        let dir = TestDir::new("save-atomic");
+        let store = dir.store();
+        let name = gen_test_name("save-atomic");
+        assert!(store.save(&name, &gen_sample_prefs()).is_ok());
+        let mut updated = gen_sample_prefs();
+        updated.insert("foo".into(), "baz".into());
+        assert!(store.save(&name, &updated).is_ok());
+        assert_eq!(store.load::<PreferencesMap<String>, _>(&name).unwrap(), updated);
+        let path = store.file_path(&name, Format::default()).unwrap();
+        let leftovers = fs::read_dir(path.parent().unwrap())
+            .unwrap()
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_name().to_string_lossy().ends_with(TEMP_FILE_SUFFIX))
+            .count();
+        assert_eq!(leftovers, 0);
+    }
+    #[test]
+    fn test_delete() {
+        let dir = TestDir::new("delete");
+        let store = dir.store();
+        let name = gen_test_name("delete/nested/prefs");
+        assert!(store.save(&name, &gen_sample_prefs()).is_ok());
+        let path = store.file_path(&name, Format::default()).unwrap();
+        assert!(store.exists(&name).unwrap());
+        assert!(store.delete(&name).is_ok());
+        assert!(!store.exists(&name).unwrap());
+        assert!(!path.parent().unwrap().exists());
+        assert!(store.load::<PreferencesMap<String>, _>(&name).is_err());
+        assert!(store.delete(&name).is_err());
+
+        // The free functions do the same with the default store.
+        let _backend = TestBackend::install();
+        assert!(gen_sample_prefs().save(&APP_INFO, &name).is_ok());
+        assert!(PreferencesMap::<String>::exists(&APP_INFO, &name).unwrap());
+        assert!(PreferencesMap::<String>::delete(&APP_INFO, &name).is_ok());
+        assert!(!prefs_exist(&APP_INFO, &name).unwrap());
+        assert!(delete_prefs(&APP_INFO, &name).is_err());
+    }
+    #[test]
+    fn test_list_prefs() {
+        let _backend = TestBackend::install();
+        let prefix = gen_test_name("list");
+        let keys = ["list/a", "list/nested/b", "list/weird: key?", "list/ünïcødé"];
+        for key in &keys {
+            assert!(gen_sample_prefs().save(&APP_INFO, gen_test_name(key)).is_ok());
+        }
+        let expected: Vec<String> = keys.iter().map(|key| gen_test_name(key)).collect();
+        let mut sorted = expected.clone();
+        sorted.sort();
+        assert_eq!(list_prefs(&APP_INFO, &prefix).unwrap(), sorted);
+        assert_eq!(list_prefs(&APP_INFO, prefix.clone() + "/nested//").unwrap(),
+                   vec![gen_test_name("list/nested/b")]);
+        assert!(list_prefs(&APP_INFO, prefix.clone() + "/missing").unwrap().is_empty());
+    }
+    #[test]
+    fn test_save_pretty() {
+        let dir = TestDir::new("save-pretty");
+        let store = dir.store();
+        let sample = gen_sample_prefs();
+        let name = gen_test_name("save-pretty");
+        let options = SaveOptions::new().pretty(true);
+        assert!(store.save_with_options(&name, &sample, &options).is_ok());
+        assert_eq!(store.load::<PreferencesMap<String>, _>(&name).unwrap(), sample);
+        let path = store.file_path(&name, Format::default()).unwrap();
+        let text = fs::read_to_string(path).unwrap();
+        assert_eq!(text,
+                   "{\n  \"PI\": \"3.14\",\n  \"age\": \"23\",\n  \"foo\": \"bar\",\n  \
+                    \"offset\": \"-9\"\n}");
+    }
+    #[test]
+    fn test_try_save_load_locked() {
+        let dir = TestDir::new("try-save-load");
+        let store = dir.store();
+        let sample = gen_sample_prefs();
+        let name = gen_test_name("try-save-load");
+        assert!(store.try_save(&name, &sample).is_ok());
+        assert_eq!(store.try_load::<PreferencesMap<String>, _>(&name).unwrap(), sample);
+        let path = store.file_path(&name, Format::default()).unwrap();
+        {
+            let _lock = FileLock::acquire(&path, true, Wait::Block).unwrap();
+            match store.try_save(&name, &sample).as_ref().map_err(PreferencesError::inner) {
+                Err(&PreferencesError::WouldBlock) => {}
+                other => panic!("expected WouldBlock, got {:?}", other),
+            }
+            let result = store.try_load::<PreferencesMap<String>, _>(&name);
+            match result.as_ref().map_err(PreferencesError::inner) {
+                Err(&PreferencesError::WouldBlock) => {}
+                other => panic!("expected WouldBlock, got {:?}", other),
+            }
+        }
+        assert!(store.try_save(&name, &sample).is_ok());
+    }
+    #[test]
+    fn test_load_or_default() {
+        let backend = TestBackend::install();
+        let name = gen_test_name("load-or-default");
+        match u32::load(&APP_INFO, &name) {
+            Err(PreferencesError::NotFound { ref key, ref path }) => {
+                assert_eq!(*key, name);
+                assert_eq!(*path, file_path(&name));
+            }
+            other => panic!("expected NotFound, got {:?}", other),
+        }
+        assert_eq!(u32::load_or_default(&APP_INFO, &name).unwrap(), 0);
+        assert_eq!(u32::load_or_else(&APP_INFO, &name, || 7).unwrap(), 7);
+        assert!(!prefs_exist(&APP_INFO, &name).unwrap());
+        assert_eq!(u32::load_or_insert_with(&APP_INFO, &name, || 7).unwrap(), 7);
+        assert_eq!(u32::load_or_default(&APP_INFO, &name).unwrap(), 7);
+
+        backend.backend().write(&file_path(&name), b"corrupt".to_vec()).unwrap();
+        match u32::load_or_default(&APP_INFO, &name).as_ref().map_err(PreferencesError::inner) {
+            Err(&PreferencesError::Parse { line: 1, ref snippet, .. }) => {
+                assert_eq!(snippet, "corrupt");
+            }
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+        delete_prefs(&APP_INFO, &name).unwrap();
+    }
+    #[allow(dead_code)]
+    fn check_format_round_trip(format: Format) {
+        let _backend = TestBackend::install();
+        let sample = gen_sample_prefs();
+        let name = gen_test_name(&format!("save-load-{}", format.extension()));
+        let save_result = sample.save_with_format(&APP_INFO, &name, format);
+        assert!(save_result.is_ok());
+        let load_result = PreferencesMap::load_with_format(&APP_INFO, &name, format);
+        assert!(load_result.is_ok());
+        assert_eq!(load_result.unwrap(), sample);
+        assert!(PreferencesMap::<String>::load(&APP_INFO, &name).is_err());
+        assert_eq!(PreferencesMap::load_any_format(&APP_INFO, &name).ok(), Some(sample));
+    }
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn test_save_load_yaml() {
+        check_format_round_trip(Format::Yaml);
+    }
+    #[cfg(feature = "ini")]
+    #[test]
+    fn test_save_load_ini() {
+        check_format_round_trip(Format::Ini);
+    }
+    #[cfg(feature = "xml")]
+    #[test]
+    fn test_save_load_xml() {
+        check_format_round_trip(Format::Xml);
+    }
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn test_save_load_bincode() {
+        check_format_round_trip(Format::Bincode);
+    }
+    #[cfg(feature = "ron")]
+    #[test]
+    fn test_save_load_ron() {
+        check_format_round_trip(Format::Ron);
+    }
+    #[cfg(feature = "toml")]
+    #[test]
+    fn test_save_load_toml() {
+        check_format_round_trip(Format::Toml);
+    }
+    #[cfg(feature = "plist")]
+    #[test]
+    fn test_save_load_plist() {
+        check_format_round_trip(Format::Plist);
+    }
 }
@@ -0,0 +1,52 @@
+//! Persistence of a memory backend in the browser's `localStorage`, for WebAssembly.
+
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use web_sys::Storage;
+
+/// Prefix of the `localStorage` keys under which user data is stored, to tell it apart from the
+/// web app's other data.
+static KEY_PREFIX: &str = "preferences-rs:";
+
+/// Returns all user data stored in `localStorage`, keyed by the path its file would have.
+pub fn load_all() -> io::Result<Vec<(PathBuf, Vec<u8>)>> {
+    let storage = storage()?;
+    let mut found = Vec::new();
+    for i in 0..storage.length().map_err(js_error)? {
+        let key = match storage.key(i).map_err(js_error)? {
+            Some(key) => key,
+            None => continue,
+        };
+        if let Some(path) = key.strip_prefix(KEY_PREFIX) {
+            if let Some(value) = storage.get_item(&key).map_err(js_error)? {
+                found.push((PathBuf::from(path), value.into_bytes()));
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// Stores `data` for the file at `path`, or removes it if `data` is `None`.
+pub fn store(path: &Path, data: Option<&[u8]>) -> io::Result<()> {
+    let key = format!("{}{}", KEY_PREFIX, path.to_string_lossy());
+    match data {
+        Some(data) => {
+            // `localStorage` only holds strings, so binary formats can't be stored.
+            let text = ::std::str::from_utf8(data)
+                .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+            storage()?.set_item(&key, text).map_err(js_error)
+        }
+        None => storage()?.remove_item(&key).map_err(js_error),
+    }
+}
+
+fn storage() -> io::Result<Storage> {
+    web_sys::window()
+        .and_then(|window| window.local_storage().ok())
+        .and_then(|storage| storage)
+        .ok_or_else(|| io::Error::new(ErrorKind::Unsupported, "localStorage is not available"))
+}
+
+fn js_error<E>(_: E) -> io::Error {
+    io::Error::other("localStorage operation failed")
+}
@@ -0,0 +1,195 @@
+//! Where on disk user data is stored.
+
+#[cfg(feature = "app_dirs")]
+use app_dirs::AppDataType;
+use std::env;
+use std::path::{Path, PathBuf};
+use crate::dirs;
+use crate::{AppId, AppInfo, PreferencesError, app_dir_in, base_dir_override};
+
+/// Name of the marker file that enables
+/// [`StorageLocation::Portable`](enum.StorageLocation.html#variant.Portable) when it's placed next
+/// to the executable.
+pub const PORTABLE_MARKER_FILE: &str = "portable";
+
+/// Name of the directory next to the executable in which portable apps store their user data.
+pub const PORTABLE_DIR: &str = "config";
+
+/// Location of an app's user data, set with
+/// [`PreferencesStore::with_location`](struct.PreferencesStore.html#method.with_location).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum StorageLocation {
+    /// The platform's directory for user configuration, e.g. `~/.config/<app name>` on Linux.
+    /// (See [`prefs_base_dir`](fn.prefs_base_dir.html).)
+    #[default]
+    User,
+    /// The `config` directory next to the app's executable if a file named `portable` exists
+    /// next to the executable, and the same as `User` otherwise.
+    ///
+    /// This is the usual setup of portable apps, which are shipped with the marker file so that
+    /// they keep their settings with the executable (e.g. on a USB stick), but can also be
+    /// installed normally.
+    Portable,
+    /// The `config` directory next to the app's executable, regardless of any marker file (e.g.
+    /// because the app was started with a `--portable` flag).
+    AlwaysPortable,
+}
+
+impl StorageLocation {
+    /// Returns the directory in which the user data of `app` is stored at this location.
+    ///
+    /// # Failures
+    /// If the directory cannot be determined, e.g. because the path of the executable is unknown.
+    pub fn app_dir(&self, app: &AppInfo) -> Result<PathBuf, PreferencesError> {
+        self.dir_of(&AppId::from(app))
+    }
+
+    /// Same as `app_dir`, but for the app identified by `app`.
+    pub(crate) fn dir_of(&self, app: &AppId) -> Result<PathBuf, PreferencesError> {
+        match *self {
+            StorageLocation::User => user_app_dir(Scope::User, app),
+            StorageLocation::Portable => {
+                match portable_dir() {
+                    Ok(ref dir) if has_marker(dir) => Ok(dir.join(PORTABLE_DIR)),
+                    _ => user_app_dir(Scope::User, app),
+                }
+            }
+            StorageLocation::AlwaysPortable => Ok(portable_dir()?.join(PORTABLE_DIR)),
+        }
+    }
+}
+
+/// Kind of user data, which determines the platform directory in which it's stored. Set with
+/// [`PreferencesStore::with_scope`](struct.PreferencesStore.html#method.with_scope).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Scope {
+    /// Configuration of the current user. This is the scope used by
+    /// [`Preferences`](trait.Preferences.html).
+    #[default]
+    User,
+    /// Configuration of the current user that is specific to this machine, e.g. monitor layouts,
+    /// device names or paths of local files. On Windows, `User` is in the roaming `AppData`
+    /// directory, which follows users to other machines of their network, so this scope uses the
+    /// local `AppData` directory instead. Elsewhere, it's the same directory as `User`.
+    ///
+    /// To keep only some keys on the machine, use a store with this scope for them, and one with
+    /// `User` for the others.
+    Local,
+    /// Configuration shared by all users of the machine, e.g. in `/etc/xdg` on Linux or in
+    /// `ProgramData` on Windows. Usually only administrators can write to it, so it's suited for
+    /// machine-wide defaults that each user's preferences override.
+    System,
+    /// Cached data of the current user, which the app can recreate if it's deleted.
+    Cache,
+    /// Non-configuration data of the current user, e.g. saved games.
+    Data,
+    /// State of the current user's session that should persist between restarts but isn't
+    /// configuration, e.g. window positions or recently opened files. The default
+    /// [`DirProvider`](trait.DirProvider.html) stores it with `Data`;
+    /// [`XdgProvider`](struct.XdgProvider.html) stores it in `$XDG_STATE_HOME`.
+    State,
+}
+
+impl Scope {
+    /// Returns the `app_dirs` data type of this scope.
+    ///
+    /// Requires the `app_dirs` feature.
+    #[cfg(feature = "app_dirs")]
+    pub fn data_type(&self) -> AppDataType {
+        match *self {
+            Scope::User => AppDataType::UserConfig,
+            // `app_dirs` uses the local `AppData` directory for `UserData` on Windows.
+            Scope::Local if cfg!(windows) => AppDataType::UserData,
+            Scope::Local => AppDataType::UserConfig,
+            Scope::System => AppDataType::SharedConfig,
+            Scope::Cache => AppDataType::UserCache,
+            Scope::Data | Scope::State => AppDataType::UserData,
+        }
+    }
+
+    /// Returns the directory in which the user data of `app` is stored in this scope.
+    ///
+    /// For `Scope::User`, this is the same as `StorageLocation::User.app_dir(app)`, i.e. it
+    /// honors the `PREFERENCES_RS_DIR` environment variable, and so does `Scope::Local`, which
+    /// then uses the same directory; the other scopes don't.
+    ///
+    /// # Failures
+    /// If the directory cannot be determined (e.g. because `app` has an empty name).
+    pub fn app_dir(&self, app: &AppInfo) -> Result<PathBuf, PreferencesError> {
+        self.dir_of(&AppId::from(app))
+    }
+
+    /// Same as `app_dir`, but for the app identified by `app`.
+    pub(crate) fn dir_of(&self, app: &AppId) -> Result<PathBuf, PreferencesError> {
+        match *self {
+            Scope::User | Scope::Local => user_app_dir(*self, app),
+            _ => dirs::provider_app_dir(*self, app),
+        }
+    }
+}
+
+/// Returns the directory of `app` in `scope`, which is `User` or `Local`.
+fn user_app_dir(scope: Scope, app: &AppId) -> Result<PathBuf, PreferencesError> {
+    match base_dir_override() {
+        Some(base) => app_dir_in(&base, app.info()),
+        // In the browser, the data is kept in `localStorage`, and the paths are only its keys.
+        None if cfg!(all(target_arch = "wasm32", target_os = "unknown")) => {
+            app_dir_in(Path::new("/"), app.info())
+        }
+        None => dirs::provider_app_dir(scope, app),
+    }
+}
+
+/// Returns the directory containing the current executable.
+fn portable_dir() -> Result<PathBuf, PreferencesError> {
+    let mut exe = env::current_exe()?;
+    exe.pop();
+    Ok(exe)
+}
+
+fn has_marker(dir: &Path) -> bool {
+    dir.join(PORTABLE_MARKER_FILE).is_file()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Scope, StorageLocation};
+    use crate::testing::{APP_INFO, TestBackend};
+    use crate::{DirProvider, DirectoriesProvider, PreferencesStore};
+    use std::env;
+
+    #[test]
+    fn test_storage_location() {
+        let exe_dir = env::current_exe().unwrap().parent().unwrap().to_owned();
+        assert_eq!(StorageLocation::AlwaysPortable.app_dir(&APP_INFO).unwrap(),
+                   exe_dir.join("config"));
+        // The test executable isn't shipped with a marker file.
+        assert_eq!(StorageLocation::Portable.app_dir(&APP_INFO).unwrap(),
+                   StorageLocation::User.app_dir(&APP_INFO).unwrap());
+    }
+
+    #[test]
+    fn test_scope() {
+        let _backend = TestBackend::install();
+        let store = PreferencesStore::new(&APP_INFO).unwrap().with_scope(Scope::Cache).unwrap();
+        assert_eq!(store.root(),
+                   DirectoriesProvider.app_dir(Scope::Cache, &APP_INFO).unwrap());
+        let key = "tests/module/scope";
+        store.save(key, &42u32).unwrap();
+        assert_eq!(store.load::<u32, _>(key).unwrap(), 42);
+        assert!(!PreferencesStore::new(&APP_INFO).unwrap().exists(key).unwrap());
+        store.delete(key).unwrap();
+    }
+
+    #[test]
+    fn test_local_scope() {
+        let local = Scope::Local.app_dir(&APP_INFO).unwrap();
+        let user = Scope::User.app_dir(&APP_INFO).unwrap();
+        if cfg!(windows) && crate::base_dir_override().is_none() {
+            assert_eq!(local, DirectoriesProvider.app_dir(Scope::Local, &APP_INFO).unwrap());
+            assert_ne!(local, user);
+        } else {
+            assert_eq!(local, user);
+        }
+    }
+}
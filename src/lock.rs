@@ -0,0 +1,210 @@
+//! Advisory locking of preferences files.
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use fs2::{self, FileExt};
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use crate::{compression, PreferencesError};
+
+static LOCK_FILE_SUFFIX: &str = ".lock";
+
+/// Whether to wait for a lock held by someone else, or to give up immediately.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Wait {
+    Block,
+    Try,
+}
+
+/// An advisory lock on a preferences file, released when dropped.
+///
+/// The lock is taken on a separate, hidden lock file next to the preferences file, since the
+/// preferences file itself is replaced on every save.
+///
+/// A shared lock isn't taken if there's nothing to read, so that loading data that was never
+/// saved leaves no lock file behind, or if the lock file can't be created because the directory
+/// isn't writable, so that data the user can't change can still be loaded.
+#[derive(Debug)]
+pub struct FileLock {
+    file: Option<File>,
+    path: PathBuf,
+}
+
+impl FileLock {
+    /// Locks the preferences file at `path`, exclusively for writing or shared for reading.
+    pub fn acquire(path: &Path, exclusive: bool, wait: Wait) -> Result<Self, PreferencesError> {
+        let lock_path = lock_file_path(path);
+        loop {
+            let file = match open_lock_file(path, &lock_path, exclusive)? {
+                Some(file) => file,
+                None => return Ok(FileLock { file: None, path: lock_path }),
+            };
+            match lock_file(&file, exclusive, wait) {
+                Ok(()) => {}
+                Err(ref e) if is_contended(e) => return Err(PreferencesError::WouldBlock),
+                Err(e) => return Err(e.into()),
+            }
+            // If the lock file was removed while waiting for it, the lock protects nothing, and
+            // whoever comes next locks the file that replaces it.
+            if is_current(&file, &lock_path) {
+                return Ok(FileLock { file: Some(file), path: lock_path });
+            }
+        }
+    }
+
+    /// Removes the lock file and releases the lock. The file is removed while it's still locked,
+    /// so anyone waiting for it notices that it's gone once they get it.
+    ///
+    /// Outside of Unix, where a removed lock file can't be told apart from a new one, the lock
+    /// file is kept.
+    pub fn remove(self) {
+        if cfg!(unix) && self.file.is_some() {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        if let Some(ref file) = self.file {
+            let _ = unlock_file(file);
+        }
+    }
+}
+
+/// Opens the lock file at `lock_path` for the preferences file at `path`, or returns `None` if a
+/// shared lock shouldn't be taken.
+fn open_lock_file(path: &Path, lock_path: &Path, exclusive: bool) -> io::Result<Option<File>> {
+    let create = || {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(lock_path)
+    };
+    if exclusive {
+        return create().map(Some);
+    }
+    match File::open(lock_path) {
+        Ok(file) => return Ok(Some(file)),
+        Err(ref e) if e.kind() == ErrorKind::NotFound => {}
+        Err(ref e) if is_read_only(e) => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    if !compression::find(path).0.exists() {
+        return Ok(None);
+    }
+    match create() {
+        Ok(file) => Ok(Some(file)),
+        Err(ref e) if e.kind() == ErrorKind::NotFound || is_read_only(e) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Returns whether the locked `file` is still the lock file at `lock_path`.
+#[cfg(unix)]
+fn is_current(file: &File, lock_path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (file.metadata(), fs::metadata(lock_path)) {
+        (Ok(locked), Ok(current)) => locked.dev() == current.dev() && locked.ino() == current.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn is_current(_file: &File, _lock_path: &Path) -> bool {
+    true
+}
+
+/// Returns whether `e` means that a file can't be written, because of its permissions or a
+/// read-only file system.
+fn is_read_only(e: &io::Error) -> bool {
+    // EROFS is 30 on Linux, macOS and the BSDs.
+    e.kind() == ErrorKind::PermissionDenied || cfg!(unix) && e.raw_os_error() == Some(30)
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+fn lock_file(file: &File, exclusive: bool, wait: Wait) -> io::Result<()> {
+    match (exclusive, wait) {
+        (true, Wait::Block) => file.lock_exclusive(),
+        (true, Wait::Try) => file.try_lock_exclusive(),
+        (false, Wait::Block) => FileExt::lock_shared(file),
+        (false, Wait::Try) => FileExt::try_lock_shared(file),
+    }
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+fn unlock_file(file: &File) -> io::Result<()> {
+    FileExt::unlock(file)
+}
+
+// In the browser there are no other processes to lock out (and no files to lock).
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+fn lock_file(_file: &File, _exclusive: bool, _wait: Wait) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+fn unlock_file(_file: &File) -> io::Result<()> {
+    Ok(())
+}
+
+/// Returns the path of the lock file for the preferences file at `path`.
+pub fn lock_file_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    if let Some(file_name) = path.file_name() {
+        name.push(file_name);
+    }
+    name.push(LOCK_FILE_SUFFIX);
+    path.with_file_name(name)
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+fn is_contended(e: &io::Error) -> bool {
+    e.kind() == ErrorKind::WouldBlock ||
+    e.raw_os_error().is_some() && e.raw_os_error() == fs2::lock_contended_error().raw_os_error()
+}
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+fn is_contended(e: &io::Error) -> bool {
+    e.kind() == ErrorKind::WouldBlock
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::{lock_file_path, FileLock, Wait};
+    use crate::testing::TestDir;
+    use crate::PreferencesError;
+    use std::time::Duration;
+    use std::{fs, thread};
+
+    #[test]
+    fn test_lock_file_removed() {
+        let dir = TestDir::new("lock");
+        fs::create_dir_all(dir.path()).unwrap();
+        let path = dir.path().join("theme.prefs.json");
+
+        // Nothing to read, so nothing to lock.
+        drop(FileLock::acquire(&path, false, Wait::Block).unwrap());
+        assert!(!lock_file_path(&path).exists());
+
+        let lock = FileLock::acquire(&path, true, Wait::Block).unwrap();
+        let waiting_path = path.clone();
+        let waiting = thread::spawn(move || {
+            let _lock = FileLock::acquire(&waiting_path, true, Wait::Block).unwrap();
+            assert!(lock_file_path(&waiting_path).exists());
+            thread::sleep(Duration::from_millis(200));
+        });
+        thread::sleep(Duration::from_millis(100));
+        lock.remove();
+        thread::sleep(Duration::from_millis(100));
+        match FileLock::acquire(&path, true, Wait::Try) {
+            Err(PreferencesError::WouldBlock) => {}
+            other => panic!("expected WouldBlock, got {:?}", other),
+        }
+        waiting.join().unwrap();
+    }
+}
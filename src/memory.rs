@@ -0,0 +1,470 @@
+//! In-memory storage of user data, for hermetic tests.
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+use crate::local_storage;
+use crate::backup::backup_path;
+use crate::{FileNames, Format, KeyLayout, StorageBackend, keys};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::SystemTime;
+
+thread_local! {
+    static TEST_BACKEND: RefCell<Option<MemoryBackend>> = const { RefCell::new(None) };
+}
+
+/// Storage for user data that keeps it in memory instead of in files.
+///
+/// A memory backend holds the contents that the preferences files would have, keyed by the
+/// paths they would have, so saved data can be loaded, listed and deleted as usual, but nothing
+/// is written to disk. Clones of a backend share the same data. Since there are no files, saves
+/// don't take locks. Backups (see [`SaveOptions::backups`](struct.SaveOptions.html#method.backups))
+/// are kept in the backend, next to the data.
+///
+/// Use it for a single [`PreferencesStore`](struct.PreferencesStore.html) with
+/// [`with_memory_backend`](struct.PreferencesStore.html#method.with_memory_backend), or for
+/// everything on the current thread with
+/// [`set_backend_for_tests`](fn.set_backend_for_tests.html).
+#[derive(Clone, Debug, Default)]
+pub struct MemoryBackend {
+    files: Arc<Mutex<BTreeMap<PathBuf, Vec<u8>>>>,
+    modified: Arc<Mutex<BTreeMap<PathBuf, SystemTime>>>,
+    #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+    persistent: bool,
+    /// The backend that keeps the data instead of the maps, if any.
+    storage: Option<Storage>,
+}
+
+/// A [`StorageBackend`](trait.StorageBackend.html), and the directory that the paths it stores
+/// are relative to.
+#[derive(Clone, Debug)]
+struct Storage {
+    root: PathBuf,
+    backend: Arc<dyn StorageBackend>,
+}
+
+impl Storage {
+    /// Returns the path of the data at `path` in the backend.
+    fn relative(&self, path: &Path) -> io::Result<String> {
+        let relative = path.strip_prefix(&self.root).map_err(|_| {
+            io::Error::new(ErrorKind::InvalidInput,
+                           format!("{} is outside of {}", path.display(), self.root.display()))
+        })?;
+        let segments: Vec<_> = relative.components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect();
+        Ok(segments.join("/"))
+    }
+
+    /// Returns the paths of the data stored below the directory `dir`.
+    fn list(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut prefix = self.relative(dir)?;
+        if !prefix.is_empty() {
+            prefix.push('/');
+        }
+        Ok(self.backend.list(&prefix)?.into_iter().map(|path| self.root.join(path)).collect())
+    }
+}
+
+impl MemoryBackend {
+    /// Creates an empty backend.
+    pub fn new() -> Self {
+        MemoryBackend::default()
+    }
+
+    /// Creates a backend that also keeps its data in the browser's `localStorage`, starting with
+    /// the data stored there before. This is the backend used by default in WebAssembly builds
+    /// for the browser, where there are no files.
+    ///
+    /// Since `localStorage` only holds strings, data in binary formats (e.g. `Format::Bincode`)
+    /// can't be saved in this backend.
+    ///
+    /// # Failures
+    /// If `localStorage` is not available, e.g. outside of a browser window.
+    #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+    pub fn local_storage() -> io::Result<Self> {
+        let backend = MemoryBackend {
+            persistent: true,
+            ..MemoryBackend::default()
+        };
+        backend.files().extend(local_storage::load_all()?);
+        Ok(backend)
+    }
+
+    /// Creates a backend that keeps its data in `backend`, with paths relative to `root`.
+    pub(crate) fn with_storage(root: PathBuf, backend: Arc<dyn StorageBackend>) -> Self {
+        MemoryBackend {
+            storage: Some(Storage { root, backend }),
+            ..MemoryBackend::default()
+        }
+    }
+
+    /// Makes the paths in the storage backend, if any, relative to `root`.
+    pub(crate) fn set_root(&mut self, root: &Path) {
+        if let Some(ref mut storage) = self.storage {
+            storage.root = root.to_owned();
+        }
+    }
+
+    /// Returns whether no user data is stored in this backend.
+    pub fn is_empty(&self) -> bool {
+        if let Some(ref storage) = self.storage {
+            return storage.backend.list("").is_ok_and(|paths| paths.is_empty());
+        }
+        self.files().is_empty()
+    }
+
+    /// Removes all user data stored in this backend.
+    pub fn clear(&self) {
+        if let Some(ref storage) = self.storage {
+            if let Ok(paths) = storage.backend.list("") {
+                for path in paths {
+                    let _ = storage.backend.delete(&path);
+                }
+            }
+            return;
+        }
+        let mut files = self.files();
+        for path in files.keys() {
+            let _ = self.persist(path, None);
+        }
+        files.clear();
+        self.modified().clear();
+    }
+
+    pub(crate) fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        if let Some(ref storage) = self.storage {
+            return storage.backend
+                .read(&storage.relative(path)?)?
+                .ok_or_else(|| ErrorKind::NotFound.into());
+        }
+        self.files().get(path).cloned().ok_or_else(|| ErrorKind::NotFound.into())
+    }
+
+    pub(crate) fn write(&self, path: &Path, data: Vec<u8>) -> io::Result<()> {
+        if let Some(ref storage) = self.storage {
+            return storage.backend.write(&storage.relative(path)?, &data);
+        }
+        self.persist(path, Some(&data))?;
+        self.files().insert(path.to_owned(), data);
+        // There's no clock in WebAssembly for the browser.
+        #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+        self.modified().insert(path.to_owned(), SystemTime::now());
+        Ok(())
+    }
+
+    /// Returns when the data for `path` was last written, if known.
+    pub(crate) fn modified_time(&self, path: &Path) -> Option<SystemTime> {
+        if let Some(ref storage) = self.storage {
+            let path = storage.relative(path).ok()?;
+            return storage.backend.modified(&path).ok().flatten();
+        }
+        self.modified().get(path).cloned()
+    }
+
+    pub(crate) fn contains(&self, path: &Path) -> bool {
+        if self.storage.is_some() {
+            return self.read(path).is_ok();
+        }
+        self.files().contains_key(path)
+    }
+
+    pub(crate) fn remove(&self, path: &Path) -> io::Result<()> {
+        if let Some(ref storage) = self.storage {
+            return match storage.backend.delete(&storage.relative(path)?)? {
+                true => Ok(()),
+                false => Err(ErrorKind::NotFound.into()),
+            };
+        }
+        let mut files = self.files();
+        if !files.contains_key(path) {
+            return Err(ErrorKind::NotFound.into());
+        }
+        self.persist(path, None)?;
+        files.remove(path);
+        self.modified().remove(path);
+        Ok(())
+    }
+
+    /// Keeps a copy of the data for `path` as its most recent backup, discarding backups beyond
+    /// the `count` most recent ones, like `backup::rotate_backups` does for files.
+    pub(crate) fn rotate_backups(&self, path: &Path, count: usize) -> io::Result<()> {
+        if count == 0 || !self.contains(path) {
+            return Ok(());
+        }
+        let _ = self.remove(&backup_path(path, count));
+        for n in (1..count).rev() {
+            self.rename(&backup_path(path, n), &backup_path(path, n + 1))?;
+        }
+        self.write(&backup_path(path, 1), self.read(path)?)
+    }
+
+    /// Replaces the data for `path` with its most recent backup, and makes the older backups
+    /// more recent by one.
+    pub(crate) fn restore_backup(&self, path: &Path) -> io::Result<()> {
+        if !self.rename(&backup_path(path, 1), path)? {
+            return Err(ErrorKind::NotFound.into());
+        }
+        let mut n = 2;
+        while self.rename(&backup_path(path, n), &backup_path(path, n - 1))? {
+            n += 1;
+        }
+        Ok(())
+    }
+
+    /// Removes all backups of the data for `path`.
+    pub(crate) fn remove_backups(&self, path: &Path) {
+        let mut n = 1;
+        while self.remove(&backup_path(path, n)).is_ok() {
+            n += 1;
+        }
+    }
+
+    /// Moves the data for `from` to `to`, or returns `false` if there's none.
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<bool> {
+        let data = match self.read(from) {
+            Ok(data) => data,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        self.write(to, data)?;
+        self.remove(from)?;
+        Ok(true)
+    }
+
+    /// Removes everything stored below the directory `dir`.
+    pub(crate) fn remove_dir(&self, dir: &Path) -> io::Result<()> {
+        if let Some(ref storage) = self.storage {
+            for path in storage.list(dir)? {
+                storage.backend.delete(&storage.relative(&path)?)?;
+            }
+            return Ok(());
+        }
+        let mut files = self.files();
+        let removed: Vec<PathBuf> = files.keys()
+            .filter(|path| path.starts_with(dir))
+            .cloned()
+            .collect();
+        for path in removed {
+            self.persist(&path, None)?;
+            files.remove(&path);
+            self.modified().remove(&path);
+        }
+        Ok(())
+    }
+
+    /// Returns the total size of the data stored below the directory `dir`, except for the paths
+    /// in `excluded`.
+    pub(crate) fn total_size<P: AsRef<Path>>(&self, dir: &Path, excluded: &[P]) -> u64 {
+        if let Some(ref storage) = self.storage {
+            return storage.list(dir)
+                .unwrap_or_default()
+                .iter()
+                .filter(|&path| !excluded.iter().any(|e| e.as_ref() == path))
+                .filter_map(|path| self.read(path).ok())
+                .map(|data| data.len() as u64)
+                .sum();
+        }
+        self.files()
+            .iter()
+            .filter(|&(path, _)| {
+                path.starts_with(dir) && !excluded.iter().any(|e| e.as_ref() == path)
+            })
+            .map(|(_, data)| data.len() as u64)
+            .sum()
+    }
+
+    /// Same as `collect_keys`, for the data stored below the directory `dir`.
+    pub(crate) fn list_keys(&self, dir: &Path, prefix: &str, names: &FileNames) -> Vec<String> {
+        let mut found = Vec::new();
+        let paths = match self.storage {
+            Some(ref storage) => storage.list(dir).unwrap_or_default(),
+            None => self.files().keys().cloned().collect(),
+        };
+        for path in paths {
+            let key = path.strip_prefix(dir).ok().and_then(|path| relative_key(path, names));
+            if let Some(key) = key {
+                found.push(if prefix.is_empty() {
+                    key
+                } else {
+                    format!("{}/{}", prefix, key)
+                });
+            }
+        }
+        found
+    }
+
+    /// Returns the names of the directories directly below the directory `dir`.
+    pub(crate) fn list_dirs(&self, dir: &Path) -> Vec<String> {
+        let paths = match self.storage {
+            Some(ref storage) => storage.list(dir).unwrap_or_default(),
+            None => self.files().keys().cloned().collect(),
+        };
+        let mut found: Vec<String> = paths.iter()
+            .filter_map(|path| {
+                let mut components = path.strip_prefix(dir).ok()?.components();
+                let name = components.next()?.as_os_str().to_str()?;
+                components.next().map(|_| name.to_owned())
+            })
+            .collect();
+        found.sort();
+        found.dedup();
+        found
+    }
+
+    /// Stores `data` for `path` in `localStorage` if this backend is persistent.
+    #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+    fn persist(&self, path: &Path, data: Option<&[u8]>) -> io::Result<()> {
+        if self.persistent {
+            local_storage::store(path, data)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    fn persist(&self, _path: &Path, _data: Option<&[u8]>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn files(&self) -> MutexGuard<'_, BTreeMap<PathBuf, Vec<u8>>> {
+        // A test that panicked while holding the lock can't have left the map inconsistent.
+        self.files.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn modified(&self) -> MutexGuard<'_, BTreeMap<PathBuf, SystemTime>> {
+        self.modified.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Maps the path of a prefs file, relative to a directory, back to the key relative to that
+/// directory. Returns `None` if the path isn't a prefs file in the default format, named as set
+/// by `names`.
+fn relative_key(path: &Path, names: &FileNames) -> Option<String> {
+    let extension = Format::default().extension();
+    let mut segments = Vec::new();
+    let mut components = path.components().peekable();
+    while let Some(component) = components.next() {
+        let mut name = component.as_os_str().to_str()?;
+        if name.starts_with('.') {
+            return None;
+        }
+        if components.peek().is_none() {
+            match names.layout {
+                KeyLayout::LeafAsFile => {
+                    name = name.strip_suffix(extension)
+                        .and_then(|name| name.strip_suffix('.'))
+                        .and_then(|name| name.strip_suffix(&names.suffix[..]))
+                        .filter(|name| !name.is_empty())?;
+                }
+                // The file is named the same for all keys, which are named by its directory.
+                KeyLayout::LeafAsDir => {
+                    let default_name = name.strip_suffix(extension)
+                        .and_then(|name| name.strip_suffix('.'))?;
+                    if default_name != names.default_name {
+                        return None;
+                    }
+                    break;
+                }
+            }
+        }
+        segments.push(keys::unsanitized(name)?);
+    }
+    if segments.is_empty() {
+        None
+    } else {
+        Some(segments.join("/"))
+    }
+}
+
+/// Makes all user data saved and loaded on the current thread go to `backend` instead of to
+/// files, or back to files if `backend` is `None`.
+///
+/// This is meant for tests: since each test runs on its own thread, tests that install a fresh
+/// `MemoryBackend` run hermetically and in parallel, without touching the user's real
+/// preferences. The backend applies to everything that goes through
+/// [`PreferencesStore`](struct.PreferencesStore.html), i.e. the
+/// [`Preferences`](trait.Preferences.html) methods, the free functions like
+/// [`list_prefs`](fn.list_prefs.html), and [`PreferencesNode`](struct.PreferencesNode.html). Data
+/// saved by other threads that the test spawns still goes to files.
+///
+/// # Example
+/// ```
+/// use preferences::{AppInfo, MemoryBackend, Preferences, set_backend_for_tests};
+///
+/// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+///
+/// let backend = MemoryBackend::new();
+/// set_backend_for_tests(Some(backend.clone()));
+/// 42u32.save(&APP_INFO, "tests/docs/memory").unwrap();
+/// assert_eq!(u32::load(&APP_INFO, "tests/docs/memory").unwrap(), 42);
+/// assert!(!backend.is_empty());
+/// set_backend_for_tests(None);
+/// ```
+pub fn set_backend_for_tests(backend: Option<MemoryBackend>) {
+    TEST_BACKEND.with(|current| *current.borrow_mut() = backend);
+}
+
+/// Returns the backend installed for the current thread with `set_backend_for_tests`.
+pub fn backend_for_tests() -> Option<MemoryBackend> {
+    TEST_BACKEND.with(|current| current.borrow().clone())
+}
+
+/// Returns the backend that stores user data on platforms without files, i.e. `localStorage` in
+/// WebAssembly builds for the browser.
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+pub fn platform_backend() -> io::Result<Option<MemoryBackend>> {
+    thread_local! {
+        static LOCAL_STORAGE: RefCell<Option<MemoryBackend>> = const { RefCell::new(None) };
+    }
+    LOCAL_STORAGE.with(|cached| {
+        let mut cached = cached.borrow_mut();
+        if cached.is_none() {
+            *cached = Some(MemoryBackend::local_storage()?);
+        }
+        Ok(cached.clone())
+    })
+}
+
+/// Returns the backend that stores user data on platforms without files, i.e. `None` on
+/// platforms that have files.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub fn platform_backend() -> io::Result<Option<MemoryBackend>> {
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MemoryBackend, set_backend_for_tests};
+    use crate::testing::APP_INFO;
+    use crate::{Preferences, PreferencesNode, PreferencesStore, list_prefs};
+
+    #[test]
+    fn test_memory_backend() {
+        let backend = MemoryBackend::new();
+        set_backend_for_tests(Some(backend.clone()));
+        let key = "tests/module/memory/value";
+        let path = PreferencesStore::new(&APP_INFO)
+            .unwrap()
+            .file_path(key, Default::default())
+            .unwrap();
+
+        42u32.save(&APP_INFO, key).unwrap();
+        "other".to_owned().save(&APP_INFO, "tests/module/memory/sub/other").unwrap();
+        assert!(!path.exists());
+        assert_eq!(u32::load(&APP_INFO, key).unwrap(), 42);
+        assert_eq!(list_prefs(&APP_INFO, "tests/module/memory").unwrap(),
+                   vec!["tests/module/memory/sub/other", "tests/module/memory/value"]);
+
+        let node = PreferencesNode::root(&APP_INFO).node("tests/module/memory");
+        assert_eq!(node.keys().unwrap(), vec!["value"]);
+        assert_eq!(node.children().unwrap(), vec!["sub"]);
+        node.remove_node().unwrap();
+        assert!(backend.is_empty());
+        assert!(u32::load(&APP_INFO, key).unwrap_err().is_not_found());
+
+        set_backend_for_tests(None);
+        assert!(!PreferencesStore::new(&APP_INFO).unwrap().is_in_memory());
+    }
+}
@@ -0,0 +1,43 @@
+//! Information about saved user data, without loading it.
+
+use std::time::SystemTime;
+use crate::{AppInfo, Format, PreferencesError, PreferencesStore};
+
+/// Information about the user data saved with a key, returned by
+/// [`metadata`](fn.metadata.html).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PrefsMetadata {
+    /// When the data was last saved. (In a memory backend in WebAssembly for the browser, where
+    /// there's no clock, this is always `UNIX_EPOCH`.)
+    pub modified: SystemTime,
+    /// The size of the saved data in bytes, after compression.
+    pub size: u64,
+    /// The format in which the data was saved.
+    pub format: Format,
+}
+
+/// Returns when and in which format the user data of `app` with the given `key` was saved, and
+/// its size, e.g. to show when settings were last changed, or to reload cached data only after
+/// it was saved again. If the data was saved in several formats, the most recently saved one is
+/// described.
+///
+/// # Failures
+/// `PreferencesError::NotFound` if no data was saved with `key`, or an error if the data
+/// directory cannot be determined or the file's metadata cannot be read.
+///
+/// # Example
+/// ```
+/// use preferences::{AppInfo, Format, Preferences, metadata};
+///
+/// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+///
+/// # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
+/// let key = "tests/docs/metadata";
+/// 42u32.save(&APP_INFO, key).unwrap();
+/// let metadata = metadata(&APP_INFO, key).unwrap();
+/// assert_eq!(metadata.size, 2);
+/// assert_eq!(metadata.format, Format::Json);
+/// ```
+pub fn metadata<S: AsRef<str>>(app: &AppInfo, key: S) -> Result<PrefsMetadata, PreferencesError> {
+    PreferencesStore::new(app)?.metadata(key)
+}
@@ -0,0 +1,229 @@
+//! Importing user data from the configuration files of other crates or older versions of an app.
+
+use serde_json::Value;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use crate::{AppInfo, Format, Operation, PreferencesError, PreferencesStore};
+
+/// A place where user data may have been kept before the app used this crate. (See
+/// [`migrate_from`](fn.migrate_from.html).)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LegacyLocation {
+    /// A file at the given path, e.g. the configuration file of a previous version of the app.
+    /// It's read in the format of its extension (e.g. `.toml` with the `toml` feature), or as
+    /// JSON if no enabled format uses the extension.
+    File(PathBuf),
+    /// The configuration file that the `confy` crate keeps for `confy::load(app_name,
+    /// config_name)`, in TOML. A `config_name` of `None` is confy's default configuration.
+    ///
+    /// Requires the `toml` feature.
+    #[cfg(feature = "toml")]
+    Confy {
+        /// The app name passed to confy.
+        app_name: String,
+        /// The configuration name passed to confy, if any.
+        config_name: Option<String>,
+    },
+}
+
+impl LegacyLocation {
+    /// Returns the path of the legacy file. This makes no guarantees that the file exists.
+    ///
+    /// # Failures
+    /// If the directory of the file cannot be determined.
+    pub fn path(&self) -> Result<PathBuf, PreferencesError> {
+        match *self {
+            LegacyLocation::File(ref path) => Ok(path.clone()),
+            #[cfg(feature = "toml")]
+            LegacyLocation::Confy { ref app_name, ref config_name } => {
+                let dirs = directories_next::ProjectDirs::from("rs", "", app_name)
+                    .ok_or(crate::DirError::NotSupported)?;
+                let name = config_name.as_deref().unwrap_or("default-config");
+                Ok(dirs.config_dir().join(format!("{}.toml", name)))
+            }
+        }
+    }
+}
+
+/// Options of [`PreferencesStore::migrate_from`](struct.PreferencesStore.html#method.migrate_from).
+///
+/// By default, legacy files are kept, and they aren't imported if data is already saved with
+/// the key they would be imported to.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MigrateOptions {
+    remove_originals: bool,
+    overwrite: bool,
+}
+
+impl MigrateOptions {
+    /// Creates the default options.
+    pub fn new() -> Self {
+        MigrateOptions::default()
+    }
+
+    /// Sets whether legacy files are removed once they're imported.
+    pub fn remove_originals(mut self, remove: bool) -> Self {
+        self.remove_originals = remove;
+        self
+    }
+
+    /// Sets whether legacy files are imported even if data is already saved with their key,
+    /// replacing that data.
+    pub fn overwrite(mut self, overwrite: bool) -> Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    /// Returns whether legacy files are removed once they're imported.
+    pub fn is_remove_originals(&self) -> bool {
+        self.remove_originals
+    }
+
+    /// Returns whether legacy files replace data that is already saved.
+    pub fn is_overwrite(&self) -> bool {
+        self.overwrite
+    }
+}
+
+/// What a migration did. If no legacy file was found, all lists are empty.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MigrationReport {
+    /// The legacy files that were imported, with the keys with which their data was saved.
+    pub migrated: Vec<(PathBuf, String)>,
+    /// The legacy files that were removed after they were imported.
+    pub removed: Vec<PathBuf>,
+    /// The legacy files that weren't imported because data was already saved with their key.
+    pub skipped: Vec<(PathBuf, String)>,
+}
+
+impl MigrationReport {
+    /// Returns whether any legacy file was imported.
+    pub fn is_migrated(&self) -> bool {
+        !self.migrated.is_empty()
+    }
+}
+
+/// Imports the legacy file at `old_path` into the user data of `app` with the given `key`, if
+/// the file exists and no data is saved with `key` yet. The legacy file is kept; to remove it,
+/// use [`PreferencesStore::migrate_from`](struct.PreferencesStore.html#method.migrate_from)
+/// with [`MigrateOptions`](struct.MigrateOptions.html).
+///
+/// # Failures
+/// If the data directory cannot be determined, or the legacy file exists but cannot be read or
+/// parsed, or its data cannot be saved.
+///
+/// # Example
+/// ```
+/// use preferences::{AppInfo, Preferences, PreferencesMap, migrate_from_path};
+/// # use std::fs;
+///
+/// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+///
+/// # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
+/// # let old_path = std::env::temp_dir().join("preferences-rs-doc-legacy.json");
+/// # fs::write(&old_path, r#"{"theme": "dark"}"#).unwrap();
+/// let report = migrate_from_path(&old_path, &APP_INFO, "tests/docs/migrated").unwrap();
+/// if report.is_migrated() {
+///     println!("Imported the settings of the previous version");
+/// }
+/// let settings = PreferencesMap::<String>::load(&APP_INFO, "tests/docs/migrated").unwrap();
+/// assert_eq!(settings["theme"], "dark");
+/// # fs::remove_file(&old_path).unwrap();
+/// ```
+pub fn migrate_from_path<P, S>(old_path: P,
+                               app: &AppInfo,
+                               key: S)
+                               -> Result<MigrationReport, PreferencesError>
+    where P: AsRef<Path>,
+          S: AsRef<str>
+{
+    migrate_from(&LegacyLocation::File(old_path.as_ref().to_owned()), app, key)
+}
+
+/// Same as `migrate_from_path`, but finds the legacy file at `location`, e.g. where the `confy`
+/// crate keeps the app's configuration.
+pub fn migrate_from<S: AsRef<str>>(location: &LegacyLocation,
+                                   app: &AppInfo,
+                                   key: S)
+                                   -> Result<MigrationReport, PreferencesError> {
+    PreferencesStore::new(app)?.migrate_from(location, key, &MigrateOptions::default())
+}
+
+/// Imports the legacy file at `location` into `store` with the given `key`.
+pub(crate) fn migrate(store: &PreferencesStore,
+                      location: &LegacyLocation,
+                      key: &str,
+                      options: &MigrateOptions)
+                      -> Result<MigrationReport, PreferencesError> {
+    let mut report = MigrationReport::default();
+    let path = location.path()?;
+    let bytes = match fs::read(&path) {
+        Ok(bytes) => bytes,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(report),
+        Err(e) => return Err(e.into()),
+    };
+    if !options.overwrite && store.detect_format(key)?.is_some() {
+        report.skipped.push((path, key.to_owned()));
+        return Ok(report);
+    }
+    let format = path.extension()
+        .and_then(|extension| Format::from_extension(&extension.to_string_lossy()))
+        .unwrap_or(Format::Json);
+    let value: Value = format.deserialize_slice(&bytes)
+        .map_err(|e| e.with_context(Operation::Load, key, &path))?;
+    store.save(key, &value)?;
+    report.migrated.push((path.clone(), key.to_owned()));
+    if options.remove_originals {
+        fs::remove_file(&path).map_err(|e| {
+            PreferencesError::from(e).with_context(Operation::Delete, key, &path)
+        })?;
+        report.removed.push(path);
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LegacyLocation, MigrateOptions};
+    use crate::testing::TestDir;
+    use serde_json::Value;
+    use std::fs;
+
+    #[test]
+    fn test_migrate() {
+        let dir = TestDir::new("migrate");
+        for store in dir.stores() {
+            let old_path = dir.path().join("legacy").join("settings.conf");
+            fs::create_dir_all(old_path.parent().unwrap()).unwrap();
+            fs::write(&old_path, r#"{"theme": "dark"}"#).unwrap();
+            let location = LegacyLocation::File(old_path.clone());
+
+            let report = store.migrate_from(&location, "settings", &MigrateOptions::new())
+                .unwrap();
+            assert_eq!(report.migrated, [(old_path.clone(), "settings".to_owned())]);
+            assert!(report.removed.is_empty());
+            let value: Value = store.load("settings").unwrap();
+            assert_eq!(value["theme"], "dark");
+
+            // Data that is already saved isn't replaced, unless asked to.
+            fs::write(&old_path, r#"{"theme": "light"}"#).unwrap();
+            let report = store.migrate_from(&location, "settings", &MigrateOptions::new())
+                .unwrap();
+            assert!(!report.is_migrated());
+            assert_eq!(report.skipped, [(old_path.clone(), "settings".to_owned())]);
+
+            let options = MigrateOptions::new().overwrite(true).remove_originals(true);
+            let report = store.migrate_from(&location, "settings", &options).unwrap();
+            assert!(report.is_migrated());
+            assert_eq!(report.removed, [old_path.as_path()]);
+            assert!(!old_path.exists());
+            let value: Value = store.load("settings").unwrap();
+            assert_eq!(value["theme"], "light");
+
+            // A missing legacy file is no error.
+            let report = store.migrate_from(&location, "settings", &options).unwrap();
+            assert_eq!(report, Default::default());
+        }
+    }
+}
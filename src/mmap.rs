@@ -0,0 +1,160 @@
+//! Memory-mapped user data, for deserializing large values without copying them.
+
+use memmap2::Mmap;
+use serde::Deserialize;
+use std::fs::File;
+use std::io::Read;
+use std::ops::Deref;
+use std::path::Path;
+use crate::checksum;
+use crate::compression;
+use crate::lock::Wait;
+use crate::{Compression, Format, PreferencesError, PreferencesStore};
+
+/// The saved user data of a key, memory-mapped, returned by
+/// [`PreferencesStore::map_prefs`](struct.PreferencesStore.html#method.map_prefs).
+///
+/// [`load_borrowed`](#method.load_borrowed) deserializes the data into a value that can borrow
+/// strings and byte slices from it instead of copying them, e.g. a struct with `&str` fields.
+/// For values of several megabytes, this avoids reading the whole file into memory first, and
+/// allocating each string of the value.
+///
+/// The file is mapped as it is when `map_prefs` is called. Saves replace the file with a new
+/// one, so they don't affect the mapping, but the file must not be changed in place (e.g. by
+/// truncating it) while it's mapped. Compressed data, and data in a memory or storage backend,
+/// is read into memory instead of mapped.
+///
+/// Requires the `mmap` feature.
+///
+/// # Example
+/// ```
+/// #[macro_use]
+/// extern crate serde_derive;
+/// extern crate preferences;
+/// use preferences::{AppInfo, PreferencesStore};
+///
+/// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+///
+/// #[derive(Deserialize)]
+/// struct Library<'a> {
+///     #[serde(borrow)]
+///     titles: Vec<&'a str>,
+/// }
+///
+/// fn main() {
+///     # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
+///     let store = PreferencesStore::new(&APP_INFO).unwrap();
+///     store.save("tests/docs/mmap", &serde_json::json!({"titles": ["A", "B"]})).unwrap();
+///
+///     let mapped = store.map_prefs("tests/docs/mmap").unwrap();
+///     let library: Library = mapped.load_borrowed().unwrap();
+///     assert_eq!(library.titles, ["A", "B"]);
+/// }
+/// ```
+#[derive(Debug)]
+pub struct MappedPrefs {
+    data: Data,
+    format: Format,
+}
+
+#[derive(Debug)]
+enum Data {
+    Mapped(Mmap),
+    Read(Vec<u8>),
+}
+
+impl MappedPrefs {
+    /// Returns the saved data, as it is stored (e.g. with a checksum header, if any).
+    pub fn as_bytes(&self) -> &[u8] {
+        match self.data {
+            Data::Mapped(ref map) => map,
+            Data::Read(ref bytes) => bytes,
+        }
+    }
+
+    /// Returns the format in which the data is deserialized.
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Deserializes the data into a value that can borrow from it. Data saved in an envelope
+    /// isn't unwrapped; it can be deserialized as an [`Envelope<T>`](struct.Envelope.html).
+    ///
+    /// # Failures
+    /// If the data can't be deserialized as `T`, or its checksum doesn't match. Property lists
+    /// aren't supported.
+    pub fn load_borrowed<'a, T: Deserialize<'a>>(&'a self) -> Result<T, PreferencesError> {
+        self.format.deserialize_borrowed(checksum::verify(self.as_bytes())?)
+    }
+}
+
+impl Deref for MappedPrefs {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+/// Maps the data of `store` at `path`, saved in `format`.
+pub(crate) fn map(store: &PreferencesStore,
+                  path: &Path,
+                  format: Format)
+                  -> Result<MappedPrefs, PreferencesError> {
+    let data = match store.memory() {
+        Some(memory) => Data::Read(memory.read(path)?),
+        None => map_file(path)?,
+    };
+    Ok(MappedPrefs { data, format })
+}
+
+fn map_file(path: &Path) -> Result<Data, PreferencesError> {
+    let _lock = crate::lock_shared(path, Wait::Block)?;
+    let (path, compression) = compression::find(path);
+    let file = File::open(path)?;
+    if compression != Compression::None {
+        let mut bytes = Vec::new();
+        compression.decompress(file)?.read_to_end(&mut bytes)?;
+        return Ok(Data::Read(bytes));
+    }
+    // Empty files can't be mapped on all platforms.
+    if file.metadata()?.len() == 0 {
+        return Ok(Data::Read(Vec::new()));
+    }
+    // SAFETY: Saves replace the file atomically instead of writing to it, so the mapped file
+    // isn't changed while it's mapped (unless something other than this crate changes it).
+    let map = unsafe { Mmap::map(&file)? };
+    Ok(Data::Mapped(map))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::TestDir;
+    use crate::{PreferencesError, SaveOptions};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_map_prefs() {
+        let dir = TestDir::new("mmap");
+        for store in dir.stores() {
+            let store = store.with_options(SaveOptions::default().checksum(true));
+            let mut names = BTreeMap::new();
+            for i in 0..1000 {
+                names.insert(format!("key{}", i), format!("value {}", i));
+            }
+            store.save("names", &names).unwrap();
+            store.save("empty", &"").unwrap();
+
+            let mapped = store.map_prefs("names").unwrap();
+            let borrowed: BTreeMap<&str, &str> = mapped.load_borrowed().unwrap();
+            assert_eq!(borrowed.len(), 1000);
+            assert_eq!(borrowed["key42"], "value 42");
+            assert_eq!(store.map_prefs("empty").unwrap().load_borrowed::<&str>().unwrap(), "");
+            match store.map_prefs("missing") {
+                Err(PreferencesError::NotFound { .. }) => {}
+                other => panic!("expected the data not to be found, got {:?}", other),
+            }
+            store.delete_all("").unwrap();
+        }
+    }
+}
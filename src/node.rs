@@ -0,0 +1,162 @@
+//! Hierarchical access to user data, modeled after Java's `Preferences` nodes.
+
+use crate::keys;
+use crate::{AppInfo, Preferences, PreferencesError, PreferencesStore, delete_prefs};
+
+/// A node in the hierarchy of an app's user data.
+///
+/// Nodes map onto the key hierarchy used by [`Preferences`](trait.Preferences.html): the node at
+/// path `options/graphics` contains the user data saved with keys like
+/// `options/graphics/resolution`, and has child nodes like `options/graphics/advanced`. Nodes
+/// exist implicitly; a node has no data of its own apart from the values and children below it.
+///
+/// # Example
+/// ```
+/// extern crate preferences;
+/// use preferences::{AppInfo, PreferencesNode};
+///
+/// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+///
+/// fn main() {
+///     # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
+///     let root = PreferencesNode::root(&APP_INFO);
+///     let graphics = root.node("tests/docs/node/graphics");
+///
+///     graphics.put("vsync", &true).unwrap();
+///     graphics.node("advanced").put("msaa", &4u8).unwrap();
+///
+///     assert_eq!(graphics.get::<bool, _>("vsync").unwrap(), true);
+///     assert_eq!(graphics.keys().unwrap(), vec!["vsync"]);
+///     assert_eq!(graphics.children().unwrap(), vec!["advanced"]);
+///
+///     graphics.remove_node().unwrap();
+///     assert!(graphics.keys().unwrap().is_empty());
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PreferencesNode {
+    app: AppInfo,
+    path: String,
+}
+
+impl PreferencesNode {
+    /// Returns the root node of the given app's user data.
+    pub fn root(app: &AppInfo) -> Self {
+        PreferencesNode {
+            app: app.clone(),
+            path: String::new(),
+        }
+    }
+
+    /// Returns the descendant node at the given `path`, relative to this node. The path may
+    /// contain several slash-separated segments.
+    pub fn node<S: AsRef<str>>(&self, path: S) -> Self {
+        PreferencesNode {
+            app: self.app.clone(),
+            path: self.key(path),
+        }
+    }
+
+    /// Returns the parent of this node, or `None` if this is the root node.
+    pub fn parent(&self) -> Option<Self> {
+        if self.path.is_empty() {
+            return None;
+        }
+        let path = match self.path.rfind('/') {
+            Some(index) => self.path[..index].to_owned(),
+            None => String::new(),
+        };
+        Some(PreferencesNode {
+            app: self.app.clone(),
+            path,
+        })
+    }
+
+    /// Returns the name of this node, i.e. the last segment of its path. The root node's name is
+    /// empty.
+    pub fn name(&self) -> &str {
+        match self.path.rfind('/') {
+            Some(index) => &self.path[index + 1..],
+            None => &self.path,
+        }
+    }
+
+    /// Returns the full path of this node, which is also the key prefix of its values.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Returns the app whose user data this node belongs to.
+    pub fn app(&self) -> &AppInfo {
+        &self.app
+    }
+
+    /// Returns the full key of the value named `name` in this node.
+    pub fn key<S: AsRef<str>>(&self, name: S) -> String {
+        let mut segments = keys::key_segments(&self.path);
+        segments.extend(keys::key_segments(name.as_ref()));
+        segments.join("/")
+    }
+
+    /// Loads the value named `name` from this node.
+    ///
+    /// # Failures
+    /// Same as [`Preferences::load`](trait.Preferences.html#tymethod.load).
+    pub fn get<T, S>(&self, name: S) -> Result<T, PreferencesError>
+        where T: Preferences,
+              S: AsRef<str>
+    {
+        T::load(&self.app, self.key(name))
+    }
+
+    /// Saves `value` under the given `name` in this node.
+    ///
+    /// # Failures
+    /// Same as [`Preferences::save`](trait.Preferences.html#tymethod.save).
+    pub fn put<T, S>(&self, name: S, value: &T) -> Result<(), PreferencesError>
+        where T: Preferences,
+              S: AsRef<str>
+    {
+        value.save(&self.app, self.key(name))
+    }
+
+    /// Removes the value named `name` from this node.
+    ///
+    /// # Failures
+    /// Same as [`delete_prefs`](fn.delete_prefs.html).
+    pub fn remove<S: AsRef<str>>(&self, name: S) -> Result<(), PreferencesError> {
+        delete_prefs(&self.app, self.key(name))
+    }
+
+    /// Lists the names of the values saved directly in this node, in sorted order.
+    pub fn keys(&self) -> Result<Vec<String>, PreferencesError> {
+        Ok(self.read_entries()?.into_iter().filter(|name| !name.contains('/')).collect())
+    }
+
+    /// Lists the names of this node's children that contain any user data, in sorted order.
+    pub fn children(&self) -> Result<Vec<String>, PreferencesError> {
+        let mut found: Vec<String> = self.read_entries()?
+            .into_iter()
+            .filter_map(|name| name.find('/').map(|index| name[..index].to_owned()))
+            .collect();
+        found.dedup();
+        Ok(found)
+    }
+
+    /// Removes this node, including all of its values and descendants. Removing a node that
+    /// contains no data is not an error.
+    pub fn remove_node(&self) -> Result<(), PreferencesError> {
+        PreferencesStore::new(&self.app)?.delete_all(&self.path)
+    }
+
+    /// Lists the keys of all values below this node, relative to this node, in sorted order.
+    fn read_entries(&self) -> Result<Vec<String>, PreferencesError> {
+        let offset = if self.path.is_empty() {
+            0
+        } else {
+            self.path.len() + 1
+        };
+        let found = PreferencesStore::new(&self.app)?.list(&self.path)?;
+        Ok(found.into_iter().map(|key| key[offset..].to_owned()).collect())
+    }
+}
@@ -0,0 +1,110 @@
+//! Callbacks on changes of user data made by the current process.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use crate::bus;
+use crate::keys;
+
+type Callback = Arc<dyn Fn(&str) + Send + Sync>;
+
+struct Subscriber {
+    id: usize,
+    root: PathBuf,
+    prefix: String,
+    callback: Callback,
+}
+
+static SUBSCRIBERS: Mutex<Vec<Subscriber>> = Mutex::new(Vec::new());
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Handle to a callback registered with
+/// [`PreferencesStore::on_change`](struct.PreferencesStore.html#method.on_change).
+///
+/// The callback is unregistered when this handle is dropped.
+#[derive(Debug)]
+#[must_use = "the callback is unregistered when the subscription is dropped"]
+pub struct Subscription {
+    id: usize,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        subscribers().retain(|subscriber| subscriber.id != self.id);
+    }
+}
+
+/// Registers `callback` for the keys under `prefix` of the data stored in `root`.
+pub(crate) fn subscribe<F>(root: &Path, prefix: &str, callback: F) -> Subscription
+    where F: Fn(&str) + Send + Sync + 'static
+{
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    subscribers().push(Subscriber {
+        id,
+        root: root.to_owned(),
+        prefix: keys::key_segments(prefix).join("/"),
+        callback: Arc::new(callback),
+    });
+    Subscription { id }
+}
+
+/// Calls the callbacks registered for `key` of the data stored in `root`, and announces the
+/// change on the change bus of `root`, if the current process is a member.
+pub(crate) fn notify(root: &Path, key: &str) {
+    let key = keys::key_segments(key).join("/");
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    bus::publish(root, &key);
+    // The callbacks are called without holding the lock, so that they can save or subscribe.
+    let callbacks: Vec<Callback> = subscribers()
+        .iter()
+        .filter(|subscriber| subscriber.root == root && is_under(&key, &subscriber.prefix))
+        .map(|subscriber| subscriber.callback.clone())
+        .collect();
+    for callback in callbacks {
+        callback(&key);
+    }
+}
+
+fn is_under(key: &str, prefix: &str) -> bool {
+    prefix.is_empty() || key == prefix ||
+    key.starts_with(prefix) && key[prefix.len()..].starts_with('/')
+}
+
+fn subscribers() -> MutexGuard<'static, Vec<Subscriber>> {
+    // A callback that panicked can't have left the list inconsistent.
+    SUBSCRIBERS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{AppInfo, MemoryBackend, PreferencesStore};
+    use std::sync::{Arc, Mutex};
+
+    const APP_INFO: AppInfo = AppInfo {
+        name: "preferences",
+        author: "Rust language community",
+    };
+
+    #[test]
+    fn test_on_change() {
+        let store = PreferencesStore::new(&APP_INFO)
+            .unwrap()
+            .with_memory_backend(MemoryBackend::new());
+        let changed = Arc::new(Mutex::new(Vec::new()));
+        let subscription = {
+            let changed = changed.clone();
+            store.on_change("tests/module/observe",
+                            move |key| changed.lock().unwrap().push(key.to_owned()))
+        };
+        store.save("tests/module/observe/a", &1u32).unwrap();
+        store.save("tests/module/observed", &1u32).unwrap();
+        let mut transaction = store.transaction();
+        transaction.save("tests/module/observe/b", &1u32).unwrap();
+        transaction.commit().unwrap();
+        drop(subscription);
+        store.save("tests/module/observe/c", &1u32).unwrap();
+        assert_eq!(*changed.lock().unwrap(),
+                   vec!["tests/module/observe/a", "tests/module/observe/b"]);
+    }
+}
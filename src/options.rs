@@ -0,0 +1,402 @@
+//! Options for saving user data.
+
+use serde::Serialize;
+use std::borrow::Cow;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+use crate::envelope::{self, Envelope, EnvelopeMeta};
+use crate::header::{self, FileHeader};
+use crate::{AppId, Compression, Format, PreferencesError};
+
+/// How thoroughly saved data is written to storage before a save returns. (See
+/// [`SaveOptions::durability`](struct.SaveOptions.html#method.durability).)
+///
+/// Files are always replaced atomically, so a crash never leaves a mix of old and new data.
+/// Syncing determines whether the new data survives a power failure or an operating system crash
+/// right after saving.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Durability {
+    /// The data is left to the operating system to write to storage later. This is the fastest,
+    /// but after a power failure, the file may have its previous contents, or on some file
+    /// systems be empty.
+    None,
+    /// The contents of the file are written to storage (like `fdatasync`), but not necessarily
+    /// its metadata, such as the modification time.
+    Flush,
+    /// The contents and metadata of the file are written to storage (like `fsync`).
+    #[default]
+    Fsync,
+    /// Same as `Fsync`, and the directory of the file is synced after the file is moved into
+    /// place, so that the replacement itself is on storage when the save returns. (Outside of
+    /// Unix, this is the same as `Fsync`.)
+    FsyncDir,
+}
+
+impl Durability {
+    /// Writes `file`, which was just written, to storage as far as this requires.
+    pub(crate) fn sync_file(self, file: &File) -> io::Result<()> {
+        match self {
+            Durability::None => Ok(()),
+            Durability::Flush => file.sync_data(),
+            Durability::Fsync | Durability::FsyncDir => file.sync_all(),
+        }
+    }
+
+    /// Writes the directory `dir`, in which a file was just replaced, to storage if this
+    /// requires it.
+    pub(crate) fn sync_dir(self, dir: &Path) -> io::Result<()> {
+        if self != Durability::FsyncDir || !cfg!(unix) {
+            return Ok(());
+        }
+        File::open(dir)?.sync_all()
+    }
+}
+
+/// Options controlling how user data is saved.
+///
+/// Use with [`Preferences::save_with_options`](trait.Preferences.html#tymethod.save_with_options).
+///
+/// # Example
+/// ```
+/// use preferences::{Format, SaveOptions};
+///
+/// let options = SaveOptions::new().format(Format::Json).pretty(true);
+/// assert!(options.is_pretty());
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SaveOptions {
+    format: Format,
+    pretty: bool,
+    backups: usize,
+    compression: Compression,
+    compression_threshold: usize,
+    checksum: bool,
+    header: bool,
+    envelope: bool,
+    schema_version: Option<u32>,
+    /// What is written in the header or envelope about the data, set by the store that saves it.
+    origin: Option<Origin>,
+    private: bool,
+    durability: Durability,
+    max_size: Option<u64>,
+    max_total_size: Option<u64>,
+}
+
+/// What the store that saves data knows about it, for its header or envelope.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Origin {
+    key: String,
+    app: String,
+    app_version: Option<String>,
+    /// When the data was first saved in an envelope, if it was saved in one before.
+    created: Option<SystemTime>,
+}
+
+impl SaveOptions {
+    /// Creates the default options: compact output in the default format.
+    pub fn new() -> Self {
+        SaveOptions::default()
+    }
+
+    /// Sets the format in which the data is stored.
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Sets whether the data is written in a human-friendly layout, with indentation and with
+    /// map keys and struct fields in sorted order, so that the files are easy to hand-edit and
+    /// diff. Formats that have no compact layout (e.g. TOML) ignore this option.
+    pub fn pretty(mut self, pretty: bool) -> Self {
+        self.pretty = pretty;
+        self
+    }
+
+    /// Sets how many previous versions of the data are kept as backups. Before each save, the
+    /// existing file is copied to `<file>.bak`, and older backups are renamed to `<file>.bak.2`
+    /// and so on, up to `count`. (See
+    /// [`Preferences::load_with_recovery`](trait.Preferences.html#tymethod.load_with_recovery).)
+    ///
+    /// The default is 0, i.e. no backups are kept.
+    pub fn backups(mut self, count: usize) -> Self {
+        self.backups = count;
+        self
+    }
+
+    /// Sets the compression applied to the saved files. (See
+    /// [`Compression`](enum.Compression.html).)
+    ///
+    /// The default is `Compression::None`.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Sets the size in bytes below which serialized data is saved uncompressed even if a
+    /// compression is set, so that small files stay human-readable.
+    ///
+    /// The default is 0, i.e. all data is compressed.
+    pub fn compress_above(mut self, size: usize) -> Self {
+        self.compression_threshold = size;
+        self
+    }
+
+    /// Sets whether a CRC-32 checksum of the data is stored in the saved files and verified when
+    /// they're loaded, so that data damaged on disk (e.g. by a truncated write or failing
+    /// storage) is reported as
+    /// [`PreferencesError::Corrupted`](enum.PreferencesError.html#variant.Corrupted) instead of
+    /// being loaded or failing with a parse error.
+    ///
+    /// The checksum is written in a header on a line before the data (see
+    /// [`header`](#method.header)), so files saved with it can't be hand-edited, and JSON files
+    /// are no longer valid JSON to other tools. Files are verified whenever their header has a
+    /// checksum, regardless of this option.
+    ///
+    /// The default is `false`.
+    pub fn checksum(mut self, checksum: bool) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    /// Sets whether a header describing the data is stored in the saved files: the key with
+    /// which it was saved, the app's name, the format, the schema version and when it was saved.
+    /// The header can be read with [`file_header`](fn.file_header.html), e.g. to find out where a
+    /// stray file came from.
+    ///
+    /// The header is written on a line before the data, so JSON files with it are no longer
+    /// valid JSON to other tools. Loading skips the header whenever there is one,
+    /// regardless of this option.
+    ///
+    /// The default is `false`.
+    pub fn header(mut self, header: bool) -> Self {
+        self.header = header;
+        self
+    }
+
+    /// Sets whether the data is saved in an [`Envelope`](struct.Envelope.html) with metadata
+    /// managed by the crate: the schema version, when the data was first and last saved, and the
+    /// version of the app that saved it. Unlike a header, the envelope is part of the data, so
+    /// files stay valid in their format. Each save keeps the creation time of the envelope it
+    /// replaces.
+    ///
+    /// Loading unwraps the data from its envelope transparently, and
+    /// [`load_with_meta`](struct.PreferencesStore.html#method.load_with_meta) returns the
+    /// envelope. Data is loaded the same with or without an envelope, regardless of this option,
+    /// so an app can enable envelopes (or disable them again) when its users already have saved
+    /// data: each file is converted the next time it's saved. Bincode data, which isn't
+    /// self-describing, is never saved in an envelope.
+    ///
+    /// The default is `false`, i.e. the data is saved as it is.
+    pub fn envelope(mut self, envelope: bool) -> Self {
+        self.envelope = envelope;
+        self
+    }
+
+    /// Sets the version of the layout of the saved values, which is stored in the header (see
+    /// `header`) or envelope (see `envelope`), so that an app can tell which migrations data
+    /// saved by its earlier versions needs.
+    ///
+    /// The default is no version.
+    pub fn schema_version(mut self, version: u32) -> Self {
+        self.schema_version = Some(version);
+        self
+    }
+
+    /// Sets whether the saved files are only accessible to the current user, e.g. because the
+    /// data contains access tokens. On Unix, files are then created with mode `0600` and new
+    /// directories with `0700`, regardless of the umask; existing directories are left as they
+    /// are. On Windows, new files and directories get an access control list that only grants
+    /// access to the current user and doesn't inherit from their parent. On other platforms, the
+    /// files get the permissions of their directory.
+    ///
+    /// The default is `false`.
+    pub fn private(mut self, private: bool) -> Self {
+        self.private = private;
+        self
+    }
+
+    /// Sets how thoroughly the data is written to storage before saving returns. (See
+    /// [`Durability`](enum.Durability.html).)
+    ///
+    /// The default is `Durability::Fsync`.
+    pub fn durability(mut self, durability: Durability) -> Self {
+        self.durability = durability;
+        self
+    }
+
+    /// Sets the largest size in bytes that the serialized data of a single key may have (before
+    /// compression). Larger data isn't saved, and saving it fails with
+    /// [`PreferencesError::TooLarge`](enum.PreferencesError.html#variant.TooLarge), so that a bug
+    /// can't fill the user's disk or roaming profile.
+    ///
+    /// The default is no limit.
+    pub fn max_size(mut self, size: u64) -> Self {
+        self.max_size = Some(size);
+        self
+    }
+
+    /// Sets the largest total size in bytes of all of the app's saved files (in the store's
+    /// directory, including backups), which saving with these options may not exceed. Saving
+    /// data that would exceed it fails with
+    /// [`PreferencesError::TooLarge`](enum.PreferencesError.html#variant.TooLarge). The new data
+    /// is counted with its serialized size, before compression.
+    ///
+    /// Computing the total reads the sizes of all of the app's files on every save, so this is
+    /// meant for apps with a moderate number of keys. The default is no limit.
+    pub fn max_total_size(mut self, size: u64) -> Self {
+        self.max_total_size = Some(size);
+        self
+    }
+
+    /// Returns the format in which the data is stored.
+    pub fn get_format(&self) -> Format {
+        self.format
+    }
+
+    /// Returns whether the data is written in a human-friendly layout.
+    pub fn is_pretty(&self) -> bool {
+        self.pretty
+    }
+
+    /// Returns how many previous versions of the data are kept as backups.
+    pub fn get_backups(&self) -> usize {
+        self.backups
+    }
+
+    /// Returns the compression applied to the saved files.
+    pub fn get_compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// Returns the size in bytes below which serialized data is saved uncompressed.
+    pub fn get_compression_threshold(&self) -> usize {
+        self.compression_threshold
+    }
+
+    /// Returns whether a checksum is stored in the saved files.
+    pub fn has_checksum(&self) -> bool {
+        self.checksum
+    }
+
+    /// Returns whether a header describing the data is stored in the saved files.
+    pub fn has_header(&self) -> bool {
+        self.header
+    }
+
+    /// Returns whether the data is saved in an envelope with metadata.
+    pub fn has_envelope(&self) -> bool {
+        self.envelope
+    }
+
+    /// Returns the version of the layout of the saved values, if set.
+    pub fn get_schema_version(&self) -> Option<u32> {
+        self.schema_version
+    }
+
+    /// Returns the options for saving the data of `app` with `key`, whose previous envelope was
+    /// `created` at the given time. The options differ only if a header or envelope is written.
+    pub(crate) fn for_key(&self,
+                          key: &str,
+                          app: &AppId,
+                          created: Option<SystemTime>)
+                          -> Cow<'_, SaveOptions> {
+        if !self.header && !self.envelope {
+            return Cow::Borrowed(self);
+        }
+        let mut options = self.clone();
+        options.origin = Some(Origin {
+            key: key.to_owned(),
+            app: app.info().name.to_owned(),
+            app_version: app.get_version().map(str::to_owned),
+            created,
+        });
+        Cow::Owned(options)
+    }
+
+    /// Returns whether the saved files are only accessible to the current user.
+    pub fn is_private(&self) -> bool {
+        self.private
+    }
+
+    /// Returns how thoroughly the data is written to storage.
+    pub fn get_durability(&self) -> Durability {
+        self.durability
+    }
+
+    /// Returns the largest size of the serialized data of a single key, if limited.
+    pub fn get_max_size(&self) -> Option<u64> {
+        self.max_size
+    }
+
+    /// Returns the largest total size of all of the app's saved files, if limited.
+    pub fn get_max_total_size(&self) -> Option<u64> {
+        self.max_total_size
+    }
+
+    /// Serializes `value` in the format and layout set by these options, in an envelope if
+    /// enabled, and adds a header, with a checksum if enabled.
+    pub(crate) fn serialize<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, PreferencesError> {
+        let mut bytes = Vec::new();
+        if self.envelope && envelope::supports(self.format) {
+            let (created, app_version) = match self.origin {
+                Some(ref origin) => (origin.created, origin.app_version.clone()),
+                None => (None, None),
+            };
+            let meta = EnvelopeMeta::new(self.schema_version, created, app_version);
+            self.serialize_into(&Envelope { meta, data: value }, &mut bytes)?;
+        } else {
+            self.serialize_into(value, &mut bytes)?;
+        }
+        if let Some(limit) = self.max_size {
+            if bytes.len() as u64 > limit {
+                return Err(PreferencesError::TooLarge {
+                    size: bytes.len() as u64,
+                    limit,
+                });
+            }
+        }
+        if self.header || self.checksum {
+            // Without `header`, the header only holds the checksum and what it requires.
+            let (key, app) = match self.origin {
+                Some(ref origin) if self.header => (Some(&origin.key[..]), Some(&origin.app[..])),
+                _ => (None, None),
+            };
+            let version = if self.header { self.schema_version } else { None };
+            let mut header = FileHeader::new(key, app, self.format, version);
+            if self.checksum {
+                header.checksum = Some(crc32fast::hash(&bytes));
+            }
+            bytes = header::prepend(&header, bytes);
+        }
+        Ok(bytes)
+    }
+
+    /// Serializes `value` into `bytes` in the format and layout set by these options.
+    fn serialize_into<T: Serialize>(&self,
+                                    value: &T,
+                                    bytes: &mut Vec<u8>)
+                                    -> Result<(), PreferencesError> {
+        if self.pretty {
+            self.format.serialize_pretty(value, bytes)
+        } else {
+            self.format.serialize(value, bytes)
+        }
+    }
+
+    /// Returns the compression to apply to `size` bytes of serialized data, which is none if
+    /// they're fewer than the threshold.
+    pub(crate) fn compression_for(&self, size: usize) -> Compression {
+        if size < self.compression_threshold { Compression::None } else { self.compression }
+    }
+
+    /// Compresses the serialized data in `bytes` if it's large enough. Returns the resulting
+    /// file contents and the compression that was applied.
+    pub(crate) fn compress(&self,
+                           bytes: Vec<u8>)
+                           -> Result<(Vec<u8>, Compression), PreferencesError> {
+        let compression = self.compression_for(bytes.len());
+        Ok((compression.compress(bytes)?, compression))
+    }
+}
@@ -0,0 +1,165 @@
+//! Owner-only permissions for the files of private user data.
+
+use std::fs::{DirBuilder, File, OpenOptions};
+use std::io;
+use std::path::Path;
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+
+/// Creates `dir` and its missing parents. If `private`, the new directories are only accessible
+/// to their owner.
+pub(crate) fn create_dirs(dir: &Path, private: bool) -> io::Result<()> {
+    let mut builder = DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        if private {
+            builder.mode(0o700);
+        }
+    }
+    #[cfg(windows)]
+    {
+        if private {
+            // The directories that don't exist yet, from the outermost one.
+            let mut created: Vec<_> = dir.ancestors().take_while(|dir| !dir.exists()).collect();
+            created.reverse();
+            builder.create(dir)?;
+            return created.into_iter().try_for_each(|dir| windows::set_owner_only(dir, true));
+        }
+    }
+    #[cfg(not(any(unix, windows)))]
+    let _ = private;
+    builder.create(dir)
+}
+
+/// Creates or truncates the file at `path` for writing. If `private` and the file is created, it's
+/// only accessible to its owner.
+pub(crate) fn create_file(path: &Path, private: bool) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        if private {
+            options.mode(0o600);
+        }
+    }
+    #[cfg(windows)]
+    {
+        if private && !path.exists() {
+            // The file is still empty when its permissions are set.
+            let file = options.open(path)?;
+            windows::set_owner_only(path, false)?;
+            return Ok(file);
+        }
+    }
+    #[cfg(not(any(unix, windows)))]
+    let _ = private;
+    options.open(path)
+}
+
+#[cfg(windows)]
+mod windows {
+    use std::io;
+    use std::os::windows::ffi::OsStrExt;
+    use std::path::Path;
+    use std::ptr;
+    use windows_sys::Win32::Foundation::{CloseHandle, ERROR_SUCCESS, GENERIC_ALL, HANDLE, HLOCAL,
+                                         LocalFree};
+    use windows_sys::Win32::Security::Authorization::{EXPLICIT_ACCESS_W, NO_MULTIPLE_TRUSTEE,
+                                                      SE_FILE_OBJECT, SET_ACCESS,
+                                                      SetEntriesInAclW, SetNamedSecurityInfoW,
+                                                      TRUSTEE_IS_SID, TRUSTEE_IS_USER,
+                                                      TRUSTEE_W};
+    use windows_sys::Win32::Security::{ACL, DACL_SECURITY_INFORMATION, GetTokenInformation,
+                                       NO_INHERITANCE, PROTECTED_DACL_SECURITY_INFORMATION,
+                                       SUB_CONTAINERS_AND_OBJECTS_INHERIT, TOKEN_QUERY,
+                                       TOKEN_USER, TokenUser};
+    use windows_sys::Win32::System::Threading::{GetCurrentProcess, OpenProcessToken};
+
+    /// Replaces the permissions of the file or directory at `path`, including those it would
+    /// inherit from its parent, with full access for the current user only. If `inherit`, the
+    /// files and directories created in it later get the same permissions.
+    pub(super) fn set_owner_only(path: &Path, inherit: bool) -> io::Result<()> {
+        let user = current_user()?;
+        let inheritance = if inherit { SUB_CONTAINERS_AND_OBJECTS_INHERIT } else { NO_INHERITANCE };
+        let access = EXPLICIT_ACCESS_W {
+            grfAccessPermissions: GENERIC_ALL,
+            grfAccessMode: SET_ACCESS,
+            grfInheritance: inheritance,
+            Trustee: TRUSTEE_W {
+                pMultipleTrustee: ptr::null_mut(),
+                MultipleTrusteeOperation: NO_MULTIPLE_TRUSTEE,
+                TrusteeForm: TRUSTEE_IS_SID,
+                TrusteeType: TRUSTEE_IS_USER,
+                // With `TRUSTEE_IS_SID`, the name is the SID.
+                ptstrName: unsafe { (*(user.as_ptr() as *const TOKEN_USER)).User.Sid } as *mut u16,
+            },
+        };
+        let mut acl: *mut ACL = ptr::null_mut();
+        check(unsafe { SetEntriesInAclW(1, &access, ptr::null(), &mut acl) })?;
+        let name: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+        let result = check(unsafe {
+            SetNamedSecurityInfoW(name.as_ptr(),
+                                  SE_FILE_OBJECT,
+                                  DACL_SECURITY_INFORMATION | PROTECTED_DACL_SECURITY_INFORMATION,
+                                  ptr::null_mut(),
+                                  ptr::null_mut(),
+                                  acl,
+                                  ptr::null())
+        });
+        unsafe { LocalFree(acl as HLOCAL) };
+        result
+    }
+
+    /// Returns the `TOKEN_USER` of the current process, which refers to the user's SID stored
+    /// after it, in a buffer aligned for it.
+    fn current_user() -> io::Result<Vec<u64>> {
+        let mut token: HANDLE = ptr::null_mut();
+        if unsafe { OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut len = 0;
+        // This fails with the buffer size that's needed.
+        unsafe { GetTokenInformation(token, TokenUser, ptr::null_mut(), 0, &mut len) };
+        let mut user = vec![0u64; (len as usize).div_ceil(8)];
+        let result = if unsafe {
+            GetTokenInformation(token, TokenUser, user.as_mut_ptr().cast(), len, &mut len)
+        } == 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(user)
+        };
+        unsafe { CloseHandle(token) };
+        result
+    }
+
+    fn check(error: u32) -> io::Result<()> {
+        if error == ERROR_SUCCESS {
+            Ok(())
+        } else {
+            Err(io::Error::from_raw_os_error(error as i32))
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::testing::TestDir;
+    use crate::SaveOptions;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+
+    #[test]
+    fn test_private_permissions() {
+        let dir = TestDir::new("private");
+        let store = dir.store();
+        let options = SaveOptions::new().private(true);
+        store.save_with_options("secrets/token", &"hunter2", &options).unwrap();
+
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        let path = store.file_path("secrets/token", Default::default()).unwrap();
+        assert_eq!(mode(&path), 0o600);
+        assert_eq!(mode(path.parent().unwrap()), 0o700);
+    }
+}
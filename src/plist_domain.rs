@@ -0,0 +1,139 @@
+//! macOS-style preferences domains stored as property lists.
+
+use plist::{self, Dictionary, Value};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::env;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use crate::{PreferencesError, write_atomically};
+
+/// A preferences domain in the style of macOS user defaults, stored as a single property list
+/// file that maps keys to values.
+///
+/// Unlike [`Preferences`](trait.Preferences.html), which uses one file per key, all keys of a
+/// domain live in the same file, by default `~/Library/Preferences/<bundle id>.plist`. This is
+/// where macOS apps conventionally keep their settings, so they can be inspected and changed with
+/// `defaults read` / `defaults write` and are included in the usual system backups.
+///
+/// Note that macOS caches user defaults in the `cfprefsd` daemon, so changes made with
+/// `defaults write` while the app isn't running are picked up on the next `load`, but the domain
+/// shouldn't be written through this type and through `NSUserDefaults` at the same time.
+///
+/// Requires the `plist` feature.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PlistDomain {
+    path: PathBuf,
+}
+
+impl PlistDomain {
+    /// Opens the domain of the given bundle identifier (e.g. `com.example.MyApp`) in the user's
+    /// `~/Library/Preferences` directory.
+    ///
+    /// # Failures
+    /// If the user's home directory cannot be determined.
+    pub fn new(bundle_id: &str) -> Result<Self, PreferencesError> {
+        let home = env::var_os("HOME").ok_or_else(|| {
+            io::Error::new(ErrorKind::NotFound, "Home directory could not be determined")
+        })?;
+        let mut path = PathBuf::from(home);
+        path.push("Library");
+        path.push("Preferences");
+        path.push(format!("{}.plist", bundle_id));
+        Ok(Self::with_path(path))
+    }
+
+    /// Opens a domain stored in an arbitrary property list file.
+    pub fn with_path<P: Into<PathBuf>>(path: P) -> Self {
+        PlistDomain { path: path.into() }
+    }
+
+    /// Returns the path of the property list file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Saves `value` under `key`, keeping the other keys of the domain intact.
+    pub fn save<T: Serialize>(&self, key: &str, value: &T) -> Result<(), PreferencesError> {
+        let mut dict = self.read()?;
+        dict.insert(key.into(), plist::to_value(value)?);
+        self.write(&dict)
+    }
+
+    /// Loads the value saved under `key`.
+    ///
+    /// # Failures
+    /// If the value cannot be deserialized as `T`, or if the file or the key doesn't exist (in
+    /// which case an `Io` error of kind `NotFound` is returned).
+    pub fn load<T: DeserializeOwned>(&self, key: &str) -> Result<T, PreferencesError> {
+        let dict = self.read()?;
+        match dict.get(key) {
+            Some(value) => plist::from_value(value).map_err(Into::into),
+            None => Err(key_not_found(key)),
+        }
+    }
+
+    /// Removes the value saved under `key`. Removing a key that doesn't exist is not an error.
+    pub fn delete(&self, key: &str) -> Result<(), PreferencesError> {
+        let mut dict = self.read()?;
+        if dict.remove(key).is_some() {
+            self.write(&dict)?;
+        }
+        Ok(())
+    }
+
+    /// Lists all keys of the domain, in sorted order.
+    pub fn keys(&self) -> Result<Vec<String>, PreferencesError> {
+        let mut keys: Vec<String> = self.read()?.keys().cloned().collect();
+        keys.sort();
+        Ok(keys)
+    }
+
+    fn read(&self) -> Result<Dictionary, PreferencesError> {
+        match Value::from_file(&self.path) {
+            Ok(Value::Dictionary(dict)) => Ok(dict),
+            Ok(_) => {
+                let msg = "Property list file does not contain a dictionary";
+                Err(io::Error::new(ErrorKind::InvalidData, msg).into())
+            }
+            Err(ref e) if e.as_io().map(io::Error::kind) == Some(ErrorKind::NotFound) => {
+                Ok(Dictionary::new())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn write(&self, dict: &Dictionary) -> Result<(), PreferencesError> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        write_atomically(&self.path,
+                         |file| plist::to_writer_binary(file, dict).map_err(Into::into))
+    }
+}
+
+fn key_not_found(key: &str) -> PreferencesError {
+    let msg = format!("No value for key {:?} in property list", key);
+    io::Error::new(ErrorKind::NotFound, msg).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PlistDomain;
+    use crate::testing::TestDir;
+
+    #[test]
+    fn test_save_load_delete() {
+        let dir = TestDir::new("plist-domain");
+        let domain = PlistDomain::with_path(dir.path().join("plist-domain.plist"));
+        assert!(domain.save("volume", &0.5f64).is_ok());
+        assert!(domain.save("name", &"Rust").is_ok());
+        assert_eq!(domain.load::<f64>("volume").unwrap(), 0.5);
+        assert_eq!(domain.load::<String>("name").unwrap(), "Rust");
+        assert_eq!(domain.keys().unwrap(), vec!["name", "volume"]);
+        assert!(domain.delete("volume").is_ok());
+        assert!(domain.load::<f64>("volume").is_err());
+        assert_eq!(domain.keys().unwrap(), vec!["name"]);
+    }
+}
@@ -0,0 +1,121 @@
+//! Keys that are tied to the type of the value saved with them.
+
+use std::fmt;
+use std::marker::PhantomData;
+
+/// A preferences key together with the type of the value saved with it, for
+/// [`PreferencesStore::get`](struct.PreferencesStore.html#method.get) and
+/// [`set`](struct.PreferencesStore.html#method.set).
+///
+/// Declaring each key once, as a constant, means that the value can't be loaded as a different
+/// type than it was saved as by mistake, which with plain string keys is only noticed when the
+/// data fails to deserialize (or worse, when it deserializes into the wrong type).
+///
+/// # Example
+/// ```
+/// #[macro_use]
+/// extern crate serde_derive;
+/// extern crate preferences;
+/// use preferences::{AppInfo, PrefKey, PreferencesStore};
+///
+/// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+///
+/// #[derive(Serialize, Deserialize, PartialEq, Debug)]
+/// enum Theme {
+///     Light,
+///     Dark,
+/// }
+///
+/// const THEME: PrefKey<Theme> = PrefKey::new("tests/docs/pref-key/theme");
+///
+/// fn main() {
+///     # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
+///     let store = PreferencesStore::new(&APP_INFO).unwrap();
+///     store.set(&THEME, &Theme::Dark).unwrap();
+///     assert_eq!(store.get(&THEME).unwrap(), Theme::Dark);
+///     // `store.set(&THEME, &"dark")` wouldn't compile.
+/// }
+/// ```
+pub struct PrefKey<T> {
+    key: &'static str,
+    value_type: PhantomData<fn() -> T>,
+}
+
+impl<T> PrefKey<T> {
+    /// Creates a key for values of type `T`. The key is only validated when it's used.
+    pub const fn new(key: &'static str) -> Self {
+        PrefKey {
+            key,
+            value_type: PhantomData,
+        }
+    }
+
+    /// Returns the key as a string.
+    pub const fn key(&self) -> &'static str {
+        self.key
+    }
+}
+
+impl<T> AsRef<str> for PrefKey<T> {
+    fn as_ref(&self) -> &str {
+        self.key
+    }
+}
+
+// Implemented manually, so that they don't require `T` to implement them.
+impl<T> Clone for PrefKey<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for PrefKey<T> {}
+
+impl<T> fmt::Debug for PrefKey<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("PrefKey").field(&self.key).finish()
+    }
+}
+
+impl<T> PartialEq for PrefKey<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key
+    }
+}
+
+impl<T> Eq for PrefKey<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::PrefKey;
+    use crate::{AppInfo, MemoryBackend, PreferencesStore};
+    use std::collections::BTreeMap;
+
+    const APP_INFO: AppInfo = AppInfo {
+        name: "preferences",
+        author: "Rust language community",
+    };
+
+    const VOLUME: PrefKey<u8> = PrefKey::new("audio/volume");
+    const DEVICES: PrefKey<BTreeMap<String, bool>> = PrefKey::new("audio/devices");
+
+    #[test]
+    fn test_pref_key() {
+        let store = PreferencesStore::new(&APP_INFO)
+            .unwrap()
+            .with_memory_backend(MemoryBackend::new());
+        assert_eq!(VOLUME.key(), "audio/volume");
+        assert_eq!(format!("{:?}", VOLUME), r#"PrefKey("audio/volume")"#);
+        assert!(store.get(&VOLUME).unwrap_err().is_not_found());
+
+        store.set(&VOLUME, &7).unwrap();
+        let mut devices = BTreeMap::new();
+        devices.insert("headphones".to_owned(), true);
+        store.set(&DEVICES, &devices).unwrap();
+        assert_eq!(store.get(&VOLUME).unwrap(), 7);
+        assert_eq!(store.get(&DEVICES).unwrap(), devices);
+        // The key can be used wherever a string key can.
+        assert_eq!(store.list("audio").unwrap(), ["audio/devices", "audio/volume"]);
+        assert!(store.exists(VOLUME).unwrap());
+    }
+}
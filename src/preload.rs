@@ -0,0 +1,154 @@
+//! Loading user data on background threads ahead of when it's needed.
+
+use serde::de::DeserializeOwned;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use crate::{PreferencesError, PreferencesStore};
+
+/// A value that is being loaded on a background thread, returned by
+/// [`PreferencesStore::preload`](struct.PreferencesStore.html#method.preload).
+///
+/// This lets an app start loading its user data early, e.g. while it shows a splash screen, and
+/// only wait for each value when it's first needed.
+///
+/// # Example
+/// ```
+/// use preferences::{AppInfo, PreferencesStore};
+///
+/// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+///
+/// # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
+/// let store = PreferencesStore::new(&APP_INFO).unwrap();
+/// store.save("tests/docs/preload/volume", &7u32).unwrap();
+///
+/// let mut loading = store.preload::<u32>(["tests/docs/preload/volume",
+///                                         "tests/docs/preload/brightness"]);
+/// // ... show the splash screen ...
+/// let brightness = loading.pop().unwrap();
+/// assert_eq!(loading.pop().unwrap().wait().unwrap(), 7);
+/// assert!(brightness.wait().unwrap_err().is_not_found());
+/// ```
+pub struct Preloaded<T> {
+    key: String,
+    slot: Arc<Slot<T>>,
+}
+
+/// Where the preloader thread puts a loaded value, or the panic of loading it.
+struct Slot<T> {
+    result: Mutex<Option<thread::Result<Result<T, PreferencesError>>>>,
+    ready: Condvar,
+}
+
+impl<T> Slot<T> {
+    fn lock(&self) -> MutexGuard<'_, Option<thread::Result<Result<T, PreferencesError>>>> {
+        self.result.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<T> Preloaded<T>
+    where T: DeserializeOwned + Send + 'static
+{
+    /// Starts loading the values saved with `keys` in `store`, one after the other, on a new
+    /// thread.
+    pub(crate) fn spawn(store: PreferencesStore, keys: Vec<String>) -> Vec<Self> {
+        let preloaded: Vec<Self> = keys.into_iter()
+            .map(|key| {
+                Preloaded {
+                    key,
+                    slot: Arc::new(Slot {
+                        result: Mutex::new(None),
+                        ready: Condvar::new(),
+                    }),
+                }
+            })
+            .collect();
+        let slots: Vec<_> = preloaded.iter().map(|p| (p.key.clone(), p.slot.clone())).collect();
+        if !slots.is_empty() {
+            thread::Builder::new()
+                .name("preferences preloader".into())
+                .spawn(move || {
+                    for (key, slot) in slots {
+                        let result = panic::catch_unwind(AssertUnwindSafe(|| store.load(&key)));
+                        *slot.lock() = Some(result);
+                        slot.ready.notify_all();
+                    }
+                })
+                .expect("failed to spawn the preloader thread");
+        }
+        preloaded
+    }
+}
+
+impl<T> Preloaded<T> {
+    /// Returns the key of the value.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Returns whether the value has been loaded (or failed to load), so that `wait` won't block.
+    pub fn is_ready(&self) -> bool {
+        self.slot.lock().is_some()
+    }
+
+    /// Waits until the value has been loaded, and returns it.
+    ///
+    /// # Failures
+    /// Same as [`PreferencesStore::load`](struct.PreferencesStore.html#method.load).
+    pub fn wait(self) -> Result<T, PreferencesError> {
+        let mut result = self.slot.lock();
+        loop {
+            match result.take() {
+                Some(Ok(result)) => return result,
+                // Deserialization code of `T` panicked.
+                Some(Err(payload)) => panic::resume_unwind(payload),
+                None => {
+                    result = self.slot.ready.wait(result).unwrap_or_else(|e| e.into_inner());
+                }
+            }
+        }
+    }
+}
+
+impl<T> fmt::Debug for Preloaded<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Preloaded")
+            .field("key", &self.key)
+            .field("ready", &self.is_ready())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{AppInfo, MemoryBackend, PreferencesStore};
+    use std::collections::BTreeMap;
+
+    const APP_INFO: AppInfo = AppInfo {
+        name: "preferences",
+        author: "Rust language community",
+    };
+
+    #[test]
+    fn test_preload() {
+        let store = PreferencesStore::new(&APP_INFO)
+            .unwrap()
+            .with_memory_backend(MemoryBackend::new());
+        let mut bindings = BTreeMap::new();
+        bindings.insert("jump".to_owned(), "space".to_owned());
+        store.save("bindings", &bindings).unwrap();
+        store.save("volume", &7u32).unwrap();
+
+        let mut loading_bindings = store.preload::<BTreeMap<String, String>>(vec!["bindings"]);
+        let mut loading = store.preload::<u32>(["volume", "missing"].iter());
+        assert_eq!(loading.len(), 2);
+        let loading_missing = loading.pop().unwrap();
+        let loading_volume = loading.pop().unwrap();
+        assert_eq!(loading_volume.key(), "volume");
+        assert_eq!(loading_volume.wait().unwrap(), 7);
+        assert_eq!(loading_bindings.pop().unwrap().wait().unwrap(), bindings);
+        assert!(loading_missing.wait().unwrap_err().is_not_found());
+        assert!(store.preload::<u32>(Vec::<String>::new()).is_empty());
+    }
+}
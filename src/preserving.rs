@@ -0,0 +1,132 @@
+//! A wrapper that keeps the fields of saved user data that its type doesn't know about.
+
+use serde::de::{DeserializeOwned, Deserializer, Error as _};
+use serde::ser::{Error as _, Serializer};
+use serde::{Deserialize, Serialize};
+use serde_json::{self, Map, Value};
+use std::ops::{Deref, DerefMut};
+
+/// User data that keeps the fields that its type `T` doesn't have when it's loaded, and writes
+/// them back when it's saved.
+///
+/// Normally, loading data as a struct ignores the fields that the struct doesn't have, so saving
+/// it again removes them from the file. When several versions of an app (or several plugins)
+/// share the same data, each would delete the settings of the others. Loading and saving the
+/// data as `PreservingPreferences<T>` instead keeps them.
+///
+/// The wrapper dereferences to the value. It implements `Serialize` and `Deserialize` itself,
+/// so it's loaded and saved like any other data, e.g. with
+/// [`Preferences`](trait.Preferences.html). Only the fields at the top level of the data are
+/// preserved, and only in self-describing formats (i.e. not `Format::Bincode`).
+///
+/// # Example
+/// ```
+/// #[macro_use]
+/// extern crate serde_derive;
+/// extern crate preferences;
+/// use preferences::{AppInfo, Preferences, PreservingPreferences};
+///
+/// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+///
+/// #[derive(Serialize, Deserialize)]
+/// struct NewOptions {
+///     theme: String,
+///     font: String,
+/// }
+///
+/// #[derive(Serialize, Deserialize)]
+/// struct OldOptions {
+///     theme: String,
+/// }
+///
+/// fn main() {
+///     # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
+///     let key = "tests/docs/preserving";
+///     let new = NewOptions { theme: "dark".into(), font: "Sans".into() };
+///     new.save(&APP_INFO, key).unwrap();
+///
+///     // An older version of the app changes the theme, but keeps the font.
+///     let mut old = PreservingPreferences::<OldOptions>::load(&APP_INFO, key).unwrap();
+///     old.theme = "light".into();
+///     old.save(&APP_INFO, key).unwrap();
+///
+///     let new = NewOptions::load(&APP_INFO, key).unwrap();
+///     assert_eq!(new.theme, "light");
+///     assert_eq!(new.font, "Sans");
+/// }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PreservingPreferences<T> {
+    value: T,
+    unknown: Map<String, Value>,
+}
+
+impl<T> PreservingPreferences<T> {
+    /// Wraps `value`, with no unknown fields.
+    pub fn new(value: T) -> Self {
+        PreservingPreferences {
+            value,
+            unknown: Map::new(),
+        }
+    }
+
+    /// Returns the fields of the loaded data that `T` doesn't have.
+    pub fn unknown_fields(&self) -> &Map<String, Value> {
+        &self.unknown
+    }
+
+    /// Returns the fields of the loaded data that `T` doesn't have, e.g. to remove some of them.
+    pub fn unknown_fields_mut(&mut self) -> &mut Map<String, Value> {
+        &mut self.unknown
+    }
+
+    /// Returns the value, dropping the unknown fields.
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T> Deref for PreservingPreferences<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for PreservingPreferences<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<T: Serialize> Serialize for PreservingPreferences<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut value = serde_json::to_value(&self.value).map_err(S::Error::custom)?;
+        if let Value::Object(ref mut fields) = value {
+            for (name, field) in &self.unknown {
+                fields.entry(name.clone()).or_insert_with(|| field.clone());
+            }
+        }
+        value.serialize(serializer)
+    }
+}
+
+impl<'de, T> Deserialize<'de> for PreservingPreferences<T>
+    where T: Serialize + DeserializeOwned
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let saved = Value::deserialize(deserializer)?;
+        let value = T::deserialize(&saved).map_err(D::Error::custom)?;
+        let mut unknown = Map::new();
+        if let Value::Object(saved) = saved {
+            let known = serde_json::to_value(&value).map_err(D::Error::custom)?;
+            for (name, field) in saved {
+                if known.get(&name).is_none() {
+                    unknown.insert(name, field);
+                }
+            }
+        }
+        Ok(PreservingPreferences { value, unknown })
+    }
+}
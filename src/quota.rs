@@ -0,0 +1,87 @@
+//! Limits on the total size of an app's saved user data.
+
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use crate::{Compression, PreferencesError, PreferencesStore, SaveOptions};
+
+/// Checks that saving `size` bytes at `path` in `store`, replacing any data there, keeps all of
+/// the store's data within the total size allowed by `options`.
+pub(crate) fn check_total(store: &PreferencesStore,
+                          path: &Path,
+                          size: u64,
+                          options: &SaveOptions)
+                          -> Result<(), PreferencesError> {
+    match options.get_max_total_size() {
+        Some(limit) => check_writes(store, &[(path, size)], limit),
+        None => Ok(()),
+    }
+}
+
+/// Checks that saving data with the given paths and sizes in `store`, replacing any data there,
+/// keeps all of the store's data within `limit` bytes.
+pub(crate) fn check_writes(store: &PreferencesStore,
+                           writes: &[(&Path, u64)],
+                           limit: u64)
+                           -> Result<(), PreferencesError> {
+    let replaced: Vec<_> = writes.iter()
+        .flat_map(|&(path, _)| Compression::all().iter().map(move |c| c.apply_to(path)))
+        .collect();
+    let others = match store.memory() {
+        Some(memory) => memory.total_size(store.root(), &replaced),
+        None => dir_size(store.root(), &replaced)?,
+    };
+    let total = others + writes.iter().map(|&(_, size)| size).sum::<u64>();
+    if total > limit {
+        return Err(PreferencesError::TooLarge { size: total, limit });
+    }
+    Ok(())
+}
+
+/// Returns the total size of the files in `dir` and its subdirectories, except for `excluded`.
+fn dir_size<P: AsRef<Path>>(dir: &Path, excluded: &[P]) -> Result<u64, PreferencesError> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+    let mut total = 0;
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            total += dir_size(&path, excluded)?;
+        } else if file_type.is_file() && !excluded.iter().any(|e| e.as_ref() == path) {
+            total += entry.metadata()?.len();
+        }
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::TestDir;
+    use crate::{PreferencesError, SaveOptions};
+
+    #[test]
+    fn test_size_limits() {
+        let dir = TestDir::new("quota");
+        for store in dir.stores() {
+            let store = store.with_options(SaveOptions::new().max_size(10).max_total_size(25));
+            store.save("a", &"x".repeat(8)).unwrap();
+            match store.save("b", &"x".repeat(9)).as_ref().map_err(PreferencesError::inner) {
+                Err(&PreferencesError::TooLarge { size: 11, limit: 10 }) => {}
+                other => panic!("expected the key to be too large, got {:?}", other),
+            }
+            store.save("b", &"x".repeat(8)).unwrap();
+            // Replacing data only counts its new size.
+            store.save("b", &"y".repeat(8)).unwrap();
+            match store.save("c", &"x".repeat(8)).as_ref().map_err(PreferencesError::inner) {
+                Err(&PreferencesError::TooLarge { size: 30, limit: 25 }) => {}
+                other => panic!("expected the app's data to be too large, got {:?}", other),
+            }
+            assert!(!store.exists("c").unwrap());
+        }
+    }
+}
@@ -0,0 +1,194 @@
+//! Storage of all of an app's user data in an embedded redb database, for frequent writes.
+
+use redb::{Database, ReadableTable, TableDefinition};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::{Durability, StorageBackend};
+
+/// File name of the database created by `RedbBackend::in_dir`.
+static DATABASE_FILE_NAME: &str = "prefs.redb";
+
+/// The data of each path, with its modification time in milliseconds since the Unix epoch.
+const TABLE: TableDefinition<&str, (u64, &[u8])> = TableDefinition::new("prefs");
+
+/// A [`StorageBackend`](trait.StorageBackend.html) that keeps all of a store's data in one
+/// [redb](https://www.redb.org) database, an embedded key-value store written in Rust.
+///
+/// This suits apps that save their data very often (e.g. editor state or lists of recently used
+/// files): a save updates a page of the database instead of writing a new file, and with
+/// `Durability::None` or `Durability::Flush`, saves aren't synced to storage one by one. Each
+/// save is still atomic, so a crash loses at most the latest saves, and never leaves the
+/// database inconsistent.
+///
+/// Only one process can open the database at a time.
+///
+/// Requires the `redb` feature.
+///
+/// # Example
+/// ```
+/// extern crate preferences;
+/// use preferences::{AppInfo, Durability, PreferencesStore, RedbBackend};
+/// use std::sync::Arc;
+///
+/// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+///
+/// fn main() {
+///     let store = PreferencesStore::new(&APP_INFO).unwrap();
+///     # let store = store.with_base_dir(std::env::temp_dir().join("preferences-docs"));
+///     let database = RedbBackend::in_dir(store.root())
+///         .unwrap()
+///         .with_durability(Durability::None);
+///     let store = store.with_backend(Arc::new(database));
+///
+///     for line in 0..100u32 {
+///         store.save("tests/docs/redb", &line).unwrap();
+///     }
+///     assert_eq!(store.load::<u32, _>("tests/docs/redb").unwrap(), 99);
+///     # store.delete("tests/docs/redb").unwrap();
+/// }
+/// ```
+#[derive(Debug)]
+pub struct RedbBackend {
+    database: Database,
+    durability: Durability,
+}
+
+impl RedbBackend {
+    /// Opens the database at `path`, creating it if it doesn't exist.
+    ///
+    /// # Failures
+    /// If the database can't be opened or created, e.g. because another process has it open.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let database = Database::create(path).map_err(redb_error)?;
+        let transaction = database.begin_write().map_err(redb_error)?;
+        transaction.open_table(TABLE).map_err(redb_error)?;
+        transaction.commit().map_err(redb_error)?;
+        Ok(RedbBackend {
+            database,
+            durability: Durability::default(),
+        })
+    }
+
+    /// Opens the database `prefs.redb` in the directory `dir` (usually the
+    /// [`root`](struct.PreferencesStore.html#method.root) of a store), creating both if they
+    /// don't exist.
+    ///
+    /// # Failures
+    /// If the directory or the database can't be opened or created.
+    pub fn in_dir<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        fs::create_dir_all(dir.as_ref())?;
+        RedbBackend::open(dir.as_ref().join(DATABASE_FILE_NAME))
+    }
+
+    /// Sets how thoroughly each save is written to storage before it returns. With
+    /// `Durability::None` and `Durability::Flush`, saves are only synced to storage some time
+    /// later; with `Durability::Fsync` and `Durability::FsyncDir`, each one is synced.
+    ///
+    /// The default is `Durability::Fsync`.
+    pub fn with_durability(mut self, durability: Durability) -> Self {
+        self.durability = durability;
+        self
+    }
+
+    /// Runs `f` with the table in a write transaction, and commits it.
+    fn write_table<F, R>(&self, f: F) -> io::Result<R>
+        where F: FnOnce(&mut redb::Table<&str, (u64, &[u8])>) -> Result<R, redb::StorageError>
+    {
+        let mut transaction = self.database.begin_write().map_err(redb_error)?;
+        transaction.set_durability(match self.durability {
+            Durability::None | Durability::Flush => redb::Durability::Eventual,
+            Durability::Fsync | Durability::FsyncDir => redb::Durability::Immediate,
+        });
+        let result = {
+            let mut table = transaction.open_table(TABLE).map_err(redb_error)?;
+            f(&mut table).map_err(redb_error)?
+        };
+        transaction.commit().map_err(redb_error)?;
+        Ok(result)
+    }
+
+    /// Returns the modification time and data stored at `path`, if any.
+    fn get(&self, path: &str) -> io::Result<Option<(u64, Vec<u8>)>> {
+        let transaction = self.database.begin_read().map_err(redb_error)?;
+        let table = transaction.open_table(TABLE).map_err(redb_error)?;
+        let entry = table.get(path).map_err(redb_error)?;
+        Ok(entry.map(|entry| {
+            let (mtime, data) = entry.value();
+            (mtime, data.to_vec())
+        }))
+    }
+}
+
+impl StorageBackend for RedbBackend {
+    fn read(&self, path: &str) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.get(path)?.map(|(_, data)| data))
+    }
+
+    fn write(&self, path: &str, data: &[u8]) -> io::Result<()> {
+        let mtime = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis() as u64);
+        self.write_table(|table| table.insert(path, (mtime, data)).map(|_| ()))
+    }
+
+    fn delete(&self, path: &str) -> io::Result<bool> {
+        self.write_table(|table| table.remove(path).map(|removed| removed.is_some()))
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        let transaction = self.database.begin_read().map_err(redb_error)?;
+        let table = transaction.open_table(TABLE).map_err(redb_error)?;
+        let mut found = Vec::new();
+        for entry in table.range(prefix..).map_err(redb_error)? {
+            let (path, _) = entry.map_err(redb_error)?;
+            let path = path.value();
+            if !path.starts_with(prefix) {
+                break;
+            }
+            found.push(path.to_owned());
+        }
+        Ok(found)
+    }
+
+    fn modified(&self, path: &str) -> io::Result<Option<SystemTime>> {
+        Ok(self.get(path)?.map(|(mtime, _)| UNIX_EPOCH + Duration::from_millis(mtime)))
+    }
+}
+
+fn redb_error<E: Into<redb::Error>>(error: E) -> io::Error {
+    io::Error::other(error.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RedbBackend;
+    use crate::testing::{APP_INFO, TestDir};
+    use crate::{Durability, PreferencesStore, StorageBackend};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_redb_backend() {
+        let dir = TestDir::new("redb");
+        let database = RedbBackend::in_dir(dir.path()).unwrap().with_durability(Durability::None);
+        let database = Arc::new(database);
+        let store = PreferencesStore::new(&APP_INFO).unwrap().with_backend(database.clone());
+        for i in 0..100u32 {
+            store.save(format!("recent/{}", i), &i).unwrap();
+        }
+        store.save("recents", &100u32).unwrap();
+        assert_eq!(store.load::<u32, _>("recent/42").unwrap(), 42);
+        assert_eq!(store.list("recent").unwrap().len(), 100);
+        assert!(store.metadata("recent/0").unwrap().modified > std::time::UNIX_EPOCH);
+
+        store.delete_all("recent").unwrap();
+        assert_eq!(database.list("").unwrap(), ["recents.prefs.json"]);
+        assert!(store.load::<u32, _>("recent/42").unwrap_err().is_not_found());
+
+        // The data is still there after reopening the database.
+        drop((store, database));
+        let database = RedbBackend::in_dir(dir.path()).unwrap();
+        assert_eq!(database.read("recents.prefs.json").unwrap().unwrap(), b"100");
+    }
+}
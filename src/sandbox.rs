@@ -0,0 +1,106 @@
+//! Detection of Linux application sandboxes (Flatpak and Snap).
+
+use std::env;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use crate::Scope;
+
+/// A Linux application sandbox that the app is running in. (See [`sandbox`](fn.sandbox.html).)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Sandbox {
+    /// A [Flatpak](https://flatpak.org/) sandbox.
+    Flatpak,
+    /// A [Snap](https://snapcraft.io/) sandbox.
+    Snap,
+}
+
+/// Returns the Linux application sandbox that the app is running in, if any.
+///
+/// In a sandbox, only a few directories are writable. The default
+/// [`DirProvider`](trait.DirProvider.html) takes this into account and stores the user's data in
+/// the sandbox's private directories, e.g. `~/.var/app/<app id>/config` for Flatpak and
+/// `$SNAP_USER_DATA/.config` for Snap. `Scope::System` can usually be read but not written.
+pub fn sandbox() -> Option<Sandbox> {
+    detect(|name| env::var_os(name), Path::new("/.flatpak-info").is_file())
+}
+
+fn detect<F>(var: F, has_flatpak_info: bool) -> Option<Sandbox>
+    where F: Fn(&str) -> Option<OsString>
+{
+    if has_flatpak_info || var("FLATPAK_ID").is_some() {
+        Some(Sandbox::Flatpak)
+    } else if var("SNAP").is_some() && var("SNAP_USER_DATA").is_some() {
+        Some(Sandbox::Snap)
+    } else {
+        None
+    }
+}
+
+/// Returns the writable base directory for `scope` in the sandbox the app is running in, or
+/// `None` if it isn't sandboxed or `scope` has no such directory.
+pub(crate) fn sandbox_base_dir(scope: Scope) -> Option<PathBuf> {
+    if !cfg!(target_os = "linux") {
+        return None;
+    }
+    base_dir(sandbox()?, scope, |name| env::var_os(name))
+}
+
+fn base_dir<F>(sandbox: Sandbox, scope: Scope, var: F) -> Option<PathBuf>
+    where F: Fn(&str) -> Option<OsString>
+{
+    let (xdg_var, subdir) = match scope {
+        Scope::User | Scope::Local => ("XDG_CONFIG_HOME", ".config"),
+        Scope::Cache => ("XDG_CACHE_HOME", ".cache"),
+        Scope::Data | Scope::State => ("XDG_DATA_HOME", ".local/share"),
+        Scope::System => return None,
+    };
+    // Both sandboxes usually point the XDG variables at their private directories.
+    if let Some(dir) = var(xdg_var).map(PathBuf::from).filter(|dir| dir.is_absolute()) {
+        return Some(dir);
+    }
+    match sandbox {
+        Sandbox::Flatpak => {
+            let subdir = subdir.trim_start_matches('.').trim_start_matches("local/");
+            let home = PathBuf::from(var("HOME")?);
+            Some(home.join(".var/app").join(var("FLATPAK_ID")?).join(subdir))
+        }
+        Sandbox::Snap => Some(PathBuf::from(var("SNAP_USER_DATA")?).join(subdir)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Sandbox, base_dir, detect};
+    use crate::Scope;
+    use std::ffi::OsString;
+    use std::path::Path;
+
+    #[test]
+    fn test_sandbox_dirs() {
+        let flatpak = |name: &str| -> Option<OsString> {
+            match name {
+                "HOME" => Some("/home/user".into()),
+                "FLATPAK_ID" => Some("org.example.App".into()),
+                _ => None,
+            }
+        };
+        assert_eq!(detect(flatpak, false), Some(Sandbox::Flatpak));
+        assert_eq!(base_dir(Sandbox::Flatpak, Scope::User, flatpak).unwrap(),
+                   Path::new("/home/user/.var/app/org.example.App/config"));
+        assert_eq!(base_dir(Sandbox::Flatpak, Scope::Data, flatpak).unwrap(),
+                   Path::new("/home/user/.var/app/org.example.App/share"));
+
+        let snap = |name: &str| -> Option<OsString> {
+            match name {
+                "SNAP" => Some("/snap/app/1".into()),
+                "SNAP_USER_DATA" => Some("/home/user/snap/app/1".into()),
+                _ => None,
+            }
+        };
+        assert_eq!(detect(snap, false), Some(Sandbox::Snap));
+        assert_eq!(base_dir(Sandbox::Snap, Scope::User, snap).unwrap(),
+                   Path::new("/home/user/snap/app/1/.config"));
+        assert_eq!(base_dir(Sandbox::Snap, Scope::System, snap), None);
+        assert_eq!(detect(|_: &str| None, false), None);
+    }
+}
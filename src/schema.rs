@@ -0,0 +1,125 @@
+//! JSON Schemas of preferences types, for users who edit their preferences files by hand.
+
+use schemars::{JsonSchema, Schema};
+use std::fs::create_dir_all;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use crate::{AppInfo, PreferencesError, PreferencesStore, file_path_in, write_atomically};
+
+/// Extension of the file in which `save_schema` saves the schema of a key's data.
+static SCHEMA_EXTENSION: &str = "schema.json";
+
+/// Returns the JSON Schema of the preferences type `T`, which derives or implements
+/// `schemars::JsonSchema`.
+///
+/// Requires the `schema` feature.
+pub fn schema_for<T: JsonSchema>() -> Schema {
+    schemars::SchemaGenerator::default().into_root_schema_for::<T>()
+}
+
+/// Saves the JSON Schema of the preferences type `T` next to the user data of `app` saved with
+/// the given `key`, in the file `<key>.prefs.schema.json`.
+///
+/// With the schema, editors can offer completion and validation to users who edit the
+/// preferences file by hand, e.g. in VS Code with a `json.schemas` setting that maps
+/// `*.prefs.json` files to their schema. Apps typically save the schema whenever they save the
+/// default preferences, so that it always matches the type of the saved data.
+///
+/// Requires the `schema` feature.
+///
+/// # Failures
+/// If the data directory cannot be determined, or the schema can't be written.
+///
+/// # Example
+/// ```
+/// #[macro_use]
+/// extern crate serde_derive;
+/// extern crate preferences;
+/// extern crate schemars;
+/// use preferences::{AppInfo, Preferences, save_schema};
+/// use schemars::JsonSchema;
+///
+/// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+///
+/// /// Settings of the editor.
+/// #[derive(Serialize, Deserialize, JsonSchema)]
+/// struct Editor {
+///     /// Width of a tab, in spaces.
+///     tab_width: u8,
+/// }
+///
+/// fn main() {
+///     # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
+///     Editor { tab_width: 4 }.save(&APP_INFO, "tests/docs/schema").unwrap();
+///     save_schema::<Editor, _>(&APP_INFO, "tests/docs/schema").unwrap();
+/// }
+/// ```
+pub fn save_schema<T, S>(app: &AppInfo, key: S) -> Result<(), PreferencesError>
+    where T: JsonSchema,
+          S: AsRef<str>
+{
+    PreferencesStore::new(app)?.save_schema::<T, _>(key)
+}
+
+/// Returns the path of the file of `store` in which the schema of the data saved with `key` is
+/// saved.
+pub(crate) fn path(store: &PreferencesStore, key: &str) -> Result<PathBuf, PreferencesError> {
+    file_path_in(store.root(), key, SCHEMA_EXTENSION)
+}
+
+/// Saves the schema of `T` in `store`, at `path`.
+pub(crate) fn save<T: JsonSchema>(store: &PreferencesStore,
+                                  path: &Path)
+                                  -> Result<(), PreferencesError> {
+    let bytes = serde_json::to_vec_pretty(&schema_for::<T>())?;
+    if !store.writes()? {
+        return Ok(());
+    }
+    match store.memory() {
+        Some(memory) => memory.write(path, bytes).map_err(Into::into),
+        None => {
+            path.parent().map(create_dir_all);
+            write_atomically(path, |file| file.write_all(&bytes).map_err(Into::into))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::schema_for;
+    use crate::testing::TestDir;
+    use schemars::JsonSchema;
+    use serde_json::Value;
+    use std::fs;
+
+    #[derive(JsonSchema)]
+    #[allow(dead_code)]
+    struct Editor {
+        /// Width of a tab, in spaces.
+        tab_width: u8,
+        font: Option<String>,
+    }
+
+    #[test]
+    fn test_save_schema() {
+        let schema = serde_json::to_value(schema_for::<Editor>()).unwrap();
+        assert_eq!(schema["properties"]["tab_width"]["description"],
+                   "Width of a tab, in spaces.");
+        assert_eq!(schema["required"], serde_json::json!(["tab_width"]));
+
+        let dir = TestDir::new("schema");
+        for store in dir.stores() {
+            store.save("editor", &7u8).unwrap();
+            store.save_schema::<Editor, _>("editor").unwrap();
+            let path = store.schema_path("editor").unwrap();
+            assert!(path.ends_with("editor.prefs.schema.json"));
+            let saved: Value = match store.memory() {
+                Some(memory) => serde_json::from_slice(&memory.read(&path).unwrap()).unwrap(),
+                None => serde_json::from_slice(&fs::read(&path).unwrap()).unwrap(),
+            };
+            assert_eq!(saved, schema);
+            // The schema isn't mistaken for the data of another key.
+            assert_eq!(store.list("").unwrap(), ["editor"]);
+        }
+    }
+}
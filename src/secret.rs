@@ -0,0 +1,72 @@
+//! Storage of secrets in the platform's secret store.
+
+use keyring::Entry;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use crate::{AppInfo, PreferencesError};
+
+/// Trait for types that can be saved & loaded as secrets in the platform's secret store (the
+/// macOS Keychain, the Windows Credential Manager, or the Secret Service on Linux).
+///
+/// This works like [`Preferences`](trait.Preferences.html), and is likewise implemented for all
+/// serializable types, but the data doesn't end up in the app's preferences files. Use it for
+/// passwords, tokens and the like, and keep everything else in ordinary preferences.
+///
+/// Secrets are stored as JSON in an entry whose service name is the app's name, and whose user
+/// name is the given `key`. Secret stores are meant for small values, so keep secrets short.
+///
+/// Requires the `keyring` feature.
+///
+/// # Example
+/// ```no_run
+/// extern crate preferences;
+/// use preferences::{AppInfo, SecretPreferences};
+///
+/// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+///
+/// fn main() {
+///     let token = String::from("hunter2");
+///     token.save_secret(&APP_INFO, "accounts/example.com").unwrap();
+///     let loaded = String::load_secret(&APP_INFO, "accounts/example.com").unwrap();
+///     assert_eq!(loaded, token);
+/// }
+/// ```
+pub trait SecretPreferences: Sized {
+    /// Saves this value in the platform's secret store, replacing any previous secret with the
+    /// same `key`.
+    ///
+    /// # Failures
+    /// If a serialization error occurs, or if the secret store is unavailable or refuses access.
+    fn save_secret<S: AsRef<str>>(&self, app: &AppInfo, key: S) -> Result<(), PreferencesError>;
+    /// Loads a value previously saved with `save_secret`.
+    ///
+    /// # Failures
+    /// If a deserialization error occurs, if the secret store is unavailable or refuses access,
+    /// or if no secret exists for that `key` (`keyring::Error::NoEntry`).
+    fn load_secret<S: AsRef<str>>(app: &AppInfo, key: S) -> Result<Self, PreferencesError>;
+    /// Deletes a secret previously saved with `save_secret`.
+    ///
+    /// # Failures
+    /// If the secret store is unavailable or refuses access, or if no secret exists for that
+    /// `key`.
+    fn delete_secret<S: AsRef<str>>(app: &AppInfo, key: S) -> Result<(), PreferencesError> {
+        entry(app, key.as_ref())?.delete_password().map_err(Into::into)
+    }
+}
+
+impl<T> SecretPreferences for T
+    where T: Serialize + DeserializeOwned + Sized
+{
+    fn save_secret<S: AsRef<str>>(&self, app: &AppInfo, key: S) -> Result<(), PreferencesError> {
+        let secret = serde_json::to_string(self)?;
+        entry(app, key.as_ref())?.set_password(&secret).map_err(Into::into)
+    }
+    fn load_secret<S: AsRef<str>>(app: &AppInfo, key: S) -> Result<Self, PreferencesError> {
+        let secret = entry(app, key.as_ref())?.get_password()?;
+        serde_json::from_str(&secret).map_err(Into::into)
+    }
+}
+
+fn entry(app: &AppInfo, key: &str) -> Result<Entry, PreferencesError> {
+    Entry::new(app.name, key).map_err(Into::into)
+}
@@ -0,0 +1,179 @@
+//! User data shared between threads.
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use crate::{AppInfo, PreferencesError, PreferencesStore};
+
+/// User data that can be shared between threads, read and changed by any of them, and saved.
+///
+/// Clones of a `SharedPreferences` refer to the same value, so give each thread its own clone.
+/// The value is guarded by a read-write lock: `read` gives shared access, and `write` exclusive
+/// access. Changes aren't saved automatically; call `save` after changing the value, or use
+/// `update`, which does both.
+///
+/// # Example
+/// ```
+/// #[macro_use]
+/// extern crate serde_derive;
+/// extern crate preferences;
+/// use preferences::{AppInfo, Preferences, SharedPreferences};
+/// use std::thread;
+///
+/// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+///
+/// #[derive(Serialize, Deserialize, Default)]
+/// struct Stats {
+///     downloads: u32,
+/// }
+///
+/// fn main() {
+///     # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
+///     let key = "tests/docs/shared";
+///     let stats = SharedPreferences::<Stats>::load_or_default(&APP_INFO, key).unwrap();
+///     let workers: Vec<_> = (0..4).map(|_| {
+///         let stats = stats.clone();
+///         thread::spawn(move || stats.write().downloads += 1)
+///     }).collect();
+///     for worker in workers {
+///         worker.join().unwrap();
+///     }
+///     stats.save().unwrap();
+///     assert_eq!(Stats::load(&APP_INFO, key).unwrap().downloads, 4);
+/// }
+/// ```
+#[derive(Debug)]
+pub struct SharedPreferences<T> {
+    inner: Arc<Shared<T>>,
+}
+
+#[derive(Debug)]
+struct Shared<T> {
+    store: PreferencesStore,
+    key: String,
+    value: RwLock<T>,
+}
+
+impl<T> Clone for SharedPreferences<T> {
+    fn clone(&self) -> Self {
+        SharedPreferences { inner: self.inner.clone() }
+    }
+}
+
+impl<T> SharedPreferences<T> {
+    /// Shares `value`, to be saved with the given `key` in `store`.
+    pub fn new<S: AsRef<str>>(store: PreferencesStore, key: S, value: T) -> Self {
+        SharedPreferences {
+            inner: Arc::new(Shared {
+                store,
+                key: key.as_ref().to_owned(),
+                value: RwLock::new(value),
+            }),
+        }
+    }
+
+    /// Returns the key with which the value is saved.
+    pub fn key(&self) -> &str {
+        &self.inner.key
+    }
+
+    /// Returns shared access to the value, waiting while another thread is changing it.
+    ///
+    /// A panic while the value was being changed doesn't make it inaccessible; the value is
+    /// returned as the panicking thread left it.
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        self.inner.value.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Returns exclusive access to the value, e.g. to change it, waiting while other threads
+    /// access it.
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        self.inner.value.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<T: Serialize> SharedPreferences<T> {
+    /// Saves the value. Other threads can read it, but not change it, while it's saved.
+    ///
+    /// # Failures
+    /// Same as [`Preferences::save`](trait.Preferences.html#tymethod.save).
+    pub fn save(&self) -> Result<(), PreferencesError> {
+        self.inner.store.save(&self.inner.key, &*self.read())
+    }
+
+    /// Changes the value with `f` and saves it, without letting other threads change it in
+    /// between.
+    ///
+    /// # Failures
+    /// Same as [`Preferences::save`](trait.Preferences.html#tymethod.save). The change is kept
+    /// even if saving fails.
+    pub fn update<F: FnOnce(&mut T)>(&self, f: F) -> Result<(), PreferencesError> {
+        let mut value = self.write();
+        f(&mut value);
+        self.inner.store.save(&self.inner.key, &*value)
+    }
+}
+
+impl<T> SharedPreferences<T>
+    where T: Serialize + DeserializeOwned
+{
+    /// Loads the user data of `app` saved with the given `key`.
+    ///
+    /// # Failures
+    /// Same as [`Preferences::load`](trait.Preferences.html#tymethod.load).
+    pub fn load<S: AsRef<str>>(app: &AppInfo, key: S) -> Result<Self, PreferencesError> {
+        let store = PreferencesStore::new(app)?;
+        let value = store.load(key.as_ref())?;
+        Ok(SharedPreferences::new(store, key, value))
+    }
+
+    /// Same as `load`, but starts with the default value if nothing was saved yet.
+    ///
+    /// # Failures
+    /// Same as [`Preferences::load_or_default`](trait.Preferences.html#method.load_or_default).
+    pub fn load_or_default<S: AsRef<str>>(app: &AppInfo, key: S) -> Result<Self, PreferencesError>
+        where T: Default
+    {
+        let store = PreferencesStore::new(app)?;
+        let value = match store.load(key.as_ref()) {
+            Ok(value) => value,
+            Err(ref e) if e.is_not_found() => T::default(),
+            Err(e) => return Err(e),
+        };
+        Ok(SharedPreferences::new(store, key, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{AppInfo, MemoryBackend, PreferencesStore, SharedPreferences};
+    use std::thread;
+
+    const APP_INFO: AppInfo = AppInfo {
+        name: "preferences",
+        author: "Rust language community",
+    };
+
+    #[test]
+    fn test_shared_update() {
+        let store = PreferencesStore::new(&APP_INFO)
+            .unwrap()
+            .with_memory_backend(MemoryBackend::new());
+        let shared = SharedPreferences::new(store.clone(), "count", 0u32);
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let shared = shared.clone();
+                thread::spawn(move || {
+                    for _ in 0..25 {
+                        shared.update(|count| *count += 1).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(*shared.read(), 100);
+        assert_eq!(store.load::<u32, _>("count").unwrap(), 100);
+    }
+}
@@ -0,0 +1,239 @@
+//! Signed storage of user data, to detect changes made outside of the app.
+
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use sha2::Sha256;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use crate::{AppInfo, Format, PreferencesError, PreferencesStore, file_path_in};
+
+static SIGNED_FILE_EXTENSION: &str = "signed";
+
+/// Starts the first line of a signed file, which is followed by the signature in hex.
+const HEADER: &[u8] = b"preferences-hmac-sha256 ";
+const HEADER_LEN: usize = 24 + 64 + 1;
+
+/// Saves and loads user data signed with a secret key, so that changes made to it outside of the
+/// app, e.g. by editing the file, are detected when it's loaded.
+///
+/// The data is serialized as JSON, and an HMAC-SHA256 signature of it is written on a line before
+/// the data. Unlike with [`EncryptedPreferences`](struct.EncryptedPreferences.html), the data
+/// stays readable; it just can't be changed without the key. The app name and the preferences key
+/// are signed along with the data, so a signed file can't be passed off as the data of another
+/// key. Files use the extension `.prefs.json.signed`.
+///
+/// Signing only deters tampering as well as the key is hidden: an app that ships with the key
+/// (e.g. to check a license file) can have it extracted from its binary. Plain
+/// [`Preferences`](trait.Preferences.html) files are never signed or checked.
+///
+/// Requires the `signing` feature.
+///
+/// # Example
+/// ```
+/// extern crate preferences;
+/// use preferences::{AppInfo, PreferencesMap, SignedPreferences};
+///
+/// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+///
+/// fn main() {
+///     # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
+///     let signed = SignedPreferences::new(b"app-specific secret");
+///
+///     let mut license: PreferencesMap = PreferencesMap::new();
+///     license.insert("edition".into(), "pro".into());
+///
+///     let prefs_key = "tests/docs/signed";
+///     signed.save(&APP_INFO, prefs_key, &license).unwrap();
+///     let loaded: PreferencesMap = signed.load(&APP_INFO, prefs_key).unwrap();
+///     assert_eq!(loaded, license);
+/// }
+/// ```
+#[derive(Clone)]
+pub struct SignedPreferences {
+    mac: Hmac<Sha256>,
+}
+
+impl SignedPreferences {
+    /// Creates an instance that signs and verifies data with the given secret key, which can be
+    /// of any length, but should have at least 32 random bytes.
+    pub fn new(key: &[u8]) -> Self {
+        SignedPreferences {
+            mac: Hmac::new_from_slice(key).expect("HMAC accepts keys of any length"),
+        }
+    }
+
+    /// Signs and saves `value` under the given `key`. (See
+    /// [`Preferences::save`](trait.Preferences.html#tymethod.save).)
+    pub fn save<T, S>(&self, app: &AppInfo, key: S, value: &T) -> Result<(), PreferencesError>
+        where T: Serialize,
+              S: AsRef<str>
+    {
+        self.save_in(&PreferencesStore::new(app)?, key.as_ref(), value)
+    }
+
+    /// Loads the value previously saved under the given `key`, and verifies its signature. (See
+    /// [`Preferences::load`](trait.Preferences.html#tymethod.load).)
+    ///
+    /// # Failures
+    /// In addition to the failures of `Preferences::load`, fails with
+    /// `PreferencesError::TamperDetected` if the secret key is wrong, or the file was changed or
+    /// isn't signed.
+    pub fn load<T, S>(&self, app: &AppInfo, key: S) -> Result<T, PreferencesError>
+        where T: DeserializeOwned,
+              S: AsRef<str>
+    {
+        self.load_in(&PreferencesStore::new(app)?, key.as_ref())
+    }
+
+    /// Same as `save`, but saves to `store`.
+    pub(crate) fn save_in<T: Serialize>(&self,
+                                        store: &PreferencesStore,
+                                        key: &str,
+                                        value: &T)
+                                        -> Result<(), PreferencesError> {
+        let mut bytes = Vec::new();
+        self.write_signed(value, &associated_data(store.app(), key), &mut bytes)?;
+        store.save_bytes(key, &file_path(store, key)?, bytes, store.options())
+    }
+
+    /// Same as `load`, but loads from `store`.
+    pub(crate) fn load_in<T: DeserializeOwned>(&self,
+                                               store: &PreferencesStore,
+                                               key: &str)
+                                               -> Result<T, PreferencesError> {
+        let bytes = store.load_bytes(&file_path(store, key)?)?;
+        self.read_signed(&associated_data(store.app(), key), &mut &bytes[..])
+    }
+
+    /// Same as `save`, but writes the signed data to an arbitrary writer.
+    pub fn save_to<T, W>(&self, value: &T, writer: &mut W) -> Result<(), PreferencesError>
+        where T: Serialize,
+              W: Write
+    {
+        self.write_signed(value, &[], writer)
+    }
+
+    /// Same as `load`, but reads the signed data from an arbitrary reader.
+    pub fn load_from<T, R>(&self, reader: &mut R) -> Result<T, PreferencesError>
+        where T: DeserializeOwned,
+              R: Read
+    {
+        self.read_signed(&[], reader)
+    }
+
+    fn write_signed<T, W>(&self, value: &T, signed_data: &[u8], writer: &mut W)
+                          -> Result<(), PreferencesError>
+        where T: Serialize,
+              W: Write
+    {
+        let mut payload = Vec::new();
+        Format::Json.serialize(value, &mut payload)?;
+        let signature = self.sign(signed_data, &payload).finalize().into_bytes();
+        writer.write_all(HEADER)?;
+        for byte in signature {
+            write!(writer, "{:02x}", byte)?;
+        }
+        writer.write_all(b"\n")?;
+        writer.write_all(&payload)?;
+        Ok(())
+    }
+
+    fn read_signed<T, R>(&self, signed_data: &[u8], reader: &mut R) -> Result<T, PreferencesError>
+        where T: DeserializeOwned,
+              R: Read
+    {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        if data.len() < HEADER_LEN || !data.starts_with(HEADER) || data[HEADER_LEN - 1] != b'\n' {
+            return Err(PreferencesError::TamperDetected);
+        }
+        let signature = decode_hex(&data[HEADER.len()..HEADER_LEN - 1])
+            .ok_or(PreferencesError::TamperDetected)?;
+        let payload = &data[HEADER_LEN..];
+        self.sign(signed_data, payload)
+            .verify_slice(&signature)
+            .map_err(|_| PreferencesError::TamperDetected)?;
+        Format::Json.deserialize(&mut &payload[..])
+    }
+
+    /// Returns the MAC of the serialized data in `payload`, preceded by `signed_data`.
+    fn sign(&self, signed_data: &[u8], payload: &[u8]) -> Hmac<Sha256> {
+        let mut mac = self.mac.clone();
+        mac.update(&(signed_data.len() as u64).to_le_bytes());
+        mac.update(signed_data);
+        mac.update(payload);
+        mac
+    }
+}
+
+fn decode_hex(hex: &[u8]) -> Option<Vec<u8>> {
+    hex.chunks(2)
+        .map(|pair| {
+            std::str::from_utf8(pair).ok().and_then(|pair| u8::from_str_radix(pair, 16).ok())
+        })
+        .collect()
+}
+
+fn extension() -> String {
+    format!("{}.{}", Format::Json.extension(), SIGNED_FILE_EXTENSION)
+}
+
+/// Returns the path of the file in `store` holding the signed data with the given `key`.
+fn file_path(store: &PreferencesStore, key: &str) -> Result<PathBuf, PreferencesError> {
+    file_path_in(store.root(), key, &extension())
+}
+
+fn associated_data(app: &AppInfo, key: &str) -> Vec<u8> {
+    format!("{}\n{}", app.name, key).into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{file_path, SignedPreferences};
+    use crate::testing::{APP_INFO, TestBackend, TestDir};
+    use crate::PreferencesError;
+    use std::fs;
+
+    #[test]
+    fn test_signed_round_trip() {
+        let dir = TestDir::new("signed");
+        let store = dir.store();
+        let key = "tests/module/signed";
+        let signed = SignedPreferences::new(b"secret");
+        signed.save_in(&store, key, &vec![1u32, 2, 3]).unwrap();
+        assert_eq!(signed.load_in::<Vec<u32>>(&store, key).unwrap(), [1, 2, 3]);
+
+        let path = file_path(&store, key).unwrap();
+        let raw = fs::read_to_string(&path).unwrap();
+        assert!(raw.ends_with("\n[1,2,3]"));
+
+        let other = SignedPreferences::new(b"other secret");
+        match other.load_in::<Vec<u32>>(&store, key) {
+            Err(PreferencesError::TamperDetected) => {}
+            other => panic!("expected tampering to be detected, got {:?}", other),
+        }
+        // The signature is only valid for the key it was saved with.
+        let moved = file_path(&store, "tests/module/signed-moved").unwrap();
+        fs::copy(&path, &moved).unwrap();
+        match signed.load_in::<Vec<u32>>(&store, "tests/module/signed-moved") {
+            Err(PreferencesError::TamperDetected) => {}
+            other => panic!("expected tampering to be detected, got {:?}", other),
+        }
+
+        fs::write(&path, raw.replace("[1,2,3]", "[1,2,4]")).unwrap();
+        match signed.load_in::<Vec<u32>>(&store, key) {
+            Err(PreferencesError::TamperDetected) => {}
+            other => panic!("expected tampering to be detected, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_signed_test_backend() {
+        let backend = TestBackend::install();
+        let signed = SignedPreferences::new(b"secret");
+        signed.save(&APP_INFO, "tests/module/signed", &7u32).unwrap();
+        assert_eq!(signed.load::<u32, _>(&APP_INFO, "tests/module/signed").unwrap(), 7);
+        assert!(!backend.backend().is_empty());
+    }
+}
@@ -0,0 +1,156 @@
+//! Storage of all of an app's user data in a single SQLite database.
+
+use rusqlite::{Connection, OptionalExtension, params};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::StorageBackend;
+
+/// File name of the database created by `SqliteBackend::in_dir`.
+static DATABASE_FILE_NAME: &str = "prefs.db";
+
+/// A [`StorageBackend`](trait.StorageBackend.html) that keeps all of a store's data in one SQLite
+/// database, with a row (path, data, modification time) for each preferences file.
+///
+/// For apps with many keys, this avoids creating thousands of tiny files, and keys can be read
+/// and written faster. Each write is atomic, and several processes can use the same database;
+/// while one of them is writing, the others wait for up to 5 seconds.
+///
+/// Requires the `sqlite` feature.
+///
+/// # Example
+/// ```
+/// extern crate preferences;
+/// use preferences::{AppInfo, PreferencesStore, SqliteBackend};
+/// use std::sync::Arc;
+///
+/// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+///
+/// fn main() {
+///     let store = PreferencesStore::new(&APP_INFO).unwrap();
+///     # let store = store.with_base_dir(std::env::temp_dir().join("preferences-docs"));
+///     let database = SqliteBackend::in_dir(store.root()).unwrap();
+///     let store = store.with_backend(Arc::new(database));
+///
+///     store.save("tests/docs/sqlite", &vec!["recent.txt"]).unwrap();
+///     assert_eq!(store.load::<Vec<String>, _>("tests/docs/sqlite").unwrap(), ["recent.txt"]);
+///     # store.delete("tests/docs/sqlite").unwrap();
+/// }
+/// ```
+#[derive(Debug)]
+pub struct SqliteBackend {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteBackend {
+    /// Opens the database at `path`, creating it if it doesn't exist.
+    ///
+    /// # Failures
+    /// If the database can't be opened or created.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let connection = Connection::open(path).map_err(sqlite_error)?;
+        connection.busy_timeout(Duration::from_secs(5)).map_err(sqlite_error)?;
+        connection.execute("CREATE TABLE IF NOT EXISTS prefs (key TEXT PRIMARY KEY, value BLOB \
+                            NOT NULL, mtime INTEGER NOT NULL)",
+                           [])
+            .map_err(sqlite_error)?;
+        Ok(SqliteBackend { connection: Mutex::new(connection) })
+    }
+
+    /// Opens the database `prefs.db` in the directory `dir` (usually the
+    /// [`root`](struct.PreferencesStore.html#method.root) of a store), creating both if they
+    /// don't exist.
+    ///
+    /// # Failures
+    /// If the directory or the database can't be opened or created.
+    pub fn in_dir<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        fs::create_dir_all(dir.as_ref())?;
+        SqliteBackend::open(dir.as_ref().join(DATABASE_FILE_NAME))
+    }
+
+    fn connection(&self) -> MutexGuard<'_, Connection> {
+        self.connection.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl StorageBackend for SqliteBackend {
+    fn read(&self, path: &str) -> io::Result<Option<Vec<u8>>> {
+        self.connection()
+            .query_row("SELECT value FROM prefs WHERE key = ?1", [path], |row| row.get(0))
+            .optional()
+            .map_err(sqlite_error)
+    }
+
+    fn write(&self, path: &str, data: &[u8]) -> io::Result<()> {
+        let mtime = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis() as i64);
+        self.connection()
+            .execute("INSERT OR REPLACE INTO prefs (key, value, mtime) VALUES (?1, ?2, ?3)",
+                     params![path, data, mtime])
+            .map(|_| ())
+            .map_err(sqlite_error)
+    }
+
+    fn delete(&self, path: &str) -> io::Result<bool> {
+        self.connection()
+            .execute("DELETE FROM prefs WHERE key = ?1", [path])
+            .map(|deleted| deleted > 0)
+            .map_err(sqlite_error)
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        let connection = self.connection();
+        let mut statement = connection.prepare("SELECT key FROM prefs \
+                                                WHERE substr(key, 1, length(?1)) = ?1")
+            .map_err(sqlite_error)?;
+        let paths = statement.query_map([prefix], |row| row.get(0)).map_err(sqlite_error)?;
+        paths.collect::<Result<_, _>>().map_err(sqlite_error)
+    }
+
+    fn modified(&self, path: &str) -> io::Result<Option<SystemTime>> {
+        let mtime: Option<i64> = self.connection()
+            .query_row("SELECT mtime FROM prefs WHERE key = ?1", [path], |row| row.get(0))
+            .optional()
+            .map_err(sqlite_error)?;
+        Ok(mtime.map(|mtime| UNIX_EPOCH + Duration::from_millis(mtime.max(0) as u64)))
+    }
+}
+
+fn sqlite_error(error: rusqlite::Error) -> io::Error {
+    io::Error::other(error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SqliteBackend;
+    use crate::testing::{APP_INFO, TestDir};
+    use crate::{PreferencesStore, StorageBackend};
+    use std::fs;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_sqlite_backend() {
+        let dir = TestDir::new("sqlite");
+        let database = Arc::new(SqliteBackend::in_dir(dir.path()).unwrap());
+        let store = PreferencesStore::new(&APP_INFO).unwrap().with_backend(database.clone());
+        for i in 0..100u32 {
+            store.save(format!("recent/{}", i), &i).unwrap();
+        }
+        store.save("recent_count", &100u32).unwrap();
+        assert_eq!(store.load::<u32, _>("recent/42").unwrap(), 42);
+        assert_eq!(store.list("recent").unwrap().len(), 100);
+        assert!(store.metadata("recent/0").unwrap().modified > std::time::UNIX_EPOCH);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        store.delete_all("recent").unwrap();
+        assert_eq!(database.list("").unwrap(), ["recent_count.prefs.json"]);
+        assert!(store.load::<u32, _>("recent/42").unwrap_err().is_not_found());
+
+        // Another connection sees the same data.
+        let other = SqliteBackend::in_dir(dir.path()).unwrap();
+        assert_eq!(other.read("recent_count.prefs.json").unwrap().unwrap(), b"100");
+    }
+}
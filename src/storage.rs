@@ -0,0 +1,105 @@
+//! Saving user data in storage other than files, e.g. on embedded devices.
+
+use std::collections::HashMap;
+use crate::PreferencesError;
+
+/// A key-value store for serialized user data, for platforms that don't keep it in files (e.g.
+/// a key-value store in the flash memory of an embedded device).
+///
+/// Implementing this trait is all it takes to save and load any
+/// [`Preferences`](trait.Preferences.html) with
+/// [`save_in`](trait.Preferences.html#method.save_in) and
+/// [`load_in`](trait.Preferences.html#method.load_in): the data is serialized like with
+/// `save_to`, and the keys are validated like keys of files (if strict keys are enabled), but
+/// the file system, app directories and file locks aren't used.
+///
+/// It's implemented for `HashMap<String, Vec<u8>>`, which keeps the data in memory.
+///
+/// # Example
+/// ```
+/// use preferences::{KeyValueStorage, Preferences, PreferencesError, PreferencesMap};
+/// use std::io;
+///
+/// /// Stores data in a fixed-size table, like a flash key-value store would.
+/// struct Slots([Option<(String, Vec<u8>)>; 4]);
+///
+/// impl KeyValueStorage for Slots {
+///     fn read(&self, key: &str) -> Result<Option<Vec<u8>>, PreferencesError> {
+///         Ok(self.0.iter().flatten().find(|slot| slot.0 == key).map(|slot| slot.1.clone()))
+///     }
+///     fn write(&mut self, key: &str, bytes: &[u8]) -> Result<(), PreferencesError> {
+///         self.remove(key)?;
+///         match self.0.iter_mut().find(|slot| slot.is_none()) {
+///             Some(free) => *free = Some((key.to_owned(), bytes.to_vec())),
+///             None => return Err(io::Error::other("no free slots").into()),
+///         }
+///         Ok(())
+///     }
+///     fn remove(&mut self, key: &str) -> Result<(), PreferencesError> {
+///         for slot in &mut self.0 {
+///             if slot.as_ref().is_some_and(|slot| slot.0 == key) {
+///                 *slot = None;
+///             }
+///         }
+///         Ok(())
+///     }
+/// }
+///
+/// let mut slots = Slots(Default::default());
+/// let mut wifi: PreferencesMap = PreferencesMap::new();
+/// wifi.insert("ssid".into(), "home".into());
+/// wifi.save_in(&mut slots, "network/wifi").unwrap();
+/// assert_eq!(PreferencesMap::<String>::load_in(&slots, "network/wifi").unwrap(), wifi);
+/// ```
+pub trait KeyValueStorage {
+    /// Returns the data stored with `key`, or `None` if there is none.
+    ///
+    /// # Failures
+    /// If the storage can't be read.
+    fn read(&self, key: &str) -> Result<Option<Vec<u8>>, PreferencesError>;
+    /// Stores `bytes` with `key`, replacing any data stored with it before.
+    ///
+    /// # Failures
+    /// If the storage can't be written, e.g. because it's full.
+    fn write(&mut self, key: &str, bytes: &[u8]) -> Result<(), PreferencesError>;
+    /// Removes the data stored with `key`, if any.
+    ///
+    /// # Failures
+    /// If the storage can't be written.
+    fn remove(&mut self, key: &str) -> Result<(), PreferencesError>;
+}
+
+impl KeyValueStorage for HashMap<String, Vec<u8>> {
+    fn read(&self, key: &str) -> Result<Option<Vec<u8>>, PreferencesError> {
+        Ok(self.get(key).cloned())
+    }
+    fn write(&mut self, key: &str, bytes: &[u8]) -> Result<(), PreferencesError> {
+        self.insert(key.to_owned(), bytes.to_vec());
+        Ok(())
+    }
+    fn remove(&mut self, key: &str) -> Result<(), PreferencesError> {
+        HashMap::remove(self, key);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Preferences, PreferencesError, PreferencesMap};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_key_value_storage() {
+        let mut storage: HashMap<String, Vec<u8>> = HashMap::new();
+        let mut prefs: PreferencesMap<u32> = PreferencesMap::new();
+        prefs.insert("volume".into(), 7);
+        prefs.save_in(&mut storage, "audio").unwrap();
+        assert_eq!(storage["audio"], br#"{"volume":7}"#);
+        assert_eq!(PreferencesMap::<u32>::load_in(&storage, "audio").unwrap(), prefs);
+
+        match PreferencesMap::<u32>::load_in(&storage, "video") {
+            Err(PreferencesError::NotFound { ref key, .. }) => assert_eq!(key, "video"),
+            other => panic!("expected the data not to be found, got {:?}", other),
+        }
+    }
+}
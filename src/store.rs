@@ -0,0 +1,1614 @@
+//! A handle to an app's user data that resolves the data directory only once.
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use crate::bundle;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use crate::bus::{self, ChangeBus};
+use crate::flush::PendingWrites;
+use crate::guard;
+use crate::layered;
+use crate::layout;
+use crate::memory::{self, MemoryBackend};
+use crate::migrate;
+#[cfg(feature = "mmap")]
+use crate::mmap::{self, MappedPrefs};
+use crate::observe::{self, Subscription};
+#[cfg(feature = "schema")]
+use crate::schema;
+use crate::sync;
+#[cfg(feature = "schema")]
+use schemars::JsonSchema;
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use crate::{AppId, AppInfo, DirProvider, FileNames, Format, Journal, KeyError, KeyLayout,
+            LegacyLocation, MigrateOptions, MigrationReport, PrefDescriptor, PrefKey,
+            PreferencesError, PreferencesGuard, SaveOptions, Scope, StorageBackend,
+            StorageLocation, SyncProvider, Transaction, file_path_named, keys, remove_empty_dirs};
+#[cfg(any(feature = "mmap", feature = "schema"))]
+use crate::Operation;
+
+mod in_memory;
+mod read;
+mod write;
+
+/// A handle to the user data of one app.
+///
+/// The free functions and the [`Preferences`](trait.Preferences.html) methods look up the app's
+/// data directory on every call. A store looks it up once, when it is created, and keeps the
+/// options used for saving, so it is the cheaper choice when an app reads and writes many keys.
+///
+/// # Example
+/// ```
+/// extern crate preferences;
+/// use preferences::{AppInfo, PreferencesStore};
+///
+/// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+///
+/// fn main() {
+///     # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
+///     let store = PreferencesStore::new(&APP_INFO).unwrap();
+///     let key = "tests/docs/store";
+///
+///     store.save(key, &vec![1, 2, 3]).unwrap();
+///     assert_eq!(store.load::<Vec<u8>, _>(key).unwrap(), vec![1, 2, 3]);
+///
+///     store.delete(key).unwrap();
+///     assert!(!store.exists(key).unwrap());
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct PreferencesStore {
+    app: AppId,
+    root: PathBuf,
+    /// The app's data directory, which is the same as `root` unless a profile is selected.
+    app_dir: PathBuf,
+    profile: Option<String>,
+    /// The version selected with `with_version`.
+    version: Option<VersionScope>,
+    options: SaveOptions,
+    memory: Option<MemoryBackend>,
+    sync: Option<Arc<dyn SyncProvider>>,
+    write_mode: WriteMode,
+    format_detection: FormatDetection,
+    file_names: FileNames,
+    /// Whether `load` falls back to the file of the key in the other layout.
+    layout_fallback: bool,
+    /// Whether saving fails if the file of a key differs only in case from an existing one.
+    collision_check: bool,
+    /// The serialized defaults registered with `register_default`, by key.
+    defaults: Arc<RwLock<BTreeMap<String, Value>>>,
+    /// The validators registered for typed keys, each a `Validator<T>`, by key.
+    validators: Arc<RwLock<BTreeMap<String, Box<dyn Any + Send + Sync>>>>,
+    /// The keys registered with `alias`, with their policies, by their new keys.
+    aliases: Arc<RwLock<BTreeMap<String, (String, AliasPolicy)>>>,
+    /// The descriptors attached with `describe`, by key.
+    descriptors: Arc<RwLock<BTreeMap<String, PrefDescriptor>>>,
+    /// The auto-savers of the store and the saves that aren't durable yet, for `flush` and
+    /// `sync_all`.
+    pending: Arc<PendingWrites>,
+}
+
+/// Checks a loaded value, and returns it (possibly changed), or how to handle it if it's invalid.
+type Validator<T> = Arc<dyn Fn(T) -> Result<T, ValidationPolicy> + Send + Sync>;
+
+impl PreferencesStore {
+    /// Creates a store for the given app's user data, using the default save options.
+    ///
+    /// The data is stored in files in the platform's user configuration directory (or in the
+    /// directory set with the `PREFERENCES_RS_DIR` environment variable; see
+    /// [`prefs_base_dir`](fn.prefs_base_dir.html)), unless a memory backend was installed for the
+    /// current thread with [`set_backend_for_tests`](fn.set_backend_for_tests.html).
+    ///
+    /// # Failures
+    /// If the data directory cannot be determined (e.g. because `app` has an empty name).
+    pub fn new(app: &AppInfo) -> Result<Self, PreferencesError> {
+        PreferencesStore::for_app(&AppId::from(app))
+    }
+
+    /// Same as `new`, but for the app identified by `app`, whose bundle identifier or version
+    /// may determine the data directory. (See [`AppId`](struct.AppId.html).)
+    pub fn for_app(app: &AppId) -> Result<Self, PreferencesError> {
+        let root = StorageLocation::default().dir_of(app)?;
+        Ok(PreferencesStore {
+            app: app.clone(),
+            root: root.clone(),
+            app_dir: root,
+            profile: None,
+            version: None,
+            options: SaveOptions::default(),
+            memory: match memory::backend_for_tests() {
+                Some(backend) => Some(backend),
+                None => memory::platform_backend()?,
+            },
+            sync: sync::sync_provider(),
+            write_mode: WriteMode::default(),
+            format_detection: FormatDetection::default(),
+            file_names: FileNames::default(),
+            layout_fallback: false,
+            collision_check: cfg!(any(windows, target_os = "macos")),
+            defaults: Arc::default(),
+            validators: Arc::default(),
+            aliases: Arc::default(),
+            descriptors: Arc::default(),
+            pending: Arc::default(),
+        })
+    }
+
+    /// Makes this store keep its data below the base directory `dir`, in a subdirectory named
+    /// after the app, instead of in the platform's user configuration directory.
+    ///
+    /// This replaces the directory set by any earlier call to `with_base_dir`, `with_location`,
+    /// `with_scope` or `with_dir_provider`, and vice versa.
+    pub fn with_base_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        let root = dir.as_ref().join(keys::sanitized(self.app.info().name));
+        self.set_root(root);
+        self
+    }
+
+    /// Makes this store keep its data at the given `location`, e.g. next to the executable for
+    /// portable apps.
+    ///
+    /// # Failures
+    /// If the directory for `location` cannot be determined.
+    pub fn with_location(mut self, location: StorageLocation) -> Result<Self, PreferencesError> {
+        let root = location.dir_of(&self.app)?;
+        self.set_root(root);
+        Ok(self)
+    }
+
+    /// Makes this store keep its data in the platform directory for the given `scope`, e.g. in
+    /// the machine-wide configuration directory for `Scope::System`, or in the user data
+    /// directory (`~/.local/share` on Linux) for `Scope::Data`.
+    ///
+    /// # Example
+    /// ```
+    /// use preferences::{AppInfo, PreferencesStore, Scope};
+    ///
+    /// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+    ///
+    /// # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
+    /// let saves = PreferencesStore::new(&APP_INFO).unwrap().with_scope(Scope::Data).unwrap();
+    /// assert_eq!(saves.root(), Scope::Data.app_dir(&APP_INFO).unwrap());
+    /// saves.save("tests/docs/quicksave", &vec![1u8, 2, 3]).unwrap();
+    /// ```
+    ///
+    /// # Failures
+    /// If the directory for `scope` cannot be determined.
+    pub fn with_scope(mut self, scope: Scope) -> Result<Self, PreferencesError> {
+        let root = scope.dir_of(&self.app)?;
+        self.set_root(root);
+        Ok(self)
+    }
+
+    /// Makes this store keep its data in the directory that `provider` returns for `scope`,
+    /// instead of asking the provider installed with
+    /// [`set_dir_provider`](fn.set_dir_provider.html).
+    ///
+    /// # Failures
+    /// If `provider` cannot determine the directory.
+    pub fn with_dir_provider(mut self,
+                             provider: &dyn DirProvider,
+                             scope: Scope)
+                             -> Result<Self, PreferencesError> {
+        let root = self.app.platform_dir(provider.app_dir(scope, self.app.info())?);
+        self.set_root(root);
+        Ok(self)
+    }
+
+    /// Makes this store keep its data in `backend` instead of in files.
+    pub fn with_memory_backend(mut self, backend: MemoryBackend) -> Self {
+        self.memory = Some(backend);
+        self
+    }
+
+    /// Makes this store keep its data in a custom storage `backend`, e.g. a database, instead of
+    /// in files. (See [`StorageBackend`](trait.StorageBackend.html).)
+    ///
+    /// The paths in the backend are relative to the store's directory, so a backend should only
+    /// hold the data of one app and scope; use a separate backend for each.
+    pub fn with_backend(mut self, backend: Arc<dyn StorageBackend>) -> Self {
+        self.memory = Some(MemoryBackend::with_storage(self.app_dir.clone(), backend));
+        self
+    }
+
+    /// Makes this store keep its data separately for the given `profile`, e.g. a user account
+    /// or a workspace, so that the same keys can be used for each profile. The profile's data is
+    /// kept in a subdirectory of the app's data directory, `.profiles/<profile>`, which becomes
+    /// this store's [`root`](#method.root).
+    ///
+    /// A store without a profile accesses the data outside of any profile, which doesn't include
+    /// the profiles' data, except that `delete_all("")` deletes the profiles as well.
+    ///
+    /// # Example
+    /// ```
+    /// use preferences::{AppInfo, PreferencesStore};
+    ///
+    /// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+    ///
+    /// # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
+    /// let store = PreferencesStore::new(&APP_INFO).unwrap();
+    /// let work = store.clone().with_profile("docs-work").unwrap();
+    /// work.save("tests/docs/profile", &"work@example.com").unwrap();
+    /// assert!(store.list_profiles().unwrap().contains(&"docs-work".to_owned()));
+    /// assert!(!store.exists("tests/docs/profile").unwrap());
+    /// ```
+    ///
+    /// # Failures
+    /// `PreferencesError::InvalidKey` if `profile` is empty or too long.
+    pub fn with_profile<S: AsRef<str>>(mut self, profile: S) -> Result<Self, PreferencesError> {
+        let profile = profile.as_ref();
+        profile_dir(&self.app_dir, profile)?;
+        self.profile = Some(profile.to_owned());
+        self.update_root();
+        Ok(self)
+    }
+
+    /// Returns the profile selected with `with_profile`, if any.
+    pub fn profile(&self) -> Option<&str> {
+        self.profile.as_deref()
+    }
+
+    /// Returns the names of the profiles of the app that have any data saved, in alphabetical
+    /// order, regardless of the profile of this store.
+    ///
+    /// # Failures
+    /// If a file I/O error (e.g. permission denied) occurs.
+    pub fn list_profiles(&self) -> Result<Vec<String>, PreferencesError> {
+        let dir = self.app_dir.join(PROFILES_DIR_NAME);
+        let found = match self.memory {
+            Some(ref memory) => memory.list_dirs(&dir),
+            None => {
+                match fs::read_dir(&dir) {
+                    Ok(entries) => {
+                        let mut found = Vec::new();
+                        for entry in entries {
+                            let entry = entry?;
+                            if entry.file_type()?.is_dir() {
+                                found.extend(entry.file_name().to_str().map(str::to_owned));
+                            }
+                        }
+                        found
+                    }
+                    Err(ref e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        };
+        let mut profiles: Vec<String> = found.iter()
+            .filter(|name| !name.starts_with('.'))
+            .filter_map(|name| keys::unsanitized(name))
+            .collect();
+        profiles.sort();
+        Ok(profiles)
+    }
+
+    /// Deletes all user data of the given `profile`. Deleting a profile that has no data is not
+    /// an error.
+    ///
+    /// # Failures
+    /// If `profile` is invalid (see `with_profile`), or a file I/O error (e.g. permission denied)
+    /// occurs.
+    pub fn delete_profile<S: AsRef<str>>(&self, profile: S) -> Result<(), PreferencesError> {
+        let dir = profile_dir(&self.app_dir, profile.as_ref())?;
+        if !self.writes()? {
+            return Ok(());
+        }
+        if let Some(ref memory) = self.memory {
+            return memory.remove_dir(&dir).map_err(Into::into);
+        }
+        match fs::remove_dir_all(&dir) {
+            Ok(()) => {}
+            Err(ref e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        remove_empty_dirs(&self.app_dir, dir.parent());
+        Ok(())
+    }
+
+    /// Makes this store keep its data separately for the given `version` of the app, e.g. so
+    /// that the beta and stable builds of the app can be installed together without overwriting
+    /// each other's preferences. The version's data is kept in the subtree `<version>/` of the
+    /// app's data (or of the profile's, if one is selected), which becomes this store's
+    /// [`root`](#method.root).
+    ///
+    /// Until a key is saved in this version, loading it falls back to its value in the
+    /// `previous` versions, in the given order, where `""` stands for the data saved without a
+    /// version. The value is copied into this version the first time it's saved, and the
+    /// previous versions' data is never changed. As with aliases, only `load`, `get` and the
+    /// functions based on them fall back; e.g. `exists` and `list` only see this version's data,
+    /// and deleting a key in this version makes it fall back again.
+    ///
+    /// # Example
+    /// ```
+    /// use preferences::{AppInfo, PreferencesStore};
+    ///
+    /// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+    ///
+    /// # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
+    /// let stable = PreferencesStore::new(&APP_INFO).unwrap().with_version("v1", &[]).unwrap();
+    /// stable.save("tests/docs/version", &"light").unwrap();
+    ///
+    /// let beta = PreferencesStore::new(&APP_INFO).unwrap().with_version("v2", &["v1"]).unwrap();
+    /// assert_eq!(beta.load::<String, _>("tests/docs/version").unwrap(), "light");
+    /// beta.save("tests/docs/version", &"dark").unwrap();
+    /// assert_eq!(stable.load::<String, _>("tests/docs/version").unwrap(), "light");
+    /// ```
+    ///
+    /// # Failures
+    /// `PreferencesError::InvalidKey` if `version` is empty, or it or one of the `previous`
+    /// versions isn't a valid segment of a key.
+    pub fn with_version<S: AsRef<str>>(mut self,
+                                       version: S,
+                                       previous: &[&str])
+                                       -> Result<Self, PreferencesError> {
+        let version = version.as_ref();
+        if version.is_empty() {
+            return Err(KeyError::Empty.into());
+        }
+        let version_dir = |version: &str| -> Result<String, PreferencesError> {
+            keys::check_traversal(version)?;
+            Ok(keys::segment_file_name(version)?)
+        };
+        let mut previous_dirs = Vec::with_capacity(previous.len());
+        for &previous in previous {
+            previous_dirs.push(match previous {
+                "" => None,
+                _ => Some(version_dir(previous)?),
+            });
+        }
+        self.version = Some(VersionScope {
+            name: version.to_owned(),
+            dir: version_dir(version)?,
+            previous: previous_dirs,
+        });
+        self.update_root();
+        Ok(self)
+    }
+
+    /// Returns the version selected with `with_version`, if any.
+    pub fn version(&self) -> Option<&str> {
+        self.version.as_ref().map(|version| &version.name[..])
+    }
+
+    /// Sets the app's data directory, keeping the selected profile and version.
+    fn set_root(&mut self, app_dir: PathBuf) {
+        if let Some(ref mut memory) = self.memory {
+            memory.set_root(&app_dir);
+        }
+        self.app_dir = app_dir;
+        self.update_root();
+    }
+
+    /// Sets `root` to the directory of the selected profile and version.
+    fn update_root(&mut self) {
+        let base = self.versions_dir();
+        self.root = match self.version {
+            Some(ref version) => base.join(&version.dir),
+            None => base,
+        };
+    }
+
+    /// Returns the directory that contains the data of all versions, which is the selected
+    /// profile's directory or the app's data directory.
+    fn versions_dir(&self) -> PathBuf {
+        match self.profile {
+            Some(ref profile) => {
+                profile_dir(&self.app_dir, profile).unwrap_or_else(|_| self.app_dir.clone())
+            },
+            None => self.app_dir.clone(),
+        }
+    }
+
+    /// Makes this store synchronize its data with `provider` instead of with the provider
+    /// installed with [`set_sync_provider`](fn.set_sync_provider.html), or not at all with
+    /// `None`.
+    pub fn with_sync_provider(mut self, provider: Option<Arc<dyn SyncProvider>>) -> Self {
+        self.sync = provider;
+        self
+    }
+
+    /// Sets whether this store changes its data. With `WriteMode::DryRun` or
+    /// `WriteMode::ReadOnly`, saves, deletes and other changes of the data are skipped or fail,
+    /// e.g. for kiosk deployments, or for tests that must not change the user's real
+    /// configuration; loading works as usual.
+    ///
+    /// # Example
+    /// ```
+    /// use preferences::{AppInfo, PreferencesError, PreferencesStore, WriteMode};
+    ///
+    /// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+    ///
+    /// # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
+    /// let store = PreferencesStore::new(&APP_INFO).unwrap();
+    /// let dry_run = store.clone().with_write_mode(WriteMode::DryRun);
+    /// dry_run.save("tests/docs/dry-run", &1u32).unwrap();
+    /// assert!(!store.exists("tests/docs/dry-run").unwrap());
+    ///
+    /// let read_only = store.with_write_mode(WriteMode::ReadOnly);
+    /// let result = read_only.save("tests/docs/dry-run", &1u32);
+    /// match result.as_ref().map_err(PreferencesError::inner) {
+    ///     Err(&PreferencesError::ReadOnly) => {}
+    ///     other => panic!("expected the save to fail, got {:?}", other),
+    /// }
+    /// ```
+    pub fn with_write_mode(mut self, mode: WriteMode) -> Self {
+        self.write_mode = mode;
+        self
+    }
+
+    /// Returns whether this store changes its data. (See `with_write_mode`.)
+    pub fn write_mode(&self) -> WriteMode {
+        self.write_mode
+    }
+
+    /// Sets whether `load` finds data saved in other formats than the one of this store's
+    /// options, and whether saving converts it, e.g. to switch an app from JSON to TOML without
+    /// losing the data its users saved before.
+    ///
+    /// # Example
+    /// ```
+    /// use preferences::{AppInfo, Format, FormatDetection, PreferencesStore, SaveOptions};
+    ///
+    /// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+    ///
+    /// let store = PreferencesStore::new(&APP_INFO).unwrap();
+    /// # let store = store.with_memory_backend(preferences::MemoryBackend::new());
+    /// store.save("tests/docs/detection", &7u32).unwrap();
+    ///
+    /// # #[cfg(feature = "toml")] {
+    /// let store = store.with_options(SaveOptions::new().format(Format::Toml))
+    ///     .with_format_detection(FormatDetection::Convert);
+    /// assert_eq!(store.load::<u32, _>("tests/docs/detection").unwrap(), 7);
+    /// store.save("tests/docs/detection", &8u32).unwrap();
+    /// assert_eq!(store.detect_format("tests/docs/detection").unwrap(), Some(Format::Toml));
+    /// # }
+    /// ```
+    pub fn with_format_detection(mut self, detection: FormatDetection) -> Self {
+        self.format_detection = detection;
+        self
+    }
+
+    /// Returns whether this store loads and converts data saved in other formats. (See
+    /// `with_format_detection`.)
+    pub fn format_detection(&self) -> FormatDetection {
+        self.format_detection
+    }
+
+    /// Sets the suffix that the names of this store's files have between the last segment of
+    /// the key and the extension of the format, instead of `.prefs`, e.g. to keep the names of
+    /// files that an app saved before it used this crate. With an empty suffix, the key
+    /// `window` is saved in `window.json`.
+    ///
+    /// # Example
+    /// ```
+    /// use preferences::{AppInfo, PreferencesStore};
+    ///
+    /// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+    ///
+    /// let store = PreferencesStore::new(&APP_INFO)
+    ///     .unwrap()
+    ///     .with_file_suffix("")
+    ///     .unwrap()
+    ///     .with_default_file_name("settings")
+    ///     .unwrap();
+    /// assert!(store.file_path("", Default::default()).unwrap().ends_with("settings.json"));
+    /// assert!(store.file_path("window", Default::default()).unwrap().ends_with("window.json"));
+    /// ```
+    ///
+    /// # Failures
+    /// `PreferencesError::InvalidKey` if `suffix` contains a slash or backslash.
+    pub fn with_file_suffix<S>(mut self, suffix: S) -> Result<Self, PreferencesError>
+        where S: Into<String>
+    {
+        let suffix = suffix.into();
+        if let Some(c) = suffix.chars().find(|&c| c == '/' || c == '\\' || c == '\0') {
+            return Err(KeyError::InvalidCharacter(suffix, c).into());
+        }
+        self.file_names.suffix = suffix.into();
+        Ok(self)
+    }
+
+    /// Sets where this store keeps the file of each key. (See [`KeyLayout`](enum.KeyLayout.html).)
+    ///
+    /// Data saved in another layout isn't found in this one, unless `with_layout_fallback` is
+    /// enabled; to move it, use `migrate_layout`.
+    ///
+    /// # Example
+    /// ```
+    /// use preferences::{AppInfo, KeyLayout, PreferencesStore};
+    ///
+    /// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+    ///
+    /// let store = PreferencesStore::new(&APP_INFO).unwrap().with_key_layout(KeyLayout::LeafAsDir);
+    /// let path = store.file_path("options/graphics", Default::default()).unwrap();
+    /// assert!(path.ends_with("options/graphics/prefs.json"));
+    /// ```
+    pub fn with_key_layout(mut self, layout: KeyLayout) -> Self {
+        self.file_names.layout = layout;
+        self
+    }
+
+    /// Returns where this store keeps the file of each key. (See `with_key_layout`.)
+    pub fn key_layout(&self) -> KeyLayout {
+        self.file_names.layout
+    }
+
+    /// Sets whether `load`, `get` and the functions based on them read data from the file of
+    /// the key in the other [`KeyLayout`](enum.KeyLayout.html) if nothing is saved in this store's
+    /// layout, e.g. while an app switches layouts. Saving always writes in this store's layout, so
+    /// afterwards the new file is loaded. `exists` and `list` only see this store's layout.
+    pub fn with_layout_fallback(mut self, fallback: bool) -> Self {
+        self.layout_fallback = fallback;
+        self
+    }
+
+    /// Moves the data saved in the `from` layout to the files of this store's layout, and
+    /// returns the keys that were moved. Keys are found like with `list` (i.e. those saved in
+    /// JSON), and their data is moved in all formats. Keys that are already saved in this store's
+    /// layout are skipped, and their files in the `from` layout are kept.
+    ///
+    /// # Example
+    /// ```
+    /// use preferences::{AppInfo, KeyLayout, PreferencesStore};
+    ///
+    /// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+    ///
+    /// let store = PreferencesStore::new(&APP_INFO).unwrap();
+    /// # let store = store.with_memory_backend(preferences::MemoryBackend::new());
+    /// store.save("tests/docs/layout", &1).unwrap();
+    ///
+    /// let store = store.with_key_layout(KeyLayout::LeafAsDir);
+    /// let migrated = store.migrate_layout(KeyLayout::LeafAsFile).unwrap();
+    /// assert_eq!(migrated, ["tests/docs/layout"]);
+    /// assert_eq!(store.load::<u32, _>("tests/docs/layout").unwrap(), 1);
+    /// ```
+    ///
+    /// # Failures
+    /// If the data can't be listed or moved. The keys moved before the error stay moved.
+    pub fn migrate_layout(&self, from: KeyLayout) -> Result<Vec<String>, PreferencesError> {
+        layout::migrate(self, from)
+    }
+
+    /// Sets whether saving fails with `PreferencesError::KeyCollision` if the file of the key, or
+    /// one of its directories, has a name that differs from an existing one only in case, e.g.
+    /// when saving `theme` after `Theme`. On case-insensitive file systems (the default on
+    /// Windows and macOS), such keys share a file, and would overwrite each other's data.
+    ///
+    /// The check is enabled by default on Windows and macOS. Enabling it elsewhere keeps the data
+    /// portable to them. Memory backends never check, as their keys are case-sensitive.
+    pub fn with_collision_check(mut self, check: bool) -> Self {
+        self.collision_check = check;
+        self
+    }
+
+    /// Returns whether saving checks for keys that differ only in case. (See
+    /// `with_collision_check`.)
+    pub fn collision_check(&self) -> bool {
+        self.collision_check
+    }
+
+    /// Sets the name of the file, without the extension of the format, in which this store
+    /// saves data with the empty key, instead of `prefs`. (See `with_file_suffix`.) With an
+    /// empty suffix, the file is the same as that of the key with this name, which `list` then
+    /// includes.
+    ///
+    /// # Failures
+    /// `PreferencesError::InvalidKey` if `name` is empty or isn't a valid segment of a key.
+    pub fn with_default_file_name<S>(mut self, name: S) -> Result<Self, PreferencesError>
+        where S: AsRef<str>
+    {
+        let name = name.as_ref();
+        if name.is_empty() {
+            return Err(KeyError::Empty.into());
+        }
+        keys::check_traversal(name)?;
+        self.file_names.default_name = keys::segment_file_name(name)?.into();
+        Ok(self)
+    }
+
+    /// Returns whether changes of the data should be made, i.e. `false` in dry-run mode.
+    ///
+    /// # Failures
+    /// `PreferencesError::ReadOnly` if the store is read-only.
+    pub(crate) fn writes(&self) -> Result<bool, PreferencesError> {
+        match self.write_mode {
+            WriteMode::Write => Ok(true),
+            WriteMode::DryRun => Ok(false),
+            WriteMode::ReadOnly => Err(PreferencesError::ReadOnly),
+        }
+    }
+
+    /// Returns whether this store keeps its data in a memory or storage backend instead of in
+    /// files.
+    pub fn is_in_memory(&self) -> bool {
+        self.memory.is_some()
+    }
+
+    /// Sets the options used by `save` and `try_save`. The format set in `options` is also used
+    /// by `load` and `try_load`.
+    pub fn with_options(mut self, options: SaveOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Same as [`edit`](fn.edit.html), but in the format of this store's options.
+    pub fn edit<T, S>(&self, key: S) -> Result<PreferencesGuard<T>, PreferencesError>
+        where T: Serialize + DeserializeOwned + Default,
+              S: AsRef<str>
+    {
+        PreferencesGuard::new(self, key.as_ref())
+    }
+
+    /// Same as [`update`](fn.update.html), but in the format of this store's options.
+    pub fn update<T, S, F, R>(&self, key: S, f: F) -> Result<R, PreferencesError>
+        where T: Serialize + DeserializeOwned + Default,
+              S: AsRef<str>,
+              F: FnOnce(&mut T) -> R
+    {
+        let mut guard = self.edit(key)?;
+        let result = f(&mut guard);
+        guard.commit()?;
+        Ok(result)
+    }
+
+    /// Same as [`patch`](fn.patch.html), but in the format of this store's options.
+    pub fn patch<P, S>(&self, key: S, partial: &P) -> Result<(), PreferencesError>
+        where P: Serialize,
+              S: AsRef<str>
+    {
+        let partial = serde_json::to_value(partial)?;
+        self.update(key, |value: &mut serde_json::Value| guard::merge_patch(value, partial))
+    }
+
+    /// Same as [`Preferences::load_lenient`](trait.Preferences.html#method.load_lenient), but in
+    /// the format of this store's options.
+    pub fn load_lenient<T, S>(&self, key: S) -> Result<T, PreferencesError>
+        where T: Serialize + DeserializeOwned + Default,
+              S: AsRef<str>
+    {
+        let mut value = serde_json::to_value(T::default())?;
+        match self.load(key) {
+            Ok(saved) => layered::merge(&mut value, saved),
+            Err(ref e) if e.is_not_found() => {}
+            Err(e) => return Err(e),
+        }
+        serde_json::from_value(value).map_err(Into::into)
+    }
+
+    /// Same as [`Preferences::save_merged`](trait.Preferences.html#method.save_merged), but with
+    /// this store's options.
+    pub fn save_merged<T, S>(&self, key: S, value: &T) -> Result<(), PreferencesError>
+        where T: Serialize,
+              S: AsRef<str>
+    {
+        let value = serde_json::to_value(value)?;
+        self.update(key, |saved: &mut Value| {
+            match (saved, value) {
+                (&mut Value::Object(ref mut saved), Value::Object(fields)) => saved.extend(fields),
+                (saved, value) => *saved = value,
+            }
+        })
+    }
+
+    /// Starts a transaction, which saves several values so that either all or none of them are
+    /// updated.
+    pub fn transaction(&self) -> Transaction<'_> {
+        Transaction::new(self)
+    }
+
+    /// Returns the append-only [`Journal`](struct.Journal.html) of the user data with the given
+    /// `key` in this store.
+    pub fn journal<S: AsRef<str>>(&self, key: S) -> Journal {
+        Journal::with_store(self.clone(), key)
+    }
+
+    /// Registers `callback` to be called with the key of user data under `prefix` (or with the
+    /// key `prefix` itself) after it was successfully saved through this or any other store for
+    /// the same directory in the current process, including with
+    /// [`Preferences::save`](trait.Preferences.html#tymethod.save) and in transactions. An empty
+    /// prefix matches all keys.
+    ///
+    /// The callback runs on the thread that saved the data, after the save completed, so it can
+    /// load the new value. Changes made by other processes aren't reported; use
+    /// [`watch_prefs`](fn.watch_prefs.html) for those. The callback is unregistered when the
+    /// returned subscription is dropped.
+    pub fn on_change<S, F>(&self, prefix: S, callback: F) -> Subscription
+        where S: AsRef<str>,
+              F: Fn(&str) + Send + Sync + 'static
+    {
+        observe::subscribe(&self.root, prefix.as_ref(), callback)
+    }
+
+    /// Makes the current process a member of the change bus of this store's directory, so that
+    /// its saves are announced to the other running instances of the app, and it can subscribe
+    /// to theirs. (See [`ChangeBus`](struct.ChangeBus.html).)
+    ///
+    /// # Failures
+    /// If the store doesn't keep its data in files (e.g. it has a memory backend), or its
+    /// directory can't be created.
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    pub fn change_bus(&self) -> Result<ChangeBus, PreferencesError> {
+        bus::join(self)
+    }
+
+    /// Saves the values that [`AutoSaver`](struct.AutoSaver.html)s of this store (or of its
+    /// clones) haven't saved yet, and waits until they're saved, so that they can be loaded.
+    ///
+    /// [`AutoSavePreferences`](struct.AutoSavePreferences.html) values aren't known to the store;
+    /// save them with their own `save` or by dropping them.
+    ///
+    /// # Failures
+    /// The first error that occurred while saving with an auto-saver since it was last flushed.
+    /// The other auto-savers are flushed regardless.
+    pub fn flush(&self) -> Result<(), PreferencesError> {
+        self.pending.flush()
+    }
+
+    /// Same as `flush`, and then writes everything that this store (or its clones) saved to
+    /// storage, as far as the saves' [`Durability`](enum.Durability.html) didn't already, so that
+    /// the data survives a power failure. Apps that save with a lower durability for speed can
+    /// call this on shutdown.
+    ///
+    /// # Failures
+    /// If flushing fails, or a file or directory can't be synced. Files that weren't synced are
+    /// synced by the next call.
+    ///
+    /// # Example
+    /// ```
+    /// use preferences::{AppInfo, Durability, PreferencesStore, SaveOptions};
+    ///
+    /// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+    ///
+    /// # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
+    /// let store = PreferencesStore::new(&APP_INFO)
+    ///     .unwrap()
+    ///     .with_options(SaveOptions::new().durability(Durability::None));
+    /// store.save("tests/docs/sync_all", &[800, 600]).unwrap();
+    /// // On shutdown:
+    /// store.sync_all().unwrap();
+    /// ```
+    pub fn sync_all(&self) -> Result<(), PreferencesError> {
+        self.flush()?;
+        self.pending.sync_all()
+    }
+
+    /// Returns the app whose user data this store accesses.
+    pub fn app(&self) -> &AppInfo {
+        self.app.info()
+    }
+
+    /// Returns the identity of the app whose user data this store accesses.
+    pub fn app_id(&self) -> &AppId {
+        &self.app
+    }
+
+    /// Returns the app's data directory, or the profile's directory if a profile is selected.
+    /// This makes no guarantees that the directory exists.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns the options used by `save` and `try_save`.
+    pub fn options(&self) -> &SaveOptions {
+        &self.options
+    }
+
+    /// Returns the path of the file in which user data with the given `key` is stored in the
+    /// given `format`.
+    ///
+    /// # Failures
+    /// If strict key validation is enabled and `key` is invalid.
+    pub fn file_path<S>(&self, key: S, format: Format) -> Result<PathBuf, PreferencesError>
+        where S: AsRef<str>
+    {
+        self.file_path_with_extension(key.as_ref(), format.extension())
+    }
+
+    /// Same as `file_path`, but for a file with the given `extension` rather than one of the
+    /// formats, named with this store's suffix and layout.
+    pub(crate) fn file_path_with_extension(&self,
+                                           key: &str,
+                                           extension: &str)
+                                           -> Result<PathBuf, PreferencesError> {
+        file_path_named(&self.root, key, extension, &self.file_names)
+    }
+
+    /// Loads the value saved with the typed `key`, in the format of this store's options. (See
+    /// [`PrefKey`](struct.PrefKey.html).)
+    ///
+    /// # Failures
+    /// Same as `load`.
+    pub fn get<T>(&self, key: &PrefKey<T>) -> Result<T, PreferencesError>
+        where T: DeserializeOwned + 'static
+    {
+        let value = self.load(key.key())?;
+        self.validate(key, value)
+    }
+
+    /// Saves `value` with the typed `key`, using this store's options. (See
+    /// [`PrefKey`](struct.PrefKey.html).)
+    ///
+    /// # Failures
+    /// Same as `save`.
+    pub fn set<T: Serialize>(&self, key: &PrefKey<T>, value: &T) -> Result<(), PreferencesError> {
+        self.save(key.key(), value)
+    }
+
+    /// Registers `value` as the default of the typed `key`, which `get_or_default` returns while
+    /// nothing is saved with the key, replacing any default registered before. Clones of this
+    /// store, including ones for other profiles, share the defaults.
+    ///
+    /// # Example
+    /// ```
+    /// use preferences::{AppInfo, PrefKey, PreferencesStore};
+    ///
+    /// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+    /// const VOLUME: PrefKey<u8> = PrefKey::new("tests/docs/defaults/volume");
+    ///
+    /// # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
+    /// let store = PreferencesStore::new(&APP_INFO).unwrap();
+    /// store.register_default(&VOLUME, 5).unwrap();
+    /// assert_eq!(store.get_or_default(&VOLUME).unwrap(), 5);
+    ///
+    /// store.set(&VOLUME, &8).unwrap();
+    /// assert_eq!(store.get_or_default(&VOLUME).unwrap(), 8);
+    /// store.reset_all().unwrap();
+    /// assert_eq!(store.get_or_default(&VOLUME).unwrap(), 5);
+    /// ```
+    ///
+    /// # Failures
+    /// If `value` can't be serialized.
+    pub fn register_default<T: Serialize>(&self,
+                                          key: &PrefKey<T>,
+                                          value: T)
+                                          -> Result<(), PreferencesError> {
+        let value = serde_json::to_value(value)?;
+        let mut defaults = self.defaults.write().unwrap_or_else(|e| e.into_inner());
+        defaults.insert(key.key().to_owned(), value);
+        Ok(())
+    }
+
+    /// Loads the value saved with the typed `key`, or returns the default registered with
+    /// `register_default` if nothing was saved.
+    ///
+    /// # Failures
+    /// Same as `get`, including `PreferencesError::NotFound` if nothing was saved and no default
+    /// is registered.
+    pub fn get_or_default<T>(&self, key: &PrefKey<T>) -> Result<T, PreferencesError>
+        where T: DeserializeOwned + 'static
+    {
+        match self.get(key) {
+            Err(error) if error.is_not_found() => {
+                self.registered_default(key).unwrap_or(Err(error))
+            }
+            result => result,
+        }
+    }
+
+    /// Returns the default registered for `key`, if any.
+    fn registered_default<T>(&self, key: &PrefKey<T>) -> Option<Result<T, PreferencesError>>
+        where T: DeserializeOwned
+    {
+        let defaults = self.defaults.read().unwrap_or_else(|e| e.into_inner());
+        defaults.get(key.key())
+            .map(|default| serde_json::from_value(default.clone()).map_err(Into::into))
+    }
+
+    /// Registers `check` as the validator of the typed `key`, which `get` and `get_or_default`
+    /// run on each loaded value, e.g. to reject values that were edited by hand. Invalid values
+    /// are reported as `PreferencesError::Validation`. This replaces any validator registered
+    /// before for the key, and like defaults, validators are shared by clones of this store.
+    ///
+    /// # Example
+    /// ```
+    /// use preferences::{AppInfo, PrefKey, PreferencesError, PreferencesStore};
+    ///
+    /// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+    /// const VOLUME: PrefKey<f32> = PrefKey::new("tests/docs/validator/volume");
+    ///
+    /// # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
+    /// let store = PreferencesStore::new(&APP_INFO).unwrap();
+    /// store.validator(&VOLUME, |volume| (0.0..=1.0).contains(volume));
+    /// store.set(&VOLUME, &11.0).unwrap();
+    /// match store.get(&VOLUME) {
+    ///     Err(PreferencesError::Validation { .. }) => {}
+    ///     other => panic!("expected the volume to be invalid, got {:?}", other),
+    /// }
+    /// ```
+    pub fn validator<T, F>(&self, key: &PrefKey<T>, check: F)
+        where T: 'static,
+              F: Fn(&T) -> bool + Send + Sync + 'static
+    {
+        self.validator_with_policy(key, ValidationPolicy::Fail, check)
+    }
+
+    /// Same as `validator`, but invalid values are handled according to `policy`.
+    pub fn validator_with_policy<T, F>(&self, key: &PrefKey<T>, policy: ValidationPolicy, check: F)
+        where T: 'static,
+              F: Fn(&T) -> bool + Send + Sync + 'static
+    {
+        self.set_validator(key, Arc::new(move |value| {
+            if check(&value) { Ok(value) } else { Err(policy) }
+        }))
+    }
+
+    /// Registers a validator for the typed `key` that replaces loaded values below `min` with
+    /// `min`, and values above `max` with `max`. Values that can't be compared with them (like
+    /// `NaN`) are invalid, and handled according to `policy`.
+    pub fn clamp<T>(&self, key: &PrefKey<T>, min: T, max: T, policy: ValidationPolicy)
+        where T: PartialOrd + Clone + Send + Sync + 'static
+    {
+        self.set_validator(key, Arc::new(move |value: T| {
+            if value < min {
+                Ok(min.clone())
+            } else if value > max {
+                Ok(max.clone())
+            } else if value >= min && value <= max {
+                Ok(value)
+            } else {
+                Err(policy)
+            }
+        }))
+    }
+
+    fn set_validator<T: 'static>(&self, key: &PrefKey<T>, validator: Validator<T>) {
+        let mut validators = self.validators.write().unwrap_or_else(|e| e.into_inner());
+        validators.insert(key.key().to_owned(), Box::new(validator));
+    }
+
+    /// Runs the validator of `key`, if any, on the loaded `value`. A validator registered for the
+    /// same key with another type is ignored.
+    fn validate<T>(&self, key: &PrefKey<T>, value: T) -> Result<T, PreferencesError>
+        where T: DeserializeOwned + 'static
+    {
+        let validator = self.validators
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(key.key())
+            .and_then(|validator| validator.downcast_ref::<Validator<T>>())
+            .cloned();
+        let policy = match validator {
+            Some(validator) => {
+                match validator(value) {
+                    Ok(value) => return Ok(value),
+                    Err(policy) => policy,
+                }
+            }
+            None => return Ok(value),
+        };
+        if policy == ValidationPolicy::UseDefault {
+            if let Some(default) = self.registered_default(key) {
+                return default;
+            }
+        }
+        Err(PreferencesError::Validation {
+            key: key.key().to_owned(),
+            path: self.file_path(key.key(), self.options.get_format())?,
+        })
+    }
+
+    /// Deletes the value saved with the given `key`, so that its default applies again.
+    /// Resetting a key with nothing saved is not an error.
+    ///
+    /// # Failures
+    /// Same as `delete`, except if nothing was saved.
+    pub fn reset<S: AsRef<str>>(&self, key: S) -> Result<(), PreferencesError> {
+        match self.delete(key) {
+            Err(ref e) if e.is_not_found() => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Resets all keys that have a default registered with `register_default`. Other data is
+    /// kept.
+    ///
+    /// # Failures
+    /// Same as `reset`. If a key can't be reset, the keys after it aren't reset either.
+    pub fn reset_all(&self) -> Result<(), PreferencesError> {
+        let keys: Vec<String> = self.defaults
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .keys()
+            .cloned()
+            .collect();
+        keys.iter().try_for_each(|key| self.reset(key))
+    }
+
+    /// Registers `old` as the previous name of the key `new`, e.g. after reorganizing the keys
+    /// in a new release. When `load` or `try_load` finds nothing saved with `new`, it loads the
+    /// value saved with `old` instead, and moves it to `new`, so that later loads and saves use
+    /// the new key. Like defaults, aliases are shared by clones of this store.
+    ///
+    /// The value is only moved by a store that changes its data (see `with_write_mode`); other
+    /// stores just load it from `old`.
+    ///
+    /// # Example
+    /// ```
+    /// use preferences::{AppInfo, PreferencesStore};
+    ///
+    /// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+    ///
+    /// # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
+    /// let store = PreferencesStore::new(&APP_INFO).unwrap();
+    /// // Saved by an earlier release:
+    /// store.save("tests/docs/alias/old", &"high").unwrap();
+    ///
+    /// store.alias("tests/docs/alias/old", "tests/docs/alias/new");
+    /// assert_eq!(store.load::<String, _>("tests/docs/alias/new").unwrap(), "high");
+    /// assert!(!store.exists("tests/docs/alias/old").unwrap());
+    /// ```
+    pub fn alias<S, N>(&self, old: S, new: N)
+        where S: AsRef<str>,
+              N: AsRef<str>
+    {
+        self.alias_with_policy(old, new, AliasPolicy::Move)
+    }
+
+    /// Same as `alias`, but the value saved with `old` is handled according to `policy` when it's
+    /// loaded.
+    pub fn alias_with_policy<S, N>(&self, old: S, new: N, policy: AliasPolicy)
+        where S: AsRef<str>,
+              N: AsRef<str>
+    {
+        let mut aliases = self.aliases.write().unwrap_or_else(|e| e.into_inner());
+        aliases.insert(new.as_ref().to_owned(), (old.as_ref().to_owned(), policy));
+    }
+
+    /// Attaches `descriptor` to `key`, replacing any descriptor attached before, so that a
+    /// settings screen can be generated from the descriptors. (See
+    /// [`PrefDescriptor`](struct.PrefDescriptor.html).) Clones of this store, including ones for
+    /// other profiles, share the descriptors.
+    pub fn describe<S: AsRef<str>>(&self, key: S, descriptor: PrefDescriptor) {
+        let mut descriptors = self.descriptors.write().unwrap_or_else(|e| e.into_inner());
+        descriptors.insert(key.as_ref().to_owned(), descriptor);
+    }
+
+    /// Returns the descriptor attached to `key` with `describe`, if any.
+    pub fn descriptor<S: AsRef<str>>(&self, key: S) -> Option<PrefDescriptor> {
+        let descriptors = self.descriptors.read().unwrap_or_else(|e| e.into_inner());
+        descriptors.get(key.as_ref()).cloned()
+    }
+
+    /// Returns all descriptors attached with `describe`, with their keys, in alphabetical order
+    /// of the keys.
+    pub fn descriptors(&self) -> Vec<(String, PrefDescriptor)> {
+        let descriptors = self.descriptors.read().unwrap_or_else(|e| e.into_inner());
+        descriptors.iter().map(|(key, descriptor)| (key.clone(), descriptor.clone())).collect()
+    }
+
+    /// Maps the data saved with the given `key` into memory, in the format of this store's
+    /// options, so that it can be deserialized into a value that borrows from it. (See
+    /// [`MappedPrefs`](struct.MappedPrefs.html).)
+    ///
+    /// Requires the `mmap` feature.
+    ///
+    /// # Failures
+    /// If nothing was saved with `key`, or the data can't be read.
+    #[cfg(feature = "mmap")]
+    pub fn map_prefs<S: AsRef<str>>(&self, key: S) -> Result<MappedPrefs, PreferencesError> {
+        let key = key.as_ref();
+        let format = self.options.get_format();
+        let path = self.file_path(key, format)?;
+        mmap::map(self, &path, format).map_err(|e| e.with_context(Operation::Load, key, &path))
+    }
+
+    /// Returns the path of the file in which `save_schema` saves the schema of the data saved
+    /// with `key`. This makes no guarantees that the file exists.
+    ///
+    /// Requires the `schema` feature.
+    ///
+    /// # Failures
+    /// If the key is invalid.
+    #[cfg(feature = "schema")]
+    pub fn schema_path<S: AsRef<str>>(&self, key: S) -> Result<PathBuf, PreferencesError> {
+        schema::path(self, key.as_ref())
+    }
+
+    /// Same as [`save_schema`](fn.save_schema.html).
+    #[cfg(feature = "schema")]
+    pub fn save_schema<T, S>(&self, key: S) -> Result<(), PreferencesError>
+        where T: JsonSchema,
+              S: AsRef<str>
+    {
+        let key = key.as_ref();
+        let path = schema::path(self, key)?;
+        schema::save::<T>(self, &path).map_err(|e| e.with_context(Operation::Save, key, &path))
+    }
+
+    /// Same as [`export_all`](fn.export_all.html). The document is indented for readability.
+    pub fn export_all<W: Write>(&self, writer: W) -> Result<(), PreferencesError> {
+        serde_json::to_writer_pretty(writer, &bundle::export(self)?).map_err(Into::into)
+    }
+
+    /// Same as [`import_all`](fn.import_all.html), but in the format of this store's options.
+    pub fn import_all<R: Read>(&self, reader: R) -> Result<(), PreferencesError> {
+        bundle::import(self, reader)
+    }
+
+    /// Same as [`migrate_from`](fn.migrate_from.html), but with the given options, e.g. to
+    /// remove the legacy file once it's imported.
+    pub fn migrate_from<S: AsRef<str>>(&self,
+                                       location: &LegacyLocation,
+                                       key: S,
+                                       options: &MigrateOptions)
+                                       -> Result<MigrationReport, PreferencesError> {
+        migrate::migrate(self, location, key.as_ref(), options)
+    }
+
+    pub(crate) fn memory(&self) -> Option<&MemoryBackend> {
+        self.memory.as_ref()
+    }
+
+    pub(crate) fn pending_writes(&self) -> &PendingWrites {
+        &self.pending
+    }
+}
+
+/// Whether a [`PreferencesStore`](struct.PreferencesStore.html) changes its data, set with
+/// [`with_write_mode`](struct.PreferencesStore.html#method.with_write_mode).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum WriteMode {
+    /// Saves and deletes change the data as usual.
+    #[default]
+    Write,
+    /// Saves, deletes and other changes succeed without changing anything. Values are still
+    /// serialized, so that values that can't be saved are reported.
+    DryRun,
+    /// Saves, deletes and other changes fail with `PreferencesError::ReadOnly`.
+    ReadOnly,
+}
+
+/// Whether a [`PreferencesStore`](struct.PreferencesStore.html) loads data saved in other
+/// formats than the one of its options, set with
+/// [`with_format_detection`](struct.PreferencesStore.html#method.with_format_detection).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum FormatDetection {
+    /// Data is only loaded in the store's format.
+    #[default]
+    Off,
+    /// Data is loaded in the format it was saved in, as determined by the extension of its file
+    /// (see [`detect_format`](struct.PreferencesStore.html#method.detect_format)). It's still
+    /// saved in the store's format, so afterwards the newer file is loaded.
+    Load,
+    /// Same as `Load`, and saving data also deletes it in the other formats, so that it's
+    /// converted to the store's format the next time it's saved.
+    Convert,
+}
+
+/// How [`PreferencesStore::get`](struct.PreferencesStore.html#method.get) handles a value that
+/// its validator rejects. (See
+/// [`validator_with_policy`](struct.PreferencesStore.html#method.validator_with_policy).)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ValidationPolicy {
+    /// Fails with `PreferencesError::Validation`.
+    #[default]
+    Fail,
+    /// Returns the default registered with
+    /// [`register_default`](struct.PreferencesStore.html#method.register_default) instead, or
+    /// fails like `Fail` if there is none. The saved value is kept until it's replaced.
+    UseDefault,
+}
+
+/// What happens to the value saved with the old key of an alias when it's loaded with the new
+/// key. (See [`PreferencesStore::alias`](struct.PreferencesStore.html#method.alias).)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum AliasPolicy {
+    /// The value is moved to the new key, and deleted with the old key.
+    #[default]
+    Move,
+    /// The value is copied to the new key, and kept with the old key, e.g. so that earlier
+    /// releases of the app still find it.
+    Copy,
+}
+
+/// Name of the subdirectory of the app's data directory that holds the profiles. Keys can't
+/// refer to it, since their file names never start with a period.
+static PROFILES_DIR_NAME: &str = ".profiles";
+
+/// A version selected with
+/// [`PreferencesStore::with_version`](struct.PreferencesStore.html#method.with_version).
+#[derive(Clone, Debug)]
+struct VersionScope {
+    name: String,
+    /// The name of the version's directory.
+    dir: String,
+    /// The names of the directories of the versions whose data is inherited, in order, or `None`
+    /// for the data saved without a version.
+    previous: Vec<Option<String>>,
+}
+
+/// Returns the directory of `profile` within the app's data directory `app_dir`.
+fn profile_dir(app_dir: &Path, profile: &str) -> Result<PathBuf, PreferencesError> {
+    if profile.is_empty() {
+        return Err(KeyError::Empty.into());
+    }
+    Ok(app_dir.join(PROFILES_DIR_NAME).join(keys::segment_file_name(profile)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{APP_INFO, TestBackend, TestDir};
+    use crate::{AliasPolicy, AppInfo, Durability, Format, KeyError, MemoryBackend,
+                Preferences, PrefDescriptor, PreferencesError, PrefKey, PreferencesStore,
+                SaveOptions, Scope, ValidationPolicy, WriteMode, list_prefs};
+    use std::collections::BTreeMap;
+    use std::fs;
+    use std::time::SystemTime;
+
+    #[test]
+    fn test_store_rejects_traversal() {
+        let dir = TestDir::new("traversal");
+        for store in dir.stores() {
+            let store = store.with_base_dir(dir.path().join("app"));
+            for key in &["../escaped", "a/../../escaped", r"..\escaped", "/tmp/escaped", "C:/x"] {
+                match *store.save(key, &1u32).unwrap_err().inner() {
+                    PreferencesError::InvalidKey(KeyError::Traversal(_)) => {}
+                    ref e => panic!("expected the key to be rejected, got {:?}", e),
+                }
+                assert!(store.load::<u32, _>(key).is_err());
+                assert!(store.list(key).is_err());
+            }
+            assert_eq!(store.list("").unwrap(), Vec::<String>::new());
+        }
+        // Nothing was written next to the app's directory.
+        assert!(fs::read_dir(dir.path()).map_or(true, |mut entries| {
+            entries.all(|entry| entry.unwrap().file_name() == "app")
+        }));
+    }
+
+    #[test]
+    fn test_store_matches_free_functions() {
+        let _backend = TestBackend::install();
+        let store = PreferencesStore::new(&APP_INFO).unwrap();
+        let key = "tests/module/store/value";
+        store.save(key, &42u32).unwrap();
+        let dir = Scope::User.app_dir(&APP_INFO).unwrap().join("tests/module/store");
+        assert_eq!(store.file_path(key, Default::default()).unwrap(),
+                   dir.join("value.prefs.json"));
+        assert!(store.exists(key).unwrap());
+        assert_eq!(u32::load(&APP_INFO, key).unwrap(), 42);
+        assert_eq!(list_prefs(&APP_INFO, "tests/module/store").unwrap(),
+                   vec!["tests/module/store/value"]);
+        store.delete(key).unwrap();
+        assert!(!store.exists(key).unwrap());
+
+        let pretty = store.clone().with_options(SaveOptions::new().pretty(true));
+        pretty.save(key, &42u32).unwrap();
+        assert_eq!(store.load::<u32, _>(key).unwrap(), 42);
+        assert_eq!(store.detect_format(key).unwrap(), Some(Default::default()));
+        assert_eq!(store.load_any_format::<u32, _>(key).unwrap(), 42);
+        store.delete(key).unwrap();
+        assert_eq!(store.detect_format(key).unwrap(), None);
+    }
+
+    #[test]
+    fn test_store_with_base_dir() {
+        let dir = TestDir::new("base-dir");
+        let store = dir.store();
+        assert_eq!(store.root(), dir.path().join("preferences"));
+        store.save("options/value", &42u32).unwrap();
+        assert!(dir.path().join("preferences/options/value.prefs.json").is_file());
+        assert_eq!(store.load::<u32, _>("options/value").unwrap(), 42);
+    }
+
+    #[test]
+    fn test_store_durability() {
+        let dir = TestDir::new("durability");
+        let store = dir.store();
+        let all = [Durability::None, Durability::Flush, Durability::Fsync, Durability::FsyncDir];
+        for (i, &durability) in all.iter().enumerate() {
+            let options = SaveOptions::new().durability(durability);
+            store.save_with_options("options/value", &i, &options).unwrap();
+            assert_eq!(store.load::<usize, _>("options/value").unwrap(), i);
+
+            let mut transaction = store.transaction();
+            transaction.save_with_options("options/other", &i, &options).unwrap();
+            transaction.commit().unwrap();
+            assert_eq!(store.load::<usize, _>("options/other").unwrap(), i);
+        }
+    }
+
+    #[test]
+    fn test_store_metadata() {
+        let store = PreferencesStore::new(&APP_INFO)
+            .unwrap()
+            .with_memory_backend(MemoryBackend::new());
+        assert!(store.metadata("value").unwrap_err().is_not_found());
+        let before = SystemTime::now();
+        store.save("value", &1234u32).unwrap();
+        let metadata = store.metadata("value").unwrap();
+        assert_eq!(metadata.size, 4);
+        assert_eq!(metadata.format, Format::default());
+        assert!(metadata.modified >= before);
+    }
+
+    #[test]
+    fn test_store_lenient() {
+        let store = PreferencesStore::new(&APP_INFO)
+            .unwrap()
+            .with_memory_backend(MemoryBackend::new());
+        let mut old = BTreeMap::new();
+        old.insert("theme", "dark");
+        old.insert("plugin", "enabled");
+        store.save("options", &old).unwrap();
+
+        let mut new: BTreeMap<String, String> = store.load_lenient("options").unwrap();
+        new.remove("plugin");
+        new.insert("font".into(), "Sans".into());
+        store.save_merged("options", &new).unwrap();
+        let saved: BTreeMap<String, String> = store.load("options").unwrap();
+        assert_eq!(saved.len(), 3);
+        assert_eq!(saved["plugin"], "enabled");
+    }
+
+    #[test]
+    fn test_store_profiles() {
+        let dir = TestDir::new("profiles");
+        for store in dir.stores() {
+            let work = store.clone().with_profile("work").unwrap();
+            let home = store.clone().with_profile("home/äö").unwrap();
+            assert_eq!(home.profile(), Some("home/äö"));
+            store.save("account/name", &"default").unwrap();
+            work.save("account/name", &"work").unwrap();
+            home.save("account/name", &"home").unwrap();
+            assert_eq!(work.load::<String, _>("account/name").unwrap(), "work");
+            assert_eq!(store.load::<String, _>("account/name").unwrap(), "default");
+            assert_eq!(store.list("").unwrap(), ["account/name"]);
+            assert_eq!(work.list("").unwrap(), ["account/name"]);
+            assert_eq!(work.list_profiles().unwrap(), ["home/äö", "work"]);
+
+            store.delete_profile("home/äö").unwrap();
+            assert_eq!(store.list_profiles().unwrap(), ["work"]);
+            assert!(!home.exists("account/name").unwrap());
+            assert!(store.clone().with_profile("").is_err());
+            store.delete_all("").unwrap();
+        }
+    }
+
+    #[test]
+    fn test_store_versions() {
+        let dir = TestDir::new("versions");
+        for store in dir.stores() {
+            store.save("theme", &"light").unwrap();
+            store.save("volume", &5u8).unwrap();
+            let v1 = store.clone().with_version("v1", &[""]).unwrap();
+            v1.save("volume", &7u8).unwrap();
+            let v2 = store.clone().with_version("v2", &["v1", ""]).unwrap();
+            assert_eq!(v2.version(), Some("v2"));
+            assert_eq!(v2.load::<u8, _>("volume").unwrap(), 7);
+            assert_eq!(v2.load::<String, _>("theme").unwrap(), "light");
+            assert!(v2.list("").unwrap().is_empty());
+
+            // Saving copies the value into the new version only.
+            v2.save("volume", &9u8).unwrap();
+            assert_eq!(v2.list("").unwrap(), ["volume"]);
+            assert_eq!(v1.load::<u8, _>("volume").unwrap(), 7);
+            assert_eq!(store.list("").unwrap(), ["theme", "v1/volume", "v2/volume", "volume"]);
+
+            // Versions are kept separately for each profile.
+            let work = store.clone().with_version("v2", &["v1"]).unwrap().with_profile("work");
+            assert!(work.unwrap().load::<u8, _>("volume").unwrap_err().is_not_found());
+            assert!(store.clone().with_version("", &[]).is_err());
+            assert!(store.clone().with_version("v3", &[".."]).is_err());
+            store.delete_all("").unwrap();
+        }
+    }
+
+    #[test]
+    fn test_store_file_names() {
+        let dir = TestDir::new("file-names");
+        for store in dir.stores() {
+            let store = store.with_file_suffix("")
+                .unwrap()
+                .with_default_file_name("settings")
+                .unwrap();
+            store.save("", &1u32).unwrap();
+            store.save("options/window", &2u32).unwrap();
+            let root = store.root().to_owned();
+            assert_eq!(store.file_path("", Format::Json).unwrap(), root.join("settings.json"));
+            assert_eq!(store.file_path("options/window", Format::Json).unwrap(),
+                       root.join("options").join("window.json"));
+            assert_eq!(store.load::<u32, _>("").unwrap(), 1);
+            assert_eq!(store.list("options").unwrap(), ["options/window"]);
+            assert_eq!(store.list("").unwrap(), ["options/window", "settings"]);
+            if !store.is_in_memory() {
+                assert!(root.join("options").join("window.json").is_file());
+            }
+            assert!(store.clone().with_file_suffix("/x").is_err());
+            assert!(store.clone().with_default_file_name("..").is_err());
+            store.delete_all("").unwrap();
+        }
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn test_store_format_detection() {
+        use crate::FormatDetection;
+
+        let dir = TestDir::new("detection");
+        for store in dir.stores() {
+            let mut map = BTreeMap::new();
+            map.insert("volume".to_owned(), 5);
+            store.save("audio", &map).unwrap();
+            let toml = store.with_options(SaveOptions::new().format(Format::Toml));
+            assert!(toml.load::<BTreeMap<String, u32>, _>("audio").is_err());
+
+            let loading = toml.clone().with_format_detection(FormatDetection::Load);
+            assert_eq!(loading.format_detection(), FormatDetection::Load);
+            assert_eq!(loading.load::<BTreeMap<String, u32>, _>("audio").unwrap(), map);
+            map.insert("volume".to_owned(), 6);
+            loading.save("audio", &map).unwrap();
+            assert!(loading.load_with_format::<BTreeMap<String, u32>, _>("audio", Format::Json)
+                .is_ok());
+
+            // Converting removes the data in other formats.
+            let converting = toml.with_format_detection(FormatDetection::Convert);
+            map.insert("volume".to_owned(), 7);
+            converting.save("audio", &map).unwrap();
+            assert_eq!(converting.load::<BTreeMap<String, u32>, _>("audio").unwrap(), map);
+            assert!(converting.load_with_format::<BTreeMap<String, u32>, _>("audio", Format::Json)
+                .unwrap_err()
+                .is_not_found());
+            assert_eq!(converting.detect_format("audio").unwrap(), Some(Format::Toml));
+            converting.delete_all("").unwrap();
+        }
+    }
+
+    #[test]
+    fn test_store_write_mode() {
+        let dir = TestDir::new("write-mode");
+        for store in dir.stores() {
+            store.save("kept", &1u32).unwrap();
+            let version = store.load_versioned::<u32, _>("kept").unwrap().1;
+
+            let dry_run = store.clone().with_write_mode(WriteMode::DryRun);
+            dry_run.save("kept", &2u32).unwrap();
+            dry_run.save("new", &2u32).unwrap();
+            dry_run.update("kept", |value: &mut u32| *value = 3).unwrap();
+            dry_run.compare_and_save("kept", version, &4u32).unwrap();
+            dry_run.delete("kept").unwrap();
+            dry_run.delete_all("").unwrap();
+            assert_eq!(dry_run.load::<u32, _>("kept").unwrap(), 1);
+            assert_eq!(store.list("").unwrap(), ["kept"]);
+
+            let read_only = store.clone().with_write_mode(WriteMode::ReadOnly);
+            assert_eq!(read_only.write_mode(), WriteMode::ReadOnly);
+            for result in [read_only.save("kept", &2u32), read_only.delete("kept")] {
+                match result.as_ref().map_err(PreferencesError::inner) {
+                    Err(&PreferencesError::ReadOnly) => {}
+                    other => panic!("expected the store to be read-only, got {:?}", other),
+                }
+            }
+            assert_eq!(read_only.load::<u32, _>("kept").unwrap(), 1);
+            store.delete_all("").unwrap();
+        }
+    }
+
+    #[test]
+    fn test_store_defaults() {
+        const THEME: PrefKey<String> = PrefKey::new("ui/theme");
+        const SIZE: PrefKey<u32> = PrefKey::new("ui/size");
+        const NAME: PrefKey<String> = PrefKey::new("account/name");
+
+        let store = PreferencesStore::new(&APP_INFO)
+            .unwrap()
+            .with_memory_backend(MemoryBackend::new());
+        store.register_default(&THEME, "dark".to_owned()).unwrap();
+        store.register_default(&SIZE, 12).unwrap();
+        assert_eq!(store.get_or_default(&THEME).unwrap(), "dark");
+        assert!(store.get(&THEME).unwrap_err().is_not_found());
+        assert!(store.get_or_default(&NAME).unwrap_err().is_not_found());
+
+        store.set(&THEME, &"light".to_owned()).unwrap();
+        store.set(&SIZE, &14).unwrap();
+        store.set(&NAME, &"user".to_owned()).unwrap();
+        assert_eq!(store.get_or_default(&THEME).unwrap(), "light");
+        store.reset(THEME).unwrap();
+        store.reset(THEME).unwrap();
+        assert_eq!(store.get_or_default(&THEME).unwrap(), "dark");
+        assert_eq!(store.get_or_default(&SIZE).unwrap(), 14);
+
+        // Clones share the defaults, and only keys with defaults are reset.
+        let clone = store.clone();
+        clone.register_default(&SIZE, 10).unwrap();
+        store.reset_all().unwrap();
+        assert_eq!(store.get_or_default(&SIZE).unwrap(), 10);
+        assert_eq!(store.get(&NAME).unwrap(), "user");
+    }
+
+    #[test]
+    fn test_store_validators() {
+        const VOLUME: PrefKey<f64> = PrefKey::new("audio/volume");
+        const NAME: PrefKey<String> = PrefKey::new("account/name");
+
+        let store = PreferencesStore::new(&APP_INFO)
+            .unwrap()
+            .with_memory_backend(MemoryBackend::new());
+        store.validator(&NAME, |name| !name.is_empty());
+        store.set(&NAME, &String::new()).unwrap();
+        match store.get(&NAME) {
+            Err(PreferencesError::Validation { ref key, .. }) => assert_eq!(key, "account/name"),
+            other => panic!("expected the name to be invalid, got {:?}", other),
+        }
+        store.register_default(&NAME, "user".to_owned()).unwrap();
+        assert!(store.get_or_default(&NAME).is_err());
+        store.validator_with_policy(&NAME, ValidationPolicy::UseDefault, |name| !name.is_empty());
+        assert_eq!(store.get(&NAME).unwrap(), "user");
+        // A validator registered for the key with another type doesn't apply.
+        assert_eq!(store.get(&PrefKey::<serde_json::Value>::new("account/name")).unwrap(), "");
+
+        store.clamp(&VOLUME, 0.0, 1.0, ValidationPolicy::Fail);
+        for &(saved, loaded) in &[(1.5, 1.0), (-1.0, 0.0), (0.5, 0.5)] {
+            store.set(&VOLUME, &saved).unwrap();
+            assert_eq!(store.get(&VOLUME).unwrap(), loaded);
+        }
+        store.save("audio/volume", &"loud").unwrap();
+        assert!(store.get(&VOLUME).is_err());
+    }
+
+    #[test]
+    fn test_store_descriptors() {
+        const THEME: PrefKey<String> = PrefKey::new("ui/theme");
+        let store = PreferencesStore::new(&APP_INFO)
+            .unwrap()
+            .with_memory_backend(MemoryBackend::new());
+        assert_eq!(store.descriptor(THEME), None);
+        let theme = PrefDescriptor::new().label("Theme").choices(&["light", "dark"]);
+        store.describe(THEME, theme.clone());
+        store.describe("audio/volume", PrefDescriptor::new().label("Volume").min(0.0).max(1.0));
+        assert_eq!(store.descriptor(THEME), Some(theme.clone()));
+
+        // Clones share the descriptors.
+        let clone = store.clone();
+        let descriptors = clone.descriptors();
+        assert_eq!(descriptors.len(), 2);
+        assert_eq!(descriptors[0].0, "audio/volume");
+        assert_eq!(descriptors[0].1.get_max(), Some(1.0));
+        assert_eq!(descriptors[1], ("ui/theme".to_owned(), theme));
+    }
+
+    #[test]
+    fn test_store_aliases() {
+        let dir = TestDir::new("aliases");
+        for store in dir.stores() {
+            store.save("old/graphics", &1u32).unwrap();
+            store.save("old/audio", &2u32).unwrap();
+            store.alias("old/graphics", "options/graphics");
+            store.alias_with_policy("old/audio", "options/audio", AliasPolicy::Copy);
+            assert!(store.load::<u32, _>("options/other").unwrap_err().is_not_found());
+
+            // A dry run loads the old value without moving it.
+            let dry_run = store.clone().with_write_mode(WriteMode::DryRun);
+            assert_eq!(dry_run.load::<u32, _>("options/graphics").unwrap(), 1);
+            assert_eq!(store.list("").unwrap(), ["old/audio", "old/graphics"]);
+
+            assert_eq!(store.load::<u32, _>("options/graphics").unwrap(), 1);
+            assert_eq!(store.try_load::<u32, _>("options/audio").unwrap(), 2);
+            assert_eq!(store.list("").unwrap(), ["old/audio", "options/audio", "options/graphics"]);
+
+            // Once the new key has a value, the old one is ignored.
+            store.save("old/audio", &3u32).unwrap();
+            assert_eq!(store.load::<u32, _>("options/audio").unwrap(), 2);
+            store.delete_all("").unwrap();
+            assert!(store.load::<u32, _>("options/audio").unwrap_err().is_not_found());
+        }
+    }
+
+    #[test]
+    fn test_store_invalid_app() {
+        let app = AppInfo {
+            name: "",
+            author: "Rust language community",
+        };
+        match PreferencesStore::new(&app) {
+            Err(PreferencesError::Directory(_)) => {}
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+    }
+}
@@ -0,0 +1,68 @@
+//! The memory backend side of a store's read and write pipelines, for stores that keep their data
+//! in a [`MemoryBackend`](struct.MemoryBackend.html) instead of in files.
+
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use crate::memory::MemoryBackend;
+use crate::{Format, PreferencesError, PrefsMetadata, PrefsVersion, SaveOptions};
+
+/// Saves `bytes` at `path`, keeping the data saved there before as a backup if `options` ask for
+/// backups.
+pub(super) fn write(memory: &MemoryBackend,
+                    path: &Path,
+                    bytes: Vec<u8>,
+                    options: &SaveOptions)
+                    -> Result<(), PreferencesError> {
+    memory.rotate_backups(path, options.get_backups())?;
+    memory.write(path, bytes).map_err(Into::into)
+}
+
+/// Copies the data at `old_path` to `new_path`, unless something was saved there already.
+pub(super) fn copy(memory: &MemoryBackend,
+                   old_path: &Path,
+                   new_path: &Path)
+                   -> Result<(), PreferencesError> {
+    if memory.read(new_path).is_err() {
+        memory.write(new_path, memory.read(old_path)?)?;
+    }
+    Ok(())
+}
+
+/// Deletes the data at `path`, with its backups.
+pub(super) fn delete(memory: &MemoryBackend, path: &Path) -> Result<(), PreferencesError> {
+    memory.remove(path)?;
+    memory.remove_backups(path);
+    Ok(())
+}
+
+/// Returns the version of the data at `path`.
+pub(super) fn version(memory: &MemoryBackend, path: &Path) -> PrefsVersion {
+    memory.read(path).ok().map_or(PrefsVersion::MISSING, |bytes| PrefsVersion::of(&bytes))
+}
+
+/// Returns the first of `Format::all()` in which data is saved, at the path that `path_of`
+/// returns for the format.
+pub(super) fn detect_format<F>(memory: &MemoryBackend,
+                               path_of: F)
+                               -> Result<Option<Format>, PreferencesError>
+    where F: Fn(Format) -> Result<PathBuf, PreferencesError>
+{
+    for &format in Format::all() {
+        if memory.contains(&path_of(format)?) {
+            return Ok(Some(format));
+        }
+    }
+    Ok(None)
+}
+
+/// Returns the metadata of the data saved in `format` at `path`.
+pub(super) fn metadata(memory: &MemoryBackend,
+                       path: &Path,
+                       format: Format)
+                       -> Result<PrefsMetadata, PreferencesError> {
+    Ok(PrefsMetadata {
+        modified: memory.modified_time(path).unwrap_or(UNIX_EPOCH),
+        size: memory.read(path)?.len() as u64,
+        format,
+    })
+}
@@ -0,0 +1,479 @@
+//! The read pipeline of a store: how a load finds the saved data, and reads it from the sync
+//! provider, a file or a memory backend.
+
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{ErrorKind, Read};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::backup;
+use crate::bulk;
+use crate::checksum;
+use crate::compression;
+use crate::envelope::{self, Envelope, EnvelopeMeta};
+use crate::header::{self, FileHeader};
+use crate::lock::{FileLock, Wait};
+use crate::observe;
+use crate::sync;
+use crate::{Compression, Expiring, FileNames, Format, KeyLayout, Operation, PreferencesError,
+            PrefsMetadata, PrefsVersion, Preloaded, Recovered, check_key, collect_keys,
+            file_path_named, key_dir_path, keys, load_locked};
+use super::in_memory;
+use super::{FormatDetection, PreferencesStore, WriteMode};
+
+impl PreferencesStore {
+    /// Loads the value saved with the given `key`, in the format of this store's options.
+    ///
+    /// # Failures
+    /// Same as [`Preferences::load`](trait.Preferences.html#tymethod.load).
+    pub fn load<T, S>(&self, key: S) -> Result<T, PreferencesError>
+        where T: DeserializeOwned,
+              S: AsRef<str>
+    {
+        let format = self.load_format(key.as_ref())?;
+        self.load_with_format(key, format)
+    }
+
+    /// Same as `load`, but reads the value in the given `format`.
+    pub fn load_with_format<T, S>(&self, key: S, format: Format) -> Result<T, PreferencesError>
+        where T: DeserializeOwned,
+              S: AsRef<str>
+    {
+        let key = key.as_ref();
+        let path = self.file_path(key, format)?;
+        self.pull(key, &path)
+            .and_then(|_| self.read_inherited(key, &path, format, Wait::Block))
+            .map_err(|e| e.with_context(Operation::Load, key, &path))
+    }
+
+    /// Same as `load`, but fails with a `PreferencesError::WouldBlock` (see
+    /// [`inner`](enum.PreferencesError.html#method.inner)) instead of waiting if the data is
+    /// locked by another thread or process.
+    pub fn try_load<T, S>(&self, key: S) -> Result<T, PreferencesError>
+        where T: DeserializeOwned,
+              S: AsRef<str>
+    {
+        let key = key.as_ref();
+        let format = self.load_format(key)?;
+        let path = self.file_path(key, format)?;
+        self.pull(key, &path)
+            .and_then(|_| self.read_inherited(key, &path, format, Wait::Try))
+            .map_err(|e| e.with_context(Operation::Load, key, &path))
+    }
+
+    /// Same as `load`, but reads the value in whatever format it was saved in. (See
+    /// `detect_format`.)
+    ///
+    /// # Failures
+    /// Same as `load`. If the data wasn't saved in any format, the error is the same as when
+    /// loading missing data in this store's format.
+    pub fn load_any_format<T, S>(&self, key: S) -> Result<T, PreferencesError>
+        where T: DeserializeOwned,
+              S: AsRef<str>
+    {
+        let format = self.detect_format(key.as_ref())?.unwrap_or_else(|| self.options.get_format());
+        self.load_with_format(key, format)
+    }
+
+    /// Same as
+    /// [`Preferences::load_with_recovery`](trait.Preferences.html#tymethod.load_with_recovery),
+    /// but in the format of this store's options.
+    pub fn load_with_recovery<T, S>(&self, key: S) -> Result<Recovered<T>, PreferencesError>
+        where T: DeserializeOwned,
+              S: AsRef<str>
+    {
+        let format = self.options.get_format();
+        let path = self.file_path(key.as_ref(), format)?;
+        let result = match self.memory {
+            Some(ref memory) => backup::load_recovering_from(memory, &path, format),
+            None => backup::load_recovering(&path, format),
+        };
+        result.map_err(|e| e.with_context(Operation::Load, key.as_ref(), &path))
+    }
+
+    /// Same as [`load_previous`](fn.load_previous.html), but in the format of this store's
+    /// options.
+    pub fn load_previous<T, S>(&self, key: S, n: usize) -> Result<T, PreferencesError>
+        where T: DeserializeOwned,
+              S: AsRef<str>
+    {
+        let format = self.options.get_format();
+        let path = self.file_path(key, format)?;
+        match self.memory {
+            Some(_) if n > 0 => self.read(&backup::backup_path(&path, n), format, Wait::Block),
+            Some(_) => self.read(&path, format, Wait::Block),
+            None => backup::load_backup(&path, format, n),
+        }
+    }
+
+    /// Same as `load`, but returns the [`Envelope`](struct.Envelope.html) in which the value was
+    /// saved (see [`SaveOptions::envelope`](struct.SaveOptions.html#method.envelope)), with its
+    /// metadata. Only the saved data is loaded: defaults, other versions and layouts aren't
+    /// consulted, and the value isn't validated.
+    ///
+    /// A value that was saved without an envelope, e.g. before the app enabled them, is returned
+    /// in an envelope without versions, which was created and modified when the file was last
+    /// modified. It's saved in a real one the next time it's saved with envelopes enabled.
+    ///
+    /// # Example
+    /// ```
+    /// use preferences::{AppInfo, PreferencesStore, SaveOptions};
+    ///
+    /// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+    ///
+    /// let options = SaveOptions::new().envelope(true).schema_version(3);
+    /// let store = PreferencesStore::new(&APP_INFO).unwrap().with_options(options);
+    /// # let store = store.with_memory_backend(preferences::MemoryBackend::new());
+    /// store.save("tests/docs/envelope", &"dark").unwrap();
+    ///
+    /// assert_eq!(store.load::<String, _>("tests/docs/envelope").unwrap(), "dark");
+    /// let envelope = store.load_with_meta::<String, _>("tests/docs/envelope").unwrap();
+    /// assert_eq!(envelope.meta.version, Some(3));
+    /// assert_eq!(envelope.data, "dark");
+    /// ```
+    ///
+    /// # Failures
+    /// Same as `load`.
+    pub fn load_with_meta<T, S>(&self, key: S) -> Result<Envelope<T>, PreferencesError>
+        where T: DeserializeOwned,
+              S: AsRef<str>
+    {
+        let key = key.as_ref();
+        let format = self.load_format(key)?;
+        let path = self.file_path(key, format)?;
+        self.pull(key, &path)
+            .and_then(|_| self.read_bytes(&path))
+            .and_then(|bytes| envelope::open(format, checksum::verify(&bytes)?))
+            .map(|(data, meta)| {
+                let meta = meta.unwrap_or_else(|| {
+                    let modified = self.modified_time(&path).unwrap_or(UNIX_EPOCH);
+                    EnvelopeMeta {
+                        version: None,
+                        created: modified,
+                        modified,
+                        app_version: None,
+                    }
+                });
+                Envelope { meta, data }
+            })
+            .map_err(|e| e.with_context(Operation::Load, key, &path))
+    }
+
+    /// Same as [`load_versioned`](fn.load_versioned.html), but in the format of this store's
+    /// options.
+    pub fn load_versioned<T, S>(&self, key: S) -> Result<(T, PrefsVersion), PreferencesError>
+        where T: DeserializeOwned,
+              S: AsRef<str>
+    {
+        let key = key.as_ref();
+        let format = self.options.get_format();
+        let path = self.file_path(key, format)?;
+        self.pull(key, &path)
+            .and_then(|_| {
+                let (bytes, compression) = match self.memory {
+                    Some(ref memory) => (memory.read(&path)?, Compression::None),
+                    None => {
+                        let _lock = FileLock::acquire(&path, false, Wait::Block)?;
+                        let (actual, compression) = compression::find(&path);
+                        (fs::read(actual)?, compression)
+                    }
+                };
+                let mut decompressed = Vec::new();
+                compression.decompress(&bytes[..])?.read_to_end(&mut decompressed)?;
+                let value = checksum::deserialize(format, &decompressed)?;
+                Ok((value, PrefsVersion::of(&bytes)))
+            })
+            .map_err(|e| e.with_context(Operation::Load, key, &path))
+    }
+
+    /// Starts loading the values saved with each of the given `keys`, one after the other, on a
+    /// background thread, and returns handles that wait for each of them when it's needed, in
+    /// the order of `keys`. (See [`Preloaded`](struct.Preloaded.html).)
+    pub fn preload<T>(&self, keys: impl IntoIterator<Item = impl AsRef<str>>) -> Vec<Preloaded<T>>
+        where T: DeserializeOwned + Send + 'static
+    {
+        let keys = keys.into_iter().map(|key| key.as_ref().to_owned()).collect();
+        Preloaded::spawn(self.clone(), keys)
+    }
+
+    /// Same as [`load_many`](fn.load_many.html), but in the format of this store's options.
+    pub fn load_many<T, S>(&self, keys: &[S]) -> HashMap<String, Result<T, PreferencesError>>
+        where T: DeserializeOwned + Send,
+              S: AsRef<str> + Sync
+    {
+        bulk::map_all(keys, |key| (key.as_ref().to_owned(), self.load(key.as_ref())))
+            .into_iter()
+            .collect()
+    }
+
+    /// Same as [`load_unexpired`](fn.load_unexpired.html), but in the format of this store's
+    /// options.
+    pub fn load_unexpired<T, S>(&self, key: S) -> Result<T, PreferencesError>
+        where T: DeserializeOwned,
+              S: AsRef<str>
+    {
+        let key = key.as_ref();
+        let saved: Expiring<T> = self.load(key)?;
+        if saved.is_expired() {
+            let path = self.file_path(key, self.options.get_format())?;
+            return Err(PreferencesError::not_found(key, &path));
+        }
+        Ok(saved.into_inner())
+    }
+
+    /// Returns the format in which user data with the given `key` was saved, or `None` if it
+    /// wasn't saved in any enabled format. If the data was saved in several formats, the format
+    /// of the most recently modified file is returned. (In a memory backend, the first of
+    /// `Format::all()` is returned instead.)
+    pub fn detect_format<S>(&self, key: S) -> Result<Option<Format>, PreferencesError>
+        where S: AsRef<str>
+    {
+        if let Some(ref memory) = self.memory {
+            return in_memory::detect_format(memory, |format| self.file_path(key.as_ref(), format));
+        }
+        let mut found = None;
+        for &format in Format::all() {
+            let (path, _) = compression::find(&self.file_path(key.as_ref(), format)?);
+            let modified = match fs::metadata(path) {
+                Ok(ref metadata) if metadata.is_file() => metadata.modified()?,
+                Ok(_) => continue,
+                Err(ref e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            if found.is_none_or(|(_, newest)| modified > newest) {
+                found = Some((format, modified));
+            }
+        }
+        Ok(found.map(|(format, _)| format))
+    }
+
+    /// Same as [`metadata`](fn.metadata.html).
+    pub fn metadata<S: AsRef<str>>(&self, key: S) -> Result<PrefsMetadata, PreferencesError> {
+        let format = match self.detect_format(key.as_ref())? {
+            Some(format) => format,
+            None => {
+                let path = self.file_path(key.as_ref(), self.options.get_format())?;
+                return Err(PreferencesError::not_found(key.as_ref(), &path));
+            }
+        };
+        self.metadata_with_format(key, format)
+    }
+
+    /// Same as `metadata`, but of the data saved in the given `format`. This only checks one
+    /// file, so it's faster than `metadata` when several formats are enabled.
+    pub fn metadata_with_format<S>(&self,
+                                   key: S,
+                                   format: Format)
+                                   -> Result<PrefsMetadata, PreferencesError>
+        where S: AsRef<str>
+    {
+        let path = self.file_path(key, format)?;
+        if let Some(ref memory) = self.memory {
+            return in_memory::metadata(memory, &path, format);
+        }
+        let metadata = fs::metadata(compression::find(&path).0)?;
+        Ok(PrefsMetadata {
+            modified: metadata.modified()?,
+            size: metadata.len(),
+            format,
+        })
+    }
+
+    /// Same as [`file_header`](fn.file_header.html).
+    pub fn file_header<S>(&self, key: S) -> Result<Option<FileHeader>, PreferencesError>
+        where S: AsRef<str>
+    {
+        let key = key.as_ref();
+        let format = match self.detect_format(key)? {
+            Some(format) => format,
+            None => {
+                let path = self.file_path(key, self.options.get_format())?;
+                return Err(PreferencesError::not_found(key, &path));
+            }
+        };
+        let path = self.file_path(key, format)?;
+        self.read_bytes(&path)
+            .and_then(|bytes| Ok(header::split(&bytes)?.0))
+            .map_err(|e| e.with_context(Operation::Load, key, &path))
+    }
+
+    /// Same as [`prefs_exist`](fn.prefs_exist.html), but in the format of this store's options.
+    pub fn exists<S: AsRef<str>>(&self, key: S) -> Result<bool, PreferencesError> {
+        let path = self.file_path(key, self.options.get_format())?;
+        if let Some(ref memory) = self.memory {
+            return Ok(memory.contains(&path));
+        }
+        match fs::metadata(compression::find(&path).0) {
+            Ok(metadata) => Ok(metadata.is_file()),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Same as [`list_prefs`](fn.list_prefs.html).
+    pub fn list<S: AsRef<str>>(&self, prefix: S) -> Result<Vec<String>, PreferencesError> {
+        let prefix = prefix.as_ref();
+        if !prefix.is_empty() {
+            check_key(prefix)?;
+        }
+        let dir = key_dir_path(&self.root, prefix)?;
+        let prefix = keys::key_segments(prefix).join("/");
+        let mut found = Vec::new();
+        match self.memory {
+            Some(ref memory) => found = memory.list_keys(&dir, &prefix, &self.file_names),
+            None => collect_keys(&dir, &prefix, &self.file_names, &mut found)?,
+        }
+        found.sort();
+        Ok(found)
+    }
+
+    /// Returns the format in which `load` reads the data saved with `key`.
+    fn load_format(&self, key: &str) -> Result<Format, PreferencesError> {
+        let format = self.options.get_format();
+        if self.format_detection == FormatDetection::Off {
+            return Ok(format);
+        }
+        Ok(self.detect_format(key)?.unwrap_or(format))
+    }
+
+    /// Same as `read_aliased`, but if nothing is saved with `key` in this store's version, reads
+    /// it from the previous versions.
+    fn read_inherited<T>(&self,
+                         key: &str,
+                         path: &Path,
+                         format: Format,
+                         wait: Wait)
+                         -> Result<T, PreferencesError>
+        where T: DeserializeOwned
+    {
+        let error = match self.read_aliased(key, path, format, wait) {
+            Err(error) if error.is_not_found() => error,
+            result => return result,
+        };
+        if self.layout_fallback {
+            let other = match self.file_names.layout {
+                KeyLayout::LeafAsFile => KeyLayout::LeafAsDir,
+                KeyLayout::LeafAsDir => KeyLayout::LeafAsFile,
+            };
+            let names = FileNames {
+                layout: other,
+                ..self.file_names.clone()
+            };
+            let other_path = file_path_named(&self.root, key, format.extension(), &names)?;
+            match self.read(&other_path, format, wait) {
+                Err(ref e) if e.is_not_found() => {}
+                result => return result,
+            }
+        }
+        let version = match self.version {
+            Some(ref version) => version,
+            None => return Err(error),
+        };
+        let versions_dir = self.versions_dir();
+        for previous in &version.previous {
+            let root = match *previous {
+                Some(ref dir) => versions_dir.join(dir),
+                None => versions_dir.clone(),
+            };
+            let path = file_path_named(&root, key, format.extension(), &self.file_names)?;
+            match self.read(&path, format, wait) {
+                Err(ref e) if e.is_not_found() => {}
+                result => return result,
+            }
+        }
+        Err(error)
+    }
+
+    /// Reads the value with `key` from `path`, or if nothing is saved there, from the key that
+    /// `key` is an alias for.
+    fn read_aliased<T>(&self,
+                       key: &str,
+                       path: &Path,
+                       format: Format,
+                       wait: Wait)
+                       -> Result<T, PreferencesError>
+        where T: DeserializeOwned
+    {
+        let error = match self.read(path, format, wait) {
+            Err(error) if error.is_not_found() => error,
+            result => return result,
+        };
+        let alias = self.aliases.read().unwrap_or_else(|e| e.into_inner()).get(key).cloned();
+        let (old_key, policy) = match alias {
+            Some(alias) => alias,
+            None => return Err(error),
+        };
+        let old_path = self.file_path(&old_key, format)?;
+        let value = match self.read(&old_path, format, wait) {
+            Err(ref e) if e.is_not_found() => return Err(error),
+            result => result?,
+        };
+        if self.write_mode == WriteMode::Write {
+            self.move_data(&old_path, path, policy)?;
+            observe::notify(&self.root, key);
+        }
+        Ok(value)
+    }
+
+    /// Pulls the data with `key`, stored at `path`, from the sync provider, if any. A store that
+    /// doesn't change its data keeps its local copy.
+    pub(super) fn pull(&self, key: &str, path: &Path) -> Result<(), PreferencesError> {
+        match self.sync {
+            Some(ref sync) if self.write_mode == WriteMode::Write => {
+                sync::pull(self, &**sync, key, path)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn read<T: DeserializeOwned>(&self,
+                                 path: &Path,
+                                 format: Format,
+                                 wait: Wait)
+                                 -> Result<T, PreferencesError> {
+        match self.memory {
+            Some(ref memory) => checksum::deserialize(format, &memory.read(path)?),
+            None => load_locked(path, format, wait),
+        }
+    }
+
+    /// Reads the saved data at `path` as it is stored, but decompressed.
+    fn read_bytes(&self, path: &Path) -> Result<Vec<u8>, PreferencesError> {
+        let _lock = match self.memory {
+            Some(_) => None,
+            None => Some(FileLock::acquire(path, false, Wait::Block)?),
+        };
+        self.read_bytes_unlocked(path)
+    }
+
+    /// Same as `read_bytes`, but without locking the file, e.g. because the caller has locked it.
+    /// (Files are replaced atomically, so the data is never half-written.)
+    pub(super) fn read_bytes_unlocked(&self, path: &Path) -> Result<Vec<u8>, PreferencesError> {
+        if let Some(ref memory) = self.memory {
+            return memory.read(path).map_err(Into::into);
+        }
+        let (actual, compression) = compression::find(path);
+        let mut bytes = Vec::new();
+        compression.decompress(fs::File::open(actual)?)?.read_to_end(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Returns when the saved data at `path` was last modified, if it exists and that's known.
+    pub(super) fn modified_time(&self, path: &Path) -> Option<SystemTime> {
+        match self.memory {
+            Some(ref memory) => memory.modified_time(path),
+            None => fs::metadata(compression::find(path).0).and_then(|m| m.modified()).ok(),
+        }
+    }
+
+    /// Reads the bytes saved with `save_bytes` to the file at `path`.
+    #[cfg(any(feature = "encryption", feature = "signing"))]
+    pub(crate) fn load_bytes(&self, path: &Path) -> Result<Vec<u8>, PreferencesError> {
+        if let Some(ref memory) = self.memory {
+            return memory.read(path).map_err(Into::into);
+        }
+        let _lock = crate::lock_shared(path, Wait::Block)?;
+        fs::read(path).map_err(Into::into)
+    }
+}
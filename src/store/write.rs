@@ -0,0 +1,425 @@
+//! The write pipeline of a store: the checks, writes and notifications that every save and
+//! delete goes through.
+
+use serde::Serialize;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+use std::time::Duration;
+use crate::backup;
+use crate::bulk;
+use crate::checksum;
+use crate::collision;
+use crate::compression;
+use crate::envelope;
+use crate::lock::{FileLock, Wait};
+use crate::observe;
+use crate::permissions;
+use crate::quota;
+use crate::sync;
+use crate::{Expiring, Format, Operation, PreferencesError, PrefsVersion, SaveOptions, check_key,
+            key_dir_path, keys, remove_empty_dirs, save_locked, write_atomically, write_file};
+use super::in_memory;
+use super::{AliasPolicy, FormatDetection, PreferencesStore, WriteMode};
+
+impl PreferencesStore {
+    /// Saves `value` with the given `key`, using this store's options.
+    ///
+    /// # Failures
+    /// Same as [`Preferences::save`](trait.Preferences.html#tymethod.save).
+    pub fn save<T, S>(&self, key: S, value: &T) -> Result<(), PreferencesError>
+        where T: Serialize,
+              S: AsRef<str>
+    {
+        self.save_with_options(key, value, &self.options)
+    }
+
+    /// Same as `save`, but uses the given options instead of this store's.
+    pub fn save_with_options<T, S>(&self,
+                                   key: S,
+                                   value: &T,
+                                   options: &SaveOptions)
+                                   -> Result<(), PreferencesError>
+        where T: Serialize,
+              S: AsRef<str>
+    {
+        self.save_waiting(key.as_ref(), value, options, Wait::Block)
+    }
+
+    /// Same as `save`, but fails with a `PreferencesError::WouldBlock` (see
+    /// [`inner`](enum.PreferencesError.html#method.inner)) instead of waiting if the data is
+    /// locked by another thread or process.
+    pub fn try_save<T, S>(&self, key: S, value: &T) -> Result<(), PreferencesError>
+        where T: Serialize,
+              S: AsRef<str>
+    {
+        self.save_waiting(key.as_ref(), value, &self.options, Wait::Try)
+    }
+
+    /// Same as [`save_many`](fn.save_many.html), but with this store's options.
+    pub fn save_many<T, S>(&self,
+                           values: &[(S, T)])
+                           -> HashMap<String, Result<(), PreferencesError>>
+        where T: Serialize + Sync,
+              S: AsRef<str> + Sync
+    {
+        bulk::map_all(values, |(key, value)| {
+                (key.as_ref().to_owned(), self.save(key.as_ref(), value))
+            })
+            .into_iter()
+            .collect()
+    }
+
+    /// Same as [`save_with_ttl`](fn.save_with_ttl.html), but with this store's options.
+    pub fn save_with_ttl<T, S>(&self,
+                               key: S,
+                               value: &T,
+                               ttl: Duration)
+                               -> Result<(), PreferencesError>
+        where T: Serialize,
+              S: AsRef<str>
+    {
+        self.save(key, &Expiring::new(value, ttl))
+    }
+
+    /// Same as [`compare_and_save`](fn.compare_and_save.html), but with this store's options.
+    pub fn compare_and_save<T, S>(&self,
+                                  key: S,
+                                  expected: PrefsVersion,
+                                  value: &T)
+                                  -> Result<PrefsVersion, PreferencesError>
+        where T: Serialize,
+              S: AsRef<str>
+    {
+        let key = key.as_ref();
+        let path = self.file_path(key, self.options.get_format())?;
+        let options = self.options_for(key, &path, &self.options);
+        options.serialize(value)
+            .and_then(|bytes| {
+                let version = self.swap(key, &path, expected, &bytes, &options)?;
+                if self.write_mode == WriteMode::Write {
+                    self.finish_write(key, &path, &options, || Ok(bytes))?;
+                }
+                Ok(version)
+            })
+            .map_err(|e| e.with_context(Operation::Save, key, &path))
+    }
+
+    /// Same as [`revert`](fn.revert.html), but in the format of this store's options.
+    pub fn revert<S: AsRef<str>>(&self, key: S) -> Result<(), PreferencesError> {
+        let path = self.file_path(key.as_ref(), self.options.get_format())?;
+        if !self.writes()? {
+            return Ok(());
+        }
+        match self.memory {
+            Some(ref memory) => memory.restore_backup(&path)?,
+            None => backup::restore_backup(&path)?,
+        }
+        observe::notify(&self.root, key.as_ref());
+        Ok(())
+    }
+
+    /// Same as [`delete_prefs`](fn.delete_prefs.html), but in the format of this store's
+    /// options.
+    pub fn delete<S: AsRef<str>>(&self, key: S) -> Result<(), PreferencesError> {
+        let path = self.file_path(key.as_ref(), self.options.get_format())?;
+        self.delete_file(&path).map_err(|e| e.with_context(Operation::Delete, key.as_ref(), &path))
+    }
+
+    /// Deletes all user data saved under the given `prefix`, in any format. As with `list`, the
+    /// data saved with the key `prefix` itself is kept, and an empty prefix deletes all of the
+    /// app's user data. Deleting a prefix under which nothing was saved is not an error.
+    pub fn delete_all<S: AsRef<str>>(&self, prefix: S) -> Result<(), PreferencesError> {
+        let prefix = prefix.as_ref();
+        if !prefix.is_empty() {
+            check_key(prefix)?;
+        }
+        let dir = key_dir_path(&self.root, prefix)?;
+        if !self.writes()? {
+            return Ok(());
+        }
+        if let Some(ref memory) = self.memory {
+            return memory.remove_dir(&dir).map_err(Into::into);
+        }
+        match fs::remove_dir_all(&dir) {
+            Ok(()) => {}
+            Err(ref e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        if !keys::key_segments(prefix).is_empty() {
+            remove_empty_dirs(&self.root, dir.parent());
+        }
+        Ok(())
+    }
+
+    fn save_waiting<T: Serialize>(&self,
+                                  key: &str,
+                                  value: &T,
+                                  options: &SaveOptions,
+                                  wait: Wait)
+                                  -> Result<(), PreferencesError> {
+        let path = self.file_path(key, options.get_format())?;
+        self.save_at(key, &path, value, options, None, wait)
+            .map_err(|e| e.with_context(Operation::Save, key, &path))
+    }
+
+    /// Saves `value` with `key` to the preferences file at `path`. Every save of a single value
+    /// goes through here or `save_serialized`, so that all of them are checked, synced and
+    /// observed alike.
+    ///
+    /// If the caller holds the file's lock, it's passed as `lock` and released after writing;
+    /// otherwise the lock is taken with `wait`.
+    pub(crate) fn save_at<T: Serialize>(&self,
+                                        key: &str,
+                                        path: &Path,
+                                        value: &T,
+                                        options: &SaveOptions,
+                                        lock: Option<FileLock>,
+                                        wait: Wait)
+                                        -> Result<(), PreferencesError> {
+        let options = self.options_for(key, path, options);
+        // A dry run still reports values that can't be saved.
+        let bytes = options.serialize(value)?;
+        self.save_serialized(key, path, bytes, &options, lock, wait)
+    }
+
+    /// Same as `save_at`, but saves `bytes` that `options`, as returned by `options_for`,
+    /// serialized.
+    pub(crate) fn save_serialized(&self,
+                                  key: &str,
+                                  path: &Path,
+                                  bytes: Vec<u8>,
+                                  options: &SaveOptions,
+                                  lock: Option<FileLock>,
+                                  wait: Wait)
+                                  -> Result<(), PreferencesError> {
+        if !self.writes()? {
+            return Ok(());
+        }
+        self.check_write(key, path)?;
+        if options.get_max_total_size().is_some() {
+            quota::check_total(self, path, bytes.len() as u64, options)?;
+        }
+        match (self.memory.as_ref(), lock) {
+            (Some(memory), _) => in_memory::write(memory, path, bytes.clone(), options)?,
+            (None, Some(lock)) => {
+                write_file(&bytes, path, options)?;
+                drop(lock);
+            }
+            (None, None) => save_locked(&bytes, path, options, wait)?,
+        }
+        self.finish_write(key, path, options, || Ok(bytes))
+    }
+
+    /// Checks that `key` can be saved to the preferences file at `path`, before anything is
+    /// written.
+    pub(crate) fn check_write(&self, key: &str, path: &Path) -> Result<(), PreferencesError> {
+        if self.collision_check && self.memory.is_none() {
+            collision::check(&self.root, path, key)?;
+        }
+        Ok(())
+    }
+
+    /// Does what follows saving the data with `key` to the preferences file at `path`, once the
+    /// file is unlocked: removes the data's other formats, notifies observers and pushes the
+    /// data, which `serialize` returns, to the sync provider.
+    pub(crate) fn finish_write<F>(&self,
+                                  key: &str,
+                                  path: &Path,
+                                  options: &SaveOptions,
+                                  serialize: F)
+                                  -> Result<(), PreferencesError>
+        where F: FnOnce() -> Result<Vec<u8>, PreferencesError>
+    {
+        if self.memory.is_none() {
+            self.pending.saved(path, options.get_durability());
+        }
+        if self.format_detection == FormatDetection::Convert {
+            self.remove_other_formats(key, options.get_format())?;
+        }
+        observe::notify(&self.root, key);
+        match self.sync {
+            Some(ref sync) => sync::push(self, &**sync, key, path, &serialize()?),
+            None => Ok(()),
+        }
+    }
+
+    /// Saves `bytes` that were encoded elsewhere, e.g. signed, for the data with `key` to the
+    /// file at `path`, with the permissions and durability of `options`. The file isn't one of
+    /// the formats of `key`, so other formats are kept, and observers and the sync provider
+    /// aren't told about it.
+    #[cfg(any(feature = "encryption", feature = "signing"))]
+    pub(crate) fn save_bytes(&self,
+                             key: &str,
+                             path: &Path,
+                             bytes: Vec<u8>,
+                             options: &SaveOptions)
+                             -> Result<(), PreferencesError> {
+        if !self.writes()? {
+            return Ok(());
+        }
+        self.check_write(key, path)?;
+        if let Some(ref memory) = self.memory {
+            return memory.write(path, bytes).map_err(Into::into);
+        }
+        if let Some(dir) = path.parent() {
+            permissions::create_dirs(dir, options.is_private())?;
+        }
+        {
+            let _lock = FileLock::acquire(path, true, Wait::Block)?;
+            crate::write_atomically_with(path,
+                                         options,
+                                         |file| file.write_all(&bytes).map_err(Into::into))?;
+        }
+        self.pending.saved(path, options.get_durability());
+        Ok(())
+    }
+
+    /// Saves `bytes`, which `options` serialized, at `path` if the data there is in the
+    /// `expected` version. In dry-run mode, only checks the version.
+    fn swap(&self,
+            key: &str,
+            path: &Path,
+            expected: PrefsVersion,
+            bytes: &[u8],
+            options: &SaveOptions)
+            -> Result<PrefsVersion, PreferencesError> {
+        let conflict = || {
+            PreferencesError::Conflict {
+                key: key.to_owned(),
+                path: path.to_owned(),
+            }
+        };
+        if !self.writes()? {
+            let current = match self.memory {
+                Some(ref memory) => in_memory::version(memory, path),
+                None => file_version(path)?,
+            };
+            return if current == expected { Ok(current) } else { Err(conflict()) };
+        }
+        self.check_write(key, path)?;
+        if let Some(ref memory) = self.memory {
+            if in_memory::version(memory, path) != expected {
+                return Err(conflict());
+            }
+            quota::check_total(self, path, bytes.len() as u64, options)?;
+            in_memory::write(memory, path, bytes.to_vec(), options)?;
+            return Ok(PrefsVersion::of(bytes));
+        }
+        if let Some(dir) = path.parent() {
+            permissions::create_dirs(dir, options.is_private())?;
+        }
+        let _lock = FileLock::acquire(path, true, Wait::Block)?;
+        if file_version(path)? != expected {
+            return Err(conflict());
+        }
+        if options.get_max_total_size().is_some() {
+            quota::check_total(self, path, bytes.len() as u64, options)?;
+        }
+        write_file(bytes, path, options)?;
+        file_version(path)
+    }
+
+    /// Copies the data at `old_path` to `new_path` as it is, unless something was saved there in
+    /// the meantime, and deletes it at `old_path` with `AliasPolicy::Move`.
+    pub(super) fn move_data(&self,
+                            old_path: &Path,
+                            new_path: &Path,
+                            policy: AliasPolicy)
+                            -> Result<(), PreferencesError> {
+        match self.memory {
+            Some(ref memory) => in_memory::copy(memory, old_path, new_path)?,
+            None => {
+                if let Some(dir) = new_path.parent() {
+                    permissions::create_dirs(dir, self.options.is_private())?;
+                }
+                let _lock = FileLock::acquire(new_path, true, Wait::Block)?;
+                if !compression::find(new_path).0.is_file() {
+                    let (bytes, compression) = {
+                        let _lock = FileLock::acquire(old_path, false, Wait::Block)?;
+                        let (actual, compression) = compression::find(old_path);
+                        (fs::read(actual)?, compression)
+                    };
+                    write_atomically(&compression.apply_to(new_path),
+                                     |file| file.write_all(&bytes).map_err(Into::into))?;
+                }
+            }
+        }
+        if policy == AliasPolicy::Move {
+            self.delete_file(old_path)?;
+        }
+        Ok(())
+    }
+
+    /// Deletes the data saved with `key` in formats other than `format`, in which it was just
+    /// saved.
+    fn remove_other_formats(&self,
+                            key: &str,
+                            format: Format)
+                            -> Result<(), PreferencesError> {
+        for &other in Format::all() {
+            if other == format {
+                continue;
+            }
+            let path = self.file_path(key, other)?;
+            let exists = match self.memory {
+                Some(ref memory) => memory.contains(&path),
+                None => compression::find(&path).0.is_file(),
+            };
+            if exists {
+                self.delete_file(&path)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn delete_file(&self, path: &Path) -> Result<(), PreferencesError> {
+        if !self.writes()? {
+            return Ok(());
+        }
+        // With a sync provider, the version is kept, so that the remote copy isn't restored by
+        // the next load unless it's newer.
+        if self.sync.is_none() {
+            sync::remove_version(self, path);
+        }
+        if let Some(ref memory) = self.memory {
+            return in_memory::delete(memory, path);
+        }
+        let lock = FileLock::acquire(path, true, Wait::Block)?;
+        let (actual, _) = compression::find(path);
+        fs::remove_file(&actual)?;
+        backup::remove_backups(&actual);
+        lock.remove();
+        remove_empty_dirs(&self.root, path.parent());
+        Ok(())
+    }
+
+    /// Returns `options` for saving the data with `key` at `path`, with what they write in its
+    /// header or envelope, e.g. when its existing envelope was created.
+    pub(crate) fn options_for<'a>(&self,
+                                  key: &str,
+                                  path: &Path,
+                                  options: &'a SaveOptions)
+                                  -> Cow<'a, SaveOptions> {
+        if !options.has_envelope() {
+            return options.for_key(key, &self.app, None);
+        }
+        // Data that was saved without an envelope keeps its modification time as its creation
+        // time, the same as `load_with_meta` reports for it.
+        let created = self.read_bytes_unlocked(path).ok().and_then(|bytes| {
+            let bytes = checksum::verify(&bytes).ok()?;
+            envelope::created(options.get_format(), bytes).or_else(|| self.modified_time(path))
+        });
+        options.for_key(key, &self.app, created)
+    }
+}
+
+fn file_version(path: &Path) -> Result<PrefsVersion, PreferencesError> {
+    match fs::read(compression::find(path).0) {
+        Ok(bytes) => Ok(PrefsVersion::of(&bytes)),
+        Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(PrefsVersion::MISSING),
+        Err(e) => Err(e.into()),
+    }
+}
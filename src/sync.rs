@@ -0,0 +1,367 @@
+//! Hooks for synchronizing user data between devices.
+
+use serde_json::{self, Map, Value};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fmt::Debug;
+use std::fs::{self, create_dir_all};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::keys::key_segments;
+use crate::lock::{FileLock, Wait};
+use crate::{Compression, PreferencesError, PreferencesStore, compression, observe,
+            write_atomically};
+
+static SYNC_PROVIDER: RwLock<Option<Arc<dyn SyncProvider>>> = RwLock::new(None);
+
+/// Suffix of the hidden files next to the preferences files that keep their sync versions.
+static VERSION_FILE_SUFFIX: &str = ".sync";
+
+/// A remote copy of user data, e.g. in a cloud service, that is kept in sync with the local
+/// files.
+///
+/// When a provider is installed with [`set_sync_provider`](fn.set_sync_provider.html) (or with
+/// [`with_sync_provider`](struct.PreferencesStore.html#method.with_sync_provider) for one store),
+/// every `save` (and `try_save`) of a [`PreferencesStore`](struct.PreferencesStore.html), and
+/// therefore of [`Preferences`](trait.Preferences.html), pushes the saved data to it after
+/// writing the local file. Every `load` (and `try_load`) pulls the data from it first, and
+/// replaces the local file if the remote copy is newer.
+///
+/// Each copy has a [`SyncVersion`](struct.SyncVersion.html), which tells whether one copy is
+/// newer than the other, or whether they were changed on different devices independently. In
+/// that case, `prefer_remote` decides which one is kept.
+///
+/// The data is exchanged as the serialized bytes, in the format of the store's options, so all
+/// devices should use the same format. Other ways of saving (such as transactions, edit guards
+/// and asynchronous saves) don't push to the provider, but their changes are pushed with the
+/// next `save`.
+pub trait SyncProvider: Debug + Send + Sync {
+    /// Returns the identifier of this device, which must be unique among the devices that share
+    /// the data.
+    fn device_id(&self) -> String;
+
+    /// Stores `bytes` as the remote copy of the data with the given `key`, with `version`.
+    ///
+    /// # Failures
+    /// If the remote copy cannot be updated. The local file has been saved in that case.
+    fn push(&self, key: &str, bytes: &[u8], version: &SyncVersion) -> Result<(), PreferencesError>;
+
+    /// Returns the remote copy of the data with the given `key`, or `None` if there is none.
+    ///
+    /// # Failures
+    /// If the remote copy cannot be fetched. This fails the `load`, so providers that are
+    /// offline should return `Ok(None)` instead, to keep using the local data.
+    fn pull(&self, key: &str) -> Result<Option<SyncRecord>, PreferencesError>;
+
+    /// Returns whether the remote copy of the data with the given `key` is kept when it
+    /// conflicts with the local one, i.e. when both were changed since they were last in sync.
+    ///
+    /// The default keeps the copy that was saved last.
+    fn prefer_remote(&self, key: &str, local: &SyncVersion, remote: &SyncVersion) -> bool {
+        let _ = key;
+        remote.modified > local.modified
+    }
+}
+
+/// A copy of user data exchanged with a [`SyncProvider`](trait.SyncProvider.html).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SyncRecord {
+    /// The serialized data.
+    pub bytes: Vec<u8>,
+    /// The version of the data.
+    pub version: SyncVersion,
+}
+
+/// The version of a copy of user data, for detecting conflicts between copies.
+///
+/// The version is a vector clock, which counts the saves on each device, and the time of the
+/// last save. A copy is newer than another if it has seen all of its saves, and more.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SyncVersion {
+    /// When the data was last saved.
+    pub modified: SystemTime,
+    /// The number of saves on each device, by device identifier.
+    pub clock: BTreeMap<String, u64>,
+}
+
+impl SyncVersion {
+    /// Creates the version of data that was never saved.
+    pub fn new() -> Self {
+        SyncVersion {
+            modified: UNIX_EPOCH,
+            clock: BTreeMap::new(),
+        }
+    }
+
+    /// Compares the clocks of two versions. Returns `None` if neither is newer, i.e. the data was
+    /// changed on different devices independently.
+    pub fn compare(&self, other: &SyncVersion) -> Option<Ordering> {
+        let mut ordering = Ordering::Equal;
+        for device in self.clock.keys().chain(other.clock.keys()) {
+            let mine = self.clock.get(device).cloned().unwrap_or(0);
+            let theirs = other.clock.get(device).cloned().unwrap_or(0);
+            match (ordering, mine.cmp(&theirs)) {
+                (_, Ordering::Equal) => {}
+                (Ordering::Equal, device_ordering) => ordering = device_ordering,
+                (ordering, device_ordering) if ordering != device_ordering => return None,
+                _ => {}
+            }
+        }
+        Some(ordering)
+    }
+
+    /// Combines the saves seen by both versions.
+    pub fn merge(&mut self, other: &SyncVersion) {
+        for (device, &count) in &other.clock {
+            let entry = self.clock.entry(device.clone()).or_insert(0);
+            *entry = (*entry).max(count);
+        }
+        self.modified = self.modified.max(other.modified);
+    }
+
+    /// Records a save on `device`, now.
+    fn increment(&mut self, device: String) {
+        *self.clock.entry(device).or_insert(0) += 1;
+        self.modified = SystemTime::now().max(self.modified);
+    }
+
+    fn to_bytes(&self) -> Result<Vec<u8>, PreferencesError> {
+        let millis = self.modified
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_millis() as u64)
+            .unwrap_or(0);
+        let mut map = Map::new();
+        map.insert("modified".into(), millis.into());
+        map.insert("clock".into(), serde_json::to_value(&self.clock)?);
+        serde_json::to_vec(&map).map_err(Into::into)
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, PreferencesError> {
+        let mut map: Map<String, Value> = serde_json::from_slice(bytes)?;
+        let millis = map.get("modified").and_then(Value::as_u64).unwrap_or(0);
+        let clock = map.get_mut("clock").map(Value::take).unwrap_or(Value::Null);
+        Ok(SyncVersion {
+            modified: UNIX_EPOCH + Duration::from_millis(millis),
+            clock: serde_json::from_value(clock)?,
+        })
+    }
+}
+
+impl Default for SyncVersion {
+    fn default() -> Self {
+        SyncVersion::new()
+    }
+}
+
+/// Installs the [`SyncProvider`](trait.SyncProvider.html) used by the stores created from now
+/// on (including those created by the free functions and the
+/// [`Preferences`](trait.Preferences.html) methods), or removes it with `None`.
+pub fn set_sync_provider(provider: Option<Arc<dyn SyncProvider>>) {
+    *SYNC_PROVIDER.write().unwrap_or_else(|e| e.into_inner()) = provider;
+}
+
+/// Returns the provider installed with `set_sync_provider`.
+pub(crate) fn sync_provider() -> Option<Arc<dyn SyncProvider>> {
+    SYNC_PROVIDER.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Pushes `bytes`, which were just saved to the preferences file at `path`, to `provider` with
+/// a new version.
+pub(crate) fn push(store: &PreferencesStore,
+                   provider: &dyn SyncProvider,
+                   key: &str,
+                   path: &Path,
+                   bytes: &[u8])
+                   -> Result<(), PreferencesError> {
+    let version = {
+        let _lock = lock(store, path)?;
+        let mut version = read_version(store, path)?;
+        version.increment(provider.device_id());
+        write_version(store, path, &version)?;
+        version
+    };
+    provider.push(&key_segments(key).join("/"), bytes, &version)
+}
+
+/// Replaces the preferences file at `path` with the copy from `provider`, if that is newer or
+/// wins the conflict with the local file.
+pub(crate) fn pull(store: &PreferencesStore,
+                   provider: &dyn SyncProvider,
+                   key: &str,
+                   path: &Path)
+                   -> Result<(), PreferencesError> {
+    let sync_key = key_segments(key).join("/");
+    let remote = match provider.pull(&sync_key)? {
+        Some(remote) => remote,
+        None => return Ok(()),
+    };
+    let _lock = lock(store, path)?;
+    let mut version = read_version(store, path)?;
+    let use_remote = match remote.version.compare(&version) {
+        Some(Ordering::Greater) => true,
+        Some(_) => return Ok(()),
+        // Merging the versions makes the next save supersede both copies, even if the local
+        // copy is kept now.
+        None => provider.prefer_remote(&sync_key, &version, &remote.version),
+    };
+    version.merge(&remote.version);
+    if use_remote {
+        match store.memory() {
+            Some(memory) => memory.write(path, remote.bytes)?,
+            None => {
+                write_atomically(path, |file| file.write_all(&remote.bytes).map_err(Into::into))?;
+                compression::remove_others(path, Compression::None);
+            }
+        }
+    }
+    write_version(store, path, &version)?;
+    drop(_lock);
+    if use_remote {
+        observe::notify(store.root(), key);
+    }
+    Ok(())
+}
+
+/// Removes the sync version of the preferences file at `path`, if it has one.
+pub(crate) fn remove_version(store: &PreferencesStore, path: &Path) {
+    let path = version_file_path(path);
+    let _ = match store.memory() {
+        Some(memory) => memory.remove(&path),
+        None => fs::remove_file(&path),
+    };
+}
+
+fn lock(store: &PreferencesStore, path: &Path) -> Result<Option<FileLock>, PreferencesError> {
+    if store.memory().is_some() {
+        return Ok(None);
+    }
+    path.parent().map(create_dir_all);
+    FileLock::acquire(path, true, Wait::Block).map(Some)
+}
+
+fn read_version(store: &PreferencesStore, path: &Path) -> Result<SyncVersion, PreferencesError> {
+    let path = version_file_path(path);
+    let bytes = match store.memory() {
+        Some(memory) => memory.read(&path),
+        None => fs::read(&path),
+    };
+    match bytes {
+        Ok(bytes) => SyncVersion::from_bytes(&bytes),
+        Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(SyncVersion::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn write_version(store: &PreferencesStore,
+                 path: &Path,
+                 version: &SyncVersion)
+                 -> Result<(), PreferencesError> {
+    let path = version_file_path(path);
+    let bytes = version.to_bytes()?;
+    match store.memory() {
+        Some(memory) => memory.write(&path, bytes).map_err(Into::into),
+        None => write_atomically(&path, |file| file.write_all(&bytes).map_err(Into::into)),
+    }
+}
+
+/// Returns the path of the file that keeps the sync version of the preferences file at `path`.
+fn version_file_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    if let Some(file_name) = path.file_name() {
+        name.push(file_name);
+    }
+    name.push(VERSION_FILE_SUFFIX);
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SyncProvider, SyncRecord, SyncVersion};
+    use crate::{AppInfo, MemoryBackend, PreferencesError, PreferencesStore};
+    use std::cmp::Ordering;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    const APP_INFO: AppInfo = AppInfo {
+        name: "preferences",
+        author: "Rust language community",
+    };
+
+    type Cloud = Mutex<HashMap<String, SyncRecord>>;
+
+    #[derive(Debug)]
+    struct Device {
+        id: &'static str,
+        cloud: Arc<Cloud>,
+    }
+
+    impl SyncProvider for Device {
+        fn device_id(&self) -> String {
+            self.id.into()
+        }
+
+        fn push(&self,
+                key: &str,
+                bytes: &[u8],
+                version: &SyncVersion)
+                -> Result<(), PreferencesError> {
+            let record = SyncRecord {
+                bytes: bytes.to_vec(),
+                version: version.clone(),
+            };
+            self.cloud.lock().unwrap().insert(key.into(), record);
+            Ok(())
+        }
+
+        fn pull(&self, key: &str) -> Result<Option<SyncRecord>, PreferencesError> {
+            Ok(self.cloud.lock().unwrap().get(key).cloned())
+        }
+    }
+
+    #[test]
+    fn test_sync() {
+        let cloud = Arc::new(Cloud::default());
+        let device = |id| {
+            PreferencesStore::new(&APP_INFO)
+                .unwrap()
+                .with_memory_backend(MemoryBackend::new())
+                .with_sync_provider(Some(Arc::new(Device {
+                    id,
+                    cloud: cloud.clone(),
+                })))
+        };
+        let (laptop, phone) = (device("laptop"), device("phone"));
+        laptop.save("theme", &"dark".to_owned()).unwrap();
+        assert_eq!(phone.load::<String, _>("theme").unwrap(), "dark");
+
+        phone.save("theme", &"light".to_owned()).unwrap();
+        assert_eq!(laptop.load::<String, _>("theme").unwrap(), "light");
+        let versions = cloud.lock().unwrap()["theme"].version.clone();
+        assert_eq!(versions.clock["laptop"], 1);
+        assert_eq!(versions.clock["phone"], 1);
+
+        let mut transaction = laptop.transaction();
+        transaction.save("theme", &"blue".to_owned()).unwrap();
+        transaction.commit().unwrap();
+        assert_eq!(phone.load::<String, _>("theme").unwrap(), "blue");
+        laptop.edit::<String, _>("theme").unwrap().push('!');
+        assert_eq!(phone.load::<String, _>("theme").unwrap(), "blue!");
+    }
+
+    #[test]
+    fn test_compare_versions() {
+        let version = |laptop, phone| {
+            let mut version = SyncVersion::new();
+            version.clock.insert("laptop".into(), laptop);
+            version.clock.insert("phone".into(), phone);
+            version
+        };
+        assert_eq!(version(1, 2).compare(&version(1, 1)), Some(Ordering::Greater));
+        assert_eq!(version(1, 2).compare(&version(1, 2)), Some(Ordering::Equal));
+        assert_eq!(SyncVersion::new().compare(&version(0, 1)), Some(Ordering::Less));
+        assert_eq!(version(2, 1).compare(&version(1, 2)), None);
+    }
+}
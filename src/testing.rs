@@ -0,0 +1,78 @@
+//! Fixtures shared by the tests of the crate.
+
+use std::path::{Path, PathBuf};
+use std::{env, fs, process};
+use crate::{AppId, AppInfo, MemoryBackend, PreferencesStore, set_backend_for_tests};
+
+pub(crate) const APP_INFO: AppInfo = AppInfo {
+    name: "preferences",
+    author: "Rust language community",
+};
+
+/// A temporary directory for the data of a test, removed when dropped, even if the test fails.
+pub(crate) struct TestDir {
+    path: PathBuf,
+}
+
+impl TestDir {
+    /// Returns a directory for the test called `name`, which doesn't exist yet.
+    pub(crate) fn new(name: &str) -> Self {
+        let path = env::temp_dir().join(format!("preferences-rs-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&path);
+        TestDir { path }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns a store that keeps its data in files in this directory.
+    pub(crate) fn store(&self) -> PreferencesStore {
+        PreferencesStore::new(&APP_INFO).unwrap().with_base_dir(&self.path)
+    }
+
+    /// Returns a store that keeps its data in files in this directory, and one that keeps it in
+    /// memory, for tests that both should pass.
+    pub(crate) fn stores(&self) -> [PreferencesStore; 2] {
+        self.stores_for(&AppId::from(&APP_INFO))
+    }
+
+    /// Same as `stores`, but of the data of `app`.
+    pub(crate) fn stores_for(&self, app: &AppId) -> [PreferencesStore; 2] {
+        let file = PreferencesStore::for_app(app).unwrap().with_base_dir(&self.path);
+        let memory = PreferencesStore::for_app(app)
+            .unwrap()
+            .with_memory_backend(MemoryBackend::new());
+        [file, memory]
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Keeps the data that is saved on the current thread with stores of the default location, e.g.
+/// by the free functions, in memory until dropped, so that tests don't touch the user's data.
+pub(crate) struct TestBackend {
+    backend: MemoryBackend,
+}
+
+impl TestBackend {
+    pub(crate) fn install() -> Self {
+        let backend = MemoryBackend::new();
+        set_backend_for_tests(Some(backend.clone()));
+        TestBackend { backend }
+    }
+
+    pub(crate) fn backend(&self) -> &MemoryBackend {
+        &self.backend
+    }
+}
+
+impl Drop for TestBackend {
+    fn drop(&mut self) {
+        set_backend_for_tests(None);
+    }
+}
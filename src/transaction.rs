@@ -0,0 +1,287 @@
+//! Saving several values at once, so that either all or none of them are updated.
+
+use serde::Serialize;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use crate::backup;
+use crate::lock::{FileLock, Wait};
+use crate::{compression, permissions, quota};
+use crate::{Compression, PreferencesError, PreferencesStore, SaveOptions, compute_temp_file_path};
+
+/// A batch of values to be saved together, created with
+/// [`PreferencesStore::transaction`](struct.PreferencesStore.html#method.transaction).
+///
+/// The values are serialized when they're added, but nothing is written until `commit`, which
+/// writes all of them to temporary files first and then moves them into place. If anything
+/// fails, the files that were already replaced are restored, so related settings can't end up
+/// half-updated. Dropping a transaction without committing it discards it.
+///
+/// # Example
+/// ```
+/// extern crate preferences;
+/// use preferences::{AppInfo, PreferencesStore};
+///
+/// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+///
+/// fn main() {
+///     # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
+///     let store = PreferencesStore::new(&APP_INFO).unwrap();
+///     let mut transaction = store.transaction();
+///     transaction.save("tests/docs/transaction/width", &800u32).unwrap();
+///     transaction.save("tests/docs/transaction/height", &600u32).unwrap();
+///     transaction.commit().unwrap();
+///
+///     assert_eq!(store.load::<u32, _>("tests/docs/transaction/height").unwrap(), 600);
+///     store.delete_all("tests/docs/transaction").unwrap();
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Transaction<'a> {
+    store: &'a PreferencesStore,
+    writes: Vec<PendingWrite>,
+}
+
+#[derive(Debug)]
+struct PendingWrite {
+    key: String,
+    path: PathBuf,
+    bytes: Vec<u8>,
+    compression: Compression,
+    /// The serialized size of the value, before compression.
+    size: u64,
+    options: SaveOptions,
+}
+
+/// Progress of committing one file, for rolling back.
+struct Commit {
+    path: PathBuf,
+    temp_path: PathBuf,
+    /// Where the previous version of the file was moved, if it existed.
+    original: Option<PathBuf>,
+    replaced: bool,
+}
+
+impl<'a> Transaction<'a> {
+    pub(crate) fn new(store: &'a PreferencesStore) -> Self {
+        Transaction {
+            store,
+            writes: Vec::new(),
+        }
+    }
+
+    /// Adds `value` to be saved with the given `key`, using the store's options. A later save
+    /// with the same key replaces the earlier one.
+    ///
+    /// # Failures
+    /// If `key` is invalid or `value` cannot be serialized.
+    pub fn save<T, S>(&mut self, key: S, value: &T) -> Result<(), PreferencesError>
+        where T: Serialize,
+              S: AsRef<str>
+    {
+        let options = self.store.options().clone();
+        self.save_with_options(key, value, &options)
+    }
+
+    /// Same as `save`, but uses the given options instead of the store's.
+    pub fn save_with_options<T, S>(&mut self,
+                                   key: S,
+                                   value: &T,
+                                   options: &SaveOptions)
+                                   -> Result<(), PreferencesError>
+        where T: Serialize,
+              S: AsRef<str>
+    {
+        let path = self.store.file_path(key.as_ref(), options.get_format())?;
+        let serialized = self.store.options_for(key.as_ref(), &path, options).serialize(value)?;
+        let size = serialized.len() as u64;
+        // Data kept in memory isn't compressed.
+        let (bytes, compression) = match self.store.memory() {
+            Some(_) => (serialized, Compression::None),
+            None => options.compress(serialized)?,
+        };
+        self.writes.retain(|write| write.path != path);
+        self.writes.push(PendingWrite {
+            key: key.as_ref().to_owned(),
+            path,
+            bytes,
+            compression,
+            size,
+            options: options.clone(),
+        });
+        Ok(())
+    }
+
+    /// Returns the number of values to be saved.
+    pub fn len(&self) -> usize {
+        self.writes.len()
+    }
+
+    /// Returns whether no values were added.
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    /// Saves all values added to this transaction.
+    ///
+    /// # Failures
+    /// If any of the files cannot be written. In that case, none of them are changed (unless
+    /// restoring a previous version fails, too), but backups kept by
+    /// [`SaveOptions::backups`](struct.SaveOptions.html#method.backups) may have been rotated.
+    pub fn commit(mut self) -> Result<(), PreferencesError> {
+        if !self.store.writes()? {
+            return Ok(());
+        }
+        for write in &self.writes {
+            self.store.check_write(&write.key, &write.path)?;
+        }
+        let limit = self.writes.iter().filter_map(|write| write.options.get_max_total_size()).min();
+        if let Some(limit) = limit {
+            let writes: Vec<_> = self.writes
+                .iter()
+                .map(|write| (write.path.as_path(), write.size))
+                .collect();
+            quota::check_writes(self.store, &writes, limit)?;
+        }
+        if let Some(memory) = self.store.memory() {
+            for write in &self.writes {
+                memory.rotate_backups(&write.path, write.options.get_backups())?;
+                memory.write(&write.path, write.bytes.clone())?;
+            }
+            return self.finish();
+        }
+        // Locking in a consistent order keeps concurrent transactions from deadlocking.
+        self.writes.sort_by(|a, b| a.path.cmp(&b.path));
+        let mut locks = Vec::new();
+        for write in &self.writes {
+            if let Some(dir) = write.path.parent() {
+                permissions::create_dirs(dir, write.options.is_private())?;
+            }
+            locks.push(FileLock::acquire(&write.path, true, Wait::Block)?);
+        }
+        let mut commits = Vec::new();
+        let result = self.write_all(&mut commits);
+        let mut result = result.and_then(|_| replace_all(&mut commits));
+        match result {
+            Ok(()) => {
+                for commit in &commits {
+                    if let Some(ref original) = commit.original {
+                        let _ = fs::remove_file(original);
+                    }
+                }
+                for write in &self.writes {
+                    compression::remove_others(&write.path, write.compression);
+                }
+                result = self.sync_dirs();
+            }
+            Err(_) => roll_back(&commits),
+        }
+        drop(locks);
+        result.and_then(|_| self.finish())
+    }
+
+    /// Does what follows saving each of the values, as the store does after saving one.
+    fn finish(&self) -> Result<(), PreferencesError> {
+        let mut result = Ok(());
+        for write in &self.writes {
+            let finished = self.store.finish_write(&write.key, &write.path, &write.options, || {
+                let mut bytes = Vec::new();
+                write.compression.decompress(&write.bytes[..])?.read_to_end(&mut bytes)?;
+                Ok(bytes)
+            });
+            result = result.and(finished);
+        }
+        result
+    }
+
+    /// Writes all values to temporary files and rotates the backups.
+    fn write_all(&self, commits: &mut Vec<Commit>) -> Result<(), PreferencesError> {
+        for write in &self.writes {
+            let path = write.compression.apply_to(&write.path);
+            let temp_path = compute_temp_file_path(&path);
+            commits.push(Commit {
+                path,
+                temp_path: temp_path.clone(),
+                original: None,
+                replaced: false,
+            });
+            let mut file = permissions::create_file(&temp_path, write.options.is_private())?;
+            file.write_all(&write.bytes)?;
+            write.options.get_durability().sync_file(&file)?;
+        }
+        for (write, commit) in self.writes.iter().zip(commits.iter()) {
+            backup::rotate_backups(&commit.path, write.options.get_backups())?;
+        }
+        Ok(())
+    }
+
+    /// Syncs the directories of the replaced files, if their durability requires it.
+    fn sync_dirs(&self) -> Result<(), PreferencesError> {
+        for write in &self.writes {
+            if let Some(dir) = write.path.parent() {
+                write.options.get_durability().sync_dir(dir)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Moves the previous versions of the files aside and the temporary files into their place.
+fn replace_all(commits: &mut [Commit]) -> Result<(), PreferencesError> {
+    for commit in commits {
+        if commit.path.is_file() {
+            let original = compute_temp_file_path(&commit.path);
+            fs::rename(&commit.path, &original)?;
+            commit.original = Some(original);
+        }
+        fs::rename(&commit.temp_path, &commit.path)?;
+        commit.replaced = true;
+    }
+    Ok(())
+}
+
+/// Restores the previous versions of the files and removes the temporary files.
+fn roll_back(commits: &[Commit]) {
+    for commit in commits {
+        if commit.replaced {
+            let _ = fs::remove_file(&commit.path);
+        } else {
+            let _ = fs::remove_file(&commit.temp_path);
+        }
+        if let Some(ref original) = commit.original {
+            let _ = fs::rename(original, &commit.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::TestDir;
+    use std::fs;
+
+    #[test]
+    fn test_transaction_rollback() {
+        let dir = TestDir::new("transaction");
+        let store = dir.store();
+        store.save("a", &1u32).unwrap();
+        // A non-empty directory in place of the second file makes replacing it fail.
+        let blocked = store.file_path("b", Default::default()).unwrap();
+        fs::create_dir_all(blocked.join("child")).unwrap();
+
+        let mut transaction = store.transaction();
+        transaction.save("a", &2u32).unwrap();
+        transaction.save("b", &2u32).unwrap();
+        assert_eq!(transaction.len(), 2);
+        assert!(transaction.commit().is_err());
+        assert_eq!(store.load::<u32, _>("a").unwrap(), 1);
+
+        fs::remove_dir_all(&blocked).unwrap();
+        let mut transaction = store.transaction();
+        transaction.save("a", &3u32).unwrap();
+        transaction.save("b", &3u32).unwrap();
+        transaction.commit().unwrap();
+        assert_eq!(store.load::<u32, _>("a").unwrap(), 3);
+        assert_eq!(store.load::<u32, _>("b").unwrap(), 3);
+        assert_eq!(store.list("").unwrap(), vec!["a", "b"]);
+    }
+}
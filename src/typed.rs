@@ -0,0 +1,157 @@
+//! Typed access to the values of a `PreferencesMap<String>`.
+
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use crate::PreferencesMap;
+
+/// Error type describing a value in a [`PreferencesMap`](type.PreferencesMap.html) that can't be
+/// parsed as the requested type.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ValueError {
+    /// The key of the value.
+    pub key: String,
+    /// The value that couldn't be parsed.
+    pub value: String,
+    /// The name of the requested type, e.g. `"bool"`.
+    pub expected: &'static str,
+}
+
+impl fmt::Display for ValueError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "Value {:?} of {:?} is not a valid {}",
+               self.value,
+               self.key,
+               self.expected)
+    }
+}
+
+impl Error for ValueError {}
+
+/// Typed getters and setters for a [`PreferencesMap<String>`](type.PreferencesMap.html), in the
+/// style of Java's Preferences API, so that simple apps can store numbers and flags without
+/// defining their own structs.
+///
+/// Values are stored as strings: the setters format them with `to_string`, and the getters
+/// parse them with `FromStr`. The getters return `Ok(None)` if there's no value for the key, so
+/// use e.g. `unwrap_or` to fall back to a default.
+///
+/// # Example
+/// ```
+/// use preferences::{PreferencesMap, PreferencesMapExt};
+///
+/// let mut prefs: PreferencesMap = PreferencesMap::new();
+/// prefs.put_bool("fullscreen", true);
+/// prefs.put_i64("volume", 7);
+/// assert_eq!(prefs.get_bool("fullscreen"), Ok(Some(true)));
+/// assert_eq!(prefs.get_i64("volume").unwrap().unwrap_or(5), 7);
+/// assert_eq!(prefs.get_f64("missing"), Ok(None));
+/// ```
+pub trait PreferencesMapExt {
+    /// Returns the value for `key` parsed as `T`, where `expected` names `T` for the error.
+    ///
+    /// # Failures
+    /// If the value can't be parsed as `T`.
+    fn get_parsed<T: FromStr>(&self,
+                              key: &str,
+                              expected: &'static str)
+                              -> Result<Option<T>, ValueError>;
+    /// Sets the value for `key` to `value` formatted as a string.
+    fn put_formatted<T: ToString>(&mut self, key: &str, value: T);
+
+    /// Returns the value for `key`, if any.
+    fn get_str(&self, key: &str) -> Option<&str>;
+    /// Sets the value for `key`.
+    fn put_str(&mut self, key: &str, value: &str) {
+        self.put_formatted(key, value);
+    }
+
+    /// Returns the value for `key` parsed as a `bool` (`true` or `false`).
+    fn get_bool(&self, key: &str) -> Result<Option<bool>, ValueError> {
+        self.get_parsed(key, "bool")
+    }
+    /// Sets the value for `key` to a `bool`.
+    fn put_bool(&mut self, key: &str, value: bool) {
+        self.put_formatted(key, value);
+    }
+
+    /// Returns the value for `key` parsed as an `i64`.
+    fn get_i64(&self, key: &str) -> Result<Option<i64>, ValueError> {
+        self.get_parsed(key, "i64")
+    }
+    /// Sets the value for `key` to an `i64`.
+    fn put_i64(&mut self, key: &str, value: i64) {
+        self.put_formatted(key, value);
+    }
+
+    /// Returns the value for `key` parsed as a `u64`.
+    fn get_u64(&self, key: &str) -> Result<Option<u64>, ValueError> {
+        self.get_parsed(key, "u64")
+    }
+    /// Sets the value for `key` to a `u64`.
+    fn put_u64(&mut self, key: &str, value: u64) {
+        self.put_formatted(key, value);
+    }
+
+    /// Returns the value for `key` parsed as an `f64`.
+    fn get_f64(&self, key: &str) -> Result<Option<f64>, ValueError> {
+        self.get_parsed(key, "f64")
+    }
+    /// Sets the value for `key` to an `f64`.
+    fn put_f64(&mut self, key: &str, value: f64) {
+        self.put_formatted(key, value);
+    }
+}
+
+impl PreferencesMapExt for PreferencesMap<String> {
+    fn get_parsed<T: FromStr>(&self,
+                              key: &str,
+                              expected: &'static str)
+                              -> Result<Option<T>, ValueError> {
+        match self.get(key) {
+            Some(value) => {
+                value.trim().parse().map(Some).map_err(|_| {
+                    ValueError {
+                        key: key.to_owned(),
+                        value: value.clone(),
+                        expected,
+                    }
+                })
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn put_formatted<T: ToString>(&mut self, key: &str, value: T) {
+        self.insert(key.to_owned(), value.to_string());
+    }
+
+    fn get_str(&self, key: &str) -> Option<&str> {
+        self.get(key).map(|value| &value[..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PreferencesMapExt, ValueError};
+    use crate::PreferencesMap;
+
+    #[test]
+    fn test_typed_values() {
+        let mut prefs: PreferencesMap = PreferencesMap::new();
+        prefs.put_f64("ratio", 1.5);
+        prefs.put_u64("count", 3);
+        prefs.put_str("name", "yes");
+        assert_eq!(prefs.get_f64("ratio"), Ok(Some(1.5)));
+        assert_eq!(prefs.get_u64("count"), Ok(Some(3)));
+        assert_eq!(prefs.get_i64("count"), Ok(Some(3)));
+        assert_eq!(prefs.get_str("name"), Some("yes"));
+        assert_eq!(prefs.get_bool("name"),
+                   Err(ValueError {
+                       key: "name".into(),
+                       value: "yes".into(),
+                       expected: "bool",
+                   }));
+    }
+}
@@ -0,0 +1,143 @@
+//! Read-modify-write updates that detect changes made by other processes in between.
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use crate::{AppInfo, PreferencesError, PreferencesStore};
+
+/// The version of saved user data, returned by [`load_versioned`](fn.load_versioned.html) and
+/// checked by [`compare_and_save`](fn.compare_and_save.html).
+///
+/// A version identifies the contents of the saved file, so it changes whenever the data is saved
+/// with a different value, by any process.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PrefsVersion(Option<(u32, u64)>);
+
+impl PrefsVersion {
+    /// The version of user data that wasn't saved yet.
+    pub const MISSING: PrefsVersion = PrefsVersion(None);
+
+    /// Returns whether the data exists in this version, i.e. whether it isn't `MISSING`.
+    pub fn exists(&self) -> bool {
+        self.0.is_some()
+    }
+
+    /// Returns the version of a file with the contents `bytes`.
+    pub(crate) fn of(bytes: &[u8]) -> Self {
+        PrefsVersion(Some((crc32fast::hash(bytes), bytes.len() as u64)))
+    }
+}
+
+/// Loads the user data of `app` with the given `key`, together with its current version, for
+/// a later [`compare_and_save`](fn.compare_and_save.html).
+///
+/// # Failures
+/// Same as [`Preferences::load`](trait.Preferences.html#tymethod.load). In particular, if no
+/// data was saved with `key`, this fails with `PreferencesError::NotFound`, and the data can be
+/// created with `compare_and_save` and `PrefsVersion::MISSING` instead.
+pub fn load_versioned<T, S>(app: &AppInfo, key: S) -> Result<(T, PrefsVersion), PreferencesError>
+    where T: DeserializeOwned,
+          S: AsRef<str>
+{
+    PreferencesStore::new(app)?.load_versioned(key)
+}
+
+/// Saves `value` as the user data of `app` with the given `key`, but only if the data is still
+/// in the `expected` version, i.e. if no other process or thread changed it since it was loaded
+/// with [`load_versioned`](fn.load_versioned.html). Returns the version of the saved data.
+///
+/// The check and the save are done while holding the lock on the data, so of several processes
+/// updating the same version, exactly one succeeds. The others can load the data again, reapply
+/// their change, and retry.
+///
+/// # Failures
+/// `PreferencesError::Conflict` if the data isn't in the `expected` version, or the same as
+/// [`Preferences::save`](trait.Preferences.html#tymethod.save).
+///
+/// # Example
+/// ```
+/// use preferences::{AppInfo, PreferencesError, PrefsVersion, compare_and_save, load_versioned};
+///
+/// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+///
+/// # preferences::set_backend_for_tests(Some(preferences::MemoryBackend::new()));
+/// let key = "tests/docs/compare-and-save";
+/// compare_and_save(&APP_INFO, key, PrefsVersion::MISSING, &1u32).unwrap();
+///
+/// let (launches, version) = load_versioned::<u32, _>(&APP_INFO, key).unwrap();
+/// compare_and_save(&APP_INFO, key, version, &(launches + 1)).unwrap();
+///
+/// // Saving based on the outdated version fails.
+/// match compare_and_save(&APP_INFO, key, version, &(launches + 1)) {
+///     Err(PreferencesError::Conflict { .. }) => {}
+///     other => panic!("expected a conflict, got {:?}", other),
+/// }
+/// ```
+pub fn compare_and_save<T, S>(app: &AppInfo,
+                              key: S,
+                              expected: PrefsVersion,
+                              value: &T)
+                              -> Result<PrefsVersion, PreferencesError>
+    where T: Serialize,
+          S: AsRef<str>
+{
+    PreferencesStore::new(app)?.compare_and_save(key, expected, value)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{APP_INFO, TestDir};
+    use crate::{MemoryBackend, PreferencesError, PreferencesStore, PrefsVersion};
+    use std::sync::{Arc, Barrier};
+    use std::thread;
+
+    #[test]
+    fn test_compare_and_save_memory() {
+        let store = PreferencesStore::new(&APP_INFO)
+            .unwrap()
+            .with_memory_backend(MemoryBackend::new());
+        assert!(store.load_versioned::<u32, _>("count").unwrap_err().is_not_found());
+        let first = store.compare_and_save("count", PrefsVersion::MISSING, &1u32).unwrap();
+        assert!(first.exists());
+        assert_eq!(store.load_versioned::<u32, _>("count").unwrap(), (1, first));
+        match store.compare_and_save("count", PrefsVersion::MISSING, &2u32) {
+            Err(PreferencesError::Conflict { ref key, .. }) => assert_eq!(key, "count"),
+            other => panic!("expected a conflict, got {:?}", other),
+        }
+        store.compare_and_save("count", first, &2u32).unwrap();
+        assert_eq!(store.load::<u32, _>("count").unwrap(), 2);
+    }
+
+    #[test]
+    fn test_compare_and_save_concurrent() {
+        let dir = TestDir::new("version");
+        let store = dir.store();
+        store.compare_and_save("count", PrefsVersion::MISSING, &0u32).unwrap();
+
+        let threads = 4;
+        let barrier = Arc::new(Barrier::new(threads));
+        let handles: Vec<_> = (0..threads)
+            .map(|_| {
+                let store = store.clone();
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    barrier.wait();
+                    for _ in 0..10 {
+                        loop {
+                            let (count, version) = store.load_versioned::<u32, _>("count")
+                                .unwrap();
+                            match store.compare_and_save("count", version, &(count + 1)) {
+                                Ok(_) => break,
+                                Err(PreferencesError::Conflict { .. }) => continue,
+                                Err(e) => panic!("{}", e),
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(store.load::<u32, _>("count").unwrap(), 40);
+    }
+}
@@ -0,0 +1,135 @@
+//! Watching preferences files for changes.
+
+use notify::{self, Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::fs::create_dir_all;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use crate::{AppInfo, Format, Preferences, PreferencesError, PreferencesStore};
+
+/// Handle to a running watch on user data, returned by [`watch_prefs`](fn.watch_prefs.html).
+///
+/// Watching stops when this handle is dropped.
+pub struct PrefsWatcher {
+    path: PathBuf,
+    _watcher: RecommendedWatcher,
+}
+
+impl PrefsWatcher {
+    /// Returns the path of the watched preferences file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Watches the user data saved with the given `key` and calls `callback` with the freshly loaded
+/// value whenever the file changes, e.g. because another process saved it or the user edited it.
+///
+/// The callback runs on a background thread. It receives the result of loading the data, so a
+/// file that was edited into an invalid state is reported as an error rather than ignored. Saves
+/// made by this process trigger the callback as well. A single change may occasionally be
+/// reported more than once. Deleting the data is not reported.
+///
+/// Requires the `notify` feature.
+///
+/// # Failures
+/// If the data directory cannot be determined or created, or if the platform's file watching
+/// facility fails.
+///
+/// # Example
+/// ```no_run
+/// extern crate preferences;
+/// use preferences::{AppInfo, PreferencesMap, watch_prefs};
+///
+/// const APP_INFO: AppInfo = AppInfo{name: "preferences", author: "Rust language community"};
+///
+/// fn main() {
+///     let _watcher = watch_prefs(&APP_INFO, "options/graphics", |result| {
+///         let graphics: PreferencesMap = result.unwrap();
+///         println!("Graphics settings changed: {:?}", graphics);
+///     }).unwrap();
+///     // ... keep `_watcher` alive for as long as changes should be reported
+/// }
+/// ```
+pub fn watch_prefs<T, S, F>(app: &AppInfo,
+                            key: S,
+                            mut callback: F)
+                            -> Result<PrefsWatcher, PreferencesError>
+    where T: Preferences,
+          S: AsRef<str>,
+          F: FnMut(Result<T, PreferencesError>) + Send + 'static
+{
+    let store = PreferencesStore::new(app)?;
+    let app = app.clone();
+    let owned = key.as_ref().to_owned();
+    watch(&store, key.as_ref(), move |changed| {
+        callback(changed.and_then(|()| T::load(&app, &owned)))
+    })
+}
+
+/// Calls `callback` whenever the file of the data saved in `store` with `key` changes, or with
+/// the error if watching it fails.
+pub(crate) fn watch<F>(store: &PreferencesStore,
+                       key: &str,
+                       mut callback: F)
+                       -> Result<PrefsWatcher, PreferencesError>
+    where F: FnMut(Result<(), PreferencesError>) + Send + 'static
+{
+    let path = store.file_path(key, Format::default())?;
+    let dir = match path.parent() {
+        Some(dir) => dir.to_owned(),
+        None => return Err(io::Error::from(ErrorKind::NotFound).into()),
+    };
+    create_dir_all(&dir)?;
+
+    let target = path.clone();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        match event {
+            Ok(ref event) if is_change_of(event, &target) => callback(Ok(())),
+            Ok(_) => {}
+            Err(e) => callback(Err(e.into())),
+        }
+    })?;
+    watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+    Ok(PrefsWatcher {
+        path,
+        _watcher: watcher,
+    })
+}
+
+fn is_change_of(event: &Event, target: &Path) -> bool {
+    match event.kind {
+        EventKind::Create(_) | EventKind::Modify(_) => event.paths.iter().any(|p| p == target),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::watch;
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+    use crate::testing::TestDir;
+
+    #[test]
+    fn test_watch_prefs() {
+        let dir = TestDir::new("watch");
+        let store = dir.store();
+        let key = "tests/module/watch";
+        assert!(store.save(key, &1u32).is_ok());
+        let (sender, receiver) = channel();
+        let watched = store.clone();
+        let _watcher = watch(&store, key, move |changed| {
+                let result = changed.and_then(|()| watched.load::<u32, _>(key));
+                let _ = sender.send(result.ok());
+            })
+            .unwrap();
+        assert!(store.save(key, &2u32).is_ok());
+        let timeout = Duration::from_secs(10);
+        loop {
+            match receiver.recv_timeout(timeout).unwrap() {
+                Some(2) => break,
+                _ => continue,
+            }
+        }
+    }
+}